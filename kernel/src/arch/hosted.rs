@@ -3,14 +3,16 @@ pub mod mem;
 pub mod process;
 pub mod rand;
 pub mod syscall;
+pub mod timer;
 
 use std::cell::RefCell;
 use std::convert::TryInto;
 use std::env;
 use std::io::Read;
 use std::net::{IpAddr, Ipv4Addr, SocketAddr, TcpListener, TcpStream, ToSocketAddrs};
-use std::sync::mpsc::{channel, Receiver, Sender};
+use std::sync::mpsc::{channel, Receiver, RecvTimeoutError, Sender};
 use std::thread_local;
+use std::time::Duration;
 
 use crate::arch::process::Process;
 use crate::services::SystemServices;
@@ -20,6 +22,7 @@ use xous_kernel::{MemoryAddress, ProcessInit, ProcessKey, Result, SysCall, Threa
 enum ThreadMessage {
     SysCall(PID, TID, SysCall),
     NewConnection(TcpStream, ProcessKey),
+    Preempt,
 }
 
 #[derive(Debug)]
@@ -36,6 +39,10 @@ thread_local!(static NETWORK_LISTEN_ADDRESS: RefCell<SocketAddr> = RefCell::new(
 thread_local!(static SEND_ADDR: RefCell<Option<Sender<SocketAddr>>> = RefCell::new(None));
 thread_local!(static PID1_KEY: RefCell<[u8; 16]> = RefCell::new([0u8; 16]));
 
+/// How long a process may run before it is preempted, unless overridden by
+/// the `XOUS_QUANTUM_MS` environment variable.
+const DEFAULT_QUANTUM: Duration = Duration::from_millis(10);
+
 #[cfg(test)]
 pub fn set_pid1_key(new_key: [u8; 16]) {
     PID1_KEY.with(|p1k| *p1k.borrow_mut() = new_key);
@@ -112,8 +119,13 @@ fn handle_connection(
                 .send(
                     if (packet_data[1] == xous_kernel::syscall::SysCallNumber::SendMessage as _
                         || packet_data[1]
-                            == xous_kernel::syscall::SysCallNumber::TrySendMessage as _)
-                        && (packet_data[3] == 1 || packet_data[3] == 2 || packet_data[3] == 3)
+                            == xous_kernel::syscall::SysCallNumber::TrySendMessage as _
+                        || packet_data[1]
+                            == xous_kernel::syscall::SysCallNumber::SendMessageTimeout as _)
+                        // `SendMessageTimeout` packs its timeout above the message type
+                        && (packet_data[3] & 0xff == 1
+                            || packet_data[3] & 0xff == 2
+                            || packet_data[3] & 0xff == 3)
                     {
                         let mut v = vec![0; packet_data[6]];
                         if conn.read_exact(&mut v).is_err() {
@@ -123,6 +135,8 @@ fn handle_connection(
                         ServerMessage::ServerPacketWithData(packet_data, v)
                     } else if packet_data[1]
                         == xous_kernel::syscall::SysCallNumber::ReturnMemory as _
+                        || packet_data[1]
+                            == xous_kernel::syscall::SysCallNumber::ReturnMemoryWithValid as _
                     {
                         let mut v = vec![0; packet_data[4]];
                         if conn.read_exact(&mut v).is_err() {
@@ -179,9 +193,16 @@ fn handle_connection(
                             pkt[0], pkt[1], pkt[2], pkt[3], pkt[4], pkt[5], pkt[6], pkt[7]
                         );
                     }
-                    Ok(call) => chn
-                        .send(ThreadMessage::SysCall(pid, thread_id, call))
-                        .expect("couldn't make syscall"),
+                    Ok(call) => {
+                        // A process that terminates itself mustn't be terminated again
+                        // when its connection closes, since its PID may have been reused.
+                        let is_terminate = matches!(call, SysCall::TerminateProcess(_));
+                        chn.send(ThreadMessage::SysCall(pid, thread_id, call))
+                            .expect("couldn't make syscall");
+                        if is_terminate {
+                            return;
+                        }
+                    }
                 }
             }
             ServerMessage::ServerPacketWithData(pkt, data) => {
@@ -204,7 +225,8 @@ fn handle_connection(
                         // );
                         match call {
                             SysCall::SendMessage(ref _cid, ref mut envelope)
-                            | SysCall::TrySendMessage(ref _cid, ref mut envelope) => {
+                            | SysCall::TrySendMessage(ref _cid, ref mut envelope)
+                            | SysCall::SendMessageTimeout(ref _cid, ref mut envelope, _) => {
                                 match envelope {
                                     xous_kernel::Message::MutableBorrow(msg)
                                     | xous_kernel::Message::Borrow(msg)
@@ -237,7 +259,8 @@ fn handle_connection(
                                     | xous_kernel::Message::BlockingScalar(_) => (),
                                 }
                             }
-                            SysCall::ReturnMemory(ref _sender, ref mut buf) => {
+                            SysCall::ReturnMemory(ref _sender, ref mut buf)
+                            | SysCall::ReturnMemoryWithValid(ref _sender, ref mut buf, _, _) => {
                                 let sliced_data = data.into_boxed_slice();
                                 assert_eq!(
                                     sliced_data.len(),
@@ -271,7 +294,7 @@ fn handle_connection(
     chn.send(ThreadMessage::SysCall(
         pid,
         1,
-        xous_kernel::SysCall::TerminateProcess,
+        xous_kernel::SysCall::TerminateProcess(0),
     ))
     .unwrap();
 }
@@ -455,6 +478,31 @@ pub fn idle() -> bool {
         receiver
     };
 
+    let quantum = env::var("XOUS_QUANTUM_MS")
+        .ok()
+        .and_then(|ms| ms.parse().ok())
+        .filter(|ms| *ms > 0)
+        .map(Duration::from_millis)
+        .unwrap_or(DEFAULT_QUANTUM);
+
+    // Stand in for the timer interrupt that preempts processes on real
+    // hardware by posting a `Preempt` message once every quantum.
+    let (stop_watchdog, watchdog_stop_receiver) = channel();
+    let watchdog_sender = sender.clone();
+    let watchdog_handle = std::thread::Builder::new()
+        .name("kernel preemption watchdog".to_owned())
+        .spawn(move || loop {
+            match watchdog_stop_receiver.recv_timeout(quantum) {
+                Err(RecvTimeoutError::Timeout) => {
+                    if watchdog_sender.send(ThreadMessage::Preempt).is_err() {
+                        return;
+                    }
+                }
+                Ok(()) | Err(RecvTimeoutError::Disconnected) => return,
+            }
+        })
+        .expect("couldn't spawn preemption watchdog");
+
     let listen_thread_handle = SEND_ADDR.with(|sa| {
         let sa = sa.borrow_mut().take();
         std::thread::Builder::new()
@@ -522,6 +570,18 @@ pub fn idle() -> bool {
                 })
                 .unwrap();
             }
+            ThreadMessage::Preempt => {
+                crate::syscall::expire_timeouts();
+                let pid = crate::arch::process::current_pid();
+                // The process or thread that ran last may have exited since,
+                // in which case there's nothing to preempt.
+                if let Some(tid) = crate::arch::process::running_tid() {
+                    match crate::syscall::preempt(pid, tid) {
+                        Ok(()) | Err(xous_kernel::Error::ProcessNotFound) => (),
+                        Err(e) => println!("KERNEL: couldn't preempt PID {}: {:?}", pid, e),
+                    }
+                }
+            }
             ThreadMessage::SysCall(pid, thread_id, call) => {
                 // println!("KERNEL({}): Received syscall {:?}", pid, call);
                 crate::arch::process::set_current_pid(pid);
@@ -529,7 +589,7 @@ pub fn idle() -> bool {
 
                 // If the call being made is to terminate the current process, we need to know
                 // because we won't be able to send a response.
-                let is_terminate = call == SysCall::TerminateProcess;
+                let is_terminate = matches!(call, SysCall::TerminateProcess(_));
                 let is_shutdown = call == SysCall::Shutdown;
 
                 // For a "Shutdown" command, send the response before we issue the shutdown.
@@ -549,7 +609,7 @@ pub fn idle() -> bool {
                             "Unable to send response to process: {:?} -- terminating",
                             _e
                         );
-                        crate::syscall::handle(pid, thread_id, false, SysCall::TerminateProcess)
+                        crate::syscall::handle(pid, thread_id, false, SysCall::TerminateProcess(0))
                             .ok();
                     });
                     // println!("KERNEL: Done sending");
@@ -586,7 +646,7 @@ pub fn idle() -> bool {
                             "KERNEL({}): Unable to send response to process: {:?} -- terminating",
                             pid, _e
                         );
-                        crate::syscall::handle(pid, thread_id, false, SysCall::TerminateProcess)
+                        crate::syscall::handle(pid, thread_id, false, SysCall::TerminateProcess(0))
                             .ok();
                     });
                     crate::arch::process::set_current_pid(existing_pid);
@@ -602,6 +662,11 @@ pub fn idle() -> bool {
         }
     }
 
+    stop_watchdog.send(()).ok();
+    watchdog_handle
+        .join()
+        .expect("error waiting for preemption watchdog to return");
+
     // println!("Exiting Xous because the listen thread channel has closed. Waiting for thread to finish...");
     listen_thread_handle
         .join()
@@ -12,6 +12,10 @@ pub fn enable_irq(_irq_no: usize) {
     unimplemented!();
 }
 
+pub fn disable_irq(_irq_no: usize) -> Result<(), xous_kernel::Error> {
+    unimplemented!();
+}
+
 pub unsafe fn set_isr_return_pair(_pid: PID, _ctx: TID) {
     unimplemented!()
 }
@@ -93,10 +93,15 @@ pub fn return_page_inner(
     _dest_pid: PID,
     _dest_space: &MemoryMapping,
     _dest_addr: *mut u8,
+    _restore: bool,
 ) -> Result<usize, Error> {
     unimplemented!()
 }
 
+pub fn page_lent_immutably(_virt: usize) -> bool {
+    unimplemented!()
+}
+
 pub fn unmap_page_inner(_mm: &mut MemoryManager, virt: usize) -> Result<usize, Error> {
     Ok(virt)
 }
@@ -108,3 +113,7 @@ pub fn hand_page_to_user(_virt: *mut u8) -> Result<(), Error> {
 pub fn virt_to_phys(virt: usize) -> Result<usize, Error> {
     Ok(virt)
 }
+
+pub fn read_user_word(_virt: usize) -> Result<usize, Error> {
+    unimplemented!()
+}
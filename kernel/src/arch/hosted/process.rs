@@ -1,6 +1,8 @@
 pub const MAX_THREAD: TID = 31;
+use crate::arch::mem::{MemoryMapping, DEFAULT_MEMORY_MAPPING, PAGE_SIZE};
 use crate::services::ProcessInner;
 use core::cell::RefCell;
+use core::num::NonZeroU8;
 use std::io::Write;
 use std::net::TcpStream;
 use std::thread_local;
@@ -9,6 +11,9 @@ use xous_kernel::{ProcessInit, ProcessKey, ThreadInit, PID, TID};
 pub const INITIAL_TID: usize = 1;
 pub const MAX_PROCESS_COUNT: usize = 32;
 
+/// The most pages of extra connection slots that a process may have.
+pub const MAX_CONNECTION_MAP_PAGES: usize = 4;
+
 pub struct Process {
     pid: PID,
 }
@@ -18,6 +23,9 @@ struct ProcessImpl {
     /// Global parameters used by the operating system
     pub inner: ProcessInner,
 
+    /// Connection slots that don't fit in `ProcessInner`
+    extra_connections: Vec<Option<NonZeroU8>>,
+
     /// A 16-byte key used to register a process when it first starts
     key: ProcessKey,
 
@@ -65,6 +73,23 @@ pub fn current_pid() -> PID {
     PROCESS_TABLE.with(|pt| pt.borrow().current)
 }
 
+/// The thread the kernel last switched to in the current process, unless it
+/// has exited since. Hosted threads all run at once, so this is only the one
+/// the kernel is tracking.
+pub fn running_tid() -> Option<TID> {
+    PROCESS_TABLE.with(|pt| {
+        let pt = pt.borrow();
+        let process = pt.table.get(pt.current.get() as usize - 1)?.as_ref()?;
+        let tid = process.current_thread;
+        let thread = process.threads.get(tid.checked_sub(1)?)?;
+        if thread.allocated && thread.exit_code.is_none() {
+            Some(tid)
+        } else {
+            None
+        }
+    })
+}
+
 pub fn set_current_pid(pid: PID) {
     PROCESS_TABLE.with(|pt| {
         let pid_idx = (pid.get() - 1) as usize;
@@ -111,11 +136,17 @@ pub fn register_connection_for_key(
 /// In a `std` environment, we can't manage threads so this is a no-op.
 pub struct Thread {
     allocated: bool,
+
+    /// Set once the thread has exited, until somebody joins it
+    exit_code: Option<usize>,
 }
 
 impl Default for Thread {
     fn default() -> Self {
-        Thread { allocated: false }
+        Thread {
+            allocated: false,
+            exit_code: None,
+        }
     }
 }
 
@@ -171,6 +202,56 @@ impl Process {
         })
     }
 
+    /// Calls the provided function with the current inner process state, along
+    /// with any connection slots that were added beyond `connection_map`.
+    pub fn with_connections<F, R>(f: F) -> R
+    where
+        F: FnOnce(&ProcessInner, &[Option<NonZeroU8>]) -> R,
+    {
+        PROCESS_TABLE.with(|pt| {
+            let process_table = pt.borrow();
+            let current = &process_table.table[process_table.current.get() as usize - 1]
+                .as_ref()
+                .unwrap();
+            f(&current.inner, &current.extra_connections)
+        })
+    }
+
+    /// Calls the provided function with the current inner process state, along
+    /// with any connection slots that were added beyond `connection_map`.
+    pub fn with_connections_mut<F, R>(f: F) -> R
+    where
+        F: FnOnce(&mut ProcessInner, &mut [Option<NonZeroU8>]) -> R,
+    {
+        PROCESS_TABLE.with(|pt| {
+            let mut process_table = pt.borrow_mut();
+            let current_pid_idx = process_table.current.get() as usize - 1;
+            let current = process_table.table[current_pid_idx].as_mut().unwrap();
+            f(&mut current.inner, &mut current.extra_connections)
+        })
+    }
+
+    /// Add another page worth of connection slots to the current process.
+    ///
+    /// # Errors
+    ///
+    /// * **OutOfMemory**: The process already has `MAX_CONNECTION_MAP_PAGES`
+    ///                    pages of connections
+    pub fn grow_connections() -> Result<(), xous_kernel::Error> {
+        PROCESS_TABLE.with(|pt| {
+            let mut process_table = pt.borrow_mut();
+            let current_pid_idx = process_table.current.get() as usize - 1;
+            let current = process_table.table[current_pid_idx].as_mut().unwrap();
+            if current.inner.connection_map_pages as usize >= MAX_CONNECTION_MAP_PAGES {
+                return Err(xous_kernel::Error::OutOfMemory);
+            }
+            let new_len = current.extra_connections.len() + PAGE_SIZE;
+            current.extra_connections.resize(new_len, None);
+            current.inner.connection_map_pages += 1;
+            Ok(())
+        })
+    }
+
     /// Calls the provided function with the current inner process state.
     pub fn with_current_mut<F, R>(f: F) -> R
     where
@@ -268,6 +349,46 @@ impl Process {
         })
     }
 
+    /// Whether `tid` is a thread that has been created and not yet joined.
+    pub fn thread_exists(&self, tid: TID) -> bool {
+        PROCESS_TABLE.with(|pt| {
+            let process_table = pt.borrow();
+            let current_pid_idx = process_table.current.get() as usize - 1;
+            let process = process_table.table[current_pid_idx].as_ref().unwrap();
+            tid > 0
+                && process
+                    .threads
+                    .get(tid - 1)
+                    .map(|thread| thread.allocated)
+                    .unwrap_or(false)
+        })
+    }
+
+    /// Record that `tid` has exited with `code`. Its slot is kept until
+    /// `take_exit_code()` is called.
+    pub fn exit_thread(&mut self, tid: TID, code: usize) {
+        assert!(tid > 0);
+        PROCESS_TABLE.with(|pt| {
+            let mut process_table = pt.borrow_mut();
+            let current_pid_idx = process_table.current.get() as usize - 1;
+            let process = &mut process_table.table[current_pid_idx].as_mut().unwrap();
+            process.threads[tid - 1].exit_code = Some(code);
+        })
+    }
+
+    /// If `tid` has exited, free its slot and return its exit code.
+    pub fn take_exit_code(&mut self, tid: TID) -> Option<usize> {
+        assert!(tid > 0);
+        PROCESS_TABLE.with(|pt| {
+            let mut process_table = pt.borrow_mut();
+            let current_pid_idx = process_table.current.get() as usize - 1;
+            let process = &mut process_table.table[current_pid_idx].as_mut().unwrap();
+            let code = process.threads[tid - 1].exit_code.take()?;
+            process.threads[tid - 1].allocated = false;
+            Some(code)
+        })
+    }
+
     pub fn set_thread_result(&mut self, tid: TID, result: xous_kernel::Result) {
         assert!(tid > 0);
         PROCESS_TABLE.with(|pt| {
@@ -327,19 +448,23 @@ impl Process {
 
     /// Initialize this process with the given memory space. THIS DOES NOT
     /// INITIALIZE A MAIN THREAD. You must call `setup_thread()` in order to
-    /// select a main thread.
-    pub fn create(pid: PID, init_data: ProcessInit) -> PID {
+    /// select a main thread, so no initial thread is returned.
+    pub fn create(
+        pid: PID,
+        init_data: ProcessInit,
+    ) -> Result<(MemoryMapping, Option<ThreadInit>), xous_kernel::Error> {
         PROCESS_TABLE.with(|process_table| {
             let mut process_table = process_table.borrow_mut();
             let pid_idx = (pid.get() - 1) as usize;
             use crate::filled_array;
             let process = ProcessImpl {
                 inner: Default::default(),
+                extra_connections: Vec::new(),
                 conn: None,
                 key: init_data.key,
                 memory_to_return: filled_array![None; 32 /* MAX_THREAD */],
                 current_thread: INITIAL_TID,
-                threads: [Thread { allocated: false }; MAX_THREAD + 1],
+                threads: [Thread::default(); MAX_THREAD + 1],
             };
 
             process_table.total += 1;
@@ -350,7 +475,7 @@ impl Process {
             } else {
                 panic!("pid already allocated!");
             }
-            pid
+            Ok((DEFAULT_MEMORY_MAPPING, None))
         })
     }
 
@@ -361,13 +486,11 @@ impl Process {
             if pid_idx >= process_table.table.len() {
                 panic!("attempted to destroy PID that exceeds table index: {}", pid);
             }
+            // A process that never connected has nothing to shut down.
             let process = process_table.table[pid_idx].as_mut().unwrap();
-            process
-                .conn
-                .as_mut()
-                .unwrap()
-                .shutdown(std::net::Shutdown::Both)
-                .unwrap();
+            if let Some(conn) = process.conn.as_mut() {
+                conn.shutdown(std::net::Shutdown::Both).unwrap();
+            }
             process_table.table[pid_idx] = None;
             process_table.total -= 1;
             Ok(())
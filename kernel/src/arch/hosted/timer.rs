@@ -0,0 +1,9 @@
+use std::thread_local;
+use std::time::Instant;
+
+thread_local!(static START_TIME: Instant = Instant::now());
+
+/// The number of milliseconds since the kernel first asked for the time.
+pub fn elapsed_ms() -> u64 {
+    START_TIME.with(|start| start.elapsed().as_millis() as u64)
+}
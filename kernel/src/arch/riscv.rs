@@ -7,6 +7,7 @@ pub mod mem;
 pub mod process;
 pub mod rand;
 pub mod syscall;
+pub mod timer;
 
 pub use process::Thread;
 
@@ -15,6 +16,7 @@ pub fn current_pid() -> PID {
 }
 
 pub fn init() {
+    timer::init();
     unsafe {
         sstatus::set_sie();
         sie::set_ssoft();
@@ -1,8 +1,8 @@
 use crate::arch::current_pid;
 use crate::arch::mem::MemoryMapping;
 use crate::arch::process::Process as ArchProcess;
-use crate::arch::process::{Thread, RETURN_FROM_ISR};
-use crate::mem::{MemoryManager, PAGE_SIZE};
+use crate::arch::process::{Thread, EXIT_THREAD, RETURN_FROM_ISR};
+use crate::mem::MemoryManager;
 use crate::services::SystemServices;
 use riscv::register::{scause, sepc, sie, sstatus, stval, vexriscv::sim, vexriscv::sip};
 use xous_kernel::{SysCall, PID, TID};
@@ -11,10 +11,6 @@ extern "Rust" {
     fn _xous_syscall_return_result(result: &xous_kernel::Result, context: &Thread) -> !;
 }
 
-extern "C" {
-    fn flush_mmu();
-}
-
 /// Disable external interrupts
 pub fn disable_all_irqs() {
     unsafe { sie::clear_sext() };
@@ -126,50 +122,72 @@ pub extern "C" fn trap_handler(
                     "KERNEL({}): RISC-V fault: {} @ {:08x}, addr {:08x}",
                     pid, ex, pc, addr
                 );
-                let entry = crate::arch::mem::pagetable_entry(addr).unwrap_or_else(|x| {
-                    // MemoryManagerHandle::get().print_ownership();
-                    MemoryMapping::current().print_map();
-                    panic!(
-                        "error {:?} at {:08x}: memory not mapped or reserved for addr {:08x}",
-                        x, pc, addr
-                    );
-                });
-                let flags = *entry & 0x1ff;
-
-                // If the flags are nonzero, but the "Valid" bit is not 1 and
-                // the page isn't shared, then this is a reserved page. Allocate
-                // a real page to back it and resume execution.
-                if flags & 1 == 0 && flags != 0 && flags & (1 << 8) == 0 {
-                    let new_page = MemoryManager::with_mut(|mm| {
-                        mm.alloc_page(pid).expect("Couldn't allocate new page")
-                    });
-                    let ppn1 = (new_page >> 22) & ((1 << 12) - 1);
-                    let ppn0 = (new_page >> 12) & ((1 << 10) - 1);
-                    unsafe {
-                        // Map the page to our process
-                        *entry = (ppn1 << 20)
-                            | (ppn0 << 10)
-                            | (flags | (1 << 0) /* valid */ | (1 << 6) /* D */ | (1 << 7)/* A */);
-                        flush_mmu();
-
-                        // Zero-out the page
-                        let virt = addr & !0xfff;
-                        (virt as *mut usize)
-                            .write_bytes(0, PAGE_SIZE / core::mem::size_of::<usize>());
-
-                        // Move the page into userspace
-                        *entry = (ppn1 << 20)
-                            | (ppn0 << 10)
-                            | (flags | (1 << 0) /* valid */ | (1 << 4) /* USER */ | (1 << 6) /* D */ | (1 << 7)/* A */);
-                        flush_mmu();
-                    };
-
-                    ArchProcess::with_current_mut(|process| {
+                // If this is a reserved page, allocate a real page to back it
+                // and resume execution.
+                match MemoryManager::with_mut(|mm| {
+                    crate::arch::mem::back_reserved_page(mm, pid, addr)
+                }) {
+                    Ok(()) => ArchProcess::with_current_mut(|process| {
                         crate::arch::syscall::resume(
                             current_pid().get() == 1,
                             process.current_thread(),
                         )
-                    });
+                    }),
+                    // A process that has run out of memory can't carry on, but
+                    // the rest of the system can.
+                    Err(xous_kernel::Error::OutOfMemory) if pid.get() != 1 => {
+                        println!(
+                            "KERNEL({}): out of memory at {:08x}, terminating process",
+                            pid, pc
+                        );
+                        let tid = crate::arch::process::current_tid();
+                        let in_irq = unsafe { PREVIOUS_PAIR.is_some() };
+                        match crate::syscall::terminate_out_of_memory(pid, tid, in_irq) {
+                            Ok(_) => ArchProcess::with_current_mut(|process| {
+                                crate::arch::syscall::resume(
+                                    current_pid().get() == 1,
+                                    process.current_thread(),
+                                )
+                            }),
+                            // A process can't be terminated from its own
+                            // interrupt handler, so abandon the handler and go
+                            // back to whatever it interrupted. The process is
+                            // terminated if it runs out of memory again.
+                            Err(e) if in_irq => {
+                                println!(
+                                    "KERNEL({}): couldn't terminate process in its interrupt handler: {:?}",
+                                    pid, e
+                                );
+                                let (previous_pid, previous_context) =
+                                    unsafe { PREVIOUS_PAIR.take().unwrap() };
+                                if let Err(e) = SystemServices::with_mut(|ss| {
+                                    ss.finish_callback_and_resume(previous_pid, previous_context)
+                                }) {
+                                    println!(
+                                        "KERNEL({}): couldn't resume PID {}: {:?}",
+                                        pid, previous_pid, e
+                                    );
+                                } else {
+                                    enable_all_irqs();
+                                    ArchProcess::with_current_mut(|process| {
+                                        crate::arch::syscall::resume(
+                                            current_pid().get() == 1,
+                                            process.current_thread(),
+                                        )
+                                    });
+                                }
+                            }
+                            // The faulting thread can't carry on, so fall
+                            // through and halt with the details below.
+                            Err(e) => {
+                                println!("KERNEL({}): couldn't terminate process: {:?}", pid, e)
+                            }
+                        }
+                    }
+                    Err(xous_kernel::Error::OutOfMemory) => {
+                        panic!("kernel ran out of memory at {:08x}", pc)
+                    }
+                    Err(_) => (),
                 }
             }
             RiscvException::InstructionPageFault(RETURN_FROM_ISR, _offset) => {
@@ -198,6 +216,19 @@ pub extern "C" fn trap_handler(
                     crate::arch::syscall::resume(current_pid().get() == 1, process.current_thread())
                 });
             }
+            RiscvException::InstructionPageFault(EXIT_THREAD, _offset) => {
+                // The thread returned from its entrypoint, so treat its return
+                // value as its exit code.
+                let (tid, code) = ArchProcess::with_current(|process| {
+                    (process.current_tid(), process.current_thread().registers[9])
+                });
+                crate::syscall::handle(pid, tid, false, SysCall::ExitThread(code))
+                    .expect("couldn't exit thread");
+
+                ArchProcess::with_current_mut(|process| {
+                    crate::arch::syscall::resume(current_pid().get() == 1, process.current_thread())
+                });
+            }
             _ => (),
         }
         println!("SYSTEM HALT: CPU Exception on PID {}: {}", pid, ex);
@@ -208,7 +239,32 @@ pub extern "C" fn trap_handler(
         MemoryMapping::current().print_map();
         loop {}
     } else {
-        let irqs_pending = sip::read();
+        let mut irqs_pending = sip::read();
+
+        // The preemption timer belongs to the kernel, so it's never passed on
+        // to userspace. Each tick wakes any senders whose timeouts have
+        // expired. If it's the only interrupt pending and we're not already
+        // in the middle of an interrupt handler, the current thread's quantum
+        // is up.
+        if irqs_pending & (1 << crate::arch::timer::IRQ) != 0 {
+            crate::arch::timer::acknowledge();
+            crate::syscall::expire_timeouts();
+            irqs_pending &= !(1 << crate::arch::timer::IRQ);
+            if irqs_pending == 0 {
+                if unsafe { PREVIOUS_PAIR.is_none() } {
+                    // If the thread can't be preempted, let it carry on
+                    // rather than bring down the whole system.
+                    let tid = crate::arch::process::current_tid();
+                    if let Err(e) = crate::syscall::preempt(pid, tid) {
+                        println!("KERNEL({}): couldn't preempt thread {}: {:?}", pid, tid, e);
+                    }
+                }
+                ArchProcess::with_current_mut(|process| {
+                    crate::arch::syscall::resume(current_pid().get() == 1, process.current_thread())
+                });
+            }
+        }
+
         // Safe to access globals since interrupts are disabled
         // when this function runs.
         unsafe {
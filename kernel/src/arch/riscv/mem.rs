@@ -1,9 +1,9 @@
 use crate::mem::MemoryManager;
 use core::fmt;
-use riscv::register::satp;
+use riscv::register::{satp, sstatus};
 use xous_kernel::{MemoryFlags, PID};
 
-// pub const DEFAULT_STACK_TOP: usize = 0x8000_0000;
+pub const DEFAULT_STACK_TOP: usize = 0x8000_0000;
 pub const DEFAULT_HEAP_BASE: usize = 0x2000_0000;
 pub const DEFAULT_MESSAGE_BASE: usize = 0x4000_0000;
 pub const DEFAULT_BASE: usize = 0x6000_0000;
@@ -12,6 +12,11 @@ pub const USER_AREA_END: usize = 0xff00_0000;
 pub const PAGE_SIZE: usize = 4096;
 const PAGE_TABLE_OFFSET: usize = 0xff40_0000;
 const PAGE_TABLE_ROOT_OFFSET: usize = 0xff80_0000;
+const CONTEXT_OFFSET: usize = 0xff80_1000;
+
+/// A kernel-only address where pages belonging to some other address space
+/// get mapped while the kernel fills them in.
+const SCRATCH_PAGE_OFFSET: usize = 0xff80_8000;
 
 extern "C" {
     fn flush_mmu();
@@ -206,6 +211,98 @@ impl MemoryMapping {
         l0_pt.entries[vpn0] = translate_flags(flags).bits();
         Ok(())
     }
+
+    /// Create a new address space for `pid`, laid out the same way as the
+    /// ones the loader builds. The pagetables and the process context are
+    /// mapped at their usual addresses, the kernel is shared with the current
+    /// address space, and nothing else is mapped yet. The new address space
+    /// is not activated.
+    ///
+    /// # Errors
+    ///
+    /// * **OutOfMemory**: There aren't enough free pages. Any pages that were
+    ///                    allocated still belong to `pid`.
+    pub fn allocate(mm: &mut MemoryManager, pid: PID) -> Result<MemoryMapping, xous_kernel::Error> {
+        let root = mm.alloc_page(pid)?;
+        // The leaf pagetable covering the root pagetable and the context
+        let root_l0 = mm.alloc_page(pid)?;
+        // The leaf pagetable covering all of the leaf pagetables
+        let pagetable_l0 = mm.alloc_page(pid)?;
+        let context = mm.alloc_page(pid)?;
+
+        let table = |phys: usize| ((phys >> 12) << 10) | MMUFlags::VALID.bits();
+        let page = |phys: usize| {
+            ((phys >> 12) << 10)
+                | (MMUFlags::VALID | MMUFlags::R | MMUFlags::W | MMUFlags::D | MMUFlags::A).bits()
+        };
+        let kernel = unsafe { (*(PAGE_TABLE_ROOT_OFFSET as *const RootPageTable)).entries[1023] };
+
+        fill_page(mm, root, |bytes| {
+            let l1_pt = unsafe { &mut (*(bytes.as_mut_ptr() as *mut RootPageTable)) };
+            l1_pt.entries[PAGE_TABLE_OFFSET >> 22] = table(pagetable_l0);
+            l1_pt.entries[PAGE_TABLE_ROOT_OFFSET >> 22] = table(root_l0);
+            l1_pt.entries[1023] = kernel;
+            Ok(())
+        })?;
+        fill_page(mm, root_l0, |bytes| {
+            let l0_pt = unsafe { &mut (*(bytes.as_mut_ptr() as *mut LeafPageTable)) };
+            l0_pt.entries[(PAGE_TABLE_ROOT_OFFSET >> 12) & ((1 << 10) - 1)] = page(root);
+            l0_pt.entries[(CONTEXT_OFFSET >> 12) & ((1 << 10) - 1)] = page(context);
+            Ok(())
+        })?;
+        // Leaf pagetable `n` is mapped at `PAGE_TABLE_OFFSET + n * PAGE_SIZE`
+        fill_page(mm, pagetable_l0, |bytes| {
+            let l0_pt = unsafe { &mut (*(bytes.as_mut_ptr() as *mut LeafPageTable)) };
+            l0_pt.entries[PAGE_TABLE_OFFSET >> 22] = page(pagetable_l0);
+            l0_pt.entries[PAGE_TABLE_ROOT_OFFSET >> 22] = page(root_l0);
+            Ok(())
+        })?;
+        fill_page(mm, context, |_| Ok(()))?;
+
+        Ok(MemoryMapping {
+            satp: 0x8000_0000 | ((pid.get() as usize) << 22) | (root >> 12),
+        })
+    }
+}
+
+/// Map `phys` into the current address space at `SCRATCH_PAGE_OFFSET`, zero
+/// it, and let `f` fill it in before it gets unmapped again. This is how the
+/// kernel writes to pages that belong to an address space that isn't active.
+pub fn fill_page<F>(mm: &mut MemoryManager, phys: usize, f: F) -> Result<(), xous_kernel::Error>
+where
+    F: FnOnce(&mut [u8]) -> Result<(), xous_kernel::Error>,
+{
+    let pid = crate::arch::current_pid();
+    map_page_inner(
+        mm,
+        pid,
+        phys,
+        SCRATCH_PAGE_OFFSET,
+        MemoryFlags::W | MemoryFlags::R,
+        false,
+    )?;
+    let page_addr = SCRATCH_PAGE_OFFSET as *mut usize;
+    unsafe { page_addr.write_bytes(0, PAGE_SIZE / core::mem::size_of::<usize>()) };
+    let result = f(unsafe { core::slice::from_raw_parts_mut(page_addr as *mut u8, PAGE_SIZE) });
+    unmap_page_inner(mm, SCRATCH_PAGE_OFFSET)?;
+    result
+}
+
+/// Map `phys` into the userspace of `space`, which belongs to `pid` and need
+/// not be the active address space.
+pub fn map_page_into(
+    mm: &mut MemoryManager,
+    space: &MemoryMapping,
+    pid: PID,
+    phys: usize,
+    virt: usize,
+    flags: MemoryFlags,
+) -> Result<(), xous_kernel::Error> {
+    let current = MemoryMapping::current();
+    space.activate()?;
+    let result = map_page_inner(mm, pid, phys, virt, flags, true);
+    current.activate().unwrap();
+    result
 }
 
 pub const DEFAULT_MEMORY_MAPPING: MemoryMapping = MemoryMapping { satp: 0 };
@@ -397,6 +494,55 @@ pub fn pagetable_entry(addr: usize) -> Result<&'static mut usize, xous_kernel::E
     Ok(entry)
 }
 
+/// Read a word out of the current process' memory. The kernel normally isn't
+/// permitted to touch user pages, so access is only granted for this one read.
+///
+/// # Errors
+///
+/// * **BadAlignment**: The address isn't word-aligned
+/// * **BadAddress**: The address isn't readable by the process
+pub fn read_user_word(virt: usize) -> Result<usize, xous_kernel::Error> {
+    let readable = (MMUFlags::VALID | MMUFlags::R | MMUFlags::USER).bits();
+    if *pagetable_entry(virt)? & readable != readable {
+        return Err(xous_kernel::Error::BadAddress);
+    }
+    unsafe {
+        sstatus::set_sum();
+        let word = (virt as *const usize).read_volatile();
+        sstatus::clear_sum();
+        Ok(word)
+    }
+}
+
+/// Copy bytes out of the current process' memory, which may have any
+/// alignment. As with `read_user_word()`, every page they come from must be
+/// readable by the process.
+///
+/// # Errors
+///
+/// * **BadAddress**: Part of the range isn't readable by the process
+pub fn read_user_bytes(virt: usize, dest: &mut [u8]) -> Result<(), xous_kernel::Error> {
+    if dest.is_empty() {
+        return Ok(());
+    }
+    let end = virt
+        .checked_add(dest.len())
+        .filter(|&end| end <= USER_AREA_END)
+        .ok_or(xous_kernel::Error::BadAddress)?;
+    let readable = (MMUFlags::VALID | MMUFlags::R | MMUFlags::USER).bits();
+    for page in ((virt & !(PAGE_SIZE - 1))..end).step_by(PAGE_SIZE) {
+        if *pagetable_entry(page)? & readable != readable {
+            return Err(xous_kernel::Error::BadAddress);
+        }
+    }
+    unsafe {
+        sstatus::set_sum();
+        core::ptr::copy_nonoverlapping(virt as *const u8, dest.as_mut_ptr(), dest.len());
+        sstatus::clear_sum();
+    }
+    Ok(())
+}
+
 /// Ummap the given page from the specified process table.  Never allocate a new
 /// page.
 ///
@@ -479,8 +625,8 @@ pub fn lend_page_inner(
 
     let result = if mutable {
         // If we try to share a page that's already mutable, that's a sharing
-        // violation.
-        if *entry & MMUFlags::S.bits() != 0 {
+        // violation. So is lending out write access that we don't have.
+        if *entry & MMUFlags::S.bits() != 0 || *entry & MMUFlags::W.bits() == 0 {
             return Err(xous_kernel::Error::ShareViolation);
         }
 
@@ -500,6 +646,12 @@ pub fn lend_page_inner(
             dest_pid.get() != 1,
         )
     } else {
+        // A page that is mutably lent can't be lent again until it comes
+        // back, but one that is immutably lent can have more borrowers.
+        if *entry & (MMUFlags::S | MMUFlags::VALID).bits() == MMUFlags::S.bits() {
+            return Err(xous_kernel::Error::ShareViolation);
+        }
+
         // Page is immutably shared.  Mark the page as read-only in this
         // process.
         let previous_flag = if *entry & MMUFlags::W.bits() != 0 {
@@ -533,7 +685,9 @@ pub fn lend_page_inner(
     result.map(|_| phys)
 }
 
-/// Return a page from `src_space` back to `dest_space`.
+/// Return a page from `src_space` back to `dest_space`. If other processes
+/// are still borrowing the page, it is only unmapped from `src_space`, and
+/// `restore` is `false` so the page stays lent in `dest_space`.
 pub fn return_page_inner(
     _mm: &mut MemoryManager,
     src_space: &MemoryMapping,
@@ -541,6 +695,7 @@ pub fn return_page_inner(
     _dest_pid: PID,
     dest_space: &MemoryMapping,
     dest_addr: *mut u8,
+    restore: bool,
 ) -> Result<usize, xous_kernel::Error> {
     let src_entry = pagetable_entry(src_addr as usize)?;
     let phys = (*src_entry >> 10) << 12;
@@ -551,6 +706,9 @@ pub fn return_page_inner(
 
     *src_entry = 0;
     unsafe { flush_mmu() };
+    if !restore {
+        return Ok(phys);
+    }
 
     dest_space.activate()?;
     let dest_entry =
@@ -604,6 +762,51 @@ pub fn virt_to_phys(virt: usize) -> Result<usize, xous_kernel::Error> {
     Ok((l0_pt.entries[vpn0] >> 10) << 12)
 }
 
+/// Determine whether the page at `virt` in the current address space is
+/// already lent to at least one process that may only read it.
+pub fn page_lent_immutably(virt: usize) -> bool {
+    let lent = (MMUFlags::S | MMUFlags::VALID).bits();
+    matches!(pagetable_entry(virt), Ok(entry) if *entry & lent == lent)
+}
+
+/// Give the current process a zeroed page to back `virt`, which it has just
+/// touched for the first time. Memory is only reserved when it is mapped
+/// without a physical address or added to the heap, and a page is allocated
+/// here once it is actually used.
+///
+/// # Errors
+///
+/// * **BadAddress**: `virt` isn't in a page that was reserved
+/// * **OutOfMemory**: There are no free pages, or `pid` is at its memory limit
+pub fn back_reserved_page(
+    mm: &mut MemoryManager,
+    pid: PID,
+    virt: usize,
+) -> Result<(), xous_kernel::Error> {
+    let page = virt & !0xfff;
+    let entry = pagetable_entry(page).or(Err(xous_kernel::Error::BadAddress))?;
+    let flags = *entry & 0x1ff;
+
+    // If the flags are nonzero, but the "Valid" bit is not 1 and the page
+    // isn't shared, then this is a reserved page.
+    if flags & MMUFlags::VALID.bits() != 0 || flags == 0 || flags & MMUFlags::S.bits() != 0 {
+        return Err(xous_kernel::Error::BadAddress);
+    }
+
+    let new_page = mm.alloc_page(pid)?;
+    let ppn = (new_page >> 12) << 10;
+    let mapped = (MMUFlags::VALID | MMUFlags::D | MMUFlags::A).bits();
+
+    // Map the page for the kernel first so it can be zeroed, and only then
+    // hand it to the process.
+    *entry = ppn | flags | mapped;
+    unsafe { flush_mmu() };
+    unsafe { (page as *mut usize).write_bytes(0, PAGE_SIZE / core::mem::size_of::<usize>()) };
+    *entry = ppn | flags | mapped | MMUFlags::USER.bits();
+    unsafe { flush_mmu() };
+    Ok(())
+}
+
 /// Determine whether a virtual address has been mapped
 pub fn address_available(virt: usize) -> bool {
     virt_to_phys(virt).is_err()
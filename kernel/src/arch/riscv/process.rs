@@ -1,11 +1,13 @@
 use core::mem;
+use core::num::NonZeroU8;
 static mut PROCESS: *mut ProcessImpl = 0xff80_1000 as *mut ProcessImpl;
 pub const MAX_THREAD: TID = 31;
 pub const INITIAL_TID: TID = 1;
 pub const IRQ_TID: TID = 0;
-use crate::arch::mem::PAGE_SIZE;
+use crate::arch::mem::{MemoryMapping, PAGE_SIZE};
+use crate::mem::MemoryManager;
 use crate::services::ProcessInner;
-use xous_kernel::{ProcessInit, ThreadInit, PID, TID};
+use xous_kernel::{MemoryFlags, MemoryRange, ProcessInit, ThreadInit, PID, TID};
 
 // use crate::args::KernelArguments;
 pub const DEFAULT_STACK_SIZE: usize = 131072;
@@ -15,8 +17,22 @@ pub const MAX_PROCESS_COUNT: usize = 32;
 /// This is the address a program will jump to in order to return from an ISR.
 pub const RETURN_FROM_ISR: usize = 0xff80_2000;
 
-/// This is the address a thread will return to when it exits.
-const EXIT_THREAD: usize = 0xff80_3000;
+/// This is the address a thread will return to when it exits. A thread that
+/// has exited but hasn't yet been joined keeps this as its `sepc`, and its
+/// exit code in `a0`.
+pub const EXIT_THREAD: usize = 0xff80_3000;
+
+/// Connection slots that don't fit in `ProcessInner` live in pages mapped
+/// into the process-specific area starting at this address.
+const CONNECTION_MAP_OFFSET: usize = 0xff80_4000;
+
+/// Section flags in a MiniElf image
+const MINIELF_FLAG_WRITE: usize = 1;
+const MINIELF_FLAG_NOCOPY: usize = 2;
+const MINIELF_FLAG_EXECUTE: usize = 4;
+
+/// The most pages of extra connection slots that a process may have.
+pub const MAX_CONNECTION_MAP_PAGES: usize = 4;
 
 // Thread IDs have three possible meaning:
 // Logical Thread ID: What the user sees
@@ -167,6 +183,57 @@ impl Process {
         f(&mut process.inner)
     }
 
+    /// Calls the provided function with the current inner process state, along
+    /// with any connection slots that were added beyond `connection_map`.
+    pub fn with_connections<F, R>(f: F) -> R
+    where
+        F: FnOnce(&ProcessInner, &[Option<NonZeroU8>]) -> R,
+    {
+        let process = unsafe { &*PROCESS };
+        let extra = unsafe {
+            core::slice::from_raw_parts(
+                CONNECTION_MAP_OFFSET as *const Option<NonZeroU8>,
+                process.inner.connection_map_pages as usize * PAGE_SIZE,
+            )
+        };
+        f(&process.inner, extra)
+    }
+
+    /// Calls the provided function with the current inner process state, along
+    /// with any connection slots that were added beyond `connection_map`.
+    pub fn with_connections_mut<F, R>(f: F) -> R
+    where
+        F: FnOnce(&mut ProcessInner, &mut [Option<NonZeroU8>]) -> R,
+    {
+        let process = unsafe { &mut *PROCESS };
+        let extra = unsafe {
+            core::slice::from_raw_parts_mut(
+                CONNECTION_MAP_OFFSET as *mut Option<NonZeroU8>,
+                process.inner.connection_map_pages as usize * PAGE_SIZE,
+            )
+        };
+        f(&mut process.inner, extra)
+    }
+
+    /// Map another page of connection slots into the current process.
+    ///
+    /// # Errors
+    ///
+    /// * **OutOfMemory**: The process already has `MAX_CONNECTION_MAP_PAGES`
+    ///                    pages of connections, or no free page was available
+    pub fn grow_connections() -> Result<(), xous_kernel::Error> {
+        let process = unsafe { &mut *PROCESS };
+        let pages = process.inner.connection_map_pages as usize;
+        if pages >= MAX_CONNECTION_MAP_PAGES {
+            return Err(xous_kernel::Error::OutOfMemory);
+        }
+        crate::mem::MemoryManager::with_mut(|mm| {
+            mm.map_kernel_page(process.inner.pid, CONNECTION_MAP_OFFSET + pages * PAGE_SIZE)
+        })?;
+        process.inner.connection_map_pages += 1;
+        Ok(())
+    }
+
     pub fn current_thread_mut(&mut self) -> &mut Thread {
         let process = unsafe { &mut *PROCESS };
         assert!(process.hardware_thread != 0, "thread number was 0");
@@ -227,6 +294,36 @@ impl Process {
         None
     }
 
+    /// Whether `tid` is a thread that has been created and not yet joined.
+    pub fn thread_exists(&self, tid: TID) -> bool {
+        let process = unsafe { &*PROCESS };
+        tid != IRQ_TID
+            && process
+                .threads
+                .get(tid)
+                .map(|thread| thread.sepc != 0)
+                .unwrap_or(false)
+    }
+
+    /// Record that `tid` has exited with `code`. The thread will never run
+    /// again, but its slot is kept until `take_exit_code()` is called.
+    pub fn exit_thread(&mut self, tid: TID, code: usize) {
+        let thread = self.thread_mut(tid);
+        thread.sepc = EXIT_THREAD;
+        thread.registers[9] = code;
+    }
+
+    /// If `tid` has exited, free its slot and return its exit code.
+    pub fn take_exit_code(&mut self, tid: TID) -> Option<usize> {
+        let thread = self.thread_mut(tid);
+        if thread.sepc != EXIT_THREAD {
+            return None;
+        }
+        let code = thread.registers[9];
+        *thread = Default::default();
+        Some(code)
+    }
+
     pub fn set_thread_result(&mut self, thread_nr: TID, result: xous_kernel::Result) {
         let vals = unsafe { mem::transmute::<_, [usize; 8]>(result) };
         let thread = self.thread_mut(thread_nr);
@@ -310,6 +407,7 @@ impl Process {
         }
 
         process.inner = Default::default();
+        process.inner.mem_heap_max = MemoryManager::with_mut(|mm| mm.heap_max(pid));
 
         // Mark the stack as "unallocated-but-free"
         let init_sp = (thread_init.stack.as_ptr() as usize) & !0xfff;
@@ -385,22 +483,166 @@ impl Process {
         );
     }
 
-    pub fn create(_pid: PID, _init_data: ProcessInit) -> PID {
-        todo!();
+    /// Build a new address space for `pid` out of the MiniElf image that the
+    /// current process lent in `init_data`. This returns the address space,
+    /// along with the thread that the process starts with once it has been
+    /// scheduled.
+    ///
+    /// # Errors
+    ///
+    /// * **BadAddress**: The image is truncated or can't be read, or it places
+    ///                   a section outside of userspace
+    /// * **OutOfMemory**: There aren't enough free pages to load the image
+    pub fn create(
+        pid: PID,
+        init_data: ProcessInit,
+    ) -> Result<(MemoryMapping, Option<ThreadInit>), xous_kernel::Error> {
+        MemoryManager::with_mut(|mm| {
+            let result = MemoryMapping::allocate(mm, pid).and_then(|mapping| {
+                let entrypoint = load_minielf(mm, pid, &mapping, &init_data.elf)?;
+                Ok((mapping, entrypoint))
+            });
+            let (mapping, entrypoint) = match result {
+                Ok(loaded) => loaded,
+                Err(e) => {
+                    mm.release_all(pid);
+                    return Err(e);
+                }
+            };
+            let stack = MemoryRange::new(
+                crate::arch::mem::DEFAULT_STACK_TOP - DEFAULT_STACK_SIZE,
+                DEFAULT_STACK_SIZE,
+            )?;
+            Ok((
+                mapping,
+                Some(ThreadInit::new(
+                    unsafe { core::mem::transmute::<usize, _>(entrypoint) },
+                    stack,
+                    None,
+                    [0u8; 12],
+                )),
+            ))
+        })
+    }
+
+    /// Free the process table entry for `pid`, along with every page that it
+    /// owns. The process must never be activated again.
+    pub fn destroy(pid: PID) -> Result<(), xous_kernel::Error> {
+        let pid_idx = pid.get() as usize - 1;
+        unsafe {
+            match PROCESS_TABLE.table.get(pid_idx) {
+                None | Some(false) => return Err(xous_kernel::Error::ProcessNotFound),
+                _ => (),
+            }
+            PROCESS_TABLE.table[pid_idx] = false;
+        }
+        MemoryManager::with_mut(|mm| mm.release_all(pid));
+        Ok(())
+    }
+}
+
+/// Copy every section of a MiniElf image, which the current process has lent
+/// to the kernel, into the address space `space` and return the entrypoint.
+/// The layout of the image is described by `xous_kernel::ProcessInit`.
+fn load_minielf(
+    mm: &mut MemoryManager,
+    pid: PID,
+    space: &MemoryMapping,
+    image: &MemoryRange,
+) -> Result<usize, xous_kernel::Error> {
+    use crate::arch::mem::{fill_page, map_page_into, read_user_bytes, USER_AREA_END};
+
+    let base = image.as_ptr() as usize;
+    let word = |offset: usize| -> Result<usize, xous_kernel::Error> {
+        if offset + 4 > image.len() {
+            return Err(xous_kernel::Error::BadAddress);
+        }
+        let mut bytes = [0u8; 4];
+        read_user_bytes(base + offset, &mut bytes)?;
+        Ok(u32::from_le_bytes(bytes) as usize)
+    };
+
+    // Each section is described by its virtual address, followed by its size
+    // with the flags in the top byte. Return its start, end and flags.
+    let section = |index: usize| -> Result<(usize, usize, usize), xous_kernel::Error> {
+        let virt = word(8 + index * 8)?;
+        let size_and_flags = word(12 + index * 8)?;
+        let end = virt
+            .checked_add(size_and_flags & 0x00ff_ffff)
+            .filter(|&end| end <= USER_AREA_END)
+            .ok_or(xous_kernel::Error::BadAddress)?;
+        Ok((virt, end, size_and_flags >> 24))
+    };
+
+    let entrypoint = word(0)?;
+    if entrypoint >= USER_AREA_END {
+        return Err(xous_kernel::Error::BadAddress);
     }
+    let section_count = word(4)?;
+
+    // The program data comes right after the table of sections.
+    let mut data_offset = section_count
+        .checked_mul(8)
+        .and_then(|size| size.checked_add(8))
+        .filter(|&offset| offset <= image.len())
+        .ok_or(xous_kernel::Error::BadAddress)?;
+
+    let mut previous_end = 0;
+    let mut mapped_until = 0;
+    for index in 0..section_count {
+        let (virt, end, flags) = section(index)?;
+        if virt < previous_end {
+            return Err(xous_kernel::Error::BadAddress);
+        }
+        previous_end = end;
+
+        // The end of one section may share a page with the start of the next,
+        // so a page is filled in from every section that touches it the first
+        // time it comes up, and is skipped after that.
+        let mut page = (virt & !(PAGE_SIZE - 1)).max(mapped_until);
+        while page < end {
+            let phys = mm.alloc_page(pid)?;
+            let mut page_flags = MemoryFlags::R;
+            fill_page(mm, phys, |bytes| {
+                let mut offset = data_offset;
+                for other in index..section_count {
+                    let (other_virt, other_end, other_flags) = section(other)?;
+                    if other_virt >= page + PAGE_SIZE {
+                        break;
+                    }
+                    if other_flags & MINIELF_FLAG_WRITE != 0 {
+                        page_flags |= MemoryFlags::W;
+                    }
+                    if other_flags & MINIELF_FLAG_EXECUTE != 0 {
+                        page_flags |= MemoryFlags::X;
+                    }
+                    // Sections that aren't copied are left zeroed.
+                    if other_flags & MINIELF_FLAG_NOCOPY != 0 {
+                        continue;
+                    }
+                    let start = other_virt.max(page);
+                    let stop = other_end.min(page + PAGE_SIZE);
+                    if start < stop {
+                        let source = offset + (start - other_virt);
+                        if source + (stop - start) > image.len() {
+                            return Err(xous_kernel::Error::BadAddress);
+                        }
+                        read_user_bytes(base + source, &mut bytes[start - page..stop - page])?;
+                    }
+                    offset += other_end - other_virt;
+                }
+                Ok(())
+            })?;
+            map_page_into(mm, space, pid, phys, page, page_flags)?;
+            page += PAGE_SIZE;
+        }
+        mapped_until = page;
 
-    pub fn destroy(_pid: PID) -> Result<(), xous_kernel::Error> {
-        todo!();
-        // let mut process_table = unsafe { &mut *PROCESS };
-        // let pid_idx = pid.get() as usize - 1;
-        // if pid_idx >= process_table.table.len() {
-        //     panic!("attempted to destroy PID that exceeds table index: {}", pid);
-        // }
-        // let process = process_table.table[pid_idx].as_mut().unwrap();
-        // process_table.table[pid_idx] = None;
-        // process_table.total -= 1;
-        // Ok(())
+        if flags & MINIELF_FLAG_NOCOPY == 0 {
+            data_offset += end - virt;
+        }
     }
+    Ok(entrypoint)
 }
 
 impl Thread {
@@ -0,0 +1,65 @@
+use crate::mem::MemoryManager;
+use utralib::generated::*;
+use xous_kernel::{MemoryFlags, MemoryType, PID};
+
+const SYSTEM_CLOCK_FREQUENCY: u32 = 100_000_000;
+
+/// How long a thread may run before it is forced to give up the CPU, unless
+/// overridden by setting `XOUS_QUANTUM_MS` when the kernel is built.
+const DEFAULT_QUANTUM_MS: u32 = 10;
+
+/// The virtual address of the preemption timer. Like the debug UART, it lives in
+/// the top 4 MiB so that it's visible no matter which process is active.
+const TIMER_BASE: usize = 0xffcf_1000;
+
+/// The interrupt that the preemption timer fires on.
+pub const IRQ: usize = utra::timer0::TIMER0_IRQ;
+
+/// Milliseconds since the timer was started, advanced once per quantum.
+static mut ELAPSED_MS: u64 = 0;
+
+fn quantum_ms() -> u32 {
+    option_env!("XOUS_QUANTUM_MS")
+        .and_then(|ms| ms.parse().ok())
+        .filter(|ms| *ms > 0)
+        .unwrap_or(DEFAULT_QUANTUM_MS)
+}
+
+/// Map the timer into the kernel and start it ticking once per quantum.
+pub fn init() {
+    MemoryManager::with_mut(|memory_manager| {
+        memory_manager
+            .map_range(
+                utra::timer0::HW_TIMER0_BASE as *mut u8,
+                TIMER_BASE as *mut u8,
+                4096,
+                PID::new(1).unwrap(),
+                MemoryFlags::R | MemoryFlags::W,
+                MemoryType::Default,
+            )
+            .expect("unable to map preemption timer")
+    });
+
+    let ticks = (SYSTEM_CLOCK_FREQUENCY / 1_000) * quantum_ms();
+    let mut timer = CSR::new(TIMER_BASE as *mut u32);
+    timer.wfo(utra::timer0::EN_EN, 0);
+    timer.wfo(utra::timer0::LOAD_LOAD, ticks);
+    timer.wfo(utra::timer0::RELOAD_RELOAD, ticks);
+    timer.wfo(utra::timer0::EN_EN, 1);
+    timer.wfo(utra::timer0::EV_ENABLE_ZERO, 1);
+
+    crate::arch::irq::enable_irq(IRQ);
+}
+
+/// Clear the pending timer interrupt so that it can fire again next quantum.
+pub fn acknowledge() {
+    let mut timer = CSR::new(TIMER_BASE as *mut u32);
+    timer.wfo(utra::timer0::EV_PENDING_ZERO, 1);
+    unsafe { ELAPSED_MS += quantum_ms() as u64 };
+}
+
+/// The number of milliseconds the kernel has been running for. This is only
+/// as precise as the quantum.
+pub fn elapsed_ms() -> u64 {
+    unsafe { ELAPSED_MS }
+}
@@ -64,3 +64,20 @@ pub fn interrupt_claim(
         result
     }
 }
+
+/// Give up every interrupt that `pid` has claimed, because it is terminating.
+/// Each one is masked until somebody else claims it.
+pub fn interrupt_release_all(pid: PID) {
+    // Unsafe is required since we're accessing a static mut array.
+    // However, we disable interrupts to prevent contention on this array.
+    unsafe {
+        arch::irq::disable_all_irqs();
+        for (irq, handler) in IRQ_HANDLERS.iter_mut().enumerate() {
+            if matches!(handler, Some((owner, _, _)) if *owner == pid) {
+                *handler = None;
+                arch::irq::disable_irq(irq).ok();
+            }
+        }
+        arch::irq::enable_all_irqs();
+    }
+}
@@ -90,6 +90,8 @@ pub extern "C" fn init(arg_offset: *const u32, init_offset: *const u32, rpt_offs
 }
 
 /// Loop through the SystemServices list to determine the next PID to be run.
+/// The runnable process with the highest priority wins. Processes that share
+/// a priority are picked round-robin, starting after `last_pid`.
 /// If no process is ready, return `None`.
 fn next_pid_to_run(last_pid: Option<PID>) -> Option<PID> {
     // PIDs are 1-indexed but arrays are 0-indexed.  By not subtracting
@@ -98,33 +100,24 @@ fn next_pid_to_run(last_pid: Option<PID>) -> Option<PID> {
     let current_pid = last_pid.unwrap_or(unsafe { PID::new_unchecked(1) }).get() as usize;
 
     SystemServices::with(|system_services| {
-        for test_idx in current_pid..system_services.processes.len() {
-            if system_services.processes[test_idx].ppid.get() == 1 {
-                // print!("PID {} is owned by PID1... ", test_idx + 1);
-                if system_services.processes[test_idx].runnable() {
-                    // println!(" and is runnable");
-                    return match pid_from_usize(test_idx + 1) {
-                        Ok(x) => Some(x),
-                        Err(_) => None,
-                    };
-                }
-                // println!(" and is NOT RUNNABLE");
+        let process_count = system_services.processes.len();
+        let mut best: Option<(Priority, usize)> = None;
+        for offset in 0..process_count {
+            let test_idx = (current_pid + offset) % process_count;
+            let process = &system_services.processes[test_idx];
+            if process.ppid.get() != 1 || !process.runnable() {
+                continue;
             }
-        }
-        for test_idx in 0..current_pid {
-            if system_services.processes[test_idx].ppid.get() == 1 {
-                // print!("PID {} is owned by PID1... ", test_idx + 1);
-                if system_services.processes[test_idx].runnable() {
-                    // println!(" and is runnable");
-                    return match pid_from_usize(test_idx + 1) {
-                        Ok(x) => Some(x),
-                        Err(_) => None,
-                    };
-                }
-                // println!(" and is NOT RUNNABLE");
+            // Only replace the candidate with a strictly higher priority, so
+            // the first process found at a given level gets to run.
+            if best
+                .map(|(priority, _)| process.priority > priority)
+                .unwrap_or(true)
+            {
+                best = Some((process.priority, test_idx));
             }
         }
-        None
+        best.and_then(|(_, idx)| pid_from_usize(idx + 1).ok())
     })
 }
 
@@ -3,9 +3,25 @@ use core::slice;
 use core::str;
 
 pub use crate::arch::mem::{MemoryMapping, PAGE_SIZE};
-use crate::arch::process::Process;
+use crate::arch::process::{Process, MAX_PROCESS_COUNT};
 
-use xous_kernel::{MemoryFlags, MemoryRange, PID};
+use xous_kernel::{MemoryFlags, MemoryRange, SharedMemoryId, PID};
+
+/// The most shared memory regions that may exist at once
+const MAX_SHARED_MEMORY: usize = 32;
+
+/// The most processes that may hold a single shared memory region
+const MAX_SHARED_HOLDERS: usize = 8;
+
+/// No single process may free a shared memory region, so its pages belong to
+/// the kernel for as long as it exists.
+const SHARED_MEMORY_OWNER: PID = unsafe { PID::new_unchecked(1) };
+
+/// The most pages that may be immutably lent to more than one process at once
+const MAX_EXTRA_BORROWS: usize = 256;
+
+/// How large a process' heap may grow if it has no memory limit
+pub const DEFAULT_HEAP_MAX: usize = 524_288;
 
 #[derive(Debug)]
 enum ClaimOrRelease {
@@ -43,6 +59,66 @@ impl fmt::Display for MemoryRangeExtra {
     }
 }
 
+/// A process that holds a reference to a shared memory region, which lets it
+/// map the region with `flags`.
+#[derive(Copy, Clone)]
+struct SharedMemoryHolder {
+    pid: PID,
+    flags: MemoryFlags,
+
+    /// Where the region is mapped in this process, if it has been
+    virt: Option<usize>,
+}
+
+/// Memory that several processes may map at once. Every process that holds
+/// the region counts as one reference, whether or not it has mapped it, and
+/// the memory is freed along with the last reference.
+#[derive(Copy, Clone)]
+struct SharedMemory {
+    /// The first of the region's physically contiguous pages. In hosted mode,
+    /// this is the memory that the creator allocated for itself, and every
+    /// process sees the region at this address.
+    base: usize,
+    size: usize,
+    holders: [Option<SharedMemoryHolder>; MAX_SHARED_HOLDERS],
+}
+
+impl SharedMemory {
+    fn holder(&self, pid: PID) -> Option<&SharedMemoryHolder> {
+        self.holders
+            .iter()
+            .flatten()
+            .find(|holder| holder.pid == pid)
+    }
+
+    fn holder_mut(&mut self, pid: PID) -> Option<&mut SharedMemoryHolder> {
+        self.holders
+            .iter_mut()
+            .flatten()
+            .find(|holder| holder.pid == pid)
+    }
+
+    fn references(&self) -> usize {
+        self.holders.iter().flatten().count()
+    }
+}
+
+/// A page that is immutably lent to more than one process at once. The
+/// pagetable only records that the page is lent, so this counts the borrowers
+/// beyond the first one. The page becomes writable again once the last of
+/// them returns it.
+#[derive(Copy, Clone)]
+struct ExtraBorrowers {
+    /// The process that lent the page, or the kernel once that process has
+    /// terminated
+    pid: PID,
+
+    /// Where the page is mapped in the lender, or its physical address if the
+    /// kernel holds it
+    addr: usize,
+    count: usize,
+}
+
 pub struct MemoryManager {
     ram_start: usize,
     ram_size: usize,
@@ -50,6 +126,19 @@ pub struct MemoryManager {
     ram_name: u32,
     #[allow(dead_code)]
     last_ram_page: usize,
+    shared: [Option<SharedMemory>; MAX_SHARED_MEMORY],
+    extra_borrowers: [Option<ExtraBorrowers>; MAX_EXTRA_BORROWS],
+
+    /// How many pages each process owns, indexed by PID - 1
+    owned_pages: [usize; MAX_PROCESS_COUNT],
+
+    /// The most pages each process may own, or 0 if it has no limit
+    #[allow(dead_code)]
+    page_limits: [usize; MAX_PROCESS_COUNT],
+
+    /// The limit for processes that weren't given one of their own
+    #[allow(dead_code)]
+    default_page_limit: usize,
 }
 
 impl Default for MemoryManager {
@@ -80,6 +169,11 @@ impl MemoryManager {
             ram_size: 0,
             ram_name: 0,
             last_ram_page: 0,
+            shared: [None; MAX_SHARED_MEMORY],
+            extra_borrowers: [None; MAX_EXTRA_BORROWS],
+            owned_pages: [0; MAX_PROCESS_COUNT],
+            page_limits: [0; MAX_PROCESS_COUNT],
+            default_page_limit: 0,
         }
     }
 
@@ -149,6 +243,15 @@ impl MemoryManager {
                         tag.data.len() * 4 / core::mem::size_of::<MemoryRangeExtra>(),
                     )
                 };
+            } else if tag.name == make_type!("MLim") {
+                // Limits are given in bytes, with 0 meaning "no limit". The
+                // first is the default, and the rest belong to the initial
+                // processes in the order they were loaded, starting at PID 2.
+                let mut limits = tag.data.iter().map(|&bytes| bytes as usize / PAGE_SIZE);
+                self.default_page_limit = limits.next().unwrap_or(0);
+                for limit in self.page_limits[1..].iter_mut() {
+                    *limit = limits.next().unwrap_or(self.default_page_limit);
+                }
             }
         }
 
@@ -161,6 +264,11 @@ impl MemoryManager {
         unsafe {
             MEMORY_ALLOCATIONS = slice::from_raw_parts_mut(base as *mut Option<PID>, mem_size)
         };
+
+        // The loader has already handed out pages to the initial processes.
+        for owner in unsafe { MEMORY_ALLOCATIONS.iter() }.flatten() {
+            self.owned_pages[owner.get() as usize - 1] += 1;
+        }
         Ok(())
     }
 
@@ -220,8 +328,18 @@ impl MemoryManager {
 
     /// Allocate a single page to the given process. DOES NOT ZERO THE PAGE!!!
     /// This function CANNOT zero the page, as it hasn't been mapped yet.
+    ///
+    /// # Errors
+    ///
+    /// * **OutOfMemory**: There are no free pages, or `pid` already owns as many pages as its limit allows
     #[cfg(baremetal)]
     pub fn alloc_page(&mut self, pid: PID) -> Result<usize, xous_kernel::Error> {
+        let pid_idx = pid.get() as usize - 1;
+        let limit = self.page_limits[pid_idx];
+        if limit != 0 && self.owned_pages[pid_idx] >= limit {
+            return Err(xous_kernel::Error::OutOfMemory);
+        }
+
         // Go through all RAM pages looking for a free page.
         // Optimization: start from the previous address.
         // println!("Allocating page for PID {}", pid);
@@ -230,6 +348,7 @@ impl MemoryManager {
                 // println!("    Checking {:08x}...", index * PAGE_SIZE + self.ram_start as usize);
                 if MEMORY_ALLOCATIONS[index].is_none() {
                     MEMORY_ALLOCATIONS[index] = Some(pid);
+                    self.owned_pages[pid_idx] += 1;
                     self.last_ram_page = index + 1;
                     let page = index * PAGE_SIZE + self.ram_start;
                     return Ok(page);
@@ -239,6 +358,7 @@ impl MemoryManager {
                 // println!("    Checking {:08x}...", index * PAGE_SIZE + self.ram_start as usize);
                 if MEMORY_ALLOCATIONS[index].is_none() {
                     MEMORY_ALLOCATIONS[index] = Some(pid);
+                    self.owned_pages[pid_idx] += 1;
                     self.last_ram_page = index + 1;
                     let page = index * PAGE_SIZE + self.ram_start;
                     return Ok(page);
@@ -248,6 +368,30 @@ impl MemoryManager {
         Err(xous_kernel::Error::OutOfMemory)
     }
 
+    /// Allocate `count` physically contiguous pages of RAM to the given
+    /// process, and return the address of the first one. As with
+    /// `alloc_page()`, the pages are not zeroed.
+    #[cfg(baremetal)]
+    fn alloc_contiguous(&mut self, pid: PID, count: usize) -> Result<usize, xous_kernel::Error> {
+        let pages = self.ram_size / PAGE_SIZE;
+        let mut start = 0;
+        while start + count <= pages {
+            let run = unsafe { &mut MEMORY_ALLOCATIONS[start..start + count] };
+            match run.iter().rposition(Option::is_some) {
+                // Carry on looking after the last page that's in use
+                Some(used) => start += used + 1,
+                None => {
+                    for owner in run.iter_mut() {
+                        *owner = Some(pid);
+                    }
+                    self.owned_pages[pid.get() as usize - 1] += count;
+                    return Ok(self.ram_start + start * PAGE_SIZE);
+                }
+            }
+        }
+        Err(xous_kernel::Error::OutOfMemory)
+    }
+
     /// Find a virtual address in the current process that is big enough
     /// to fit `size` bytes.
     pub fn find_virtual_address(
@@ -410,6 +554,83 @@ impl MemoryManager {
         Ok(virt)
     }
 
+    /// Back the given kernel address with a fresh, zeroed page owned by `pid`.
+    /// The page is not visible to userspace. Addresses in the kernel's megapage
+    /// are shared by every process, while anything below that is only mapped
+    /// into the current process.
+    #[cfg(baremetal)]
+    pub fn map_kernel_page(&mut self, pid: PID, virt: usize) -> Result<(), xous_kernel::Error> {
+        let phys = self.alloc_page(pid)?;
+        if let Err(e) = crate::arch::mem::map_page_inner(
+            self,
+            pid,
+            phys,
+            virt,
+            xous_kernel::MemoryFlags::R | xous_kernel::MemoryFlags::W,
+            false,
+        ) {
+            self.release_page(phys as *mut usize, pid).ok();
+            return Err(e);
+        }
+
+        let virt = virt as *mut usize;
+        unsafe { virt.write_bytes(0, PAGE_SIZE / core::mem::size_of::<usize>()) };
+        Ok(())
+    }
+
+    /// Give back every page that belongs to `pid`, once nothing in its
+    /// address space will be used again. The next process to get this PID
+    /// starts out with the default memory limit.
+    ///
+    /// Pages that `pid` lent out must have been handed over with
+    /// `orphan_lent_page()` first, since its borrowers still use them.
+    #[cfg(baremetal)]
+    pub fn release_all(&mut self, pid: PID) {
+        unsafe {
+            for owner in MEMORY_ALLOCATIONS.iter_mut() {
+                if *owner == Some(pid) {
+                    *owner = None;
+                }
+            }
+        }
+        for slot in self.extra_borrowers.iter_mut() {
+            if matches!(slot, Some(borrowers) if borrowers.pid == pid) {
+                *slot = None;
+            }
+        }
+        let pid_idx = pid.get() as usize - 1;
+        self.owned_pages[pid_idx] = 0;
+        self.page_limits[pid_idx] = self.default_page_limit;
+    }
+
+    /// How many pages `pid` owns.
+    pub fn owned_pages(&self, pid: PID) -> usize {
+        self.owned_pages[pid.get() as usize - 1]
+    }
+
+    /// How many pages of RAM don't belong to any process.
+    #[cfg(baremetal)]
+    pub fn free_pages(&self) -> usize {
+        let ram = unsafe { &MEMORY_ALLOCATIONS[..self.ram_size / PAGE_SIZE] };
+        ram.iter().filter(|owner| owner.is_none()).count()
+    }
+
+    /// RAM belongs to the host, so the kernel has none to give out.
+    #[cfg(not(baremetal))]
+    pub fn free_pages(&self) -> usize {
+        0
+    }
+
+    /// How large the heap of `pid` may grow. A process with a memory limit
+    /// may use all of it for its heap.
+    #[cfg(baremetal)]
+    pub fn heap_max(&self, pid: PID) -> usize {
+        match self.page_limits[pid.get() as usize - 1] {
+            0 => DEFAULT_HEAP_MAX,
+            limit => limit * PAGE_SIZE,
+        }
+    }
+
     pub fn is_main_memory(&self, phys: *mut u8) -> bool {
         (phys as usize) >= self.ram_start && (phys as usize) < self.ram_start + self.ram_size
     }
@@ -483,7 +704,34 @@ impl MemoryManager {
         crate::arch::mem::unmap_page_inner(self, virt as usize)
     }
 
+    /// Unmap a page that was moved to the current process, or that was lent
+    /// to it by a process that has since terminated. A page that nobody else
+    /// is borrowing any more is freed.
+    ///
+    /// # Errors
+    ///
+    /// * **BadAddress**: The page isn't mapped
+    /// * **MemoryInUse**: The page belongs to another process
+    pub fn forget_page(&mut self, virt: *mut usize) -> Result<usize, xous_kernel::Error> {
+        let pid = crate::arch::process::current_pid();
+        let kernel = PID::new(1).unwrap();
+        let phys = crate::arch::mem::virt_to_phys(virt as usize)?;
+        if self.release_page(phys as *mut usize, pid).is_err()
+            && !self.remove_borrower(kernel, phys)
+        {
+            self.release_page(phys as *mut usize, kernel)?;
+        }
+        crate::arch::mem::unmap_page_inner(self, virt as usize)
+    }
+
     /// Move a page from one process into another, keeping its permissions.
+    /// The page then belongs to `dest_pid`, and is freed along with it.
+    ///
+    /// # Errors
+    ///
+    /// * **BadAddress**: The page isn't mapped
+    /// * **ShareViolation**: The page is lent to another process
+    /// * **MemoryInUse**: The page doesn't belong to the source process
     #[allow(dead_code)]
     pub fn move_page(
         &mut self,
@@ -493,20 +741,71 @@ impl MemoryManager {
         dest_mapping: &MemoryMapping,
         dest_addr: *mut u8,
     ) -> Result<(), xous_kernel::Error> {
-        crate::arch::mem::move_page_inner(
+        if crate::arch::mem::page_lent_immutably(src_addr as usize) {
+            return Err(xous_kernel::Error::ShareViolation);
+        }
+        let src_pid = src_mapping.get_pid();
+        let phys = crate::arch::mem::virt_to_phys(src_addr as usize)?;
+        self.transfer_page(phys, src_pid, dest_pid)?;
+        let result = crate::arch::mem::move_page_inner(
             self,
             &src_mapping,
             src_addr,
             dest_pid,
             &dest_mapping,
             dest_addr,
-        )
+        );
+        if result.is_err() {
+            self.transfer_page(phys, dest_pid, src_pid).ok();
+        }
+        result
+    }
+
+    /// Hand the page at `phys` from `from` over to `to`. Pages that aren't
+    /// tracked, such as those outside of RAM, don't belong to anybody and
+    /// are left alone.
+    fn transfer_page(&mut self, phys: usize, from: PID, to: PID) -> Result<(), xous_kernel::Error> {
+        match self.release_page(phys as *mut usize, from) {
+            Ok(()) => self.claim_page(phys as *mut usize, to),
+            Err(xous_kernel::Error::BadAddress) => Ok(()),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Hand a page that `lender` lent out over to the kernel, because
+    /// `lender` is terminating and can't be given the page back. The page is
+    /// at `virt` in `lender` and at `phys` in memory. It is freed once its
+    /// last borrower forgets it with `forget_page()`.
+    ///
+    /// This must be called once for every borrower of the page.
+    pub fn orphan_lent_page(&mut self, lender: PID, virt: usize, phys: usize) {
+        let kernel = PID::new(1).unwrap();
+        // Only the first borrower finds the page still belonging to the
+        // lender, and any others are already counted.
+        self.transfer_page(phys, lender, kernel).ok();
+        if let Some(borrowers) = self
+            .extra_borrowers
+            .iter_mut()
+            .flatten()
+            .find(|b| b.pid == lender && b.addr == virt)
+        {
+            borrowers.pid = kernel;
+            borrowers.addr = phys;
+        }
     }
 
     /// Mark the page in the current process as being lent.  If the borrow is
     /// read-only, then additionally remove the "write" bit on it.  If the page
     /// is writable, then remove it from the current process until the borrow is
     /// returned.
+    ///
+    /// A page that is already lent read-only may be lent read-only to any
+    /// number of other processes at the same time.
+    ///
+    /// # Errors
+    ///
+    /// * **ShareViolation**: The page is mutably lent, or is to be mutably lent and is already lent or isn't writable
+    /// * **OutOfMemory**: Too many pages are lent to more than one process
     #[allow(dead_code)]
     pub fn lend_page(
         &mut self,
@@ -517,10 +816,16 @@ impl MemoryManager {
         dest_addr: *mut u8,
         mutable: bool,
     ) -> Result<usize, xous_kernel::Error> {
+        let src_pid = src_mapping.get_pid();
+        let extra = !mutable && crate::arch::mem::page_lent_immutably(src_addr as usize);
+        if extra {
+            self.add_borrower(src_pid, src_addr as usize)?;
+        }
+
         // If this page is to be writable, detach it from this process.
         // Otherwise, mark it as read-only to prevent a process from modifying
         // the page while it's borrowed.
-        crate::arch::mem::lend_page_inner(
+        let result = crate::arch::mem::lend_page_inner(
             self,
             &src_mapping,
             src_addr as _,
@@ -528,7 +833,11 @@ impl MemoryManager {
             &dest_mapping,
             dest_addr as _,
             mutable,
-        )
+        );
+        if extra && result.is_err() {
+            self.remove_borrower(src_pid, src_addr as usize);
+        }
+        result
     }
 
     /// Return the range from `src_mapping` back to `dest_mapping`
@@ -541,9 +850,8 @@ impl MemoryManager {
         dest_mapping: &MemoryMapping,
         dest_addr: *mut u8,
     ) -> Result<usize, xous_kernel::Error> {
-        // If this page is to be writable, detach it from this process.
-        // Otherwise, mark it as read-only to prevent a process from modifying
-        // the page while it's borrowed.
+        // The lender only gets its page back once nobody else is borrowing it.
+        let restore = !self.remove_borrower(dest_mapping.get_pid(), dest_addr as usize);
         crate::arch::mem::return_page_inner(
             self,
             &src_mapping,
@@ -551,9 +859,322 @@ impl MemoryManager {
             dest_pid,
             &dest_mapping,
             dest_addr,
+            restore,
         )
     }
 
+    /// Note that the page at `virt` in `pid`, which is already lent, has been
+    /// lent to one more process.
+    fn add_borrower(&mut self, pid: PID, virt: usize) -> Result<(), xous_kernel::Error> {
+        if let Some(borrowers) = self
+            .extra_borrowers
+            .iter_mut()
+            .flatten()
+            .find(|b| b.pid == pid && b.addr == virt)
+        {
+            borrowers.count += 1;
+            return Ok(());
+        }
+        let slot = self
+            .extra_borrowers
+            .iter_mut()
+            .find(|b| b.is_none())
+            .ok_or(xous_kernel::Error::OutOfMemory)?;
+        *slot = Some(ExtraBorrowers {
+            pid,
+            addr: virt,
+            count: 1,
+        });
+        Ok(())
+    }
+
+    /// Note that one borrower of the page at `virt` in `pid` has returned it.
+    /// Returns `false` if that was the only borrower left.
+    fn remove_borrower(&mut self, pid: PID, virt: usize) -> bool {
+        for slot in self.extra_borrowers.iter_mut() {
+            if let Some(borrowers) = slot {
+                if borrowers.pid == pid && borrowers.addr == virt {
+                    borrowers.count -= 1;
+                    if borrowers.count == 0 {
+                        *slot = None;
+                    }
+                    return true;
+                }
+            }
+        }
+        false
+    }
+
+    /// Turn `range`, which was just reserved in `pid` and hasn't been touched
+    /// since, into a shared memory region. `pid` must be the current process,
+    /// and holds the first reference to the region with `flags`.
+    ///
+    /// # Errors
+    ///
+    /// * **BadAddress**: The range isn't in userspace
+    /// * **MemoryInUse**: Part of the range is already backed by memory
+    /// * **OutOfMemory**: There is no free run of pages that large, or too many regions exist
+    pub fn create_shared(
+        &mut self,
+        pid: PID,
+        range: MemoryRange,
+        flags: MemoryFlags,
+    ) -> Result<SharedMemoryId, xous_kernel::Error> {
+        let id = self
+            .shared
+            .iter()
+            .position(Option::is_none)
+            .ok_or(xous_kernel::Error::OutOfMemory)?;
+        let base = self.back_shared(pid, &range, flags)?;
+        let mut holders = [None; MAX_SHARED_HOLDERS];
+        holders[0] = Some(SharedMemoryHolder {
+            pid,
+            flags,
+            virt: Some(range.as_ptr() as usize),
+        });
+        self.shared[id] = Some(SharedMemory {
+            base,
+            size: range.len(),
+            holders,
+        });
+        Ok(id)
+    }
+
+    /// The creator of a hosted region has already allocated its memory.
+    #[cfg(not(baremetal))]
+    fn back_shared(
+        &mut self,
+        _pid: PID,
+        range: &MemoryRange,
+        _flags: MemoryFlags,
+    ) -> Result<usize, xous_kernel::Error> {
+        Ok(range.as_ptr() as usize)
+    }
+
+    /// Back `range` in the current process with a run of physically contiguous,
+    /// zeroed pages, so that other processes can find every page from the
+    /// first one. Returns the address of that page.
+    #[cfg(baremetal)]
+    fn back_shared(
+        &mut self,
+        pid: PID,
+        range: &MemoryRange,
+        flags: MemoryFlags,
+    ) -> Result<usize, xous_kernel::Error> {
+        let virt = range.as_ptr() as usize;
+        let size = range.len();
+        if virt & (PAGE_SIZE - 1) != 0 || size & (PAGE_SIZE - 1) != 0 {
+            return Err(xous_kernel::Error::BadAlignment);
+        }
+        if virt + size > crate::arch::mem::USER_AREA_END {
+            return Err(xous_kernel::Error::BadAddress);
+        }
+        for page in (virt..(virt + size)).step_by(PAGE_SIZE) {
+            if !crate::arch::mem::address_available(page) {
+                return Err(xous_kernel::Error::MemoryInUse);
+            }
+        }
+
+        let phys = self.alloc_contiguous(SHARED_MEMORY_OWNER, size / PAGE_SIZE)?;
+        for offset in (0..size).step_by(PAGE_SIZE) {
+            let result =
+                crate::arch::mem::fill_page(self, phys + offset, |_| Ok(())).and_then(|_| {
+                    crate::arch::mem::map_page_inner(
+                        self,
+                        pid,
+                        phys + offset,
+                        virt + offset,
+                        flags,
+                        true,
+                    )
+                });
+            if let Err(e) = result {
+                for unmap_offset in (0..offset).step_by(PAGE_SIZE) {
+                    crate::arch::mem::unmap_page_inner(self, virt + unmap_offset).ok();
+                }
+                for release_offset in (0..size).step_by(PAGE_SIZE) {
+                    self.release_page((phys + release_offset) as *mut usize, SHARED_MEMORY_OWNER)
+                        .ok();
+                }
+                return Err(e);
+            }
+        }
+        Ok(phys)
+    }
+
+    /// Give `target` a reference to shared memory region `id` on behalf of
+    /// `pid`, which must hold the region with at least `flags`.
+    ///
+    /// # Errors
+    ///
+    /// * **AccessDenied**: `pid` doesn't hold the region, or holds it with fewer flags
+    /// * **MemoryInUse**: `target` already holds the region
+    /// * **OutOfMemory**: The region already has as many holders as it can have
+    pub fn grant_shared(
+        &mut self,
+        pid: PID,
+        id: SharedMemoryId,
+        target: PID,
+        flags: MemoryFlags,
+    ) -> Result<(), xous_kernel::Error> {
+        let region = self
+            .shared
+            .get_mut(id)
+            .and_then(Option::as_mut)
+            .ok_or(xous_kernel::Error::AccessDenied)?;
+        match region.holder(pid) {
+            Some(holder) if holder.flags.contains(flags) => (),
+            _ => return Err(xous_kernel::Error::AccessDenied),
+        }
+        if region.holder(target).is_some() {
+            return Err(xous_kernel::Error::MemoryInUse);
+        }
+        let slot = region
+            .holders
+            .iter_mut()
+            .find(|holder| holder.is_none())
+            .ok_or(xous_kernel::Error::OutOfMemory)?;
+        *slot = Some(SharedMemoryHolder {
+            pid: target,
+            flags,
+            virt: None,
+        });
+        Ok(())
+    }
+
+    /// Map shared memory region `id` into `pid`, which must be the current
+    /// process and must hold the region.
+    ///
+    /// # Errors
+    ///
+    /// * **AccessDenied**: The region doesn't exist, or `pid` doesn't hold it
+    /// * **MemoryInUse**: `pid` has already mapped the region
+    /// * **BadAddress**: There is no room for the region in the address space
+    pub fn map_shared(
+        &mut self,
+        pid: PID,
+        id: SharedMemoryId,
+    ) -> Result<MemoryRange, xous_kernel::Error> {
+        let region = self
+            .shared
+            .get(id)
+            .copied()
+            .flatten()
+            .ok_or(xous_kernel::Error::AccessDenied)?;
+        let holder = region.holder(pid).ok_or(xous_kernel::Error::AccessDenied)?;
+        if holder.virt.is_some() {
+            return Err(xous_kernel::Error::MemoryInUse);
+        }
+        let virt = self.map_shared_pages(pid, &region, holder.flags)?;
+        if let Some(holder) = self.shared[id].as_mut().and_then(|r| r.holder_mut(pid)) {
+            holder.virt = Some(virt);
+        }
+        MemoryRange::new(virt, region.size)
+    }
+
+    /// Every process sees a hosted region where its creator allocated it.
+    #[cfg(not(baremetal))]
+    fn map_shared_pages(
+        &mut self,
+        _pid: PID,
+        region: &SharedMemory,
+        _flags: MemoryFlags,
+    ) -> Result<usize, xous_kernel::Error> {
+        Ok(region.base)
+    }
+
+    #[cfg(baremetal)]
+    fn map_shared_pages(
+        &mut self,
+        pid: PID,
+        region: &SharedMemory,
+        flags: MemoryFlags,
+    ) -> Result<usize, xous_kernel::Error> {
+        let virt = self.find_virtual_address(
+            core::ptr::null_mut(),
+            region.size,
+            xous_kernel::MemoryType::Default,
+        )? as usize;
+        for offset in (0..region.size).step_by(PAGE_SIZE) {
+            if let Err(e) = crate::arch::mem::map_page_inner(
+                self,
+                pid,
+                region.base + offset,
+                virt + offset,
+                flags,
+                true,
+            ) {
+                for unmap_offset in (0..offset).step_by(PAGE_SIZE) {
+                    crate::arch::mem::unmap_page_inner(self, virt + unmap_offset).ok();
+                }
+                return Err(e);
+            }
+        }
+        Ok(virt)
+    }
+
+    /// Drop the reference that `pid`, which must be the current process,
+    /// holds to shared memory region `id`, unmapping the region first if `pid`
+    /// had mapped it. If that was the last reference, the region is freed and
+    /// its memory is returned.
+    ///
+    /// # Errors
+    ///
+    /// * **AccessDenied**: The region doesn't exist, or `pid` doesn't hold it
+    pub fn unmap_shared(
+        &mut self,
+        pid: PID,
+        id: SharedMemoryId,
+    ) -> Result<Option<MemoryRange>, xous_kernel::Error> {
+        let region = self
+            .shared
+            .get_mut(id)
+            .and_then(Option::as_mut)
+            .ok_or(xous_kernel::Error::AccessDenied)?;
+        let holder = region
+            .holders
+            .iter_mut()
+            .find(|holder| matches!(holder, Some(h) if h.pid == pid))
+            .and_then(Option::take)
+            .ok_or(xous_kernel::Error::AccessDenied)?;
+        let (base, size, references) = (region.base, region.size, region.references());
+
+        if let Some(virt) = holder.virt {
+            for page in (virt..(virt + size)).step_by(PAGE_SIZE) {
+                crate::arch::mem::unmap_page_inner(self, page).ok();
+            }
+        }
+        if references > 0 {
+            return Ok(None);
+        }
+
+        self.shared[id] = None;
+        for page in (base..(base + size)).step_by(PAGE_SIZE) {
+            self.release_page(page as *mut usize, SHARED_MEMORY_OWNER)
+                .ok();
+        }
+        Ok(Some(MemoryRange::new(base, size)?))
+    }
+
+    /// Drop every reference that `pid` holds to shared memory, because it is
+    /// terminating. The address space of `pid` must be the active one. A
+    /// hosted region whose last holder terminates is never freed, since the
+    /// memory belongs to a process.
+    pub fn release_shared(&mut self, pid: PID) {
+        for id in 0..self.shared.len() {
+            self.unmap_shared(pid, id).ok();
+        }
+    }
+
+    /// Determine whether any part of the `size` bytes at `virt` in `pid` is
+    /// shared memory, which must be given up with `unmap_shared()` instead.
+    pub fn is_shared(&self, pid: PID, virt: usize, size: usize) -> bool {
+        self.shared.iter().flatten().any(|region| {
+            matches!(region.holder(pid).and_then(|holder| holder.virt),
+                Some(start) if start < virt + size && virt < start + region.size)
+        })
+    }
+
     /// Claim the given memory for the given process, or release the memory
     /// back to the free pool.
     #[cfg(not(baremetal))]
@@ -577,6 +1198,7 @@ impl MemoryManager {
         /// the specified address.
         fn action_inner(
             addr: &mut Option<PID>,
+            owned: &mut usize,
             pid: PID,
             action: ClaimOrRelease,
         ) -> Result<(), xous_kernel::Error> {
@@ -587,14 +1209,21 @@ impl MemoryManager {
             }
             match action {
                 ClaimOrRelease::Claim => {
+                    if addr.is_none() {
+                        *owned += 1;
+                    }
                     *addr = Some(pid);
                 }
                 ClaimOrRelease::Release => {
+                    if addr.is_some() {
+                        *owned -= 1;
+                    }
                     *addr = None;
                 }
             }
             Ok(())
         }
+        let owned = &mut self.owned_pages[pid.get() as usize - 1];
         let addr = addr as usize;
 
         // Ensure the address lies on a page boundary
@@ -606,7 +1235,7 @@ impl MemoryManager {
         // Happy path: The address is in main RAM
         if addr >= self.ram_start && addr < self.ram_start + self.ram_size {
             offset += (addr - self.ram_start) / PAGE_SIZE;
-            return unsafe { action_inner(&mut MEMORY_ALLOCATIONS[offset], pid, action) };
+            return unsafe { action_inner(&mut MEMORY_ALLOCATIONS[offset], owned, pid, action) };
         }
 
         offset += self.ram_size / PAGE_SIZE;
@@ -618,7 +1247,7 @@ impl MemoryManager {
                     && addr < (region.mem_start + region.mem_size) as usize
                 {
                     offset += (addr - (region.mem_start as usize)) / PAGE_SIZE;
-                    return action_inner(&mut MEMORY_ALLOCATIONS[offset], pid, action);
+                    return action_inner(&mut MEMORY_ALLOCATIONS[offset], owned, pid, action);
                 }
                 offset += region.mem_size as usize / PAGE_SIZE;
             }
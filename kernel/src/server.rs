@@ -1,6 +1,9 @@
 pub use crate::arch::process::Thread;
+use crate::arch::process::MAX_PROCESS_COUNT;
 use core::mem;
-use xous_kernel::{MemoryAddress, MemoryRange, MemorySize, Message, MessageSender, PID, SID, TID};
+use xous_kernel::{
+    ConnectionRights, MemoryAddress, MemoryRange, MemorySize, Message, MessageSender, PID, SID, TID,
+};
 
 pub struct SenderID {
     /// The index of the server within the SystemServices table
@@ -63,6 +66,13 @@ pub enum WaitingMessage {
 
     /// This memory should be returned to the system.
     ForgetMemory(MemoryRange),
+
+    /// The sender stopped waiting for a response, so there is nobody to return to.
+    Cancelled,
+
+    /// The sender stopped waiting for a response, but the memory it lent
+    /// must still be given back to it.
+    CancelledMemory(PID, MemoryAddress, MemoryAddress, MemorySize),
 }
 
 /// Internal representation of a queued message for a server. This should be
@@ -190,6 +200,23 @@ enum QueuedMessage {
         u16,   /* client TID */
         usize, /* server return address */
     ),
+
+    /// The sender of a message that hadn't been received yet stopped waiting
+    /// for a response. This slot is skipped the next time the queue is read.
+    Cancelled,
+
+    /// The sender of a message that the server has received stopped waiting
+    /// for a response. The response is discarded when the server returns it,
+    /// and any memory that was lent goes back to the sender at that point.
+    /// The server may still be using that memory, so it can't be taken back
+    /// any sooner, even if the server never responds.
+    WaitingCancelled(
+        u16,   /* client PID */
+        u16,   /* client TID */
+        usize, /* address of memory base in server */
+        usize, /* client base address */
+        usize, /* Range size */
+    ),
 }
 
 /// A pointer to resolve a server ID to a particular process
@@ -216,6 +243,32 @@ pub struct Server {
     /// this message. If there are no available contexts, then messages will
     /// need to be queued.
     ready_threads: usize,
+
+    /// What clients may send, unless they have been given rights of their own
+    default_rights: ConnectionRights,
+
+    /// Rights given to individual clients, indexed by PID - 1
+    client_rights: [Option<ConnectionRights>; MAX_PROCESS_COUNT],
+
+    /// Whether new connections must first be approved by the server
+    pub approves_connections: bool,
+
+    /// Whether the server is told when one of its clients terminates
+    pub notifies_disconnects: bool,
+
+    /// How many of those notices were dropped because the queue was full,
+    /// since the last one that was delivered
+    pub dropped_disconnects: usize,
+
+    /// Event bits that have been signalled but not yet collected
+    pending_events: usize,
+
+    /// A bitfield of threads that are waiting for an event to be signalled
+    event_threads: usize,
+
+    /// The server to signal, and the events to set on it, whenever a message
+    /// is added to this server's queue
+    message_events: Option<(usize /* sidx */, usize /* events */)>,
 }
 
 impl Server {
@@ -261,10 +314,48 @@ impl Server {
             queue_tail: 0,
             queue,
             ready_threads: 0,
+            default_rights: ConnectionRights::ALL,
+            client_rights: [None; MAX_PROCESS_COUNT],
+            approves_connections: false,
+            notifies_disconnects: false,
+            dropped_disconnects: 0,
+            pending_events: 0,
+            event_threads: 0,
+            message_events: None,
         });
         Ok(())
     }
 
+    /// Determine what the given process may send to this server. The owner
+    /// of a server may always send it anything.
+    pub fn rights_for(&self, pid: PID) -> ConnectionRights {
+        if pid == self.pid {
+            return ConnectionRights::ALL;
+        }
+        self.client_rights[pid.get() as usize - 1].unwrap_or(self.default_rights)
+    }
+
+    /// Set the rights of the given process, or the default rights for every
+    /// process without rights of its own if no PID is specified.
+    pub fn set_rights(&mut self, pid: Option<PID>, rights: ConnectionRights) {
+        match pid {
+            Some(pid) => self.client_rights[pid.get() as usize - 1] = Some(rights),
+            None => self.default_rights = rights,
+        }
+    }
+
+    /// Forget any rights given to the process, so that they aren't inherited
+    /// by a later process with the same PID.
+    pub fn clear_rights(&mut self, pid: PID) {
+        self.client_rights[pid.get() as usize - 1] = None;
+    }
+
+    /// The page that holds this server's queue.
+    #[cfg(baremetal)]
+    pub fn queue_page(&self) -> *mut usize {
+        self.queue.as_ptr() as *mut usize
+    }
+
     /// Take a current slot and replace it with `None`, clearing out the contents of the queue.
     pub fn destroy(current: &mut Option<Server>) -> Result<(), xous_kernel::Error> {
         if let Some(mut server) = current.take() {
@@ -339,6 +430,20 @@ impl Server {
                         );
                     }
                 }
+                // Memory that the server has already received can't be
+                // returned either, so it is forgotten once the server is done.
+                QueuedMessage::WaitingReturnMemory(msg_pid, tid, server_addr, client_addr, len)
+                | QueuedMessage::WaitingCancelled(msg_pid, tid, server_addr, client_addr, len) => {
+                    if msg_pid == pid.get() as _ && len != 0 {
+                        *entry = QueuedMessage::WaitingForget(
+                            msg_pid,
+                            tid,
+                            server_addr,
+                            client_addr,
+                            len,
+                        );
+                    }
+                }
                 // For "Scalar" and "Move" messages, this memory has already
                 // been moved into this process, so memory will be reclaimed
                 // when the process terminates.
@@ -347,6 +452,56 @@ impl Server {
         }
     }
 
+    /// List the buffers that `pid` has lent to this server and not yet got
+    /// back, as their address in the server, their address in `pid`, and
+    /// their length.
+    pub fn lent_buffers(&self, pid: PID) -> impl Iterator<Item = (usize, usize, usize)> + '_ {
+        let pid = pid.get() as u16;
+        self.queue.iter().filter_map(move |entry| match *entry {
+            QueuedMessage::MemoryMessageROLend(client, _, client_addr, _, buf, len, _, _)
+            | QueuedMessage::MemoryMessageRWLend(client, _, client_addr, _, buf, len, _, _)
+            | QueuedMessage::WaitingReturnMemory(client, _, buf, client_addr, len)
+            | QueuedMessage::WaitingCancelled(client, _, buf, client_addr, len)
+                if client == pid && len != 0 =>
+            {
+                Some((buf, client_addr, len))
+            }
+            _ => None,
+        })
+    }
+
+    /// Count the pages lent through this server. Returns the number of pages
+    /// that `pid` has lent to the server and not yet got back, followed by
+    /// the number that every client has lent to it if `pid` owns the server.
+    pub fn lent_pages(&self, pid: PID) -> (usize, usize) {
+        use crate::arch::mem::PAGE_SIZE;
+        let pages = |size: usize| ((size + PAGE_SIZE - 1) & !(PAGE_SIZE - 1)) / PAGE_SIZE;
+        let mut lent = 0;
+        let mut lent_to_server = 0;
+        for entry in self.queue.iter() {
+            // Memory from a client that has terminated is no longer lent by
+            // anybody, even if its PID has been given to a new process, and
+            // neither is memory that was moved.
+            let (client, size) = match *entry {
+                QueuedMessage::MemoryMessageROLend(client, _, _, _, _, size, _, _)
+                | QueuedMessage::MemoryMessageRWLend(client, _, _, _, _, size, _, _)
+                | QueuedMessage::WaitingReturnMemory(client, _, _, _, size)
+                | QueuedMessage::WaitingCancelled(client, _, _, _, size) => (Some(client), size),
+                QueuedMessage::MemoryMessageROLendTerminated(_, _, _, _, _, size, _, _)
+                | QueuedMessage::MemoryMessageRWLendTerminated(_, _, _, _, _, size, _, _)
+                | QueuedMessage::WaitingForget(_, _, _, _, size) => (None, size),
+                _ => continue,
+            };
+            if client == Some(pid.get() as u16) {
+                lent += pages(size);
+            }
+            if self.pid == pid {
+                lent_to_server += pages(size);
+            }
+        }
+        (lent, lent_to_server)
+    }
+
     /// Convert a `QueuedMesage::WaitingReturnMemory` into `QueuedMessage::Empty`
     /// and return the pair.  Advance the tail.  Note that the `idx` could be
     /// somewhere other than the tail, but as long as it points to a valid
@@ -361,18 +516,29 @@ impl Server {
             .get_mut(idx)
             .ok_or(xous_kernel::Error::BadAddress)?;
         klog!("memory in queue[{}]: {:?}", idx, val);
-        let (pid, tid, server_addr, client_addr, len, forget, is_memory) = match *current_val {
-            QueuedMessage::WaitingReturnMemory(pid, tid, server_addr, client_addr, len) => {
-                (pid, tid, server_addr, client_addr, len, false, true)
-            }
-            QueuedMessage::WaitingForget(pid, tid, server_addr, client_addr, len) => {
-                (pid, tid, server_addr, client_addr, len, true, true)
-            }
-            QueuedMessage::WaitingReturnScalar(pid, tid, return_address) => {
-                (pid, tid, return_address, 0, 0, true, false)
-            }
-            _ => return Ok(WaitingMessage::None),
-        };
+        let (pid, tid, server_addr, client_addr, len, forget, is_memory, cancelled) =
+            match *current_val {
+                QueuedMessage::WaitingReturnMemory(pid, tid, server_addr, client_addr, len) => {
+                    (pid, tid, server_addr, client_addr, len, false, true, false)
+                }
+                QueuedMessage::WaitingForget(pid, tid, server_addr, client_addr, len) => {
+                    (pid, tid, server_addr, client_addr, len, true, true, false)
+                }
+                QueuedMessage::WaitingReturnScalar(pid, tid, return_address) => {
+                    (pid, tid, return_address, 0, 0, true, false, false)
+                }
+                QueuedMessage::WaitingCancelled(pid, tid, server_addr, client_addr, len) => (
+                    pid,
+                    tid,
+                    server_addr,
+                    client_addr,
+                    len,
+                    false,
+                    len != 0,
+                    true,
+                ),
+                _ => return Ok(WaitingMessage::None),
+            };
 
         // Sanity check the specified address was correct, and matches what we
         // had cached.
@@ -396,6 +562,28 @@ impl Server {
             tid
         );
 
+        // Nobody is waiting for a response any more, but lent memory still
+        // has to go back to where it came from.
+        if cancelled {
+            return Ok(
+                match (
+                    MemoryAddress::new(server_addr),
+                    MemoryAddress::new(client_addr),
+                    MemorySize::new(len),
+                ) {
+                    (Some(server_addr), Some(client_addr), Some(len)) => {
+                        WaitingMessage::CancelledMemory(
+                            PID::new(pid as _).unwrap(),
+                            server_addr,
+                            client_addr,
+                            len,
+                        )
+                    }
+                    _ => WaitingMessage::Cancelled,
+                },
+            );
+        }
+
         if !is_memory {
             return Ok(WaitingMessage::ScalarMessage(
                 PID::new(pid as _).unwrap(),
@@ -428,6 +616,177 @@ impl Server {
         ))
     }
 
+    /// Withdraw the blocking message at `idx` that was sent by `pid`:`tid`,
+    /// because the sender has stopped waiting for a response. A message that
+    /// hasn't been received yet is dropped from the queue. The response to a
+    /// message that has been received is discarded when it arrives, and the
+    /// server keeps any memory that was lent with it until then.
+    ///
+    /// Returns `WaitingMessage::BorrowedMemory` if the sender lent memory that
+    /// can be given back right away, or `WaitingMessage::None` otherwise.
+    pub fn cancel_message(&mut self, idx: usize, pid: PID, tid: TID) -> WaitingMessage {
+        let entry = match self.queue.get_mut(idx) {
+            Some(entry) => entry,
+            None => return WaitingMessage::None,
+        };
+        let (msg_pid, msg_tid, server_addr, client_addr, len, replacement) = match *entry {
+            QueuedMessage::BlockingScalarMessage(msg_pid, msg_tid, ..) => {
+                (msg_pid, msg_tid, 0, 0, 0, QueuedMessage::Cancelled)
+            }
+            QueuedMessage::MemoryMessageROLend(
+                msg_pid,
+                msg_tid,
+                client_addr,
+                _id,
+                buf,
+                buf_size,
+                _offset,
+                _valid,
+            )
+            | QueuedMessage::MemoryMessageRWLend(
+                msg_pid,
+                msg_tid,
+                client_addr,
+                _id,
+                buf,
+                buf_size,
+                _offset,
+                _valid,
+            ) => (
+                msg_pid,
+                msg_tid,
+                buf,
+                client_addr,
+                buf_size,
+                QueuedMessage::Cancelled,
+            ),
+            QueuedMessage::WaitingReturnScalar(msg_pid, msg_tid, _) => (
+                msg_pid,
+                msg_tid,
+                0,
+                0,
+                0,
+                QueuedMessage::WaitingCancelled(msg_pid, msg_tid, 0, 0, 0),
+            ),
+            QueuedMessage::WaitingReturnMemory(msg_pid, msg_tid, server_addr, client_addr, len) => {
+                (
+                    msg_pid,
+                    msg_tid,
+                    0,
+                    0,
+                    0,
+                    QueuedMessage::WaitingCancelled(
+                        msg_pid,
+                        msg_tid,
+                        server_addr,
+                        client_addr,
+                        len,
+                    ),
+                )
+            }
+            _ => return WaitingMessage::None,
+        };
+        if msg_pid != pid.get() as u16 || msg_tid != tid as u16 {
+            return WaitingMessage::None;
+        }
+        *entry = replacement;
+
+        match (
+            MemoryAddress::new(server_addr),
+            MemoryAddress::new(client_addr),
+            MemorySize::new(len),
+        ) {
+            (Some(server_addr), Some(client_addr), Some(len)) => {
+                WaitingMessage::BorrowedMemory(pid, tid, server_addr, client_addr, len)
+            }
+            _ => WaitingMessage::None,
+        }
+    }
+
+    /// Empty out the next occupied slot in the queue, because the server is
+    /// going away. Returns the sender if it is still waiting for a response,
+    /// along with any memory it had lent, or `None` once the queue is empty.
+    pub fn abandon_next_message(&mut self) -> Option<WaitingMessage> {
+        let entry = self
+            .queue
+            .iter_mut()
+            .find(|entry| **entry != QueuedMessage::Empty)?;
+        let (pid, tid, server_addr, client_addr, len) = match *entry {
+            QueuedMessage::BlockingScalarMessage(pid, tid, ..)
+            | QueuedMessage::WaitingReturnScalar(pid, tid, _) => (pid, tid, 0, 0, 0),
+            QueuedMessage::MemoryMessageROLend(
+                pid,
+                tid,
+                client_addr,
+                _id,
+                buf,
+                buf_size,
+                _offset,
+                _valid,
+            )
+            | QueuedMessage::MemoryMessageRWLend(
+                pid,
+                tid,
+                client_addr,
+                _id,
+                buf,
+                buf_size,
+                _offset,
+                _valid,
+            ) => (pid, tid, buf, client_addr, buf_size),
+            QueuedMessage::WaitingReturnMemory(pid, tid, server_addr, client_addr, len) => {
+                (pid, tid, server_addr, client_addr, len)
+            }
+            // The sender stopped waiting, but may still be owed its memory.
+            QueuedMessage::WaitingCancelled(pid, _tid, server_addr, client_addr, len) => {
+                *entry = QueuedMessage::Empty;
+                return Some(
+                    match (
+                        PID::new(pid as _),
+                        MemoryAddress::new(server_addr),
+                        MemoryAddress::new(client_addr),
+                        MemorySize::new(len),
+                    ) {
+                        (Some(pid), Some(server_addr), Some(client_addr), Some(len)) => {
+                            WaitingMessage::CancelledMemory(pid, server_addr, client_addr, len)
+                        }
+                        _ => WaitingMessage::None,
+                    },
+                );
+            }
+            // The lender is gone, so its memory must be discarded rather
+            // than returned.
+            QueuedMessage::MemoryMessageROLendTerminated(_, _, _, _, buf, buf_size, _, _)
+            | QueuedMessage::MemoryMessageRWLendTerminated(_, _, _, _, buf, buf_size, _, _) => {
+                *entry = QueuedMessage::Empty;
+                return Some(
+                    MemoryRange::new(buf, buf_size)
+                        .map(WaitingMessage::ForgetMemory)
+                        .unwrap_or(WaitingMessage::None),
+                );
+            }
+            // Nobody is waiting on anything else.
+            _ => {
+                *entry = QueuedMessage::Empty;
+                return Some(WaitingMessage::None);
+            }
+        };
+        *entry = QueuedMessage::Empty;
+
+        let pid = PID::new(pid as _).unwrap();
+        let tid = tid as TID;
+        match (
+            MemoryAddress::new(server_addr),
+            MemoryAddress::new(client_addr),
+            MemorySize::new(len),
+        ) {
+            (Some(server_addr), Some(client_addr), Some(len)) => Some(
+                WaitingMessage::BorrowedMemory(pid, tid, server_addr, client_addr, len),
+            ),
+            _ => Some(WaitingMessage::ScalarMessage(pid, tid)),
+        }
+    }
+
     /// Remove a message from the server's queue and replace it with either a QueuedMessage::WaitingReturnMemory
     /// or, for Scalar messages, QueuedMessage::Empty.
     ///
@@ -444,9 +803,21 @@ impl Server {
         //     self.queue_head, self.queue_tail, self.queue[self.queue_tail], sidx
         // );
         use core::convert::TryInto;
+
+        // Skip over messages whose senders gave up before they were received.
+        while self.queue[self.queue_tail] == QueuedMessage::Cancelled {
+            self.queue[self.queue_tail] = QueuedMessage::Empty;
+            self.queue_tail += 1;
+            if self.queue_tail >= self.queue.len() {
+                self.queue_tail = 0;
+            }
+        }
+
         let mut sender = SenderID::new(sidx, self.queue_tail, None);
         let (result, response) = match self.queue[self.queue_tail] {
             QueuedMessage::Empty => return None,
+            QueuedMessage::Cancelled => return None,
+            QueuedMessage::WaitingCancelled(_, _, _, _, _) => return None,
             QueuedMessage::WaitingReturnMemory(_, _, _, _, _) => return None,
             QueuedMessage::WaitingForget(_, _, _, _, _) => return None,
             QueuedMessage::WaitingReturnScalar(_, _, _) => return None,
@@ -519,7 +890,7 @@ impl Server {
                             valid: MemorySize::new(valid),
                         }),
                     },
-                    QueuedMessage::WaitingReturnMemory(pid, tid, buf, client_addr, buf_size),
+                    QueuedMessage::WaitingForget(pid, tid, buf, client_addr, buf_size),
                 )
             }
             QueuedMessage::MemoryMessageRWLendTerminated(
@@ -543,7 +914,7 @@ impl Server {
                             valid: MemorySize::new(valid),
                         }),
                     },
-                    QueuedMessage::WaitingReturnMemory(pid, tid, buf, client_addr, buf_size),
+                    QueuedMessage::WaitingForget(pid, tid, buf, client_addr, buf_size),
                 )
             }
 
@@ -837,4 +1208,81 @@ impl Server {
         self.ready_threads |= 1 << tid;
         klog!("ready threads now: {:08b}", self.ready_threads);
     }
+
+    /// Remove the given context from the list of ready and waiting contexts,
+    /// because it has stopped waiting for a message.
+    pub fn unpark_thread(&mut self, tid: TID) {
+        klog!("unparking thread {}", tid);
+        self.ready_threads &= !(1 << tid);
+    }
+
+    /// Set the given event bits. They stay set until they are collected by
+    /// `take_events()`.
+    pub fn signal_events(&mut self, events: usize) {
+        self.pending_events |= events;
+    }
+
+    /// Collect and clear every event bit that has been signalled.
+    pub fn take_events(&mut self) -> usize {
+        let events = self.pending_events;
+        self.pending_events = 0;
+        events
+    }
+
+    /// Return `true` if there is a message in the queue that hasn't been
+    /// received yet.
+    pub fn has_messages(&self) -> bool {
+        let mut idx = self.queue_tail;
+        for _ in 0..self.queue.len() {
+            match self.queue[idx] {
+                QueuedMessage::Cancelled => idx = (idx + 1) % self.queue.len(),
+                QueuedMessage::Empty
+                | QueuedMessage::WaitingCancelled(_, _, _, _, _)
+                | QueuedMessage::WaitingReturnMemory(_, _, _, _, _)
+                | QueuedMessage::WaitingForget(_, _, _, _, _)
+                | QueuedMessage::WaitingReturnScalar(_, _, _) => return false,
+                _ => return true,
+            }
+        }
+        false
+    }
+
+    /// Set `events` on the server at `sidx` whenever a message is queued, or
+    /// stop doing so if `events` is zero.
+    pub fn set_message_events(&mut self, sidx: usize, events: usize) {
+        self.message_events = if events == 0 {
+            None
+        } else {
+            Some((sidx, events))
+        };
+    }
+
+    /// The server to signal when a message is queued, and the events to set
+    pub fn message_events(&self) -> Option<(usize, usize)> {
+        self.message_events
+    }
+
+    /// Stop signalling the server at `sidx`, because it is going away.
+    pub fn forget_message_events(&mut self, sidx: usize) {
+        if matches!(self.message_events, Some((target, _)) if target == sidx) {
+            self.message_events = None;
+        }
+    }
+
+    /// Mark the given context as waiting for an event to be signalled.
+    pub fn park_event_thread(&mut self, tid: TID) {
+        klog!("parking thread {} until an event arrives", tid);
+        assert!(self.event_threads & (1 << tid) == 0);
+        self.event_threads |= 1 << tid;
+    }
+
+    /// Return a context that is waiting for an event, if there is one.
+    pub fn take_event_thread(&mut self) -> Option<TID> {
+        if self.event_threads == 0 {
+            return None;
+        }
+        let tid = self.event_threads.trailing_zeros() as TID;
+        self.event_threads &= !(1 << tid);
+        Some(tid)
+    }
 }
@@ -6,14 +6,27 @@ use xous_kernel::MemoryRange;
 
 use core::num::NonZeroU8;
 
-use crate::filled_array;
-use crate::server::Server;
+use crate::server::{Server, WaitingMessage};
 // use core::mem;
 use xous_kernel::{
-    pid_from_usize, Error, MemoryAddress, Message, ProcessInit, ThreadInit, CID, PID, SID, TID,
+    pid_from_usize, ConnectionRights, Error, MemoryAddress, MemorySize, MemoryStats, Message,
+    Priority, ProcessInit, ThreadInit, CID, DEFAULT_PRIORITY, PID, SID, TID,
 };
 
-const MAX_SERVER_COUNT: usize = 32;
+/// Server indices are stored offset by two in a `NonZeroU8` in the connection
+/// map, which limits how large the server table may grow.
+const MAX_SERVER_COUNT: usize = 254;
+
+/// Where the server table lives. This is within the kernel's megapage, so the
+/// table is visible no matter which process is active.
+#[cfg(baremetal)]
+const SERVER_TABLE_OFFSET: usize = 0xffe0_0000;
+const MAX_TIMEOUT_COUNT: usize = 32;
+const MAX_CONNECT_REQUEST_COUNT: usize = 32;
+const MAX_FUTEX_WAITER_COUNT: usize = 32;
+const MAX_THREAD_JOINER_COUNT: usize = 32;
+const MAX_DETACHED_THREAD_COUNT: usize = 32;
+const MAX_PROCESS_WAITER_COUNT: usize = 32;
 
 pub use crate::arch::process::{INITIAL_TID, MAX_PROCESS_COUNT};
 
@@ -23,8 +36,30 @@ pub struct SystemServices {
     /// A table of all processes in the system
     pub processes: [Process; MAX_PROCESS_COUNT],
 
-    /// A table of all servers in the system
-    servers: [Option<Server>; MAX_SERVER_COUNT],
+    /// A table of all servers in the system. This starts out empty and grows
+    /// a page at a time as servers are created.
+    #[cfg(baremetal)]
+    servers: &'static mut [Option<Server>],
+    #[cfg(not(baremetal))]
+    servers: Vec<Option<Server>>,
+
+    /// Threads that will only stay blocked for a limited time
+    timeouts: [Option<Timeout>; MAX_TIMEOUT_COUNT],
+
+    /// Threads waiting for a server to approve a connection
+    connect_requests: [Option<ConnectRequest>; MAX_CONNECT_REQUEST_COUNT],
+
+    /// Threads waiting for a futex to be woken
+    futex_waiters: [Option<FutexWaiter>; MAX_FUTEX_WAITER_COUNT],
+
+    /// Threads waiting for another thread to exit
+    thread_joiners: [Option<ThreadJoiner>; MAX_THREAD_JOINER_COUNT],
+
+    /// Threads that nobody will join, which are freed as soon as they exit
+    detached_threads: [Option<DetachedThread>; MAX_DETACHED_THREAD_COUNT],
+
+    /// Threads waiting for a child process to terminate
+    process_waiters: [Option<ProcessWaiter>; MAX_PROCESS_WAITER_COUNT],
 
     /// A log of the currently-active syscall depth
     _syscall_stack: [(usize, usize); 3],
@@ -57,6 +92,110 @@ pub enum ProcessState {
     /// This process is waiting for an event, such as as message or an
     /// interrupt.  There are no contexts that can be run.
     Sleeping,
+
+    /// This process has terminated, and its exit code is being kept until
+    /// its parent collects it with `WaitProcess`.
+    Zombie(usize /* exit code */),
+}
+
+/// What a thread with a timeout is blocked on.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum TimeoutKind {
+    /// Waiting for server `sidx` to respond to the message at index `idx`
+    /// of its queue
+    Send(usize /* sidx */, usize /* idx */),
+
+    /// Waiting for a message to arrive at server `sidx`
+    Receive(usize /* sidx */),
+}
+
+/// A thread that is blocked, but will only wait a limited time.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Timeout {
+    /// The process that is waiting
+    pub pid: PID,
+
+    /// The thread that is waiting
+    pub tid: TID,
+
+    /// What the thread is waiting on
+    pub kind: TimeoutKind,
+
+    /// When the thread gives up, in milliseconds since the kernel started
+    pub deadline: u64,
+}
+
+/// A thread that is blocked until a server approves or denies a connection.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct ConnectRequest {
+    /// The process that is waiting
+    pub pid: PID,
+
+    /// The thread that is waiting
+    pub tid: TID,
+
+    /// The process that gets the connection if it's approved. This differs
+    /// from `pid` when connecting on behalf of another process.
+    pub target_pid: PID,
+
+    /// The index of the server being connected to
+    pub sidx: usize,
+
+    /// Rights to give `target_pid` once the connection has been approved
+    pub rights: Option<ConnectionRights>,
+}
+
+/// A thread that is blocked until another thread in its process wakes the
+/// futex at `addr`.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct FutexWaiter {
+    /// The process that is waiting
+    pub pid: PID,
+
+    /// The thread that is waiting
+    pub tid: TID,
+
+    /// The address of the futex, in the waiting process' address space
+    pub addr: usize,
+}
+
+/// A thread that is blocked until another thread in its process exits.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct ThreadJoiner {
+    /// The process that is waiting
+    pub pid: PID,
+
+    /// The thread that is waiting
+    pub tid: TID,
+
+    /// The thread that is being waited on
+    pub target: TID,
+}
+
+/// A thread that nobody is going to join.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct DetachedThread {
+    /// The process the thread belongs to
+    pub pid: PID,
+
+    /// The thread that has been detached
+    pub tid: TID,
+
+    /// The thread's stack, which is unmapped once it has exited
+    pub stack: Option<MemoryRange>,
+}
+
+/// A thread that is blocked until one of its process' children terminates.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct ProcessWaiter {
+    /// The process that is waiting
+    pub pid: PID,
+
+    /// The thread that is waiting
+    pub tid: TID,
+
+    /// The child process that is being waited on
+    pub target: PID,
 }
 
 impl Default for ProcessState {
@@ -78,22 +217,34 @@ pub struct Process {
     /// This process' PID. This should match up with the index in the process table.
     pub pid: PID,
 
-    /// The process that created this process, which tells who is allowed to
-    /// manipulate this process.
+    /// The process that gets the CPU back when this process blocks. Only
+    /// PID 1 schedules processes, so this is always PID 1.
     pub ppid: PID,
 
+    /// The process that created this process, which tells who is allowed to
+    /// manipulate this process and wait for it to exit.
+    pub parent: PID,
+
     /// The current thread ID
     current_thread: TID,
 
     /// The context number that was active before this process was switched
     /// away.
     previous_thread: TID,
+
+    /// Scheduling priority. Higher values are run first.
+    pub priority: Priority,
+
+    /// The highest priority this process may give itself. This is the
+    /// priority it was started with, unless its parent has raised it since.
+    max_priority: Priority,
 }
 
 impl Default for Process {
     fn default() -> Self {
         Process {
             ppid: unsafe { PID::new_unchecked(1) },
+            parent: unsafe { PID::new_unchecked(1) },
             ..Default::default()
         }
     }
@@ -133,8 +284,8 @@ pub struct ProcessInner {
     /// A copy of this process' ID
     pub pid: PID,
 
-    /// Some reserved data to pad this out to a multiple of 32 bytes.
-    pub _reserved: [u8; 1],
+    /// How many pages of connection slots have been added beyond `connection_map`
+    pub connection_map_pages: u8,
 }
 
 impl Default for ProcessInner {
@@ -146,10 +297,10 @@ impl Default for ProcessInner {
             mem_message_last: arch::mem::DEFAULT_MESSAGE_BASE,
             mem_heap_base: arch::mem::DEFAULT_HEAP_BASE,
             mem_heap_size: 0,
-            mem_heap_max: 524_288,
+            mem_heap_max: crate::mem::DEFAULT_HEAP_MAX,
             connection_map: [None; 32],
             pid: unsafe { PID::new_unchecked(1) },
-            _reserved: [0; 1],
+            connection_map_pages: 0,
         }
     }
 }
@@ -171,6 +322,12 @@ impl Process {
         }
     }
 
+    /// This process has terminated, but its parent hasn't collected its exit
+    /// code yet
+    pub fn zombie(&self) -> bool {
+        matches!(self.state, ProcessState::Zombie(_))
+    }
+
     pub fn activate(&self) -> Result<(), xous_kernel::Error> {
         crate::arch::process::set_current_pid(self.pid);
         self.mapping.activate()?;
@@ -179,15 +336,13 @@ impl Process {
     }
 
     pub fn terminate(&mut self) -> Result<(), xous_kernel::Error> {
-        if self.free() {
+        if self.free() || self.zombie() {
             return Err(xous_kernel::Error::ProcessNotFound);
         }
 
-        // TODO: Free all pages
-
-        // TODO: Free all IRQs
+        crate::irq::interrupt_release_all(self.pid);
 
-        // TODO: Free memory mapping
+        // The arch process gives back every page and the memory mapping.
         crate::arch::process::Process::destroy(self.pid)?;
         self.state = ProcessState::Free;
         Ok(())
@@ -199,14 +354,21 @@ std::thread_local!(static SYSTEM_SERVICES: core::cell::RefCell<SystemServices> =
     processes: [Process {
         state: ProcessState::Free,
         ppid: unsafe { PID::new_unchecked(1) },
+        parent: unsafe { PID::new_unchecked(1) },
         pid: unsafe { PID::new_unchecked(1) },
         mapping: arch::mem::DEFAULT_MEMORY_MAPPING,
         current_thread: 0 as TID,
         previous_thread: INITIAL_TID as TID,
+        priority: DEFAULT_PRIORITY,
+        max_priority: DEFAULT_PRIORITY,
     }; MAX_PROCESS_COUNT],
-    // Note we can't use MAX_SERVER_COUNT here because of how Rust's
-    // macro tokenization works
-    servers: filled_array![None; 32],
+    servers: Vec::new(),
+    timeouts: [None; MAX_TIMEOUT_COUNT],
+    connect_requests: [None; MAX_CONNECT_REQUEST_COUNT],
+    futex_waiters: [None; MAX_FUTEX_WAITER_COUNT],
+    thread_joiners: [None; MAX_THREAD_JOINER_COUNT],
+    detached_threads: [None; MAX_DETACHED_THREAD_COUNT],
+    process_waiters: [None; MAX_PROCESS_WAITER_COUNT],
     _syscall_stack: [(0, 0), (0, 0), (0, 0)],
     _syscall_depth: 0,
 }));
@@ -216,14 +378,21 @@ static mut SYSTEM_SERVICES: SystemServices = SystemServices {
     processes: [Process {
         state: ProcessState::Free,
         ppid: unsafe { PID::new_unchecked(1) },
+        parent: unsafe { PID::new_unchecked(1) },
         pid: unsafe { PID::new_unchecked(1) },
         mapping: arch::mem::DEFAULT_MEMORY_MAPPING,
         current_thread: 0 as TID,
         previous_thread: INITIAL_TID as TID,
+        priority: DEFAULT_PRIORITY,
+        max_priority: DEFAULT_PRIORITY,
     }; MAX_PROCESS_COUNT],
-    // Note we can't use MAX_SERVER_COUNT here because of how Rust's
-    // macro tokenization works
-    servers: filled_array![None; 32],
+    servers: &mut [],
+    timeouts: [None; MAX_TIMEOUT_COUNT],
+    connect_requests: [None; MAX_CONNECT_REQUEST_COUNT],
+    futex_waiters: [None; MAX_FUTEX_WAITER_COUNT],
+    thread_joiners: [None; MAX_THREAD_JOINER_COUNT],
+    detached_threads: [None; MAX_DETACHED_THREAD_COUNT],
+    process_waiters: [None; MAX_PROCESS_WAITER_COUNT],
     _syscall_stack: [(0, 0), (0, 0), (0, 0)],
     _syscall_depth: 0,
 };
@@ -306,6 +475,7 @@ impl SystemServices {
             unsafe {
                 process.mapping.from_raw(init.satp);
                 process.ppid = PID::new_unchecked(1);
+                process.parent = PID::new_unchecked(1);
                 process.pid = PID::new(pid as _).unwrap();
             };
             if pid == 1 {
@@ -320,6 +490,20 @@ impl SystemServices {
             }
         }
 
+        // The image may start initial processes at a priority other than the
+        // default, which is also the highest they may raise themselves to.
+        // They are listed in the order they were loaded, starting at PID 2.
+        for arg in args.iter() {
+            if arg.name == make_type!("PPri") {
+                for (process, &priority) in self.processes[1..].iter_mut().zip(arg.data.iter()) {
+                    if priority <= Priority::MAX as u32 {
+                        process.priority = priority as Priority;
+                        process.max_priority = priority as Priority;
+                    }
+                }
+            }
+        }
+
         // Set up our handle with a bogus sp and pc.  These will get updated
         // once a context switch _away_ from the kernel occurs, however we need
         // to make sure other fields such as "thread number" are all valid.
@@ -335,12 +519,18 @@ impl SystemServices {
                 continue;
             }
             let new_pid = pid_from_usize(idx + 1)?;
-            arch::process::Process::create(new_pid, init_process);
-            let ppid = crate::arch::process::current_pid();
-            // println!("Creating new process for PID {} with PPID {}", new_pid, ppid);
-            entry.state = ProcessState::Allocated;
-            entry.ppid = ppid;
+            let (mapping, thread_init) = arch::process::Process::create(new_pid, init_process)?;
+            let parent = crate::arch::process::current_pid();
+            // println!("Creating new process for PID {} with parent {}", new_pid, parent);
+            entry.state = thread_init
+                .map(ProcessState::Setup)
+                .unwrap_or(ProcessState::Allocated);
+            entry.mapping = mapping;
+            entry.ppid = unsafe { PID::new_unchecked(1) };
+            entry.parent = parent;
             entry.pid = new_pid;
+            entry.priority = DEFAULT_PRIORITY;
+            entry.max_priority = DEFAULT_PRIORITY;
             return Ok(new_pid);
         }
         Err(xous_kernel::Error::ProcessNotFound)
@@ -349,6 +539,9 @@ impl SystemServices {
     pub fn get_process(&self, pid: PID) -> Result<&Process, xous_kernel::Error> {
         // PID0 doesn't exist -- process IDs are offset by 1.
         let pid_idx = pid.get() as usize - 1;
+        if pid_idx >= self.processes.len() {
+            return Err(xous_kernel::Error::ProcessNotFound);
+        }
         if cfg!(baremetal) && self.processes[pid_idx].mapping.get_pid() != pid {
             println!(
                 "Process doesn't match ({} vs {})",
@@ -363,6 +556,9 @@ impl SystemServices {
     pub fn get_process_mut(&mut self, pid: PID) -> Result<&mut Process, xous_kernel::Error> {
         // PID0 doesn't exist -- process IDs are offset by 1.
         let pid_idx = pid.get() as usize - 1;
+        if pid_idx >= self.processes.len() {
+            return Err(xous_kernel::Error::ProcessNotFound);
+        }
 
         // if self.processes[pid_idx].mapping.get_pid() != pid {
         //     println!(
@@ -375,6 +571,42 @@ impl SystemServices {
         Ok(&mut self.processes[pid_idx])
     }
 
+    /// Change the scheduling priority of `pid`. A process may adjust its own
+    /// priority, or the priority of one of its children.
+    ///
+    /// A process can't raise itself above the priority it was started with,
+    /// so a busy process can't starve the rest of the system. A parent may
+    /// raise a child as high as it may raise itself, and the child may then
+    /// go back up to that priority. PID 1 starts everything else, and isn't
+    /// limited.
+    pub fn set_priority(
+        &mut self,
+        caller: PID,
+        pid: PID,
+        priority: Priority,
+    ) -> Result<(), xous_kernel::Error> {
+        let caller_max = if caller.get() == 1 {
+            Priority::MAX
+        } else {
+            self.get_process(caller)?.max_priority
+        };
+        let process = self.get_process_mut(pid)?;
+        if process.free() || process.zombie() {
+            return Err(xous_kernel::Error::ProcessNotFound);
+        }
+        if pid != caller && process.parent != caller {
+            return Err(xous_kernel::Error::ProcessNotChild);
+        }
+        if priority > caller_max {
+            return Err(xous_kernel::Error::AccessDenied);
+        }
+        if pid != caller && priority > process.max_priority {
+            process.max_priority = priority;
+        }
+        process.priority = priority;
+        Ok(())
+    }
+
     // pub fn current_thread(&self, pid: PID) -> usize {
     //     self.processes[pid.get() as usize - 1].current_thread as usize
     // }
@@ -501,6 +733,7 @@ impl SystemServices {
                 ProcessState::Setup(_) | ProcessState::Allocated => {
                     panic!("process hasn't been set up yet")
                 }
+                ProcessState::Zombie(_) => panic!("process has already terminated"),
             };
             process.state = ProcessState::Running(available_threads);
             process.previous_thread = process.current_thread;
@@ -582,6 +815,7 @@ impl SystemServices {
             ProcessState::Free => return Err(xous_kernel::Error::ProcessNotFound),
             ProcessState::Sleeping => return Err(xous_kernel::Error::ProcessNotFound),
             ProcessState::Allocated => return Err(xous_kernel::Error::ProcessNotFound),
+            ProcessState::Zombie(_) => return Err(xous_kernel::Error::ProcessNotFound),
             ProcessState::Setup(setup) => {
                 // Activate the process, which enables its memory mapping
                 process.activate()?;
@@ -688,6 +922,17 @@ impl SystemServices {
         Ok(())
     }
 
+    /// The running thread `tid` of `pid` has used up its quantum. Put it
+    /// back on the process' list of ready threads, leaving the rest of the
+    /// process' threads as they are.
+    pub fn preempt_thread(&mut self, pid: PID, tid: TID) -> Result<(), xous_kernel::Error> {
+        match self.get_process(pid)?.state {
+            ProcessState::Running(x) if x & (1 << tid) == 0 => self.ready_thread(pid, tid),
+            ProcessState::Running(_) => Err(xous_kernel::Error::InvalidThread),
+            _ => Err(xous_kernel::Error::ProcessNotFound),
+        }
+    }
+
     /// Switches away from the specified process ID.
     /// If `can_resume` is `true`, then the current thread ID will be placed
     /// in the list of available thread IDs.
@@ -805,6 +1050,10 @@ impl SystemServices {
                     println!("PID {} was free", new_pid);
                     return Err(xous_kernel::Error::ProcessNotFound);
                 }
+                ProcessState::Zombie(_) => {
+                    println!("PID {} has terminated", new_pid);
+                    return Err(xous_kernel::Error::ProcessNotFound);
+                }
                 ProcessState::Setup(_) | ProcessState::Allocated => new_tid = INITIAL_TID,
                 ProcessState::Running(x) | ProcessState::Ready(x) => {
                     // If no new context is specified, take the previous
@@ -868,6 +1117,7 @@ impl SystemServices {
                     ProcessState::Running(0)
                 }
                 ProcessState::Free => panic!("process was suddenly Free"),
+                ProcessState::Zombie(_) => panic!("process was suddenly a Zombie"),
                 ProcessState::Ready(x) | ProcessState::Running(x) => {
                     ProcessState::Running(x & !(1 << new_tid))
                 }
@@ -1031,9 +1281,7 @@ impl SystemServices {
             let mut error = None;
 
             // Lend each subsequent page.
-            for offset in (0..(len / core::mem::size_of::<usize>()))
-                .step_by(crate::mem::PAGE_SIZE / core::mem::size_of::<usize>())
-            {
+            for offset in (0..len).step_by(crate::mem::PAGE_SIZE) {
                 mm.move_page(
                     &src_mapping,
                     src_virt.wrapping_add(offset),
@@ -1071,7 +1319,9 @@ impl SystemServices {
     /// once.
     ///
     /// If the share is mutable and the memory is already shared, then an error
-    /// is returned.
+    /// is returned. Memory that is immutably shared may be immutably lent to
+    /// other processes at the same time, and only becomes writable again once
+    /// every one of them has returned it.
     ///
     /// # Returns
     ///
@@ -1080,9 +1330,11 @@ impl SystemServices {
     /// # Errors
     ///
     /// * **ShareViolation**: Tried to mutably share a region that was already
-    ///   shared
+    ///   shared or isn't writable, or to share a region that was mutably shared
     /// * **BadAddress**: The provided address was not valid
     /// * **BadAlignment**: The provided address or length was not page-aligned
+    /// * **OutOfMemory**: Too many pages are immutably shared with more than
+    ///   one process
     #[cfg(baremetal)]
     pub fn lend_memory(
         &mut self,
@@ -1120,26 +1372,33 @@ impl SystemServices {
                 })?;
             src_mapping.activate().unwrap();
 
-            let mut error = None;
-
-            // Lend each subsequent page.
-            for offset in (0..(len / core::mem::size_of::<usize>()))
-                .step_by(crate::mem::PAGE_SIZE / core::mem::size_of::<usize>())
-            {
-                mm.lend_page(
+            // Lend each subsequent page. If one of them can't be lent, take
+            // back the ones that were.
+            for offset in (0..len).step_by(crate::mem::PAGE_SIZE) {
+                if let Err(e) = mm.lend_page(
                     &src_mapping,
                     src_virt.wrapping_add(offset),
                     dest_pid,
                     &dest_mapping,
                     dest_virt.wrapping_add(offset),
                     mutable,
-                )
-                .unwrap_or_else(|e| {
-                    error = Some(e);
-                    0
-                });
+                ) {
+                    for lent in (0..offset).step_by(crate::mem::PAGE_SIZE) {
+                        dest_mapping.activate().unwrap();
+                        mm.unlend_page(
+                            &dest_mapping,
+                            dest_virt.wrapping_add(lent),
+                            current_pid,
+                            &src_mapping,
+                            src_virt.wrapping_add(lent),
+                        )
+                        .ok();
+                    }
+                    src_mapping.activate().unwrap();
+                    return Err(e);
+                }
             }
-            error.map_or_else(|| Ok(dest_virt), |e| panic!("unable to lend: {:?}", e))
+            Ok(dest_virt)
         })
     }
 
@@ -1197,10 +1456,8 @@ impl SystemServices {
         MemoryManager::with_mut(|mm| {
             let mut error = None;
 
-            // Lend each subsequent page.
-            for offset in (0..(len / core::mem::size_of::<usize>()))
-                .step_by(crate::mem::PAGE_SIZE / core::mem::size_of::<usize>())
-            {
+            // Return each subsequent page.
+            for offset in (0..len).step_by(crate::mem::PAGE_SIZE) {
                 mm.unlend_page(
                     &src_mapping,
                     src_virt.wrapping_add(offset),
@@ -1318,34 +1575,79 @@ impl SystemServices {
             );
         }
 
-        for entry in self.servers.iter_mut() {
-            if entry == &None {
-                #[cfg(baremetal)]
-                // Allocate a single page for the server queue
-                let backing = crate::mem::MemoryManager::with_mut(|mm| {
-                    MemoryRange::new(
-                        mm.map_zeroed_page(pid, false)? as _,
-                        crate::arch::mem::PAGE_SIZE,
-                    )
-                })?;
+        // Use the first free slot, making the table bigger if there aren't any.
+        let sidx = match self.servers.iter().position(|entry| entry == &None) {
+            Some(sidx) => sidx,
+            None => {
+                let sidx = self.servers.len();
+                self.grow_servers()?;
+                sidx
+            }
+        };
 
-                #[cfg(not(baremetal))]
-                let backing = MemoryRange::new(4096, 4096).unwrap();
-                // println!(
-                //     "KERNEL({}): Found a free slot for server {:?} @ {} -- allocating an entry",
-                //     pid.get(),
-                //     sid,
-                //     _idx,
-                // );
+        #[cfg(baremetal)]
+        // Allocate a single page for the server queue
+        let backing = crate::mem::MemoryManager::with_mut(|mm| {
+            MemoryRange::new(
+                mm.map_zeroed_page(pid, false)? as _,
+                crate::arch::mem::PAGE_SIZE,
+            )
+        })?;
 
-                // Initialize the server with the given memory page.
-                Server::init(entry, pid, sid, backing).map_err(|x| x)?;
+        #[cfg(not(baremetal))]
+        let backing = MemoryRange::new(4096, 4096).unwrap();
+        // println!(
+        //     "KERNEL({}): Found a free slot for server {:?} @ {} -- allocating an entry",
+        //     pid.get(),
+        //     sid,
+        //     sidx,
+        // );
 
-                let cid = self.connect_to_server(sid)?;
-                return Ok((sid, cid));
+        // Initialize the server with the given memory page.
+        Server::init(&mut self.servers[sidx], pid, sid, backing).map_err(|x| x)?;
+
+        let cid = self.connect_to_server(sid)?;
+        Ok((sid, cid))
+    }
+
+    /// Add another page worth of slots to the end of the server table.
+    ///
+    /// # Errors
+    ///
+    /// * **OutOfMemory**: No page was available to extend the table
+    /// * **ServerNotFound**: The table already holds `MAX_SERVER_COUNT` servers
+    fn grow_servers(&mut self) -> Result<(), xous_kernel::Error> {
+        let old_len = self.servers.len();
+        if old_len >= MAX_SERVER_COUNT {
+            return Err(xous_kernel::Error::ServerNotFound);
+        }
+        let entry_size = core::mem::size_of::<Option<Server>>();
+        let page_size = crate::mem::PAGE_SIZE;
+
+        // The table only ever grows, so the existing entries stay where they
+        // are and the new page is mapped directly after them.
+        let mapped = (old_len * entry_size + page_size - 1) & !(page_size - 1);
+        let new_len = ((mapped + page_size) / entry_size).min(MAX_SERVER_COUNT);
+
+        #[cfg(baremetal)]
+        {
+            crate::mem::MemoryManager::with_mut(|mm| {
+                mm.map_kernel_page(
+                    unsafe { PID::new_unchecked(1) },
+                    SERVER_TABLE_OFFSET + mapped,
+                )
+            })?;
+            let table = SERVER_TABLE_OFFSET as *mut Option<Server>;
+            for sidx in old_len..new_len {
+                unsafe { table.add(sidx).write(None) };
             }
+            self.servers = unsafe { core::slice::from_raw_parts_mut(table, new_len) };
         }
-        Err(xous_kernel::Error::ServerNotFound)
+
+        #[cfg(not(baremetal))]
+        self.servers.resize_with(new_len, || None);
+
+        Ok(())
     }
 
     /// Generate a new server ID for this process and then create a new server.
@@ -1381,6 +1683,397 @@ impl SystemServices {
 
         result
     }
+
+    /// Determine whether `pid` must wait for the server `sid` to approve a
+    /// connection for `target_pid`, and if so return the server's index.
+    /// Servers never need to approve their own process, and processes that
+    /// are already connected don't need to be approved again.
+    ///
+    /// # Errors
+    ///
+    /// * **ServerNotFound**: The server does not exist
+    pub fn connect_needs_approval(
+        &mut self,
+        pid: PID,
+        target_pid: PID,
+        sid: SID,
+    ) -> Result<Option<usize>, xous_kernel::Error> {
+        let (sidx, server) = self
+            .servers
+            .iter()
+            .enumerate()
+            .find_map(|(sidx, server)| match server {
+                Some(server) if server.sid == sid => Some((sidx, server)),
+                _ => None,
+            })
+            .ok_or(xous_kernel::Error::ServerNotFound)?;
+        if !server.approves_connections || server.pid == pid || server.pid == target_pid {
+            return Ok(None);
+        }
+
+        Ok(if self.is_connected(target_pid, sidx)? {
+            None
+        } else {
+            Some(sidx)
+        })
+    }
+
+    /// Determine whether `pid` has a connection to the server at `sidx`.
+    ///
+    /// # Errors
+    ///
+    /// * **ProcessNotFound**: The process does not exist
+    pub fn is_connected(&self, pid: PID, sidx: usize) -> Result<bool, xous_kernel::Error> {
+        let original_pid = crate::arch::process::current_pid();
+        self.get_process(pid)?.activate()?;
+        let connected = ArchProcess::with_connections(|process_inner, extra_connections| {
+            process_inner
+                .connection_map
+                .iter()
+                .chain(extra_connections.iter())
+                .flatten()
+                .any(|mapping| mapping.get() as usize == sidx + 2)
+        });
+        self.get_process(original_pid)?.activate()?;
+        Ok(connected)
+    }
+
+    /// Report how much memory `pid` is using, and how much is free.
+    ///
+    /// # Errors
+    ///
+    /// * **ProcessNotFound**: The process does not exist, or has terminated
+    pub fn memory_stats(&self, pid: PID) -> Result<MemoryStats, xous_kernel::Error> {
+        let process = self.get_process(pid)?;
+        if process.free() || process.zombie() {
+            return Err(xous_kernel::Error::ProcessNotFound);
+        }
+
+        let original_pid = crate::arch::process::current_pid();
+        process.activate()?;
+        let heap_size = ArchProcess::with_inner(|process_inner| process_inner.mem_heap_size);
+        self.get_process(original_pid)?.activate()?;
+
+        let mut lent_pages = 0;
+        let mut lent_to_servers = 0;
+        for server in self.servers.iter().flatten() {
+            let (lent, lent_to_server) = server.lent_pages(pid);
+            lent_pages += lent;
+            lent_to_servers += lent_to_server;
+        }
+
+        let (owned_pages, free_pages) =
+            crate::mem::MemoryManager::with_mut(|mm| (mm.owned_pages(pid), mm.free_pages()));
+        Ok(MemoryStats {
+            owned_pages,
+            heap_size,
+            lent_pages,
+            lent_to_servers,
+            free_pages,
+        })
+    }
+
+    /// The number of slots in the server table. Some of them may be empty.
+    pub fn server_count(&self) -> usize {
+        self.servers.len()
+    }
+
+    /// Choose whether the server `sid`, which must belong to `pid`, has to
+    /// approve new connections.
+    ///
+    /// # Errors
+    ///
+    /// * **ServerNotFound**: The server does not exist or belongs to another process
+    pub fn require_connect_approval(
+        &mut self,
+        pid: PID,
+        sid: SID,
+        required: bool,
+    ) -> Result<(), xous_kernel::Error> {
+        let sidx = self
+            .sidx_from_sid(sid, pid)
+            .ok_or(xous_kernel::Error::ServerNotFound)?;
+        self.server_from_sidx_mut(sidx)
+            .ok_or(xous_kernel::Error::ServerNotFound)?
+            .approves_connections = required;
+        Ok(())
+    }
+
+    /// Choose whether the server `sid`, which must belong to `pid`, is told
+    /// when its clients terminate.
+    ///
+    /// # Errors
+    ///
+    /// * **ServerNotFound**: The server does not exist or belongs to another process
+    pub fn notify_disconnects(
+        &mut self,
+        pid: PID,
+        sid: SID,
+        enabled: bool,
+    ) -> Result<(), xous_kernel::Error> {
+        let sidx = self
+            .sidx_from_sid(sid, pid)
+            .ok_or(xous_kernel::Error::ServerNotFound)?;
+        self.server_from_sidx_mut(sidx)
+            .ok_or(xous_kernel::Error::ServerNotFound)?
+            .notifies_disconnects = enabled;
+        Ok(())
+    }
+
+    /// Return the PID of the process that owns the server `sid`.
+    ///
+    /// # Errors
+    ///
+    /// * **ServerNotFound**: The server does not exist
+    pub fn server_owner(&self, sid: SID) -> Result<PID, xous_kernel::Error> {
+        self.servers
+            .iter()
+            .flatten()
+            .find(|server| server.sid == sid)
+            .map(|server| server.pid)
+            .ok_or(xous_kernel::Error::ServerNotFound)
+    }
+
+    /// Remember that `pid`:`tid` is blocked until the server at `sidx` decides
+    /// whether `target_pid` may connect to it, along with any rights that
+    /// `target_pid` is given if it may.
+    ///
+    /// # Errors
+    ///
+    /// * **OutOfMemory**: Too many connections are waiting for approval
+    pub fn add_connect_request(
+        &mut self,
+        pid: PID,
+        tid: TID,
+        target_pid: PID,
+        sidx: usize,
+        rights: Option<ConnectionRights>,
+    ) -> Result<(), xous_kernel::Error> {
+        let slot = self
+            .connect_requests
+            .iter_mut()
+            .find(|request| request.is_none())
+            .ok_or(xous_kernel::Error::OutOfMemory)?;
+        *slot = Some(ConnectRequest {
+            pid,
+            tid,
+            target_pid,
+            sidx,
+            rights,
+        });
+        Ok(())
+    }
+
+    /// Remove and return the connection that `pid`:`tid` is waiting on, if any.
+    pub fn take_connect_request(&mut self, pid: PID, tid: TID) -> Option<ConnectRequest> {
+        self.connect_requests
+            .iter_mut()
+            .find(|request| matches!(request, Some(r) if r.pid == pid && r.tid == tid))?
+            .take()
+    }
+
+    /// Remember that `pid`:`tid` is blocked on the futex at `addr`.
+    ///
+    /// # Errors
+    ///
+    /// * **OutOfMemory**: Too many threads are waiting on futexes
+    pub fn add_futex_waiter(
+        &mut self,
+        pid: PID,
+        tid: TID,
+        addr: usize,
+    ) -> Result<(), xous_kernel::Error> {
+        let slot = self
+            .futex_waiters
+            .iter_mut()
+            .find(|waiter| waiter.is_none())
+            .ok_or(xous_kernel::Error::OutOfMemory)?;
+        *slot = Some(FutexWaiter { pid, tid, addr });
+        Ok(())
+    }
+
+    /// Remove and return a thread in `pid` that is blocked on the futex at
+    /// `addr`, if there is one.
+    pub fn take_futex_waiter(&mut self, pid: PID, addr: usize) -> Option<TID> {
+        self.futex_waiters
+            .iter_mut()
+            .find(|waiter| matches!(waiter, Some(w) if w.pid == pid && w.addr == addr))?
+            .take()
+            .map(|waiter| waiter.tid)
+    }
+
+    /// Remember that `pid`:`tid` is blocked until `target` exits.
+    ///
+    /// # Errors
+    ///
+    /// * **OutOfMemory**: Too many threads are waiting to join other threads
+    pub fn add_thread_joiner(
+        &mut self,
+        pid: PID,
+        tid: TID,
+        target: TID,
+    ) -> Result<(), xous_kernel::Error> {
+        let slot = self
+            .thread_joiners
+            .iter_mut()
+            .find(|joiner| joiner.is_none())
+            .ok_or(xous_kernel::Error::OutOfMemory)?;
+        *slot = Some(ThreadJoiner { pid, tid, target });
+        Ok(())
+    }
+
+    /// Whether a thread in `pid` is already waiting for `target` to exit.
+    pub fn has_thread_joiner(&self, pid: PID, target: TID) -> bool {
+        self.thread_joiners
+            .iter()
+            .any(|joiner| matches!(joiner, Some(j) if j.pid == pid && j.target == target))
+    }
+
+    /// Remove and return the thread in `pid` that is waiting for `target` to
+    /// exit, if there is one.
+    pub fn take_thread_joiner(&mut self, pid: PID, target: TID) -> Option<TID> {
+        self.thread_joiners
+            .iter_mut()
+            .find(|joiner| matches!(joiner, Some(j) if j.pid == pid && j.target == target))?
+            .take()
+            .map(|joiner| joiner.tid)
+    }
+
+    /// Remember that `tid` in `pid` should be freed, along with its stack, as
+    /// soon as it exits.
+    ///
+    /// # Errors
+    ///
+    /// * **OutOfMemory**: Too many threads have been detached
+    pub fn add_detached_thread(
+        &mut self,
+        pid: PID,
+        tid: TID,
+        stack: Option<MemoryRange>,
+    ) -> Result<(), xous_kernel::Error> {
+        let slot = self
+            .detached_threads
+            .iter_mut()
+            .find(|detached| detached.is_none())
+            .ok_or(xous_kernel::Error::OutOfMemory)?;
+        *slot = Some(DetachedThread { pid, tid, stack });
+        Ok(())
+    }
+
+    /// Whether `tid` in `pid` has already been detached.
+    pub fn is_thread_detached(&self, pid: PID, tid: TID) -> bool {
+        self.detached_threads
+            .iter()
+            .any(|detached| matches!(detached, Some(d) if d.pid == pid && d.tid == tid))
+    }
+
+    /// If `tid` in `pid` has been detached, forget about it and return the
+    /// stack that should be unmapped, if any.
+    pub fn take_detached_thread(&mut self, pid: PID, tid: TID) -> Option<Option<MemoryRange>> {
+        self.detached_threads
+            .iter_mut()
+            .find(|detached| matches!(detached, Some(d) if d.pid == pid && d.tid == tid))?
+            .take()
+            .map(|detached| detached.stack)
+    }
+
+    /// If `pid` has terminated, free it and return its exit code.
+    pub fn reap_process(&mut self, pid: PID) -> Result<Option<usize>, xous_kernel::Error> {
+        let process = self.get_process_mut(pid)?;
+        if let ProcessState::Zombie(exit_code) = process.state {
+            process.state = ProcessState::Free;
+            return Ok(Some(exit_code));
+        }
+        Ok(None)
+    }
+
+    /// Remember that `pid`:`tid` is blocked until its child `target`
+    /// terminates.
+    ///
+    /// # Errors
+    ///
+    /// * **OutOfMemory**: Too many threads are waiting for processes to terminate
+    pub fn add_process_waiter(
+        &mut self,
+        pid: PID,
+        tid: TID,
+        target: PID,
+    ) -> Result<(), xous_kernel::Error> {
+        let slot = self
+            .process_waiters
+            .iter_mut()
+            .find(|waiter| waiter.is_none())
+            .ok_or(xous_kernel::Error::OutOfMemory)?;
+        *slot = Some(ProcessWaiter { pid, tid, target });
+        Ok(())
+    }
+
+    /// Whether a thread is already waiting for `target` to terminate.
+    pub fn has_process_waiter(&self, target: PID) -> bool {
+        self.process_waiters
+            .iter()
+            .any(|waiter| matches!(waiter, Some(w) if w.target == target))
+    }
+
+    /// Remove and return the thread that is waiting for `target` to
+    /// terminate, if there is one.
+    pub fn take_process_waiter(&mut self, target: PID) -> Option<(PID, TID)> {
+        self.process_waiters
+            .iter_mut()
+            .find(|waiter| matches!(waiter, Some(w) if w.target == target))?
+            .take()
+            .map(|waiter| (waiter.pid, waiter.tid))
+    }
+
+    /// Carry out the server's decision on a connection request, returning the
+    /// result that the waiting thread should see.
+    pub fn complete_connect_request(
+        &mut self,
+        request: ConnectRequest,
+        approved: bool,
+    ) -> xous_kernel::Result {
+        if !approved {
+            return xous_kernel::Result::Error(xous_kernel::Error::AccessDenied);
+        }
+        let sid = match self.server_from_sidx(request.sidx) {
+            Some(server) => server.sid,
+            None => return xous_kernel::Result::Error(xous_kernel::Error::ServerNotFound),
+        };
+        match self.connect_with_rights(request.pid, request.target_pid, sid, request.rights) {
+            Ok(cid) => xous_kernel::Result::ConnectionID(cid),
+            Err(e) => xous_kernel::Result::Error(e),
+        }
+    }
+
+    /// Connect `target_pid` to the server `sid` on behalf of `pid`, and give
+    /// it `rights` if there are any. The rights are checked again here, since
+    /// the target's rights may have changed while the server was deciding.
+    ///
+    /// # Errors
+    ///
+    /// * **ServerNotFound**: The server does not exist
+    /// * **AccessDenied**: `pid` may not give `target_pid` these rights
+    pub fn connect_with_rights(
+        &mut self,
+        pid: PID,
+        target_pid: PID,
+        sid: SID,
+        rights: Option<ConnectionRights>,
+    ) -> Result<CID, xous_kernel::Error> {
+        if let Some(rights) = rights {
+            self.check_connection_rights(pid, target_pid, sid, &rights)?;
+        }
+        let cid = self.connect_process_to_server(target_pid, sid)?;
+        if let Some(rights) = rights {
+            self.servers
+                .iter_mut()
+                .flatten()
+                .find(|server| server.sid == sid)
+                .ok_or(xous_kernel::Error::ServerNotFound)?
+                .set_rights(Some(target_pid), rights);
+        }
+        Ok(cid)
+    }
     /// Allocate a new server ID for this process and return the address. If the
     /// server table is full, return an error.
     pub fn connect_to_server(&mut self, sid: SID) -> Result<CID, xous_kernel::Error> {
@@ -1390,12 +2083,17 @@ impl SystemServices {
 
         let pid = crate::arch::process::current_pid();
         // println!("KERNEL({}): Server table: {:?}", _pid.get(), self.servers);
-        ArchProcess::with_inner_mut(|process_inner| {
+        let result = ArchProcess::with_connections_mut(|process_inner, extra_connections| {
             assert_eq!(pid, process_inner.pid);
             let mut slot_idx = None;
             // Look through the connection map for (1) a free slot, and (2) an
             // existing connection
-            for (connection_idx, server_idx) in process_inner.connection_map.iter().enumerate() {
+            for (connection_idx, server_idx) in process_inner
+                .connection_map
+                .iter()
+                .chain(extra_connections.iter())
+                .enumerate()
+            {
                 // If we find an empty slot, use it
                 if server_idx.is_none() {
                     if slot_idx.is_none() {
@@ -1404,6 +2102,11 @@ impl SystemServices {
                     continue;
                 }
 
+                // Skip over connections to servers that have gone away.
+                if server_idx.unwrap().get() == 1 {
+                    continue;
+                }
+
                 // If a connection to this server ID exists already, return it.
                 let server_idx = (server_idx.unwrap().get() as usize) - 2;
                 if let Some(allocated_server) = &self.servers[server_idx] {
@@ -1418,14 +2121,19 @@ impl SystemServices {
                     }
                 }
             }
-            let slot_idx = slot_idx.ok_or_else(|| Error::OutOfMemory)?;
 
             // Look through all servers for one whose SID matches.
             for (server_idx, server) in self.servers.iter().enumerate() {
                 if let Some(allocated_server) = server {
                     if allocated_server.sid == sid {
-                        process_inner.connection_map[slot_idx] =
-                            Some(NonZeroU8::new((server_idx as u8) + 2).unwrap());
+                        let slot_idx = slot_idx.ok_or_else(|| Error::OutOfMemory)?;
+                        let slot = process_inner
+                            .connection_map
+                            .iter_mut()
+                            .chain(extra_connections.iter_mut())
+                            .nth(slot_idx)
+                            .unwrap();
+                        *slot = Some(NonZeroU8::new((server_idx as u8) + 2).unwrap());
                         // println!(
                         //     "KERNEL({}): New connection to {:?}. After connection, cid is {} and process connection map is: {:?}",
                         //     pid.get(),
@@ -1437,10 +2145,307 @@ impl SystemServices {
                     }
                 }
             }
-            Err(xous_kernel::Error::ServerNotFound) // May also be OutOfMemory if the table is full
+            Err(xous_kernel::Error::ServerNotFound)
+        });
+
+        match result {
+            // Every connection slot is in use, so make room for more and try again.
+            Err(xous_kernel::Error::OutOfMemory) => {
+                ArchProcess::grow_connections()?;
+                self.connect_to_server(sid)
+            }
+            result => result,
+        }
+    }
+
+    /// Release a connection held by the current process, so that the
+    /// connection ID may be handed out again.
+    ///
+    /// # Errors
+    ///
+    /// * **ServerNotFound**: The connection ID is not in use
+    pub fn disconnect_from_server(&mut self, cid: CID) -> Result<(), xous_kernel::Error> {
+        if cid < 2 {
+            return Err(xous_kernel::Error::ServerNotFound);
+        }
+        let pid = crate::arch::process::current_pid();
+        ArchProcess::with_connections_mut(|process_inner, extra_connections| {
+            assert_eq!(pid, process_inner.pid);
+            let slot = process_inner
+                .connection_map
+                .iter_mut()
+                .chain(extra_connections.iter_mut())
+                .nth(cid - 2)
+                .ok_or(xous_kernel::Error::ServerNotFound)?;
+            slot.take().ok_or(xous_kernel::Error::ServerNotFound)?;
+            Ok(())
         })
     }
 
+    /// Tear down the server `sid`, which must belong to `pid`. Clients waiting
+    /// on a response and threads waiting for a message are woken with
+    /// `ServerNotFound`, lent memory goes back to its owner, and every
+    /// connection to the server is replaced with a tombstone.
+    ///
+    /// # Errors
+    ///
+    /// * **ServerNotFound**: The server does not exist or belongs to another process
+    pub fn destroy_server(&mut self, pid: PID, sid: SID) -> Result<(), xous_kernel::Error> {
+        self.remove_server(pid, sid, false)
+    }
+
+    /// Tear down the server `sid` as `destroy_server()` does. If the server is
+    /// going away because `pid` is terminating, clients that are waiting on a
+    /// response are woken with `ProcessTerminated` instead.
+    fn remove_server(
+        &mut self,
+        pid: PID,
+        sid: SID,
+        terminating: bool,
+    ) -> Result<(), xous_kernel::Error> {
+        let sidx = self
+            .sidx_from_sid(sid, pid)
+            .ok_or(xous_kernel::Error::ServerNotFound)?;
+        let current_pid = self.current_pid();
+        self.get_process(pid)?.activate()?;
+
+        // Wake up every client that is still waiting on this server.
+        while let Some(waiting) = self
+            .server_from_sidx_mut(sidx)
+            .and_then(|server| server.abandon_next_message())
+        {
+            let (client_pid, client_tid) = match waiting {
+                WaitingMessage::BorrowedMemory(
+                    client_pid,
+                    client_tid,
+                    server_addr,
+                    client_addr,
+                    len,
+                ) => {
+                    self.return_abandoned_memory(client_pid, server_addr, client_addr, len);
+                    (client_pid, client_tid)
+                }
+                WaitingMessage::ScalarMessage(client_pid, client_tid) => (client_pid, client_tid),
+                // The sender already gave up waiting, so only its memory needs
+                // to go back.
+                WaitingMessage::CancelledMemory(client_pid, server_addr, client_addr, len) => {
+                    self.return_abandoned_memory(client_pid, server_addr, client_addr, len);
+                    continue;
+                }
+                // Memory from a process that has since terminated has nowhere
+                // to go back to.
+                WaitingMessage::ForgetMemory(range) => {
+                    if cfg!(baremetal) {
+                        crate::mem::MemoryManager::with_mut(|mm| {
+                            let virt = range.as_ptr() as usize;
+                            for addr in (virt..(virt + range.len())).step_by(crate::mem::PAGE_SIZE)
+                            {
+                                if let Err(e) = mm.forget_page(addr as *mut usize) {
+                                    println!(
+                                        "KERNEL: couldn't free abandoned page {:08x}: {:?}",
+                                        addr, e
+                                    );
+                                }
+                            }
+                        });
+                    }
+                    continue;
+                }
+                _ => continue,
+            };
+            self.remove_timeout(client_pid, client_tid);
+            self.take_connect_request(client_pid, client_tid);
+            if cfg!(baremetal) {
+                self.ready_thread(client_pid, client_tid)?;
+            }
+            self.set_thread_result(
+                client_pid,
+                client_tid,
+                xous_kernel::Result::Error(if terminating {
+                    xous_kernel::Error::ProcessTerminated
+                } else {
+                    xous_kernel::Error::ServerNotFound
+                }),
+            )?;
+        }
+
+        // Threads that are waiting for a message or an event will never get one.
+        while let Some(tid) = self.server_from_sidx_mut(sidx).and_then(|server| {
+            server
+                .take_available_thread()
+                .or_else(|| server.take_event_thread())
+        }) {
+            self.remove_timeout(pid, tid);
+            if cfg!(baremetal) {
+                self.ready_thread(pid, tid)?;
+            }
+            self.set_thread_result(
+                pid,
+                tid,
+                xous_kernel::Result::Error(xous_kernel::Error::ServerNotFound),
+            )?;
+        }
+
+        self.tombstone_connections(sidx)?;
+
+        // Nothing should signal whichever server takes over this slot either.
+        for server in self.servers.iter_mut().flatten() {
+            server.forget_message_events(sidx);
+        }
+
+        self.get_process(pid)?.activate()?;
+        #[cfg(baremetal)]
+        {
+            let queue_page = self.servers[sidx].as_ref().unwrap().queue_page();
+            if let Err(e) = crate::mem::MemoryManager::with_mut(|mm| mm.unmap_page(queue_page)) {
+                println!("KERNEL: couldn't free server queue page: {:?}", e);
+            }
+        }
+        Server::destroy(&mut self.servers[sidx])?;
+
+        self.get_process(current_pid)?.activate()
+    }
+
+    /// Give memory that was lent to a server that is going away back to
+    /// `client_pid`. The server is torn down regardless, so a failure is
+    /// reported rather than returned. In a hosted environment the server was
+    /// only ever given a copy of the memory, so the sender already has it.
+    fn return_abandoned_memory(
+        &mut self,
+        client_pid: PID,
+        server_addr: MemoryAddress,
+        client_addr: MemoryAddress,
+        len: MemorySize,
+    ) {
+        if !cfg!(baremetal) {
+            return;
+        }
+        let result = MemoryRange::new(server_addr.get(), len.get()).and_then(|buf| {
+            self.return_memory(
+                server_addr.get() as *mut u8,
+                0,
+                client_pid,
+                0,
+                client_addr.get() as *mut u8,
+                len.get(),
+                buf,
+            )
+        });
+        if let Err(e) = result {
+            println!(
+                "KERNEL: couldn't return {} bytes at {:08x} to PID {}: {:?}",
+                len.get(),
+                client_addr.get(),
+                client_pid,
+                e
+            );
+        }
+    }
+
+    /// Replace every connection to the server at `sidx` with a tombstone, so
+    /// that it can't reach whichever server takes over this slot. Connection
+    /// map entries are offset by two, because 0 == free and 1 == "tombstone".
+    /// The current memory space is left pointing at the last process visited.
+    fn tombstone_connections(&self, sidx: usize) -> Result<(), xous_kernel::Error> {
+        for process in self.processes.iter() {
+            if process.free() || process.zombie() {
+                continue;
+            }
+            process.activate()?;
+            ArchProcess::with_connections_mut(|process_inner, extra_connections| {
+                for mapping in process_inner
+                    .connection_map
+                    .iter_mut()
+                    .chain(extra_connections.iter_mut())
+                    .flatten()
+                {
+                    if mapping.get() == (sidx as u8) + 2 {
+                        *mapping = NonZeroU8::new(1).unwrap();
+                    }
+                }
+            });
+        }
+        Ok(())
+    }
+
+    /// Hand every page that `pid` has lent out over to the kernel, because
+    /// `pid` is terminating and can't be given them back. Each one is freed
+    /// once its last borrower forgets it. The current memory space is left
+    /// pointing at the last borrower visited.
+    fn orphan_lent_memory(&self, pid: PID) -> Result<(), xous_kernel::Error> {
+        for server in self.servers.iter().flatten() {
+            let mut lent = server.lent_buffers(pid).peekable();
+            if lent.peek().is_none() {
+                continue;
+            }
+            self.get_process(server.pid)?.activate()?;
+            crate::mem::MemoryManager::with_mut(|mm| {
+                for (server_addr, client_addr, len) in lent {
+                    for offset in (0..len).step_by(crate::mem::PAGE_SIZE) {
+                        if let Ok(phys) = crate::arch::mem::virt_to_phys(server_addr + offset) {
+                            mm.orphan_lent_page(pid, client_addr + offset, phys);
+                        }
+                    }
+                }
+            });
+        }
+        Ok(())
+    }
+
+    /// Check that `pid` may give `target_pid` the rights `rights` on the
+    /// server `sid`, replacing any it already has. `pid` may only hand out
+    /// rights that it holds itself, and only the owner of the server may take
+    /// rights away from a process.
+    ///
+    /// # Errors
+    ///
+    /// * **ServerNotFound**: The server does not exist
+    /// * **AccessDenied**: `pid` does not hold all of `rights`, or doesn't own
+    ///   the server and `rights` are narrower than what `target_pid` has now
+    pub fn check_connection_rights(
+        &self,
+        pid: PID,
+        target_pid: PID,
+        sid: SID,
+        rights: &ConnectionRights,
+    ) -> Result<(), xous_kernel::Error> {
+        let server = self
+            .servers
+            .iter()
+            .flatten()
+            .find(|server| server.sid == sid)
+            .ok_or(xous_kernel::Error::ServerNotFound)?;
+        if !server.rights_for(pid).includes(rights) {
+            return Err(xous_kernel::Error::AccessDenied);
+        }
+        if server.pid != pid && !rights.includes(&server.rights_for(target_pid)) {
+            return Err(xous_kernel::Error::AccessDenied);
+        }
+        Ok(())
+    }
+
+    /// Set what clients may send to the server `sid`, which must belong to
+    /// `pid`. If `target_pid` is `None`, this sets the default rights.
+    ///
+    /// # Errors
+    ///
+    /// * **ServerNotFound**: The server does not exist or belongs to another process
+    pub fn set_connection_rights(
+        &mut self,
+        pid: PID,
+        sid: SID,
+        target_pid: Option<PID>,
+        rights: ConnectionRights,
+    ) -> Result<(), xous_kernel::Error> {
+        let sidx = self
+            .sidx_from_sid(sid, pid)
+            .ok_or(xous_kernel::Error::ServerNotFound)?;
+        self.server_from_sidx_mut(sidx)
+            .ok_or(xous_kernel::Error::ServerNotFound)?
+            .set_rights(target_pid, rights);
+        Ok(())
+    }
+
     /// Retrieve the server ID index from the specified SID.
     /// This may only be called if the SID is a server owned by
     /// the current process.
@@ -1458,7 +2463,7 @@ impl SystemServices {
 
     /// Return a server based on the connection id and the current process
     pub fn server_from_sidx(&self, sidx: usize) -> Option<&Server> {
-        if sidx > self.servers.len() {
+        if sidx >= self.servers.len() {
             None
         } else {
             self.servers[sidx].as_ref()
@@ -1467,7 +2472,7 @@ impl SystemServices {
 
     /// Return a server based on the connection id and the current process
     pub fn server_from_sidx_mut(&mut self, sidx: usize) -> Option<&mut Server> {
-        if sidx > self.servers.len() {
+        if sidx >= self.servers.len() {
             None
         } else {
             self.servers[sidx].as_mut()
@@ -1488,17 +2493,22 @@ impl SystemServices {
 
         let cid = cid - 2;
 
-        ArchProcess::with_inner(|process_inner| {
+        ArchProcess::with_connections(|process_inner, extra_connections| {
             assert_eq!(crate::arch::process::current_pid(), process_inner.pid);
-            if cid >= process_inner.connection_map.len() {
+            let mapping = process_inner
+                .connection_map
+                .iter()
+                .chain(extra_connections.iter())
+                .nth(cid);
+            if mapping.is_none() {
                 // println!("KERNEL({}): CID {} > connection map len", crate::arch::process::current_pid(), cid);
                 return None;
             }
-            // if process_inner.connection_map[cid].is_none() {
+            // if mapping.unwrap().is_none() {
             //     println!("KERNEL({}): CID {} doesn't exist in the connection map", crate::arch::process::current_pid(), cid + 2);
             //     println!("KERNEL({}): Process inner is: {:?}", crate::arch::process::current_pid(), process_inner);
             // }
-            let mut server_idx = process_inner.connection_map[cid]?.get() as usize;
+            let mut server_idx = (*mapping.unwrap())?.get() as usize;
             if server_idx == 1 {
                 // println!("KERNEL({}): CID {} is no longer valid", crate::arch::process::current_pid(), cid + 2);
                 return None;
@@ -1578,6 +2588,111 @@ impl SystemServices {
         result
     }
 
+    /// Start the clock on a thread that is about to block. If it is still
+    /// blocked after `timeout_ms` milliseconds, whatever it was waiting on is
+    /// abandoned and it is woken up with `Error::Timeout`.
+    ///
+    /// # Errors
+    ///
+    /// * **OutOfMemory**: Too many threads with timeouts are outstanding
+    pub fn add_timeout(
+        &mut self,
+        pid: PID,
+        tid: TID,
+        kind: TimeoutKind,
+        timeout_ms: usize,
+    ) -> Result<(), xous_kernel::Error> {
+        let slot = self
+            .timeouts
+            .iter_mut()
+            .find(|timeout| timeout.is_none())
+            .ok_or(xous_kernel::Error::OutOfMemory)?;
+        *slot = Some(Timeout {
+            pid,
+            tid,
+            kind,
+            deadline: arch::timer::elapsed_ms().saturating_add(timeout_ms as u64),
+        });
+        Ok(())
+    }
+
+    /// Return `true` if there is room to call `add_timeout()`.
+    pub fn can_add_timeout(&self) -> bool {
+        self.timeouts.iter().any(|timeout| timeout.is_none())
+    }
+
+    /// Stop the clock on the given thread, because whatever it was waiting on
+    /// has happened.
+    pub fn remove_timeout(&mut self, pid: PID, tid: TID) {
+        for timeout in self.timeouts.iter_mut() {
+            if matches!(timeout, Some(t) if t.pid == pid && t.tid == tid) {
+                *timeout = None;
+            }
+        }
+    }
+
+    /// Remove and return a timeout whose deadline has passed, if there is one.
+    pub fn take_expired_timeout(&mut self) -> Option<Timeout> {
+        let now = arch::timer::elapsed_ms();
+        self.timeouts
+            .iter_mut()
+            .find(|timeout| matches!(timeout, Some(t) if t.deadline <= now))?
+            .take()
+    }
+
+    /// Switch to the server's address space and withdraw a blocking message
+    /// whose sender has stopped waiting for a response, then switch back to
+    /// the original address space. If the server hasn't received the message
+    /// yet, any memory that was lent with it is returned to the sender.
+    /// Otherwise the server keeps that memory until it responds.
+    ///
+    /// # Errors
+    ///
+    /// * **ServerNotFound**: The server no longer exists
+    /// * **ProcessNotFound**: The server's process no longer exists
+    pub fn cancel_server_message(
+        &mut self,
+        sidx: usize,
+        idx: usize,
+        pid: PID,
+        tid: TID,
+    ) -> Result<(), xous_kernel::Error> {
+        let current_pid = self.current_pid();
+        let server_pid = self
+            .server_from_sidx(sidx)
+            .ok_or(xous_kernel::Error::ServerNotFound)?
+            .pid;
+        self.get_process(server_pid)?.activate()?;
+        let server = self
+            .server_from_sidx_mut(sidx)
+            .expect("couldn't re-discover server index");
+        let result = match server.cancel_message(idx, pid, tid) {
+            // In a hosted environment the server was only ever given a copy of
+            // the memory, so the sender already has it.
+            WaitingMessage::BorrowedMemory(_, _, server_addr, client_addr, len)
+                if cfg!(baremetal) =>
+            {
+                MemoryRange::new(server_addr.get(), len.get()).and_then(|buf| {
+                    self.return_memory(
+                        server_addr.get() as *mut u8,
+                        tid,
+                        pid,
+                        tid,
+                        client_addr.get() as *mut u8,
+                        len.get(),
+                        buf,
+                    )
+                    .map(|_| ())
+                })
+            }
+            _ => Ok(()),
+        };
+        self.get_process(current_pid)
+            .expect("couldn't restore previous process")
+            .activate()?;
+        result
+    }
+
     // /// Get a server index based on a SID
     // pub fn server_sidx(&mut self, sid: SID) -> Option<usize> {
     //     for (idx, server) in self.servers.iter_mut().enumerate() {
@@ -1590,8 +2705,13 @@ impl SystemServices {
     //     None
     // }
 
-    /// Terminate the given process. Returns the process' parent PID.
-    pub fn terminate_process(&mut self, target_pid: PID) -> Result<PID, xous_kernel::Error> {
+    /// Terminate the given process. Returns the PID that should run next,
+    /// which is the process' `ppid`.
+    pub fn terminate_process(
+        &mut self,
+        target_pid: PID,
+        exit_code: usize,
+    ) -> Result<PID, xous_kernel::Error> {
         // To terminate a process, we must perform the following:
         //
         // 1. If we have any client connections, remove them.
@@ -1600,54 +2720,111 @@ impl SystemServices {
         // 4. Mark all "Borrowed" memory as "Free-when-returned". That way, if we've shared
         //    memory to a Server, it will be reclaimed by the system when it comes back
 
-        // 1. Find all servers associated with this PID and remove them.
-        for (idx, server) in self.servers.iter_mut().enumerate() {
-            if let Some(server) = server {
-                if server.pid == target_pid {
-                    // This is our server, so look through the connection map of each
-                    // process to determine if this connection needs to be replaced
-                    // with a tombstone.
-                    for process in self.processes.iter() {
-                        if process.free() {
-                            continue;
-                        }
-                        process.activate()?;
-                        ArchProcess::with_inner_mut(|process_inner| {
-                            // Look through the connection map for a connection
-                            // that matches this index. Note that connection map entries
-                            // are offset by two, because 0 == free and 1 == "tombstone".
-                            for mapping in process_inner.connection_map.iter_mut() {
-                                if let Some(mapping) = mapping {
-                                    if mapping.get() == (idx as u8) + 2 {
-                                        *mapping = NonZeroU8::new(1).unwrap();
-                                    }
-                                }
-                            }
-                        })
-                    }
-                }
+        // 1. Destroy all servers associated with this PID. Clients waiting on
+        //    them are told the process terminated, their connections become
+        //    tombstones, and the address is free to be claimed again, for
+        //    example by a restarted service.
+        for sidx in 0..self.servers.len() {
+            let sid = match &self.servers[sidx] {
+                Some(server) if server.pid == target_pid => server.sid,
+                _ => continue,
+            };
+            self.remove_server(target_pid, sid, true)?;
+        }
+
+        // 4. Memory that this process lent out stays with its borrowers, and
+        //    is freed once they're done with it.
+        self.orphan_lent_memory(target_pid)?;
+        for server in self.servers.iter_mut().flatten() {
+            // Look through this server's memory space to determine if this process
+            // is mentioned there as having some memory lent out.
+            server.discard_messages_for_pid(target_pid);
+            server.clear_rights(target_pid);
+        }
 
-                // Look through this server's memory space to determine if this process
-                // is mentioned there as having some memory lent out.
-                server.discard_messages_for_pid(target_pid);
+        // Nobody is left to wake up if this process' messages time out, if
+        // its connections are approved, if its futexes are woken, if its
+        // threads exit, or if its children terminate.
+        for timeout in self.timeouts.iter_mut() {
+            if matches!(timeout, Some(t) if t.pid == target_pid) {
+                *timeout = None;
+            }
+        }
+        for request in self.connect_requests.iter_mut() {
+            if matches!(request, Some(r) if r.pid == target_pid) {
+                *request = None;
+            }
+        }
+        for waiter in self.futex_waiters.iter_mut() {
+            if matches!(waiter, Some(w) if w.pid == target_pid) {
+                *waiter = None;
+            }
+        }
+        for joiner in self.thread_joiners.iter_mut() {
+            if matches!(joiner, Some(j) if j.pid == target_pid) {
+                *joiner = None;
+            }
+        }
+        for detached in self.detached_threads.iter_mut() {
+            if matches!(detached, Some(d) if d.pid == target_pid) {
+                *detached = None;
+            }
+        }
+        for waiter in self.process_waiters.iter_mut() {
+            if matches!(waiter, Some(w) if w.pid == target_pid) {
+                *waiter = None;
             }
         }
         let process = self.get_process_mut(target_pid)?;
         process.activate()?;
-        let parent_pid = process.ppid;
+        let ppid = process.ppid;
+        let parent_pid = process.parent;
+
+        // Shared memory has to be unmapped while the process' address space
+        // is still active.
+        crate::mem::MemoryManager::with_mut(|mm| mm.release_shared(target_pid));
         process.terminate()?;
         // println!("KERNEL({}): Terminated", target_pid);
 
-        let process = self.get_process(parent_pid)?;
+        // Children that have already terminated will never be collected now,
+        // and children that are still running are adopted by PID 1.
+        for child in self.processes.iter_mut() {
+            if child.parent != target_pid || child.free() {
+                continue;
+            }
+            if child.zombie() {
+                child.state = ProcessState::Free;
+            } else {
+                child.parent = unsafe { PID::new_unchecked(1) };
+            }
+        }
+
+        let process = self.get_process(ppid)?;
         process.activate().unwrap();
 
-        Ok(parent_pid)
+        // Hand the exit code to the parent if it's already waiting. Otherwise
+        // keep it until the parent asks, unless the parent is PID 1, which
+        // never does.
+        if let Some((waiter_pid, waiter_tid)) = self.take_process_waiter(target_pid) {
+            if cfg!(baremetal) {
+                self.ready_thread(waiter_pid, waiter_tid)?;
+            }
+            self.set_thread_result(
+                waiter_pid,
+                waiter_tid,
+                xous_kernel::Result::Scalar1(exit_code),
+            )?;
+        } else if parent_pid.get() != 1 {
+            self.get_process_mut(target_pid)?.state = ProcessState::Zombie(exit_code);
+        }
+
+        Ok(ppid)
     }
 
     /// Calls the provided function with the current inner process state.
     pub fn shutdown(&mut self) -> Result<(), xous_kernel::Error> {
         // Destroy all servers. This will cause all queued messages to be lost.
-        for server in &mut self.servers {
+        for server in self.servers.iter_mut() {
             if server.is_some() {
                 Server::destroy(server).unwrap();
             }
@@ -1655,7 +2832,7 @@ impl SystemServices {
 
         // Destroy all processes. This will cause them to immediately terminate.
         for process in &mut self.processes {
-            if !process.free() {
+            if !process.free() && !process.zombie() {
                 process.activate().unwrap();
                 process.terminate().unwrap();
             }
@@ -3,7 +3,7 @@ use crate::arch::process::Process as ArchProcess;
 use crate::irq::interrupt_claim;
 use crate::mem::{MemoryManager, PAGE_SIZE};
 use crate::server::{SenderID, WaitingMessage};
-use crate::services::SystemServices;
+use crate::services::{SystemServices, TimeoutKind};
 use core::mem;
 use xous_kernel::*;
 
@@ -44,17 +44,126 @@ fn do_yield(_pid: PID, tid: TID) -> SysCallResult {
     })
 }
 
-fn send_message(pid: PID, thread: TID, cid: CID, message: Message) -> SysCallResult {
+/// Take the CPU away from thread `tid` of process `pid` because its quantum
+/// has expired. This performs the same switch as `Yield`, except that if the
+/// kernel's own scheduler is the one running there is nothing to do.
+pub fn preempt(pid: PID, tid: TID) -> core::result::Result<(), xous_kernel::Error> {
+    if !cfg!(baremetal) {
+        // Hosted threads each run on their own host thread and can't be
+        // stopped, so the preempted thread carries straight on.
+        return SystemServices::with_mut(|ss| {
+            ss.preempt_thread(pid, tid)?;
+            ss.switch_to_thread(pid, Some(tid))
+        });
+    }
+
+    let (parent_pid, parent_ctx) = match unsafe { SWITCHTO_CALLER.take() } {
+        Some(caller) => caller,
+        None => return Ok(()),
+    };
+    SystemServices::with_mut(|ss| {
+        // Only the preempted thread goes back on the ready list, so that it
+        // runs again even if the process has other threads waiting.
+        ss.preempt_thread(pid, tid)?;
+        ss.activate_process_thread(tid, parent_pid, parent_ctx, true)
+            .map(|_| ())
+    })
+}
+
+/// Terminate process `pid` because thread `tid` needed a page that the
+/// process' memory limit doesn't allow. Whoever waits for the process gets
+/// `Error::OutOfMemory` as its exit code.
+pub fn terminate_out_of_memory(pid: PID, tid: TID, in_irq: bool) -> SysCallResult {
+    handle(
+        pid,
+        tid,
+        in_irq,
+        SysCall::TerminateProcess(xous_kernel::Error::OutOfMemory.to_usize()),
+    )
+}
+
+/// Wake every thread whose timeout has expired with `Error::Timeout`,
+/// withdrawing any message it was waiting on a response to. This runs from
+/// the timer interrupt, so a thread that can't be woken is logged and
+/// skipped rather than bringing down the kernel.
+pub fn expire_timeouts() {
+    SystemServices::with_mut(|ss| {
+        while let Some(timeout) = ss.take_expired_timeout() {
+            // The process may have terminated while the timer was pending.
+            match ss.get_process(timeout.pid) {
+                Ok(process) if !process.free() && !process.zombie() => (),
+                _ => continue,
+            }
+            match timeout.kind {
+                // If the server has gone away there's nothing left to withdraw,
+                // but the sender must still be woken up.
+                TimeoutKind::Send(sidx, idx) => {
+                    ss.cancel_server_message(sidx, idx, timeout.pid, timeout.tid)
+                        .ok();
+                }
+                // Stop the thread from being handed a message.
+                TimeoutKind::Receive(sidx) => {
+                    if let Some(server) = ss.server_from_sidx_mut(sidx) {
+                        server.unpark_thread(timeout.tid);
+                    }
+                }
+            }
+            let woken = if cfg!(baremetal) {
+                ss.ready_thread(timeout.pid, timeout.tid)
+            } else {
+                Ok(())
+            }
+            .and_then(|_| {
+                ss.set_thread_result(
+                    timeout.pid,
+                    timeout.tid,
+                    xous_kernel::Result::Error(xous_kernel::Error::Timeout),
+                )
+            });
+            if let Err(e) = woken {
+                println!(
+                    "KERNEL({}): couldn't wake timed out thread {}: {:?}",
+                    timeout.pid, timeout.tid, e
+                );
+            }
+        }
+    })
+}
+
+fn send_message(
+    pid: PID,
+    thread: TID,
+    cid: CID,
+    message: Message,
+    timeout: Option<usize>,
+) -> SysCallResult {
     SystemServices::with_mut(|ss| {
         let sidx = ss
             .sidx_from_cid(cid)
             .ok_or(xous_kernel::Error::ServerNotFound)?;
         // ::debug_here::debug_here!();
 
-        let server_pid = ss
+        let server = ss
             .server_from_sidx(sidx)
-            .expect("server couldn't be located")
-            .pid;
+            .expect("server couldn't be located");
+        let server_pid = server.pid;
+
+        // Refuse anything the client hasn't been given the right to send
+        // before any memory changes hands. Only the kernel may ask a server
+        // to approve a connection or tell it that a client has gone away.
+        if !server.rights_for(pid).allows(&message)
+            || message.id() == CONNECT_REQUEST_ID
+            || message.id() == CLIENT_DISCONNECTED_ID
+        {
+            return Err(xous_kernel::Error::AccessDenied);
+        }
+
+        // Only blocking messages wait on the server, so only they can time
+        // out. Make sure the timeout can be tracked before any memory moves.
+        let timeout = timeout.filter(|_| message.is_blocking());
+        if timeout.is_some() && !ss.can_add_timeout() {
+            return Err(xous_kernel::Error::OutOfMemory);
+        }
 
         // Remember the address the message came from, in case we need to
         // return it after the borrow is through.
@@ -66,9 +175,7 @@ fn send_message(pid: PID, thread: TID, cid: CID, message: Message) -> SysCallRes
         };
 
         // Translate memory messages from the client process to the server
-        // process. Additionally, determine whether the call is blocking. If
-        // so, switch to the server context right away.
-        let blocking = message.is_blocking();
+        // process.
         let message = match message {
             Message::Scalar(_) | Message::BlockingScalar(_) => message,
             Message::Move(msg) => {
@@ -125,125 +232,247 @@ fn send_message(pid: PID, thread: TID, cid: CID, message: Message) -> SysCallRes
             }
         };
 
-        // If the server has an available context to receive the message,
-        // transfer it right away.
-        if let Some(server_tid) = ss
-            .server_from_sidx_mut(sidx)
-            .expect("server couldn't be located")
-            .take_available_thread()
-        {
-            klog!(
-                "there are contexts available to handle this message -- marking PID {} as Ready",
-                server_pid
-            );
-            let sender_idx = if message.is_blocking() {
-                ss.remember_server_message(sidx, pid, thread, &message, client_address)
-                    .map_err(|e| {
-                        ss.server_from_sidx_mut(sidx)
-                            .expect("server couldn't be located")
-                            .return_available_thread(thread);
-                        e
-                    })?
-            } else {
-                0
-            };
-            let sender = SenderID::new(sidx, sender_idx, Some(pid));
+        deliver_message(ss, pid, thread, sidx, message, client_address, timeout)
+    })
+}
+
+/// Connect `target_pid` to the server `sid` on behalf of `pid`:`tid`. If the
+/// server needs to approve the connection, ask it and block until it answers.
+fn connect(
+    pid: PID,
+    tid: TID,
+    target_pid: PID,
+    sid: SID,
+    rights: Option<ConnectionRights>,
+) -> SysCallResult {
+    let result = SystemServices::with_mut(|ss| {
+        // Refuse rights that can't be granted before bothering the server.
+        if let Some(rights) = rights {
+            ss.check_connection_rights(pid, target_pid, sid, &rights)?;
+        }
+        let sidx = match ss.connect_needs_approval(pid, target_pid, sid)? {
+            Some(sidx) => sidx,
+            None => {
+                return ss
+                    .connect_with_rights(pid, target_pid, sid, rights)
+                    .map(xous_kernel::Result::ConnectionID)
+            }
+        };
+        ss.add_connect_request(pid, tid, target_pid, sidx, rights)?;
+        let request = Message::BlockingScalar(ScalarMessage {
+            id: CONNECT_REQUEST_ID,
+            arg1: target_pid.get() as usize,
+            arg2: 0,
+            arg3: 0,
+            arg4: 0,
+        });
+        deliver_message(ss, pid, tid, sidx, request, None, None).map_err(|e| {
+            ss.take_connect_request(pid, tid);
+            e
+        })
+    });
+    match result {
+        Ok(o) => Ok(o),
+        Err(xous_kernel::Error::ServerNotFound) => retry_syscall(pid, tid),
+        Err(e) => Err(e),
+    }
+}
+
+/// Hand `message` to the server at `sidx`, either by waking one of its threads
+/// that is waiting for a message or by adding it to the server's queue. Memory
+/// attached to the message must already have been moved into the server.
+fn deliver_message(
+    ss: &mut SystemServices,
+    pid: PID,
+    thread: TID,
+    sidx: usize,
+    message: Message,
+    client_address: Option<MemoryAddress>,
+    timeout: Option<usize>,
+) -> SysCallResult {
+    let server_pid = ss
+        .server_from_sidx(sidx)
+        .ok_or(xous_kernel::Error::ServerNotFound)?
+        .pid;
+    let blocking = message.is_blocking();
+
+    // If the server has an available context to receive the message,
+    // transfer it right away.
+    if let Some(server_tid) = ss
+        .server_from_sidx_mut(sidx)
+        .expect("server couldn't be located")
+        .take_available_thread()
+    {
+        klog!(
+            "there are contexts available to handle this message -- marking PID {} as Ready",
+            server_pid
+        );
+        ss.remove_timeout(server_pid, server_tid);
+        let sender_idx = if message.is_blocking() {
+            ss.remember_server_message(sidx, pid, thread, &message, client_address)
+                .map_err(|e| {
+                    ss.server_from_sidx_mut(sidx)
+                        .expect("server couldn't be located")
+                        .return_available_thread(thread);
+                    e
+                })?
+        } else {
+            0
+        };
+        if let Some(timeout_ms) = timeout {
+            ss.add_timeout(pid, thread, TimeoutKind::Send(sidx, sender_idx), timeout_ms)?;
+        }
+        let sender = SenderID::new(sidx, sender_idx, Some(pid));
+        klog!(
+            "server connection data: sidx: {}, idx: {}, server pid: {}",
+            sidx,
+            sender_idx,
+            server_pid
+        );
+        let envelope = MessageEnvelope {
+            sender: sender.into(),
+            body: message,
+        };
+
+        // Mark the server's context as "Ready". If this fails, return the context
+        // to the blocking list.
+        ss.ready_thread(server_pid, server_tid).map_err(|e| {
+            ss.server_from_sidx_mut(sidx)
+                .expect("server couldn't be located")
+                .return_available_thread(thread);
+            e
+        })?;
+
+        if blocking && cfg!(baremetal) {
+            // println!("Activating Server context and switching away from Client");
+            ss.activate_process_thread(thread, server_pid, server_tid, !blocking)
+                .map(|_| Ok(xous_kernel::Result::Message(envelope)))
+                .unwrap_or(Err(xous_kernel::Error::ProcessNotFound))
+        } else if blocking && !cfg!(baremetal) {
+            // println!("Blocking client, since it sent a blocking message");
+            ss.switch_from_thread(pid, thread)?;
+            ss.switch_to_thread(server_pid, Some(server_tid))?;
+            ss.set_thread_result(
+                server_pid,
+                server_tid,
+                xous_kernel::Result::Message(envelope),
+            )
+            .map(|_| xous_kernel::Result::BlockedProcess)
+        } else if cfg!(baremetal) {
+            // println!("Setting the return value of the Server and returning to Client");
+            ss.set_thread_result(
+                server_pid,
+                server_tid,
+                xous_kernel::Result::Message(envelope),
+            )
+            .map(|_| xous_kernel::Result::Ok)
+        } else {
             klog!(
-                "server connection data: sidx: {}, idx: {}, server pid: {}",
-                sidx,
-                sender_idx,
-                server_pid
+                "setting the return value of the Server to {:?} and returning to Client",
+                envelope
             );
-            let envelope = MessageEnvelope {
-                sender: sender.into(),
-                body: message,
-            };
+            // "Switch to" the server PID when not running on bare metal. This ensures
+            // that it's "Running".
+            ss.switch_to_thread(server_pid, Some(server_tid))?;
+            ss.set_thread_result(
+                server_pid,
+                server_tid,
+                xous_kernel::Result::Message(envelope),
+            )
+            .map(|_| xous_kernel::Result::Ok)
+        }
+    } else {
+        klog!(
+            "no threads available in PID {} to handle this message, so blocking",
+            server_pid
+        );
+        // Add this message to the queue.  If the queue is full, this
+        // returns an error.
+        let idx = ss.queue_server_message(sidx, pid, thread, message, client_address)?;
+        if let Some(timeout_ms) = timeout {
+            ss.add_timeout(pid, thread, TimeoutKind::Send(sidx, idx), timeout_ms)?;
+        }
 
-            // Mark the server's context as "Ready". If this fails, return the context
-            // to the blocking list.
-            ss.ready_thread(server_pid, server_tid).map_err(|e| {
-                ss.server_from_sidx_mut(sidx)
-                    .expect("server couldn't be located")
-                    .return_available_thread(thread);
-                e
-            })?;
+        // Let whoever is watching this server know that a message is waiting,
+        // as long as the watcher may still set events on its target.
+        if let Some((owner, target, events)) = ss.server_from_sidx(sidx).and_then(|server| {
+            server
+                .message_events()
+                .map(|(target, events)| (server.pid, target, events))
+        }) {
+            if ss
+                .server_from_sidx(target)
+                .map_or(false, |server| server.rights_for(owner).allows_events())
+            {
+                signal_server_events(ss, target, events).ok();
+            }
+        }
 
-            if blocking && cfg!(baremetal) {
-                // println!("Activating Server context and switching away from Client");
-                ss.activate_process_thread(thread, server_pid, server_tid, !blocking)
-                    .map(|_| Ok(xous_kernel::Result::Message(envelope)))
+        // Park this context if it's blocking.  This is roughly
+        // equivalent to a "Yield".
+        if blocking {
+            if cfg!(baremetal) {
+                // println!("Returning to parent");
+                let process = ss.get_process(pid).expect("Can't get current process");
+                let ppid = process.ppid;
+                unsafe { SWITCHTO_CALLER = None };
+                ss.activate_process_thread(thread, ppid, 0, !blocking)
+                    .map(|_| Ok(xous_kernel::Result::ResumeProcess))
                     .unwrap_or(Err(xous_kernel::Error::ProcessNotFound))
-            } else if blocking && !cfg!(baremetal) {
-                // println!("Blocking client, since it sent a blocking message");
-                ss.switch_from_thread(pid, thread)?;
-                ss.switch_to_thread(server_pid, Some(server_tid))?;
-                ss.set_thread_result(
-                    server_pid,
-                    server_tid,
-                    xous_kernel::Result::Message(envelope),
-                )
-                .map(|_| xous_kernel::Result::BlockedProcess)
-            } else if cfg!(baremetal) {
-                // println!("Setting the return value of the Server and returning to Client");
-                ss.set_thread_result(
-                    server_pid,
-                    server_tid,
-                    xous_kernel::Result::Message(envelope),
-                )
-                .map(|_| xous_kernel::Result::Ok)
             } else {
-                klog!(
-                    "setting the return value of the Server to {:?} and returning to Client",
-                    envelope
-                );
-                // "Switch to" the server PID when not running on bare metal. This ensures
-                // that it's "Running".
-                ss.switch_to_thread(server_pid, Some(server_tid))?;
-                ss.set_thread_result(
-                    server_pid,
-                    server_tid,
-                    xous_kernel::Result::Message(envelope),
-                )
-                .map(|_| xous_kernel::Result::Ok)
+                ss.switch_from_thread(pid, thread)?;
+                Ok(xous_kernel::Result::BlockedProcess)
             }
         } else {
-            klog!(
-                "no threads available in PID {} to handle this message, so blocking",
-                server_pid
-            );
-            // Add this message to the queue.  If the queue is full, this
-            // returns an error.
-            ss.queue_server_message(sidx, pid, thread, message, client_address)?;
+            // println!("Returning to Client with Ok result");
+            Ok(xous_kernel::Result::Ok)
+        }
+    }
+}
 
-            // Park this context if it's blocking.  This is roughly
-            // equivalent to a "Yield".
-            if blocking {
-                if cfg!(baremetal) {
-                    // println!("Returning to parent");
-                    let process = ss.get_process(pid).expect("Can't get current process");
-                    let ppid = process.ppid;
-                    unsafe { SWITCHTO_CALLER = None };
-                    ss.activate_process_thread(thread, ppid, 0, !blocking)
-                        .map(|_| Ok(xous_kernel::Result::ResumeProcess))
-                        .unwrap_or(Err(xous_kernel::Error::ProcessNotFound))
-                } else {
-                    ss.switch_from_thread(pid, thread)?;
-                    Ok(xous_kernel::Result::BlockedProcess)
-                }
+/// Tell every server that `pid` is connected to, other than its own, that
+/// `pid` is about to terminate, if the server has asked to be told. This is
+/// sent as `pid`:`tid`, so it arrives after any messages the process has
+/// already queued. A server whose queue is full doesn't find out, but the
+/// next notice it gets says how many it missed.
+fn notify_disconnected(ss: &mut SystemServices, pid: PID, tid: TID) {
+    for sidx in 0..ss.server_count() {
+        let dropped = match ss.server_from_sidx(sidx) {
+            Some(server) if server.pid != pid && server.notifies_disconnects => {
+                server.dropped_disconnects
+            }
+            _ => continue,
+        };
+        if !ss.is_connected(pid, sidx).unwrap_or(false) {
+            continue;
+        }
+        let notice = Message::Scalar(ScalarMessage {
+            id: CLIENT_DISCONNECTED_ID,
+            arg1: pid.get() as usize,
+            arg2: dropped,
+            arg3: 0,
+            arg4: 0,
+        });
+        let delivered = deliver_message(ss, pid, tid, sidx, notice, None, None).is_ok();
+        if let Some(server) = ss.server_from_sidx_mut(sidx) {
+            if delivered {
+                server.dropped_disconnects = 0;
             } else {
-                // println!("Returning to Client with Ok result");
-                Ok(xous_kernel::Result::Ok)
+                klog!("server {} missed that PID {} exited", sidx, pid);
+                server.dropped_disconnects += 1;
             }
         }
-    })
+    }
 }
 
+/// Give lent memory back to the client, which wakes up with `client_result`.
 fn return_memory(
     pid: PID,
     tid: TID,
     in_irq: bool,
     sender: MessageSender,
     buf: MemoryRange,
+    client_result: xous_kernel::Result,
 ) -> SysCallResult {
     SystemServices::with_mut(|ss| {
         let sender = SenderID::from(sender);
@@ -263,7 +492,10 @@ fn return_memory(
                 server_addr,
                 client_addr,
                 len,
-            ) => (client_pid, client_ctx, server_addr, client_addr, len),
+            ) => {
+                ss.remove_timeout(client_pid, client_ctx);
+                (client_pid, client_ctx, server_addr, client_addr, len)
+            }
             WaitingMessage::MovedMemory => {
                 return Ok(xous_kernel::Result::Ok);
             }
@@ -277,7 +509,7 @@ fn return_memory(
                         return Err(xous_kernel::Error::BadAlignment);
                     }
                     for addr in (virt..(virt + size)).step_by(PAGE_SIZE) {
-                        if let Err(e) = mm.unmap_page(addr as *mut usize) {
+                        if let Err(e) = mm.forget_page(addr as *mut usize) {
                             if result.is_ok() {
                                 result = Err(e);
                             }
@@ -290,6 +522,27 @@ fn return_memory(
                 println!("WARNING: Tried to wait on a message that was a scalar");
                 return Err(xous_kernel::Error::InternalError);
             }
+            // The sender gave up waiting, so there's nobody to respond to.
+            WaitingMessage::Cancelled => {
+                return Ok(xous_kernel::Result::Ok);
+            }
+            // The sender gave up waiting, but the memory it lent still goes
+            // back. In a hosted environment the server was only ever given a
+            // copy, so the sender already has it.
+            WaitingMessage::CancelledMemory(client_pid, server_addr, client_addr, len) => {
+                if cfg!(baremetal) {
+                    ss.return_memory(
+                        server_addr.get() as _,
+                        tid,
+                        client_pid,
+                        0,
+                        client_addr.get() as _,
+                        len.get(),
+                        buf,
+                    )?;
+                }
+                return Ok(xous_kernel::Result::Ok);
+            }
             WaitingMessage::None => {
                 println!("WARNING: Tried to wait on a message that didn't exist");
                 return Err(xous_kernel::Error::ProcessNotFound);
@@ -323,7 +576,7 @@ fn return_memory(
             // print!(" [waking up PID {}:{}]", client_pid, client_tid);
             ss.ready_thread(client_pid, client_tid)?;
             ss.switch_to_thread(client_pid, Some(client_tid))?;
-            ss.set_thread_result(client_pid, client_tid, xous_kernel::Result::Ok)?;
+            ss.set_thread_result(client_pid, client_tid, client_result)?;
 
             // Return success to the server
             Ok(xous_kernel::Result::Ok)
@@ -336,7 +589,7 @@ fn return_memory(
             // Switch to the client
             ss.ready_thread(client_pid, client_tid)?;
             ss.switch_to_thread(client_pid, Some(client_tid))?;
-            Ok(xous_kernel::Result::Ok)
+            Ok(client_result)
         }
     })
 }
@@ -359,14 +612,20 @@ fn return_scalar(
         }
         let result = server.take_waiting_message(sender.idx, None)?;
         let (client_pid, client_tid) = match result {
-            WaitingMessage::ScalarMessage(pid, tid) => (pid, tid),
+            WaitingMessage::ScalarMessage(pid, tid) => {
+                ss.remove_timeout(pid, tid);
+                (pid, tid)
+            }
+            // The sender gave up waiting, so there's nobody to respond to.
+            WaitingMessage::Cancelled => return Ok(xous_kernel::Result::Ok),
             WaitingMessage::ForgetMemory(_) => {
                 println!(
                     "WARNING: Tried to wait on a scalar message that was actually forgettingmemory"
                 );
                 return Err(xous_kernel::Error::ProcessNotFound);
             }
-            WaitingMessage::BorrowedMemory(_, _, _, _, _) => {
+            WaitingMessage::BorrowedMemory(_, _, _, _, _)
+            | WaitingMessage::CancelledMemory(_, _, _, _) => {
                 println!(
                     "WARNING: Tried to wait on a scalar message that was actually borrowed memory"
                 );
@@ -384,6 +643,12 @@ fn return_scalar(
             }
         };
 
+        // A reply to a connection request decides whether the connection is made.
+        let response = match ss.take_connect_request(client_pid, client_tid) {
+            Some(request) => ss.complete_connect_request(request, arg != 0),
+            None => xous_kernel::Result::Scalar1(arg),
+        };
+
         if !cfg!(baremetal) || in_irq {
             // In a hosted environment, `switch_to_thread()` doesn't continue
             // execution from the new thread. Instead it continues in the old
@@ -391,7 +656,7 @@ fn return_scalar(
             // return to the server.
             // In a baremetal environment, the opposite is true -- we instruct
             // the server to resume and return to the client.
-            ss.set_thread_result(client_pid, client_tid, xous_kernel::Result::Scalar1(arg))?;
+            ss.set_thread_result(client_pid, client_tid, response)?;
             if in_irq {
                 ss.ready_thread(client_pid, client_tid)?;
             }
@@ -405,7 +670,7 @@ fn return_scalar(
             // Switch to the client
             ss.ready_thread(client_pid, client_tid)?;
             ss.switch_to_thread(client_pid, Some(client_tid))?;
-            Ok(xous_kernel::Result::Scalar1(arg))
+            Ok(response)
         }
     })
 }
@@ -430,12 +695,18 @@ fn return_scalar2(
         }
         let result = server.take_waiting_message(sender.idx, None)?;
         let (client_pid, client_tid) = match result {
-            WaitingMessage::ScalarMessage(pid, tid) => (pid, tid),
+            WaitingMessage::ScalarMessage(pid, tid) => {
+                ss.remove_timeout(pid, tid);
+                (pid, tid)
+            }
+            // The sender gave up waiting, so there's nobody to respond to.
+            WaitingMessage::Cancelled => return Ok(xous_kernel::Result::Ok),
             WaitingMessage::ForgetMemory(_) => {
                 println!("WARNING: Tried to wait on a scalar message that was actually forgetting memory");
                 return Err(xous_kernel::Error::ProcessNotFound);
             }
-            WaitingMessage::BorrowedMemory(_, _, _, _, _) => {
+            WaitingMessage::BorrowedMemory(_, _, _, _, _)
+            | WaitingMessage::CancelledMemory(_, _, _, _) => {
                 println!(
                     "WARNING: Tried to wait on a scalar message that was actually borrowed memory"
                 );
@@ -453,6 +724,12 @@ fn return_scalar2(
             }
         };
 
+        // A reply to a connection request decides whether the connection is made.
+        let response = match ss.take_connect_request(client_pid, client_tid) {
+            Some(request) => ss.complete_connect_request(request, arg1 != 0),
+            None => xous_kernel::Result::Scalar2(arg1, arg2),
+        };
+
         if !cfg!(baremetal) || in_irq {
             // In a hosted environment, `switch_to_thread()` doesn't continue
             // execution from the new thread. Instead it continues in the old
@@ -460,11 +737,7 @@ fn return_scalar2(
             // return to the server.
             // In a baremetal environment, the opposite is true -- we instruct
             // the server to resume and return to the client.
-            ss.set_thread_result(
-                client_pid,
-                client_tid,
-                xous_kernel::Result::Scalar2(arg1, arg2),
-            )?;
+            ss.set_thread_result(client_pid, client_tid, response)?;
             if in_irq {
                 ss.ready_thread(client_pid, client_tid)?;
             }
@@ -478,12 +751,18 @@ fn return_scalar2(
             // Switch to the client
             ss.ready_thread(client_pid, client_tid)?;
             ss.switch_to_thread(client_pid, Some(client_tid))?;
-            Ok(xous_kernel::Result::Scalar2(arg1, arg2))
+            Ok(response)
         }
     })
 }
 
-fn receive_message(pid: PID, tid: TID, sid: SID, blocking: ExecutionType) -> SysCallResult {
+fn receive_message(
+    pid: PID,
+    tid: TID,
+    sid: SID,
+    blocking: ExecutionType,
+    timeout: Option<usize>,
+) -> SysCallResult {
     SystemServices::with_mut(|ss| {
         assert!(
             ss.thread_is_running(pid, tid),
@@ -493,6 +772,9 @@ fn receive_message(pid: PID, tid: TID, sid: SID, blocking: ExecutionType) -> Sys
         let sidx = ss
             .sidx_from_sid(sid, pid)
             .ok_or(xous_kernel::Error::ServerNotFound)?;
+        if timeout.is_some() && !ss.can_add_timeout() {
+            return Err(xous_kernel::Error::OutOfMemory);
+        }
         let server = ss
             .server_from_sidx_mut(sidx)
             .ok_or(xous_kernel::Error::ServerNotFound)?;
@@ -523,6 +805,9 @@ fn receive_message(pid: PID, tid: TID, sid: SID, blocking: ExecutionType) -> Sys
             tid
         );
         server.park_thread(tid);
+        if let Some(timeout_ms) = timeout {
+            ss.add_timeout(pid, tid, TimeoutKind::Receive(sidx), timeout_ms)?;
+        }
 
         // For baremetal targets, switch away from this process.
         if cfg!(baremetal) {
@@ -542,6 +827,288 @@ fn receive_message(pid: PID, tid: TID, sid: SID, blocking: ExecutionType) -> Sys
     })
 }
 
+/// Set `events` on the server that `cid` is connected to, handing them straight
+/// to one of its threads if any are waiting.
+fn signal_events(pid: PID, cid: CID, events: usize) -> SysCallResult {
+    SystemServices::with_mut(|ss| {
+        let sidx = ss
+            .sidx_from_cid(cid)
+            .ok_or(xous_kernel::Error::ServerNotFound)?;
+        let server = ss
+            .server_from_sidx(sidx)
+            .ok_or(xous_kernel::Error::ServerNotFound)?;
+        if !server.rights_for(pid).allows_events() {
+            return Err(xous_kernel::Error::AccessDenied);
+        }
+        signal_server_events(ss, sidx, events).map(|_| xous_kernel::Result::Ok)
+    })
+}
+
+/// Set `events` on the server at `sidx`, handing them straight to one of its
+/// threads if any are waiting.
+fn signal_server_events(
+    ss: &mut SystemServices,
+    sidx: usize,
+    events: usize,
+) -> core::result::Result<(), xous_kernel::Error> {
+    let server = ss
+        .server_from_sidx_mut(sidx)
+        .ok_or(xous_kernel::Error::ServerNotFound)?;
+    let server_pid = server.pid;
+    server.signal_events(events);
+
+    // Nothing is delivered until at least one bit is set.
+    if events == 0 {
+        return Ok(());
+    }
+    let server_tid = match server.take_event_thread() {
+        Some(tid) => tid,
+        None => return Ok(()),
+    };
+    let events = server.take_events();
+
+    if cfg!(baremetal) {
+        ss.ready_thread(server_pid, server_tid)?;
+    }
+    ss.set_thread_result(server_pid, server_tid, xous_kernel::Result::Scalar1(events))
+}
+
+/// Set `events` on the server that `cid` is connected to whenever a message is
+/// queued on `sid`, or stop if `events` is zero.
+fn signal_on_message(pid: PID, sid: SID, cid: CID, events: usize) -> SysCallResult {
+    SystemServices::with_mut(|ss| {
+        let sidx = ss
+            .sidx_from_sid(sid, pid)
+            .ok_or(xous_kernel::Error::ServerNotFound)?;
+        let target = ss
+            .sidx_from_cid(cid)
+            .ok_or(xous_kernel::Error::ServerNotFound)?;
+        if !ss
+            .server_from_sidx(target)
+            .ok_or(xous_kernel::Error::ServerNotFound)?
+            .rights_for(pid)
+            .allows_events()
+        {
+            return Err(xous_kernel::Error::AccessDenied);
+        }
+        let server = ss
+            .server_from_sidx_mut(sidx)
+            .ok_or(xous_kernel::Error::ServerNotFound)?;
+        server.set_message_events(target, events);
+
+        // Messages that are already waiting would otherwise go unnoticed.
+        if events != 0 && server.has_messages() {
+            signal_server_events(ss, target, events)?;
+        }
+        Ok(xous_kernel::Result::Ok)
+    })
+}
+
+/// Collect the events that have been signalled on the server `sid`, blocking
+/// until at least one of them is set if `blocking` allows it.
+fn wait_events(pid: PID, tid: TID, sid: SID, blocking: ExecutionType) -> SysCallResult {
+    SystemServices::with_mut(|ss| {
+        let sidx = ss
+            .sidx_from_sid(sid, pid)
+            .ok_or(xous_kernel::Error::ServerNotFound)?;
+        let server = ss
+            .server_from_sidx_mut(sidx)
+            .ok_or(xous_kernel::Error::ServerNotFound)?;
+
+        let events = server.take_events();
+        if events != 0 || blocking == ExecutionType::NonBlocking {
+            return Ok(xous_kernel::Result::Scalar1(events));
+        }
+
+        // Nothing has been signalled yet, so park this thread. Whoever sets
+        // the next event will fill in our return value.
+        server.park_event_thread(tid);
+        if cfg!(baremetal) {
+            unsafe { SWITCHTO_CALLER = None };
+            let ppid = ss.get_process(pid).expect("Can't get current process").ppid;
+            ss.activate_process_thread(tid, ppid, 0, false)
+                .map(|_| Ok(xous_kernel::Result::ResumeProcess))
+                .unwrap_or(Err(xous_kernel::Error::ProcessNotFound))
+        } else {
+            ss.switch_from_thread(pid, tid)
+                .map(|_| xous_kernel::Result::BlockedProcess)
+        }
+    })
+}
+
+/// Block until the futex at `addr` is woken, unless it no longer holds
+/// `expected`.
+fn futex_wait(pid: PID, tid: TID, addr: MemoryAddress, expected: usize) -> SysCallResult {
+    if addr.get() & (mem::size_of::<usize>() - 1) != 0 {
+        return Err(xous_kernel::Error::BadAlignment);
+    }
+
+    // A hosted kernel can't see into the process, so the library makes this
+    // check itself before the call is sent.
+    if cfg!(baremetal) && arch::mem::read_user_word(addr.get())? != expected {
+        return Ok(xous_kernel::Result::Ok);
+    }
+
+    SystemServices::with_mut(|ss| {
+        ss.add_futex_waiter(pid, tid, addr.get())?;
+        if cfg!(baremetal) {
+            unsafe { SWITCHTO_CALLER = None };
+            let ppid = ss.get_process(pid).expect("Can't get current process").ppid;
+            ss.activate_process_thread(tid, ppid, 0, false)
+                .map(|_| Ok(xous_kernel::Result::ResumeProcess))
+                .unwrap_or(Err(xous_kernel::Error::ProcessNotFound))
+        } else {
+            ss.switch_from_thread(pid, tid)
+                .map(|_| xous_kernel::Result::BlockedProcess)
+        }
+    })
+}
+
+/// Wake up to `count` threads in this process that are waiting on the futex at
+/// `addr`, returning how many were woken.
+fn futex_wake(pid: PID, addr: MemoryAddress, count: usize) -> SysCallResult {
+    SystemServices::with_mut(|ss| {
+        let mut woken = 0;
+        while woken < count {
+            let tid = match ss.take_futex_waiter(pid, addr.get()) {
+                Some(tid) => tid,
+                None => break,
+            };
+            if cfg!(baremetal) {
+                ss.ready_thread(pid, tid)?;
+            }
+            ss.set_thread_result(pid, tid, xous_kernel::Result::Ok)?;
+            woken += 1;
+        }
+        Ok(xous_kernel::Result::Scalar1(woken))
+    })
+}
+
+/// End the calling thread with `code`. If another thread is already waiting to
+/// join it, that thread gets the code and this thread's slot is freed right
+/// away. Otherwise the code is kept until somebody joins.
+fn exit_thread(pid: PID, tid: TID, code: usize) -> SysCallResult {
+    SystemServices::with_mut(|ss| {
+        let mut arch_process = ArchProcess::current();
+        arch_process.exit_thread(tid, code);
+        if let Some(stack) = ss.take_detached_thread(pid, tid) {
+            arch_process.take_exit_code(tid);
+            free_thread_stack(stack);
+        } else if let Some(joiner) = ss.take_thread_joiner(pid, tid) {
+            arch_process.take_exit_code(tid);
+            if cfg!(baremetal) {
+                ss.ready_thread(pid, joiner)?;
+            }
+            ss.set_thread_result(pid, joiner, xous_kernel::Result::Scalar1(code))?;
+        }
+
+        // The thread never runs again, so it never gets a response.
+        if cfg!(baremetal) {
+            unsafe { SWITCHTO_CALLER = None };
+            let ppid = ss.get_process(pid).expect("Can't get current process").ppid;
+            ss.activate_process_thread(tid, ppid, 0, false)
+                .map(|_| Ok(xous_kernel::Result::ResumeProcess))
+                .unwrap_or(Err(xous_kernel::Error::ProcessNotFound))
+        } else {
+            Ok(xous_kernel::Result::BlockedProcess)
+        }
+    })
+}
+
+/// Wait for `target` to exit, returning its exit code and freeing its slot.
+fn join_thread(pid: PID, tid: TID, target: TID) -> SysCallResult {
+    SystemServices::with_mut(|ss| {
+        let mut arch_process = ArchProcess::current();
+        if target == tid || !arch_process.thread_exists(target) || ss.has_thread_joiner(pid, target)
+        {
+            return Err(xous_kernel::Error::InvalidThread);
+        }
+        if let Some(code) = arch_process.take_exit_code(target) {
+            return Ok(xous_kernel::Result::Scalar1(code));
+        }
+
+        // `exit_thread()` will fill in our return value.
+        ss.add_thread_joiner(pid, tid, target)?;
+        if cfg!(baremetal) {
+            unsafe { SWITCHTO_CALLER = None };
+            let ppid = ss.get_process(pid).expect("Can't get current process").ppid;
+            ss.activate_process_thread(tid, ppid, 0, false)
+                .map(|_| Ok(xous_kernel::Result::ResumeProcess))
+                .unwrap_or(Err(xous_kernel::Error::ProcessNotFound))
+        } else {
+            ss.switch_from_thread(pid, tid)
+                .map(|_| xous_kernel::Result::BlockedProcess)
+        }
+    })
+}
+
+/// Let `target` be freed as soon as it exits, rather than waiting to be joined.
+fn detach_thread(pid: PID, target: TID, stack: Option<MemoryRange>) -> SysCallResult {
+    SystemServices::with_mut(|ss| {
+        let mut arch_process = ArchProcess::current();
+        if !arch_process.thread_exists(target)
+            || ss.has_thread_joiner(pid, target)
+            || ss.is_thread_detached(pid, target)
+        {
+            return Err(xous_kernel::Error::InvalidThread);
+        }
+        if arch_process.take_exit_code(target).is_some() {
+            free_thread_stack(stack);
+            return Ok(xous_kernel::Result::Ok);
+        }
+        ss.add_detached_thread(pid, target, stack)
+            .map(|_| xous_kernel::Result::Ok)
+    })
+}
+
+/// Unmap the stack of a detached thread that has exited, from the current
+/// process. Stacks only exist on hardware, since hosted threads bring their own.
+fn free_thread_stack(stack: Option<MemoryRange>) {
+    let stack = match stack {
+        Some(stack) => stack,
+        None => return,
+    };
+    MemoryManager::with_mut(|mm| {
+        let virt = stack.as_ptr() as usize;
+        for addr in (virt..(virt + stack.len())).step_by(PAGE_SIZE) {
+            if let Err(e) = mm.unmap_page(addr as *mut usize) {
+                println!(
+                    "KERNEL: couldn't free thread stack page {:08x}: {:?}",
+                    addr, e
+                );
+            }
+        }
+    })
+}
+
+fn wait_process(pid: PID, tid: TID, target: PID) -> SysCallResult {
+    SystemServices::with_mut(|ss| {
+        let child = ss.get_process(target)?;
+        if child.free() || ss.has_process_waiter(target) {
+            return Err(xous_kernel::Error::ProcessNotFound);
+        }
+        if target == pid || child.parent != pid {
+            return Err(xous_kernel::Error::ProcessNotChild);
+        }
+        if let Some(code) = ss.reap_process(target)? {
+            return Ok(xous_kernel::Result::Scalar1(code));
+        }
+
+        // `terminate_process()` will fill in our return value.
+        ss.add_process_waiter(pid, tid, target)?;
+        if cfg!(baremetal) {
+            unsafe { SWITCHTO_CALLER = None };
+            let ppid = ss.get_process(pid).expect("Can't get current process").ppid;
+            ss.activate_process_thread(tid, ppid, 0, false)
+                .map(|_| Ok(xous_kernel::Result::ResumeProcess))
+                .unwrap_or(Err(xous_kernel::Error::ProcessNotFound))
+        } else {
+            ss.switch_from_thread(pid, tid)
+                .map(|_| xous_kernel::Result::BlockedProcess)
+        }
+    })
+}
+
 pub fn handle(pid: PID, tid: TID, in_irq: bool, call: SysCall) -> SysCallResult {
     #[cfg(feature = "debug-print")]
     print!("KERNEL({}:{}): Syscall {:x?}", pid, tid, call);
@@ -638,6 +1205,10 @@ pub fn handle_inner(pid: PID, tid: TID, in_irq: bool, call: SysCall) -> SysCallR
             if virt & 0xfff != 0 {
                 return Err(xous_kernel::Error::BadAlignment);
             }
+            // Shared memory is given up with `UnmapSharedMemory` instead
+            if mm.is_shared(pid, virt, size) {
+                return Err(xous_kernel::Error::MemoryInUse);
+            }
             for addr in (virt..(virt + size)).step_by(PAGE_SIZE) {
                 if let Err(e) = mm.unmap_page(addr as *mut usize) {
                     if result.is_ok() {
@@ -668,6 +1239,37 @@ pub fn handle_inner(pid: PID, tid: TID, in_irq: bool, call: SysCall) -> SysCallR
                 ))
             })
         }
+        SysCall::CreateSharedMemory(range, flags) => MemoryManager::with_mut(|mm| {
+            mm.create_shared(pid, range, flags)
+                .map(xous_kernel::Result::Scalar1)
+        }),
+        SysCall::GrantSharedMemory(id, target, flags) => {
+            // Anything granted to a process that has terminated would be
+            // inherited by the next process to get its PID.
+            SystemServices::with(|ss| match ss.get_process(target) {
+                Ok(process) if !process.free() && !process.zombie() => Ok(()),
+                _ => Err(xous_kernel::Error::ProcessNotFound),
+            })?;
+            MemoryManager::with_mut(|mm| {
+                mm.grant_shared(pid, id, target, flags)
+                    .map(|_| xous_kernel::Result::Ok)
+            })
+        }
+        SysCall::MapSharedMemory(id) => MemoryManager::with_mut(|mm| {
+            mm.map_shared(pid, id).map(xous_kernel::Result::MemoryRange)
+        }),
+        SysCall::UnmapSharedMemory(id) => MemoryManager::with_mut(|mm| {
+            match mm.unmap_shared(pid, id)? {
+                // A hosted region was allocated by a process, so whoever
+                // drops the last reference frees it.
+                Some(range) if cfg!(not(baremetal)) => Ok(xous_kernel::Result::MemoryRange(range)),
+                _ => Ok(xous_kernel::Result::Ok),
+            }
+        }),
+        SysCall::GetMemoryStats(target) => SystemServices::with(|ss| {
+            ss.memory_stats(target)
+                .map(xous_kernel::Result::MemoryStats)
+        }),
         SysCall::DecreaseHeap(delta) => {
             if delta & 0xfff != 0 {
                 return Err(xous_kernel::Error::BadAlignment);
@@ -720,8 +1322,15 @@ pub fn handle_inner(pid: PID, tid: TID, in_irq: bool, call: SysCall) -> SysCallR
             };
             Ok(xous_kernel::Result::ResumeProcess)
         }
-        SysCall::ReceiveMessage(sid) => receive_message(pid, tid, sid, ExecutionType::Blocking),
-        SysCall::TryReceiveMessage(sid) => receive_message(pid, tid, sid, ExecutionType::NonBlocking),
+        SysCall::ReceiveMessage(sid) => {
+            receive_message(pid, tid, sid, ExecutionType::Blocking, None)
+        }
+        SysCall::TryReceiveMessage(sid) => {
+            receive_message(pid, tid, sid, ExecutionType::NonBlocking, None)
+        }
+        SysCall::ReceiveMessageTimeout(sid, timeout_ms) => {
+            receive_message(pid, tid, sid, ExecutionType::Blocking, Some(timeout_ms))
+        }
         SysCall::WaitEvent => SystemServices::with_mut(|ss| {
             let process = ss.get_process(pid).expect("Can't get current process");
             let ppid = process.ppid;
@@ -731,6 +1340,17 @@ pub fn handle_inner(pid: PID, tid: TID, in_irq: bool, call: SysCall) -> SysCallR
                 .map(|_| Ok(xous_kernel::Result::ResumeProcess))
                 .unwrap_or(Err(xous_kernel::Error::ProcessNotFound))
         }),
+        SysCall::SignalEvents(cid, events) => signal_events(pid, cid, events),
+        SysCall::SignalOnMessage(sid, cid, events) => signal_on_message(pid, sid, cid, events),
+        SysCall::WaitEvents(sid) => wait_events(pid, tid, sid, ExecutionType::Blocking),
+        SysCall::TryWaitEvents(sid) => wait_events(pid, tid, sid, ExecutionType::NonBlocking),
+        SysCall::FutexWait(addr, expected) => futex_wait(pid, tid, addr, expected),
+        SysCall::FutexWake(addr, count) => futex_wake(pid, addr, count),
+        SysCall::ExitThread(code) => exit_thread(pid, tid, code),
+        SysCall::JoinThread(target) => join_thread(pid, tid, target),
+        SysCall::DetachThread(target, stack) => detach_thread(pid, target, stack),
+        SysCall::GetThreadId => Ok(xous_kernel::Result::ThreadID(tid)),
+        SysCall::WaitProcess(target) => wait_process(pid, tid, target),
         SysCall::CreateThread(thread_init) => SystemServices::with_mut(|ss| {
             ss.create_thread(pid, thread_init).map(|new_tid| {
                 if !cfg!(baremetal) {
@@ -753,18 +1373,33 @@ pub fn handle_inner(pid: PID, tid: TID, in_irq: bool, call: SysCall) -> SysCallR
                 .map(|(sid, cid)| xous_kernel::Result::NewServerID(sid, cid))
         }),
         SysCall::TryConnect(sid) => SystemServices::with_mut(|ss| {
+            // Waiting for approval would block, which isn't allowed here.
+            if ss.connect_needs_approval(pid, pid, sid)?.is_some() {
+                return Err(xous_kernel::Error::AccessDenied);
+            }
             ss.connect_to_server(sid)
                 .map(xous_kernel::Result::ConnectionID)
         }),
-        SysCall::ReturnMemory(sender, buf) => return_memory(pid, tid, in_irq, sender, buf),
+        SysCall::ReturnMemory(sender, buf) => {
+            return_memory(pid, tid, in_irq, sender, buf, xous_kernel::Result::Ok)
+        }
+        SysCall::ReturnMemoryWithValid(sender, buf, offset, valid) => return_memory(
+            pid,
+            tid,
+            in_irq,
+            sender,
+            buf,
+            xous_kernel::Result::MemoryReturned(offset, valid),
+        ),
         SysCall::ReturnScalar1(sender, arg) => return_scalar(pid, tid, in_irq, sender, arg),
         SysCall::ReturnScalar2(sender, arg1, arg2) => {
             return_scalar2(pid, tid, in_irq, sender, arg1, arg2)
         }
-        SysCall::TrySendMessage(cid, message) => send_message(pid, tid, cid, message),
-        SysCall::TerminateProcess => SystemServices::with_mut(|ss| {
+        SysCall::TrySendMessage(cid, message) => send_message(pid, tid, cid, message, None),
+        SysCall::TerminateProcess(exit_code) => SystemServices::with_mut(|ss| {
             ss.switch_from_thread(pid, tid)?;
-            let ppid = ss.terminate_process(pid)?;
+            notify_disconnected(ss, pid, tid);
+            let ppid = ss.terminate_process(pid, exit_code)?;
             if cfg!(baremetal) {
                 ss.switch_to_thread(ppid, None)
                     .map(|_| xous_kernel::Result::ResumeProcess)
@@ -776,36 +1411,57 @@ pub fn handle_inner(pid: PID, tid: TID, in_irq: bool, call: SysCall) -> SysCallR
             SystemServices::with_mut(|ss| ss.shutdown().map(|_| xous_kernel::Result::Ok))
         }
 
-        SysCall::Connect(sid) => {
-            let result = SystemServices::with_mut(|ss| {
-                ss.connect_to_server(sid)
-                    .map(xous_kernel::Result::ConnectionID)
-            });
-            match result {
-                Ok(o) => Ok(o),
-                Err(xous_kernel::Error::ServerNotFound) => retry_syscall(pid, tid),
-                Err(e) => Err(e),
-            }
+        SysCall::Connect(sid) => connect(pid, tid, pid, sid, None),
+        SysCall::ConnectForProcess(target_pid, sid, rights) => {
+            connect(pid, tid, target_pid, sid, rights)
         }
-        SysCall::ConnectForProcess(pid, sid) => {
-            let result = SystemServices::with_mut(|ss| {
-                ss.connect_process_to_server(pid, sid)
-                    .map(xous_kernel::Result::ConnectionID)
-            });
-            match result {
-                Ok(o) => Ok(o),
-                Err(xous_kernel::Error::ServerNotFound) => retry_syscall(pid, tid),
-                Err(e) => Err(e),
+        SysCall::TryConnectForProcess(target_pid, sid) => SystemServices::with_mut(|ss| {
+            if ss.connect_needs_approval(pid, target_pid, sid)?.is_some() {
+                return Err(xous_kernel::Error::AccessDenied);
             }
+            ss.connect_process_to_server(target_pid, sid)
+                .map(xous_kernel::Result::ConnectionID)
+        }),
+        SysCall::Disconnect(cid) => SystemServices::with_mut(|ss| {
+            ss.disconnect_from_server(cid)
+                .map(|_| xous_kernel::Result::Ok)
+        }),
+        SysCall::DestroyServer(sid) => SystemServices::with_mut(|ss| {
+            ss.destroy_server(pid, sid).map(|_| xous_kernel::Result::Ok)
+        }),
+        SysCall::SetConnectionRights(sid, target, rights) => SystemServices::with_mut(|ss| {
+            ss.set_connection_rights(pid, sid, target, rights)
+                .map(|_| xous_kernel::Result::Ok)
+        }),
+        SysCall::RequireConnectApproval(sid, required) => SystemServices::with_mut(|ss| {
+            ss.require_connect_approval(pid, sid, required)
+                .map(|_| xous_kernel::Result::Ok)
+        }),
+        SysCall::NotifyDisconnects(sid, enabled) => SystemServices::with_mut(|ss| {
+            ss.notify_disconnects(pid, sid, enabled)
+                .map(|_| xous_kernel::Result::Ok)
+        }),
+        SysCall::ServerOwner(sid) => {
+            SystemServices::with(|ss| ss.server_owner(sid).map(xous_kernel::Result::ProcessID))
         }
+        SysCall::SetPriority(target, priority) => SystemServices::with_mut(|ss| {
+            ss.set_priority(pid, target.unwrap_or(pid), priority)
+                .map(|_| xous_kernel::Result::Ok)
+        }),
         SysCall::SendMessage(cid, message) => {
-            let result = send_message(pid, tid, cid, message);
+            let result = send_message(pid, tid, cid, message, None);
             match result {
                 Ok(o) => Ok(o),
                 Err(xous_kernel::Error::ServerQueueFull) => retry_syscall(pid, tid),
                 Err(e) => Err(e),
             }
         }
+        // Unlike `SendMessage`, a full queue is not retried. A server that has
+        // stopped responding will eventually fill its queue, and the caller
+        // asked not to be kept waiting.
+        SysCall::SendMessageTimeout(cid, message, timeout_ms) => {
+            send_message(pid, tid, cid, message, Some(timeout_ms))
+        }
         _ => Err(xous_kernel::Error::UnhandledSyscall),
     }
 }
@@ -397,6 +397,77 @@ fn try_receive_message() {
     main_thread.join().expect("couldn't join kernel process");
 }
 
+#[test]
+fn receive_message_timeout() {
+    let main_thread = start_kernel(SERVER_SPEC);
+
+    let (server_addr_send, server_addr_recv) = channel();
+
+    let xous_server = xous_kernel::create_process_as_thread(xous_kernel::ProcessArgsAsThread::new(
+        "receive_message_timeout server",
+        move || {
+            let sid = xous_kernel::create_server().expect("couldn't create test server");
+
+            // Nobody knows about this server yet, so nothing can arrive.
+            assert_eq!(
+                xous_kernel::receive_message_timeout(sid, 20),
+                Err(xous_kernel::Error::Timeout)
+            );
+
+            server_addr_send.send(sid).unwrap();
+            let envelope = xous_kernel::receive_message_timeout(sid, 10_000)
+                .expect("couldn't receive messages");
+            assert_eq!(
+                envelope.body,
+                xous_kernel::Message::BlockingScalar(xous_kernel::ScalarMessage {
+                    id: 11,
+                    arg1: 12,
+                    arg2: 13,
+                    arg3: 14,
+                    arg4: 15
+                })
+            );
+            xous_kernel::return_scalar(envelope.sender, 16).expect("couldn't return scalar");
+
+            // Having received a message, the timeout shouldn't fire later on.
+            std::thread::sleep(std::time::Duration::from_millis(50));
+            assert_eq!(
+                xous_kernel::try_receive_message(sid),
+                Ok(None),
+                "a message arrived from nowhere"
+            );
+        },
+    ))
+    .expect("couldn't spawn server process");
+
+    let xous_client = xous_kernel::create_process_as_thread(xous_kernel::ProcessArgsAsThread::new(
+        "receive_message_timeout client",
+        move || {
+            let sid = server_addr_recv.recv().unwrap();
+            let conn = xous_kernel::try_connect(sid).expect("couldn't connect to server");
+            let result = xous_kernel::send_message(
+                conn,
+                xous_kernel::Message::BlockingScalar(xous_kernel::ScalarMessage {
+                    id: 11,
+                    arg1: 12,
+                    arg2: 13,
+                    arg3: 14,
+                    arg4: 15,
+                }),
+            )
+            .expect("couldn't send message");
+            assert_eq!(result, xous_kernel::Result::Scalar1(16));
+        },
+    ))
+    .expect("couldn't spawn client process");
+
+    crate::wait_process_as_thread(xous_server).expect("couldn't join server process");
+    crate::wait_process_as_thread(xous_client).expect("couldn't join client process");
+    shutdown_kernel();
+
+    main_thread.join().expect("couldn't join kernel process");
+}
+
 #[test]
 fn send_blocking_scalar_message() {
     // Start the server in another thread
@@ -900,6 +971,399 @@ fn send_mutableborrow_message() {
     main_thread.join().expect("couldn't join kernel process");
 }
 
+/// Test that a page-aligned buffer can be lent without copying it into a new
+/// Carton, and that it can be immutably lent to a server by two clients at once.
+#[test]
+fn lend_page_aligned_buffer() {
+    use xous_kernel::carton::Carton;
+    use xous_kernel::MemoryFlags;
+    let main_thread = start_kernel(SERVER_SPEC);
+    let (server_addr_send, server_addr_recv) = channel();
+    let server_addr_recv = std::sync::Arc::new(std::sync::Mutex::new(server_addr_recv));
+
+    let xous_server = xous_kernel::create_process_as_thread(xous_kernel::ProcessArgsAsThread::new(
+        "lend_page_aligned_buffer server",
+        move || {
+            let sid = xous_kernel::create_server().expect("couldn't create test server");
+            server_addr_send.send(sid).unwrap();
+            server_addr_send.send(sid).unwrap();
+
+            // Hold on to both borrows before returning either of them.
+            let mut borrows = vec![];
+            while borrows.len() < 2 {
+                let envelope = xous_kernel::receive_message(sid).expect("couldn't receive message");
+                if let xous_kernel::Message::Borrow(m) = envelope.body {
+                    let bytes = unsafe { core::slice::from_raw_parts(m.buf.as_ptr(), m.buf.len()) };
+                    assert_eq!(bytes.len(), 8192);
+                    assert!(bytes.iter().all(|&b| b == m.id as u8));
+                    borrows.push((envelope.sender, m.buf));
+                }
+            }
+            for (sender, buf) in borrows {
+                xous_kernel::return_memory(sender, buf).expect("couldn't return memory");
+            }
+
+            let envelope = xous_kernel::receive_message(sid).expect("couldn't receive message");
+            if let xous_kernel::Message::MutableBorrow(m) = envelope.body {
+                let bytes =
+                    unsafe { core::slice::from_raw_parts_mut(m.buf.as_mut_ptr(), m.buf.len()) };
+                for byte in bytes.iter_mut() {
+                    *byte += 1;
+                }
+                xous_kernel::return_memory(envelope.sender, m.buf).expect("couldn't return memory");
+            } else {
+                panic!("unexpected message type");
+            }
+        },
+    ))
+    .expect("couldn't start server");
+
+    let mut clients = vec![];
+    for id in 1..=2 {
+        let server_addr_recv = server_addr_recv.clone();
+        clients.push(
+            xous_kernel::create_process_as_thread(xous_kernel::ProcessArgsAsThread::new(
+                "lend_page_aligned_buffer client",
+                move || {
+                    let sid = server_addr_recv.lock().unwrap().recv().unwrap();
+                    let conn = xous_kernel::connect(sid).expect("couldn't connect to server");
+                    let range =
+                        xous_kernel::map_memory(None, None, 8192, MemoryFlags::R | MemoryFlags::W)
+                            .expect("couldn't allocate buffer");
+                    let buffer =
+                        unsafe { core::slice::from_raw_parts_mut(range.as_mut_ptr(), range.len()) };
+                    for byte in buffer.iter_mut() {
+                        *byte = id as u8;
+                    }
+
+                    assert_eq!(
+                        Carton::from_buffer(&buffer[1..4097]).err(),
+                        Some(xous_kernel::Error::BadAlignment)
+                    );
+                    let mut carton = Carton::from_buffer(buffer).expect("couldn't wrap buffer");
+                    assert_eq!(
+                        carton.lend_mut(conn, id),
+                        Err(xous_kernel::Error::ShareViolation)
+                    );
+                    carton.lend(conn, id).expect("couldn't lend buffer");
+                    drop(carton);
+
+                    // Only one of the clients asks the server to change its buffer.
+                    if id == 2 {
+                        let mut carton =
+                            Carton::from_buffer_mut(buffer).expect("couldn't wrap buffer");
+                        carton
+                            .lend_mut(conn, id)
+                            .expect("couldn't mutably lend buffer");
+                        let lent: &[u8] = carton.as_ref();
+                        assert!(lent.iter().all(|&b| b == id as u8 + 1));
+                        drop(carton);
+                        assert!(buffer.iter().all(|&b| b == id as u8 + 1));
+                    }
+                    xous_kernel::unmap_memory(range).expect("couldn't free buffer");
+                },
+            ))
+            .expect("couldn't start client"),
+        );
+    }
+
+    for client in clients {
+        xous_kernel::wait_process_as_thread(client).expect("couldn't join client process");
+    }
+    xous_kernel::wait_process_as_thread(xous_server).expect("couldn't join server process");
+
+    shutdown_kernel();
+
+    main_thread.join().expect("couldn't join kernel process");
+}
+
+/// Test that one buffer can be immutably lent to two servers at the same
+/// time, and that each lend is counted until that server returns it.
+#[test]
+fn lend_buffer_to_two_servers() {
+    use xous_kernel::MemoryFlags;
+    let main_thread = start_kernel(SERVER_SPEC);
+    let (server_addr_send, server_addr_recv) = channel();
+    let (holding_send, holding_recv) = channel();
+
+    let mut servers = vec![];
+    let mut releases = vec![];
+    for _ in 0..2 {
+        let server_addr_send = server_addr_send.clone();
+        let holding_send = holding_send.clone();
+        let (release_send, release_recv) = channel::<()>();
+        releases.push(release_send);
+        servers.push(
+            xous_kernel::create_process_as_thread(xous_kernel::ProcessArgsAsThread::new(
+                "lend_buffer_to_two_servers server",
+                move || {
+                    let sid = xous_kernel::create_server().expect("couldn't create test server");
+                    server_addr_send.send(sid).unwrap();
+
+                    let envelope =
+                        xous_kernel::receive_message(sid).expect("couldn't receive message");
+                    if let xous_kernel::Message::Borrow(m) = envelope.body {
+                        let bytes =
+                            unsafe { core::slice::from_raw_parts(m.buf.as_ptr(), m.buf.len()) };
+                        assert!(bytes.iter().all(|&b| b == 0x5a));
+
+                        // Hold on to the buffer until told to give it back.
+                        holding_send.send(()).unwrap();
+                        release_recv.recv().unwrap();
+                        xous_kernel::return_memory(envelope.sender, m.buf)
+                            .expect("couldn't return memory");
+                    } else {
+                        panic!("unexpected message type");
+                    }
+                },
+            ))
+            .expect("couldn't start server"),
+        );
+    }
+
+    let xous_client = xous_kernel::create_process_as_thread(xous_kernel::ProcessArgsAsThread::new(
+        "lend_buffer_to_two_servers client",
+        move || {
+            let range = xous_kernel::map_memory(None, None, 8192, MemoryFlags::R | MemoryFlags::W)
+                .expect("couldn't allocate buffer");
+            let buffer =
+                unsafe { core::slice::from_raw_parts_mut(range.as_mut_ptr(), range.len()) };
+            for byte in buffer.iter_mut() {
+                *byte = 0x5a;
+            }
+
+            let mut lenders = vec![];
+            for _ in 0..2 {
+                let sid = server_addr_recv.recv().unwrap();
+                lenders.push(
+                    xous_kernel::create_thread(move || {
+                        let conn = xous_kernel::connect(sid).expect("couldn't connect to server");
+                        xous_kernel::send_message(
+                            conn,
+                            xous_kernel::Message::Borrow(xous_kernel::MemoryMessage {
+                                id: 0,
+                                buf: range,
+                                offset: None,
+                                valid: None,
+                            }),
+                        )
+                        .expect("couldn't lend buffer");
+                    })
+                    .expect("couldn't spawn thread"),
+                );
+            }
+            for lender in lenders {
+                xous_kernel::wait_thread(lender).expect("couldn't wait for thread");
+            }
+
+            // Both servers have given the buffer back untouched.
+            assert!(buffer.iter().all(|&b| b == 0x5a));
+            xous_kernel::unmap_memory(range).expect("couldn't free buffer");
+        },
+    ))
+    .expect("couldn't start client");
+    let client_pid = xous_client.pid();
+
+    holding_recv.recv().unwrap();
+    holding_recv.recv().unwrap();
+    let stats = xous_kernel::memory_stats(client_pid).expect("couldn't get stats");
+    assert_eq!(stats.lent_pages, 4);
+
+    // Every server that returns the buffer takes one lend off the count.
+    let mut expected = 4;
+    for (server, release) in servers.into_iter().zip(releases) {
+        release.send(()).unwrap();
+        xous_kernel::wait_process_as_thread(server).expect("couldn't join server process");
+        expected -= 2;
+        if expected > 0 {
+            let stats = xous_kernel::memory_stats(client_pid).expect("couldn't get stats");
+            assert_eq!(stats.lent_pages, expected);
+        }
+    }
+    xous_kernel::wait_process_as_thread(xous_client).expect("couldn't join client process");
+
+    shutdown_kernel();
+    main_thread.join().expect("couldn't join kernel process");
+}
+
+/// Test that a typed value can be lent to a server, that the server can write
+/// its reply in the same buffer, and that it never reads past `valid`.
+#[test]
+fn ipc_typed_message() {
+    use xous_kernel::ipc::{self, Buffer};
+    let main_thread = start_kernel(SERVER_SPEC);
+    let (server_addr_send, server_addr_recv) = channel();
+
+    let xous_server = xous_kernel::create_process_as_thread(xous_kernel::ProcessArgsAsThread::new(
+        "ipc_typed_message server",
+        move || {
+            let sid = xous_kernel::create_server().expect("couldn't create test server");
+            server_addr_send.send(sid).unwrap();
+
+            let mut envelope = xous_kernel::receive_message(sid).expect("couldn't receive message");
+            if let xous_kernel::Message::MutableBorrow(m) = &mut envelope.body {
+                let (op, values, extra): (&str, [u32; 3], Option<u64>) =
+                    ipc::from_memory_message(m).expect("couldn't decode request");
+                assert_eq!(op, "add");
+                let sum = values.iter().map(|&v| v as u64).sum::<u64>() + extra.unwrap_or(0);
+                ipc::replace_memory_message(m, &(sum, "done")).expect("couldn't encode reply");
+                xous_kernel::return_memory_offset_valid(envelope.sender, m.buf, m.offset, m.valid)
+                    .expect("couldn't return memory");
+            } else {
+                panic!("unexpected message type");
+            }
+
+            let envelope = xous_kernel::receive_message(sid).expect("couldn't receive message");
+            if let xous_kernel::Message::Borrow(m) = &envelope.body {
+                assert_eq!(
+                    ipc::from_memory_message::<(&str, [u32; 3], Option<u64>)>(m),
+                    Err(ipc::Error::UnexpectedEnd)
+                );
+                xous_kernel::return_memory(envelope.sender, m.buf).expect("couldn't return memory");
+            } else {
+                panic!("unexpected message type");
+            }
+        },
+    ))
+    .expect("couldn't start server");
+
+    let xous_client = xous_kernel::create_process_as_thread(xous_kernel::ProcessArgsAsThread::new(
+        "ipc_typed_message client",
+        move || {
+            let sid = server_addr_recv.recv().unwrap();
+            let conn = xous_kernel::connect(sid).expect("couldn't connect to server");
+
+            let request = ("add", [1u32, 2, 3], Some(4u64));
+            let mut buffer = Buffer::into_buf(&request).expect("couldn't encode request");
+            assert_eq!(buffer.len(), ipc::serialized_len(&request).unwrap());
+            buffer.lend_mut(conn, 1).expect("couldn't lend request");
+            let reply: (u64, &str) = buffer.to_original().expect("couldn't decode reply");
+            assert_eq!(reply, (10, "done"));
+
+            // Claim that the last byte of the request isn't there.
+            buffer.replace(&request).expect("couldn't encode request");
+            let truncated = xous_kernel::MemoryMessage {
+                id: 2,
+                buf: xous_kernel::MemoryRange::new(
+                    buffer.as_ref().as_ptr() as usize,
+                    buffer.as_ref().len(),
+                )
+                .unwrap(),
+                offset: None,
+                valid: xous_kernel::MemorySize::new(buffer.len() - 1),
+            };
+            xous_kernel::send_message(conn, xous_kernel::Message::Borrow(truncated))
+                .expect("couldn't lend truncated request");
+        },
+    ))
+    .expect("couldn't start client");
+
+    xous_kernel::wait_process_as_thread(xous_client).expect("couldn't join client process");
+    xous_kernel::wait_process_as_thread(xous_server).expect("couldn't join server process");
+
+    shutdown_kernel();
+    main_thread.join().expect("couldn't join kernel process");
+}
+
+/// Test that a server can say how much of a mutably lent buffer it filled in,
+/// and that a plain `return_memory()` leaves the client's length alone.
+#[test]
+fn return_memory_with_valid() {
+    use core::fmt::Write;
+    let main_thread = start_kernel(SERVER_SPEC);
+    let (server_addr_send, server_addr_recv) = channel();
+
+    let xous_server = xous_kernel::create_process_as_thread(xous_kernel::ProcessArgsAsThread::new(
+        "return_memory_with_valid server",
+        move || {
+            let sid = xous_kernel::create_server().expect("couldn't create test server");
+            server_addr_send.send(sid).unwrap();
+
+            let mut envelope = xous_kernel::receive_message(sid).expect("couldn't receive message");
+            if let xous_kernel::Message::MutableBorrow(m) = &mut envelope.body {
+                {
+                    let mut s = xous_kernel::String::from_message(m).expect("couldn't read string");
+                    assert_eq!(s.len(), 5);
+                    write!(s, ", world").unwrap();
+                }
+                assert_eq!(m.valid, xous_kernel::MemorySize::new(12));
+                xous_kernel::return_memory_offset_valid(envelope.sender, m.buf, m.offset, m.valid)
+                    .expect("couldn't return memory");
+            } else {
+                panic!("unexpected message type");
+            }
+
+            let envelope = xous_kernel::receive_message(sid).expect("couldn't receive message");
+            if let xous_kernel::Message::MutableBorrow(m) = &envelope.body {
+                xous_kernel::return_memory_offset_valid(
+                    envelope.sender,
+                    m.buf,
+                    xous_kernel::MemoryAddress::new(3),
+                    xous_kernel::MemorySize::new(7),
+                )
+                .expect("couldn't return memory");
+            } else {
+                panic!("unexpected message type");
+            }
+
+            let envelope = xous_kernel::receive_message(sid).expect("couldn't receive message");
+            if let xous_kernel::Message::MutableBorrow(m) = &envelope.body {
+                xous_kernel::return_memory(envelope.sender, m.buf).expect("couldn't return memory");
+            } else {
+                panic!("unexpected message type");
+            }
+        },
+    ))
+    .expect("couldn't start server");
+
+    let xous_client = xous_kernel::create_process_as_thread(xous_kernel::ProcessArgsAsThread::new(
+        "return_memory_with_valid client",
+        move || {
+            let sid = server_addr_recv.recv().unwrap();
+            let conn = xous_kernel::connect(sid).expect("couldn't connect to server");
+
+            let mut s = xous_kernel::String::new(4096);
+            write!(s, "hello").unwrap();
+            assert_eq!(
+                s.lend_mut(conn, 1),
+                Ok(xous_kernel::Result::MemoryReturned(
+                    None,
+                    xous_kernel::MemorySize::new(12)
+                ))
+            );
+            assert_eq!(s.len(), 12);
+            assert_eq!(format!("{}", s), "hello, world");
+
+            let range = xous_kernel::map_memory(None, None, 4096, xous_kernel::MemoryFlags::R | xous_kernel::MemoryFlags::W)
+                .expect("couldn't allocate buffer");
+            let message = xous_kernel::MemoryMessage {
+                id: 2,
+                buf: range,
+                offset: None,
+                valid: None,
+            };
+            assert_eq!(
+                xous_kernel::send_message(conn, xous_kernel::Message::MutableBorrow(message)),
+                Ok(xous_kernel::Result::MemoryReturned(
+                    xous_kernel::MemoryAddress::new(3),
+                    xous_kernel::MemorySize::new(7)
+                ))
+            );
+            xous_kernel::unmap_memory(range).expect("couldn't free buffer");
+
+            assert_eq!(s.lend_mut(conn, 3), Ok(xous_kernel::Result::Ok));
+            assert_eq!(s.len(), 12);
+        },
+    ))
+    .expect("couldn't start client");
+
+    xous_kernel::wait_process_as_thread(xous_client).expect("couldn't join client process");
+    xous_kernel::wait_process_as_thread(xous_server).expect("couldn't join server process");
+
+    shutdown_kernel();
+    main_thread.join().expect("couldn't join kernel process");
+}
+
 #[test]
 fn send_repeat_mutableborrow_message() {
     let main_thread = start_kernel(SERVER_SPEC);
@@ -1041,18 +1505,22 @@ fn server_client_same_process() {
     main_thread.join().expect("couldn't join kernel process");
 }
 
-/// Test that one process can have multiple contexts
+/// Test that the server and connection tables grow beyond their initial size,
+/// and that connections may be released and made again.
 #[test]
-fn multiple_contexts() {
-    // ::debug_here::debug_here!();
-    // Start the kernel in its own thread
+fn many_servers_and_disconnect() {
     let main_thread = start_kernel(SERVER_SPEC);
 
-    let internal_server = xous_kernel::create_process_as_thread(
-        xous_kernel::ProcessArgsAsThread::new("multiple_contexts process", move || {
-            let server = xous_kernel::create_server().expect("couldn't create server");
-            let connection =
-                xous_kernel::try_connect(server).expect("couldn't connect to our own server");
+    let process = xous_kernel::create_process_as_thread(xous_kernel::ProcessArgsAsThread::new(
+        "many_servers_and_disconnect process",
+        || {
+            let mut servers = vec![];
+            for _ in 0..40 {
+                let server = xous_kernel::create_server().expect("couldn't create server");
+                let connection =
+                    xous_kernel::try_connect(server).expect("couldn't connect to server");
+                servers.push((server, connection));
+            }
             let msg_contents = xous_kernel::ScalarMessage {
                 id: 1,
                 arg1: 2,
@@ -1061,109 +1529,1914 @@ fn multiple_contexts() {
                 arg4: 5,
             };
 
-            let mut server_threads = vec![];
-            for _ in 1..crate::arch::process::MAX_THREAD {
-                server_threads.push(
-                    xous_kernel::create_thread(move || {
-                        let msg =
-                            xous_kernel::receive_message(server).expect("couldn't receive message");
-                        assert_eq!(msg.body, xous_kernel::Message::Scalar(msg_contents));
-                    })
-                    .expect("couldn't spawn client thread"),
-                );
-            }
-
-            for _ in &server_threads {
+            // Every connection should still reach its own server.
+            for (server, connection) in servers.iter() {
                 xous_kernel::try_send_message(
-                    connection,
+                    *connection,
                     xous_kernel::Message::Scalar(msg_contents),
                 )
                 .expect("couldn't send message");
+                let msg = xous_kernel::receive_message(*server).expect("couldn't receive message");
+                assert_eq!(msg.body, xous_kernel::Message::Scalar(msg_contents));
             }
-            for server_thread in server_threads.into_iter() {
-                xous_kernel::wait_thread(server_thread).expect("couldn't wait for thread");
-            }
-        }),
-    )
-    .expect("couldn't create internal server");
 
-    xous_kernel::wait_process_as_thread(internal_server)
-        .expect("couldn't join internal_server process");
+            // Release a connection that lives past the original table.
+            let (server, connection) = servers[35];
+            xous_kernel::disconnect(connection).expect("couldn't disconnect");
+            assert_eq!(
+                xous_kernel::disconnect(connection),
+                Err(xous_kernel::Error::ServerNotFound)
+            );
+            assert_eq!(
+                xous_kernel::try_send_message(
+                    connection,
+                    xous_kernel::Message::Scalar(msg_contents)
+                ),
+                Err(xous_kernel::Error::ServerNotFound)
+            );
 
-    // Any process ought to be able to shut down the system currently.
+            // Connecting again reuses the slot that was freed.
+            let new_connection = xous_kernel::try_connect(server).expect("couldn't reconnect");
+            assert_eq!(new_connection, connection);
+            xous_kernel::try_send_message(
+                new_connection,
+                xous_kernel::Message::Scalar(msg_contents),
+            )
+            .expect("couldn't send message after reconnecting");
+            let msg = xous_kernel::receive_message(server).expect("couldn't receive message");
+            assert_eq!(msg.body, xous_kernel::Message::Scalar(msg_contents));
+        },
+    ))
+    .expect("couldn't spawn many_servers_and_disconnect process");
+
+    xous_kernel::wait_process_as_thread(process)
+        .expect("couldn't join many_servers_and_disconnect process");
     shutdown_kernel();
 
     main_thread.join().expect("couldn't join kernel process");
 }
 
+/// Test that destroying a server wakes up everyone waiting on it, and that
+/// connections to it stop working.
 #[test]
-fn multiple_multiple_contexts() {
-    for _ in 0..5 {
-        multiple_contexts();
-    }
-}
+fn destroy_server() {
+    let main_thread = start_kernel(SERVER_SPEC);
+    let (scalar_sid_send, scalar_sid_recv) = channel();
+    let (borrow_sid_send, borrow_sid_recv) = channel();
+    let test_bytes = b"Hello, world!";
 
-/// Test that a server can be restarted and the kernel doesn't crash
+    let xous_server = xous_kernel::create_process_as_thread(xous_kernel::ProcessArgsAsThread::new(
+        "destroy_server server",
+        move || {
+            let sid = xous_kernel::create_server_with_address(b"destroy_server_t")
+                .expect("couldn't create test server");
+            scalar_sid_send.send(sid).unwrap();
+            borrow_sid_send.send(sid).unwrap();
+
+            // Take both messages, but never respond to them.
+            for _ in 0..2 {
+                let envelope = xous_kernel::receive_message(sid).expect("couldn't receive message");
+                if let xous_kernel::Message::MutableBorrow(m) = envelope.body {
+                    let bt =
+                        unsafe { core::slice::from_raw_parts_mut(m.buf.as_mut_ptr(), m.buf.len()) };
+                    for letter in bt.iter_mut() {
+                        *letter += 1;
+                    }
+                }
+            }
+
+            // This thread is left waiting for a message that will never come.
+            let waiting_thread = xous_kernel::create_thread(move || {
+                assert_eq!(
+                    xous_kernel::receive_message(sid).err(),
+                    Some(xous_kernel::Error::ServerNotFound)
+                );
+            })
+            .expect("couldn't spawn waiting thread");
+            std::thread::sleep(std::time::Duration::from_millis(100));
+
+            xous_kernel::destroy_server(sid).expect("couldn't destroy server");
+            assert_eq!(
+                xous_kernel::destroy_server(sid),
+                Err(xous_kernel::Error::ServerNotFound)
+            );
+            xous_kernel::wait_thread(waiting_thread).expect("couldn't wait for thread");
+        },
+    ))
+    .expect("couldn't spawn server process");
+
+    let scalar_client = xous_kernel::create_process_as_thread(
+        xous_kernel::ProcessArgsAsThread::new("destroy_server scalar client", move || {
+            let sid = scalar_sid_recv.recv().unwrap();
+            let conn = xous_kernel::connect(sid).expect("couldn't connect to server");
+            let scalar = xous_kernel::ScalarMessage {
+                id: 1,
+                arg1: 0,
+                arg2: 0,
+                arg3: 0,
+                arg4: 0,
+            };
+            assert_eq!(
+                xous_kernel::send_message(conn, xous_kernel::Message::BlockingScalar(scalar)),
+                Err(xous_kernel::Error::ServerNotFound)
+            );
+
+            // The connection is now a tombstone until it is released.
+            assert_eq!(
+                xous_kernel::try_send_message(conn, xous_kernel::Message::Scalar(scalar)),
+                Err(xous_kernel::Error::ServerNotFound)
+            );
+            xous_kernel::disconnect(conn).expect("couldn't disconnect");
+        }),
+    )
+    .expect("couldn't spawn scalar client process");
+
+    let borrow_client = xous_kernel::create_process_as_thread(
+        xous_kernel::ProcessArgsAsThread::new("destroy_server borrow client", move || {
+            let sid = borrow_sid_recv.recv().unwrap();
+            let conn = xous_kernel::connect(sid).expect("couldn't connect to server");
+            let carton = xous_kernel::carton::Carton::from_bytes(test_bytes);
+            let range: &xous_kernel::MemoryRange = carton.as_ref();
+            let msg = xous_kernel::MemoryMessage {
+                id: 2,
+                buf: *range,
+                offset: None,
+                valid: None,
+            };
+            assert_eq!(
+                xous_kernel::send_message(conn, xous_kernel::Message::MutableBorrow(msg)),
+                Err(xous_kernel::Error::ServerNotFound)
+            );
+            let returned_bytes: &[u8] = carton.as_ref();
+            assert_eq!(&test_bytes[..], returned_bytes);
+        }),
+    )
+    .expect("couldn't spawn borrow client process");
+
+    xous_kernel::wait_process_as_thread(xous_server).expect("couldn't join server process");
+    xous_kernel::wait_process_as_thread(scalar_client)
+        .expect("couldn't join scalar client process");
+    xous_kernel::wait_process_as_thread(borrow_client)
+        .expect("couldn't join borrow client process");
+    shutdown_kernel();
+
+    main_thread.join().expect("couldn't join kernel process");
+}
+
+/// Test that servers can restrict what their clients send, and that only the
+/// owner can grant more than that.
 #[test]
-fn process_restart_server() {
-    let test_str = "Hello, world!";
-    let test_bytes = test_str.as_bytes();
+fn connection_rights() {
+    use xous_kernel::{ConnectionRights, Message, MessageKinds, ScalarMessage};
+    let main_thread = start_kernel(SERVER_SPEC);
+    let (trusted_sid_send, trusted_sid_recv) = channel();
+    let (untrusted_sid_send, untrusted_sid_recv) = channel();
+    let (trusted_done_send, trusted_done_recv) = channel();
+    let (trusted_pid_send, trusted_pid_recv) = channel();
+    let (narrowed_send, narrowed_recv) = channel();
+
+    fn scalar(id: usize) -> ScalarMessage {
+        ScalarMessage {
+            id,
+            arg1: 0,
+            arg2: 0,
+            arg3: 0,
+            arg4: 0,
+        }
+    }
+
+    let xous_server = xous_kernel::create_process_as_thread(xous_kernel::ProcessArgsAsThread::new(
+        "connection_rights server",
+        move || {
+            let sid = xous_kernel::create_server().expect("couldn't create test server");
+            let rights = ConnectionRights {
+                kinds: MessageKinds::SCALAR | MessageKinds::BLOCKING_SCALAR,
+                ..ConnectionRights::excluding_id(7)
+            };
+            xous_kernel::set_connection_rights(sid, None, rights)
+                .expect("couldn't set default rights");
+            trusted_sid_send.send(sid).unwrap();
+            untrusted_sid_send.send(sid).unwrap();
+
+            // The trusted client introduces itself, and is granted every right.
+            let envelope = xous_kernel::receive_message(sid).expect("couldn't receive message");
+            assert_eq!(envelope.body, Message::BlockingScalar(scalar(1)));
+            let trusted_pid = envelope.sender.pid().unwrap();
+            let cid = xous_kernel::connect_for_process_with_rights(
+                trusted_pid,
+                sid,
+                ConnectionRights::ALL,
+            )
+            .expect("couldn't grant rights");
+            xous_kernel::return_scalar(envelope.sender, cid).expect("couldn't return scalar");
+            trusted_pid_send.send(trusted_pid).unwrap();
+
+            // The trusted client sends the restricted message both before and
+            // after the untrusted client tries to take its rights away.
+            for _ in 0..2 {
+                let envelope = xous_kernel::receive_message(sid).expect("couldn't receive message");
+                assert_eq!(envelope.body, Message::Scalar(scalar(7)));
+                assert_eq!(envelope.sender.pid(), Some(trusted_pid));
+            }
+
+            // Only the allowed message from the untrusted client arrives.
+            let envelope = xous_kernel::receive_message(sid).expect("couldn't receive message");
+            assert_eq!(envelope.body, Message::Scalar(scalar(8)));
+            assert_ne!(envelope.sender.pid(), Some(trusted_pid));
+        },
+    ))
+    .expect("couldn't spawn server process");
+
+    let trusted_client = xous_kernel::create_process_as_thread(
+        xous_kernel::ProcessArgsAsThread::new("connection_rights trusted client", move || {
+            let sid = trusted_sid_recv.recv().unwrap();
+            let conn = xous_kernel::connect(sid).expect("couldn't connect to server");
+            assert_eq!(
+                xous_kernel::try_send_message(conn, Message::Scalar(scalar(7))),
+                Err(xous_kernel::Error::AccessDenied)
+            );
+            let granted_conn = xous_kernel::send_message(conn, Message::BlockingScalar(scalar(1)))
+                .expect("couldn't introduce client");
+            assert_eq!(granted_conn, xous_kernel::Result::Scalar1(conn));
+            xous_kernel::send_message(conn, Message::Scalar(scalar(7)))
+                .expect("couldn't send granted message");
+            trusted_done_send.send(()).unwrap();
+
+            narrowed_recv.recv().unwrap();
+            xous_kernel::send_message(conn, Message::Scalar(scalar(7)))
+                .expect("couldn't send granted message after narrowing attempt");
+            trusted_done_send.send(()).unwrap();
+        }),
+    )
+    .expect("couldn't spawn trusted client process");
 
+    let untrusted_client = xous_kernel::create_process_as_thread(
+        xous_kernel::ProcessArgsAsThread::new("connection_rights untrusted client", move || {
+            let sid = untrusted_sid_recv.recv().unwrap();
+            let trusted_pid = trusted_pid_recv.recv().unwrap();
+            trusted_done_recv.recv().unwrap();
+            let conn = xous_kernel::connect(sid).expect("couldn't connect to server");
+            assert_eq!(
+                xous_kernel::send_message(conn, Message::Scalar(scalar(7))),
+                Err(xous_kernel::Error::AccessDenied)
+            );
+
+            let carton = xous_kernel::carton::Carton::from_bytes(b"Hello, world!");
+            assert_eq!(carton.lend(conn, 8), Err(xous_kernel::Error::AccessDenied));
+            assert_eq!(
+                xous_kernel::signal_events(conn, 1),
+                Err(xous_kernel::Error::AccessDenied)
+            );
+
+            // IDs that don't fit in the rights are refused unless every ID is allowed.
+            assert_eq!(
+                xous_kernel::send_message(conn, Message::Scalar(scalar(0x1_0000))),
+                Err(xous_kernel::Error::AccessDenied)
+            );
+
+            // Clients can't hand out more than they were given.
+            assert_eq!(
+                xous_kernel::connect_for_process_with_rights(
+                    xous_kernel::PID::new(1).unwrap(),
+                    sid,
+                    ConnectionRights::ALL
+                ),
+                Err(xous_kernel::Error::AccessDenied)
+            );
+
+            // Only the owner of the server can take rights away from another process.
+            let rights = ConnectionRights {
+                kinds: MessageKinds::SCALAR | MessageKinds::BLOCKING_SCALAR,
+                ..ConnectionRights::excluding_id(7)
+            };
+            assert_eq!(
+                xous_kernel::connect_for_process_with_rights(trusted_pid, sid, rights),
+                Err(xous_kernel::Error::AccessDenied)
+            );
+            narrowed_send.send(()).unwrap();
+            trusted_done_recv.recv().unwrap();
+
+            xous_kernel::send_message(conn, Message::Scalar(scalar(8)))
+                .expect("couldn't send allowed message");
+        }),
+    )
+    .expect("couldn't spawn untrusted client process");
+
+    xous_kernel::wait_process_as_thread(xous_server).expect("couldn't join server process");
+    xous_kernel::wait_process_as_thread(trusted_client)
+        .expect("couldn't join trusted client process");
+    xous_kernel::wait_process_as_thread(untrusted_client)
+        .expect("couldn't join untrusted client process");
+    shutdown_kernel();
+
+    main_thread.join().expect("couldn't join kernel process");
+}
+
+/// Test that a server can approve or turn down connections, and that clients
+/// wait for its decision.
+#[test]
+fn connect_approval() {
+    use xous_kernel::{Message, ScalarMessage, CONNECT_REQUEST_ID};
     let main_thread = start_kernel(SERVER_SPEC);
+    let (approved_sid_send, approved_sid_recv) = channel();
+    let (denied_sid_send, denied_sid_recv) = channel();
+    let (approved_done_send, approved_done_recv) = channel();
 
-    fn create_destroy_server(test_bytes: &'static [u8]) {
-        let (server_addr_send, server_addr_recv) = channel();
+    let xous_server = xous_kernel::create_process_as_thread(xous_kernel::ProcessArgsAsThread::new(
+        "connect_approval server",
+        move || {
+            let sid = xous_kernel::create_server().expect("couldn't create test server");
+            xous_kernel::require_connect_approval(sid, true)
+                .expect("couldn't require connection approval");
+            approved_sid_send.send(sid).unwrap();
+            denied_sid_send.send(sid).unwrap();
+
+            let answer_request = |approved| {
+                let envelope = xous_kernel::receive_message(sid).expect("couldn't receive message");
+                if let Message::BlockingScalar(ScalarMessage { id, arg1, .. }) = envelope.body {
+                    assert_eq!(id, CONNECT_REQUEST_ID);
+                    assert_eq!(Some(arg1 as u8), envelope.sender.pid().map(|p| p.get()));
+                } else {
+                    panic!("unexpected message");
+                }
+                xous_kernel::approve_connection(envelope.sender, approved)
+                    .expect("couldn't answer connection request");
+            };
 
-        let xous_server = xous_kernel::create_process_as_thread(
-            xous_kernel::ProcessArgsAsThread::new("process_restart_server server", move || {
-                let sid = xous_kernel::create_server_with_address(b"test_recreate_se")
-                    .expect("couldn't create test server");
-                server_addr_send.send(sid).unwrap();
-                let thr = xous_kernel::create_thread(move || {
-                    let envelope =
-                        xous_kernel::receive_message(sid).expect("couldn't receive messages");
-                    // println!("Received message from {}", envelope.sender);
-                    let message = envelope.body;
-                    if let xous_kernel::Message::Move(m) = message {
-                        let buf = m.buf;
-                        let bt =
-                            unsafe { core::slice::from_raw_parts_mut(buf.as_mut_ptr(), buf.len()) };
-                        assert_eq!(*test_bytes, *bt);
-                    // let s = String::from_utf8_lossy(&bt);
-                    // println!("Got message: {:?} -> \"{}\"", bt, s);
-                    } else {
-                        panic!("unexpected message type");
-                    }
+            // Approve the first client, which then sends a message.
+            answer_request(true);
+            let envelope = xous_kernel::receive_message(sid).expect("couldn't receive message");
+            assert_eq!(
+                envelope.body,
+                Message::Scalar(ScalarMessage {
+                    id: 1,
+                    arg1: 0,
+                    arg2: 0,
+                    arg3: 0,
+                    arg4: 0
                 })
-                .unwrap();
-                xous_kernel::wait_thread(thr).unwrap();
-            }),
-        )
-        .expect("couldn't spawn server process");
+            );
 
-        // Wait for the server to start up
-        let xous_client = xous_kernel::create_process_as_thread(
-            xous_kernel::ProcessArgsAsThread::new("process_restart_server client", move || {
-                let sid = server_addr_recv.recv().unwrap();
-                let conn = xous_kernel::try_connect(sid).expect("couldn't connect to server");
-                let msg = xous_kernel::carton::Carton::from_bytes(test_bytes);
-                xous_kernel::try_send_message(
+            // Turn down the second client.
+            answer_request(false);
+        },
+    ))
+    .expect("couldn't spawn server process");
+
+    let approved_client = xous_kernel::create_process_as_thread(
+        xous_kernel::ProcessArgsAsThread::new("connect_approval approved client", move || {
+            let sid = approved_sid_recv.recv().unwrap();
+            let conn = xous_kernel::connect(sid).expect("couldn't connect to server");
+
+            // Connecting again doesn't need another approval.
+            assert_eq!(xous_kernel::try_connect(sid), Ok(conn));
+
+            // Clients can't pretend to be the kernel.
+            let forged = ScalarMessage {
+                id: CONNECT_REQUEST_ID,
+                arg1: 0,
+                arg2: 0,
+                arg3: 0,
+                arg4: 0,
+            };
+            assert_eq!(
+                xous_kernel::send_message(conn, Message::BlockingScalar(forged)),
+                Err(xous_kernel::Error::AccessDenied)
+            );
+            xous_kernel::send_message(conn, Message::Scalar(ScalarMessage { id: 1, ..forged }))
+                .expect("couldn't send message");
+            approved_done_send.send(()).unwrap();
+        }),
+    )
+    .expect("couldn't spawn approved client process");
+
+    let denied_client = xous_kernel::create_process_as_thread(
+        xous_kernel::ProcessArgsAsThread::new("connect_approval denied client", move || {
+            let sid = denied_sid_recv.recv().unwrap();
+            approved_done_recv.recv().unwrap();
+            assert_eq!(
+                xous_kernel::try_connect(sid),
+                Err(xous_kernel::Error::AccessDenied)
+            );
+            assert_eq!(
+                xous_kernel::try_connect_for_process(xous_kernel::PID::new(1).unwrap(), sid),
+                Err(xous_kernel::Error::AccessDenied)
+            );
+            assert_eq!(
+                xous_kernel::connect(sid),
+                Err(xous_kernel::Error::AccessDenied)
+            );
+        }),
+    )
+    .expect("couldn't spawn denied client process");
+
+    xous_kernel::wait_process_as_thread(approved_client)
+        .expect("couldn't join approved client process");
+    xous_kernel::wait_process_as_thread(denied_client)
+        .expect("couldn't join denied client process");
+    xous_kernel::wait_process_as_thread(xous_server).expect("couldn't join server process");
+    shutdown_kernel();
+
+    main_thread.join().expect("couldn't join kernel process");
+}
+
+#[test]
+fn signal_events() {
+    let main_thread = start_kernel(SERVER_SPEC);
+    let (sid_send, sid_recv) = channel();
+    let (ack_send, ack_recv) = channel();
+    let (done_send, done_recv) = channel();
+
+    let xous_server = xous_kernel::create_process_as_thread(xous_kernel::ProcessArgsAsThread::new(
+        "signal_events server",
+        move || {
+            let sid = xous_kernel::create_server().expect("couldn't create test server");
+            assert_eq!(xous_kernel::try_wait_events(sid), Ok(0));
+            sid_send.send(sid).unwrap();
+
+            // Block until the client sets the first event.
+            assert_eq!(xous_kernel::wait_events(sid), Ok(0b001));
+            ack_send.send(()).unwrap();
+
+            // Events that arrive while nobody is waiting accumulate.
+            done_recv.recv().unwrap();
+            assert_eq!(xous_kernel::try_wait_events(sid), Ok(0b110));
+            assert_eq!(xous_kernel::try_wait_events(sid), Ok(0));
+        },
+    ))
+    .expect("couldn't spawn server process");
+
+    let xous_client = xous_kernel::create_process_as_thread(xous_kernel::ProcessArgsAsThread::new(
+        "signal_events client",
+        move || {
+            let sid = sid_recv.recv().unwrap();
+            let conn = xous_kernel::connect(sid).expect("couldn't connect to server");
+
+            // Only the owner of a server may wait for its events.
+            assert_eq!(
+                xous_kernel::try_wait_events(sid),
+                Err(xous_kernel::Error::ServerNotFound)
+            );
+            assert_eq!(
+                xous_kernel::signal_events(conn + 1, 0b001),
+                Err(xous_kernel::Error::ServerNotFound)
+            );
+
+            xous_kernel::signal_events(conn, 0b001).expect("couldn't signal event");
+            ack_recv.recv().unwrap();
+            xous_kernel::signal_events(conn, 0b010).expect("couldn't signal event");
+            xous_kernel::signal_events(conn, 0b100).expect("couldn't signal event");
+            done_send.send(()).unwrap();
+        },
+    ))
+    .expect("couldn't spawn client process");
+
+    xous_kernel::wait_process_as_thread(xous_client).expect("couldn't join client process");
+    xous_kernel::wait_process_as_thread(xous_server).expect("couldn't join server process");
+    shutdown_kernel();
+
+    main_thread.join().expect("couldn't join kernel process");
+}
+
+#[test]
+fn signal_on_message() {
+    let main_thread = start_kernel(SERVER_SPEC);
+    let (sid_send, sid_recv) = channel();
+    let (sent_send, sent_recv) = channel();
+    let (ack_send, ack_recv) = channel();
+    let (guarded_send, guarded_recv) = channel();
+
+    let xous_server = xous_kernel::create_process_as_thread(xous_kernel::ProcessArgsAsThread::new(
+        "signal_on_message server",
+        move || {
+            let sid = xous_kernel::create_server().expect("couldn't create test server");
+            let events = xous_kernel::create_server().expect("couldn't create event server");
+            let conn = xous_kernel::connect(events).expect("couldn't connect to event server");
+            sid_send.send(sid).unwrap();
+
+            // Messages can't set events that the connection may not set itself.
+            let guarded = xous_kernel::connect(guarded_recv.recv().unwrap())
+                .expect("couldn't connect to guarded server");
+            assert_eq!(
+                xous_kernel::signal_on_message(sid, guarded, 1),
+                Err(xous_kernel::Error::AccessDenied)
+            );
+
+            // A message that is already queued signals straight away.
+            sent_recv.recv().unwrap();
+            assert_eq!(xous_kernel::try_wait_events(events), Ok(0));
+            assert_eq!(
+                xous_kernel::signal_on_message(sid, guarded + 1, 0b10),
+                Err(xous_kernel::Error::ServerNotFound)
+            );
+            xous_kernel::signal_on_message(sid, conn, 0b10).expect("couldn't watch server");
+            assert_eq!(xous_kernel::try_wait_events(events), Ok(0b10));
+            assert!(xous_kernel::try_receive_message(sid).unwrap().is_some());
+
+            // Later messages wake a thread waiting for events.
+            ack_send.send(()).unwrap();
+            assert_eq!(xous_kernel::wait_events(events), Ok(0b10));
+            assert!(xous_kernel::try_receive_message(sid).unwrap().is_some());
+
+            // Nothing is signalled once the server stops being watched.
+            xous_kernel::signal_on_message(sid, conn, 0).expect("couldn't stop watching");
+            ack_send.send(()).unwrap();
+            sent_recv.recv().unwrap();
+            assert_eq!(xous_kernel::try_wait_events(events), Ok(0));
+            assert!(xous_kernel::try_receive_message(sid).unwrap().is_some());
+        },
+    ))
+    .expect("couldn't spawn server process");
+
+    let xous_client = xous_kernel::create_process_as_thread(xous_kernel::ProcessArgsAsThread::new(
+        "signal_on_message client",
+        move || {
+            let sid = sid_recv.recv().unwrap();
+            let conn = xous_kernel::connect(sid).expect("couldn't connect to server");
+
+            // Only the owner of a server may watch it.
+            assert_eq!(
+                xous_kernel::signal_on_message(sid, conn, 1),
+                Err(xous_kernel::Error::ServerNotFound)
+            );
+
+            let guarded = xous_kernel::create_server().expect("couldn't create guarded server");
+            xous_kernel::set_connection_rights(
+                guarded,
+                None,
+                xous_kernel::ConnectionRights::kinds(xous_kernel::MessageKinds::SCALAR),
+            )
+            .expect("couldn't set default rights");
+            guarded_send.send(guarded).unwrap();
+
+            for id in 1..=3 {
+                if id > 1 {
+                    ack_recv.recv().unwrap();
+                }
+                xous_kernel::send_message(
                     conn,
-                    xous_kernel::Message::Move(msg.into_message(0)),
+                    xous_kernel::Message::Scalar(xous_kernel::ScalarMessage {
+                        id,
+                        arg1: 0,
+                        arg2: 0,
+                        arg3: 0,
+                        arg4: 0,
+                    }),
                 )
-                .expect("couldn't send a message");
-            }),
-        )
-        .expect("couldn't start client process");
+                .expect("couldn't send message");
+                if id != 2 {
+                    sent_send.send(()).unwrap();
+                }
+            }
+        },
+    ))
+    .expect("couldn't spawn client process");
 
-        xous_kernel::wait_process_as_thread(xous_server).expect("couldn't join server process");
-        xous_kernel::wait_process_as_thread(xous_client).expect("couldn't join client process");
-    }
+    xous_kernel::wait_process_as_thread(xous_client).expect("couldn't join client process");
+    xous_kernel::wait_process_as_thread(xous_server).expect("couldn't join server process");
+    shutdown_kernel();
 
-    // create_destroy_server(test_bytes);
-    create_destroy_server(test_bytes);
+    main_thread.join().expect("couldn't join kernel process");
+}
 
-    // Any process ought to be able to shut down the system currently.
+#[test]
+fn executor() {
+    use xous_kernel::executor::{join, select, Either, Executor};
+    use xous_kernel::{Message, ScalarMessage};
+
+    let main_thread = start_kernel(SERVER_SPEC);
+    let (sid_send, sid_recv) = channel();
+    let (replier_send, replier_recv) = channel();
+    let (asked_send, asked_recv) = channel();
+    let (release_send, release_recv) = channel();
+    let (done_send, done_recv) = channel();
+
+    fn scalar(id: usize, arg1: usize) -> Message {
+        Message::Scalar(ScalarMessage {
+            id,
+            arg1,
+            arg2: 0,
+            arg3: 0,
+            arg4: 0,
+        })
+    }
+
+    // Answers blocking scalars with twice their argument, holding the second
+    // reply back until it's told to send it.
+    let replier = xous_kernel::create_process_as_thread(xous_kernel::ProcessArgsAsThread::new(
+        "executor replier",
+        move || {
+            let sid = xous_kernel::create_server().expect("couldn't create replier server");
+            replier_send.send(sid).unwrap();
+            for id in 1..=2 {
+                let envelope =
+                    xous_kernel::receive_message(sid).expect("couldn't receive message");
+                if id == 2 {
+                    asked_send.send(()).unwrap();
+                    release_recv.recv().unwrap();
+                }
+                if let Message::BlockingScalar(scalar) = &envelope.body {
+                    assert_eq!(scalar.id, id);
+                    xous_kernel::return_scalar(envelope.sender, scalar.arg1 * 2)
+                        .expect("couldn't return scalar");
+                } else {
+                    panic!("unexpected message: {:?}", envelope.body);
+                }
+            }
+        },
+    ))
+    .expect("couldn't spawn replier process");
+
+    let xous_server = xous_kernel::create_process_as_thread(xous_kernel::ProcessArgsAsThread::new(
+        "executor server",
+        move || {
+            let executor = Executor::new().expect("couldn't create executor");
+            let first = xous_kernel::create_server().expect("couldn't create first server");
+            let second = xous_kernel::create_server().expect("couldn't create second server");
+            let replier = xous_kernel::connect(replier_recv.recv().unwrap())
+                .expect("couldn't connect to replier");
+            sid_send.send((first, second)).unwrap();
+
+            // Wait on both servers and a reply at once, on this one thread.
+            let ((a, b), reply) = executor.block_on(join(
+                join(
+                    executor.receive_message(first),
+                    executor.receive_message(second),
+                ),
+                executor.send_message(
+                    replier,
+                    Message::BlockingScalar(ScalarMessage {
+                        id: 1,
+                        arg1: 21,
+                        arg2: 0,
+                        arg3: 0,
+                        arg4: 0,
+                    }),
+                ),
+            ));
+            assert_eq!(a.expect("couldn't receive message").body, scalar(1, 10));
+            assert_eq!(b.expect("couldn't receive message").body, scalar(2, 20));
+            assert_eq!(reply, Ok(xous_kernel::Result::Scalar1(42)));
+
+            // The message, which is only sent once the replier has the blocking
+            // scalar, wins the race. Giving up on the reply doesn't wait for it.
+            let next = executor.block_on(select(
+                executor.send_message(
+                    replier,
+                    Message::BlockingScalar(ScalarMessage {
+                        id: 2,
+                        arg1: 0,
+                        arg2: 0,
+                        arg3: 0,
+                        arg4: 0,
+                    }),
+                ),
+                executor.receive_message(second),
+            ));
+            match next {
+                Either::Right(Ok(envelope)) => assert_eq!(envelope.body, scalar(3, 30)),
+                other => panic!("unexpected result: {:?}", other),
+            }
+            release_send.send(()).unwrap();
+            done_send.send(()).unwrap();
+        },
+    ))
+    .expect("couldn't spawn server process");
+
+    let xous_client = xous_kernel::create_process_as_thread(xous_kernel::ProcessArgsAsThread::new(
+        "executor client",
+        move || {
+            let (first, second) = sid_recv.recv().unwrap();
+            let first = xous_kernel::connect(first).expect("couldn't connect to first server");
+            let second = xous_kernel::connect(second).expect("couldn't connect to second server");
+            xous_kernel::send_message(second, scalar(2, 20)).expect("couldn't send message");
+            xous_kernel::send_message(first, scalar(1, 10)).expect("couldn't send message");
+            asked_recv.recv().unwrap();
+            xous_kernel::send_message(second, scalar(3, 30)).expect("couldn't send message");
+
+            // Exiting would tell the first server that this client has gone.
+            done_recv.recv().unwrap();
+        },
+    ))
+    .expect("couldn't spawn client process");
+
+    xous_kernel::wait_process_as_thread(xous_client).expect("couldn't join client process");
+    xous_kernel::wait_process_as_thread(xous_server).expect("couldn't join server process");
+    xous_kernel::wait_process_as_thread(replier).expect("couldn't join replier process");
+    shutdown_kernel();
+
+    main_thread.join().expect("couldn't join kernel process");
+}
+
+#[test]
+fn futex_sync() {
+    use std::sync::atomic::AtomicUsize;
+    use std::sync::Arc;
+    use xous_kernel::sync::{Condvar, Mutex, RwLock};
+
+    let main_thread = start_kernel(SERVER_SPEC);
+
+    let process = xous_kernel::create_process_as_thread(xous_kernel::ProcessArgsAsThread::new(
+        "futex_sync process",
+        move || {
+            // A futex that has already changed doesn't block, and waking one
+            // that nobody is waiting on does nothing.
+            let futex = AtomicUsize::new(1);
+            assert_eq!(xous_kernel::futex_wait(&futex, 0), Ok(()));
+            assert_eq!(xous_kernel::futex_wake(&futex, 1), Ok(0));
+
+            let shared = Arc::new((Mutex::new(0), Condvar::new(), RwLock::new(0)));
+            let mut workers = vec![];
+            for _ in 0..3 {
+                let shared = shared.clone();
+                workers.push(
+                    xous_kernel::create_thread(move || {
+                        let (count, finished, total) = &*shared;
+                        for _ in 0..20 {
+                            // Hold on to the lock across a syscall so that the
+                            // other threads have to wait for it.
+                            let mut count = count.lock();
+                            let before = *count;
+                            xous_kernel::yield_slice();
+                            *count = before + 1;
+                            *total.write() += 1;
+                        }
+                        finished.notify_all();
+                    })
+                    .expect("couldn't spawn worker thread"),
+                );
+            }
+
+            let (count, finished, total) = &*shared;
+            let count = finished.wait_while(count.lock(), |count| *count < 60);
+            assert_eq!(*count, 60);
+            assert_eq!(*total.read(), 60);
+            drop(count);
+
+            for worker in workers {
+                xous_kernel::wait_thread(worker).expect("couldn't wait for thread");
+            }
+        },
+    ))
+    .expect("couldn't spawn futex_sync process");
+
+    xous_kernel::wait_process_as_thread(process).expect("couldn't join futex_sync process");
+    shutdown_kernel();
+
+    main_thread.join().expect("couldn't join kernel process");
+}
+
+#[test]
+fn join_thread() {
+    use std::cell::Cell;
+    use std::sync::mpsc::channel;
+
+    xous_kernel::thread_local!(static COUNTER: Cell<usize> = Cell::new(0));
+
+    let main_thread = start_kernel(SERVER_SPEC);
+
+    let process = xous_kernel::create_process_as_thread(xous_kernel::ProcessArgsAsThread::new(
+        "join_thread process",
+        move || {
+            // A thread's return value is handed back when it's joined.
+            let thread = xous_kernel::create_thread(|| 7).expect("couldn't spawn thread");
+            assert_eq!(xous_kernel::wait_thread(thread), Ok(7));
+
+            // An exit code is kept for whoever joins, whether they start
+            // waiting before or after the thread exits.
+            let (sender, receiver) = channel();
+            let exiting = xous_kernel::create_thread(move || {
+                sender.send(xous_kernel::current_tid().unwrap()).unwrap();
+                std::thread::sleep(std::time::Duration::from_millis(100));
+                xous_kernel::exit_thread(42)
+            })
+            .expect("couldn't spawn exiting thread");
+            let tid = receiver.recv().unwrap();
+            assert_eq!(xous_kernel::join_thread(tid), Ok(42));
+            assert_eq!(
+                xous_kernel::join_thread(tid),
+                Err(xous_kernel::Error::InvalidThread)
+            );
+            drop(exiting);
+
+            let this_tid = xous_kernel::current_tid().unwrap();
+            assert_eq!(
+                xous_kernel::join_thread(this_tid),
+                Err(xous_kernel::Error::InvalidThread)
+            );
+
+            // Each thread sees its own copy of a thread-local, and a thread
+            // that reuses an old thread ID starts with a fresh copy.
+            COUNTER.with(|counter| counter.set(5));
+            let workers: Vec<_> = (0..3)
+                .map(|_| {
+                    xous_kernel::create_thread(|| {
+                        for _ in 0..3 {
+                            COUNTER.with(|counter| counter.set(counter.get() + 1));
+                        }
+                        COUNTER.with(|counter| counter.get())
+                    })
+                    .expect("couldn't spawn worker thread")
+                })
+                .collect();
+            for worker in workers {
+                assert_eq!(xous_kernel::wait_thread(worker), Ok(3));
+            }
+            let reused = xous_kernel::create_thread(|| COUNTER.with(|counter| counter.get()))
+                .expect("couldn't spawn thread");
+            assert_eq!(xous_kernel::wait_thread(reused), Ok(0));
+            assert_eq!(COUNTER.with(|counter| counter.get()), 5);
+
+            // A thread whose handle is dropped frees its own slot, so there
+            // is always room for more, well past the number of thread slots.
+            for _ in 0..(2 * crate::arch::process::MAX_THREAD) {
+                let (sender, receiver) = channel();
+                drop(
+                    xous_kernel::create_thread(move || sender.send(()).unwrap())
+                        .expect("couldn't spawn detached thread"),
+                );
+                receiver.recv().unwrap();
+            }
+        },
+    ))
+    .expect("couldn't spawn join_thread process");
+
+    xous_kernel::wait_process_as_thread(process).expect("couldn't join join_thread process");
+    shutdown_kernel();
+
+    main_thread.join().expect("couldn't join kernel process");
+}
+
+#[test]
+fn wait_process() {
+    let main_thread = start_kernel(SERVER_SPEC);
+
+    let process = xous_kernel::create_process_as_thread(xous_kernel::ProcessArgsAsThread::new(
+        "wait_process parent",
+        || {
+            // An exit code is kept until the parent collects it, whether it
+            // starts waiting before or after the child terminates.
+            let early = xous_kernel::create_process_as_thread(
+                xous_kernel::ProcessArgsAsThread::new("wait_process early child", || {
+                    xous_kernel::terminate_process(3)
+                }),
+            )
+            .expect("couldn't spawn early child");
+            let early_pid = early.pid();
+            xous_kernel::wait_process_as_thread(early).expect("couldn't join early child");
+            assert_eq!(xous_kernel::join_process(early_pid), Ok(3));
+            assert_eq!(
+                xous_kernel::join_process(early_pid),
+                Err(xous_kernel::Error::ProcessNotFound)
+            );
+
+            let late = xous_kernel::create_process_as_thread(
+                xous_kernel::ProcessArgsAsThread::new("wait_process late child", || {
+                    std::thread::sleep(std::time::Duration::from_millis(100));
+                    xous_kernel::terminate_process(42)
+                }),
+            )
+            .expect("couldn't spawn late child");
+            assert_eq!(xous_kernel::join_process(late.pid()), Ok(42));
+            xous_kernel::wait_process_as_thread(late).expect("couldn't join late child");
+
+            // A child that simply returns exits with 0.
+            let returning = xous_kernel::create_process_as_thread(
+                xous_kernel::ProcessArgsAsThread::new("wait_process returning child", || ()),
+            )
+            .expect("couldn't spawn returning child");
+            assert_eq!(xous_kernel::join_process(returning.pid()), Ok(0));
+            xous_kernel::wait_process_as_thread(returning).expect("couldn't join returning child");
+
+            // Only a process' own children can be waited for.
+            assert_eq!(
+                xous_kernel::join_process(xous_kernel::PID::new(1).unwrap()),
+                Err(xous_kernel::Error::ProcessNotChild)
+            );
+        },
+    ))
+    .expect("couldn't spawn wait_process parent");
+
+    xous_kernel::wait_process_as_thread(process).expect("couldn't join wait_process parent");
+    shutdown_kernel();
+
+    main_thread.join().expect("couldn't join kernel process");
+}
+
+/// Test that one process can have multiple contexts
+#[test]
+fn multiple_contexts() {
+    // ::debug_here::debug_here!();
+    // Start the kernel in its own thread
+    let main_thread = start_kernel(SERVER_SPEC);
+
+    let internal_server = xous_kernel::create_process_as_thread(
+        xous_kernel::ProcessArgsAsThread::new("multiple_contexts process", move || {
+            let server = xous_kernel::create_server().expect("couldn't create server");
+            let connection =
+                xous_kernel::try_connect(server).expect("couldn't connect to our own server");
+            let msg_contents = xous_kernel::ScalarMessage {
+                id: 1,
+                arg1: 2,
+                arg2: 3,
+                arg3: 4,
+                arg4: 5,
+            };
+
+            let mut server_threads = vec![];
+            for _ in 1..crate::arch::process::MAX_THREAD {
+                server_threads.push(
+                    xous_kernel::create_thread(move || {
+                        let msg =
+                            xous_kernel::receive_message(server).expect("couldn't receive message");
+                        assert_eq!(msg.body, xous_kernel::Message::Scalar(msg_contents));
+                    })
+                    .expect("couldn't spawn client thread"),
+                );
+            }
+
+            for _ in &server_threads {
+                xous_kernel::try_send_message(
+                    connection,
+                    xous_kernel::Message::Scalar(msg_contents),
+                )
+                .expect("couldn't send message");
+            }
+            for server_thread in server_threads.into_iter() {
+                xous_kernel::wait_thread(server_thread).expect("couldn't wait for thread");
+            }
+        }),
+    )
+    .expect("couldn't create internal server");
+
+    xous_kernel::wait_process_as_thread(internal_server)
+        .expect("couldn't join internal_server process");
+
+    // Any process ought to be able to shut down the system currently.
+    shutdown_kernel();
+
+    main_thread.join().expect("couldn't join kernel process");
+}
+
+#[test]
+fn multiple_multiple_contexts() {
+    for _ in 0..5 {
+        multiple_contexts();
+    }
+}
+
+/// Test that a server can be restarted and the kernel doesn't crash
+#[test]
+fn process_restart_server() {
+    let test_str = "Hello, world!";
+    let test_bytes = test_str.as_bytes();
+
+    let main_thread = start_kernel(SERVER_SPEC);
+
+    fn create_destroy_server(test_bytes: &'static [u8]) {
+        let (server_addr_send, server_addr_recv) = channel();
+
+        let xous_server = xous_kernel::create_process_as_thread(
+            xous_kernel::ProcessArgsAsThread::new("process_restart_server server", move || {
+                let sid = xous_kernel::create_server_with_address(b"test_recreate_se")
+                    .expect("couldn't create test server");
+                server_addr_send.send(sid).unwrap();
+                let thr = xous_kernel::create_thread(move || {
+                    let envelope =
+                        xous_kernel::receive_message(sid).expect("couldn't receive messages");
+                    // println!("Received message from {}", envelope.sender);
+                    let message = envelope.body;
+                    if let xous_kernel::Message::Move(m) = message {
+                        let buf = m.buf;
+                        let bt =
+                            unsafe { core::slice::from_raw_parts_mut(buf.as_mut_ptr(), buf.len()) };
+                        assert_eq!(*test_bytes, *bt);
+                    // let s = String::from_utf8_lossy(&bt);
+                    // println!("Got message: {:?} -> \"{}\"", bt, s);
+                    } else {
+                        panic!("unexpected message type");
+                    }
+                })
+                .unwrap();
+                xous_kernel::wait_thread(thr).unwrap();
+            }),
+        )
+        .expect("couldn't spawn server process");
+
+        // Wait for the server to start up
+        let xous_client = xous_kernel::create_process_as_thread(
+            xous_kernel::ProcessArgsAsThread::new("process_restart_server client", move || {
+                let sid = server_addr_recv.recv().unwrap();
+                let conn = xous_kernel::try_connect(sid).expect("couldn't connect to server");
+                let msg = xous_kernel::carton::Carton::from_bytes(test_bytes);
+                xous_kernel::try_send_message(
+                    conn,
+                    xous_kernel::Message::Move(msg.into_message(0)),
+                )
+                .expect("couldn't send a message");
+            }),
+        )
+        .expect("couldn't start client process");
+
+        xous_kernel::wait_process_as_thread(xous_server).expect("couldn't join server process");
+        xous_kernel::wait_process_as_thread(xous_client).expect("couldn't join client process");
+    }
+
+    // create_destroy_server(test_bytes);
+    create_destroy_server(test_bytes);
+
+    // Any process ought to be able to shut down the system currently.
+    shutdown_kernel();
+
+    main_thread.join().expect("couldn't join kernel process");
+}
+
+/// Test that a client whose server has terminated is told so, and can connect
+/// again once a new server is created at the same address. This is what lets
+/// a supervised service be restarted without its clients being restarted too.
+#[test]
+fn process_restart_client_reconnects() {
+    use xous_kernel::{Message, ScalarMessage};
+
+    let main_thread = start_kernel(SERVER_SPEC);
+
+    fn answer_once(name: &'static str) -> xous_kernel::arch::ProcessHandleAsThread {
+        xous_kernel::create_process_as_thread(xous_kernel::ProcessArgsAsThread::new(
+            name,
+            move || {
+                let sid = xous_kernel::create_server_with_address(b"test_reconnect_s")
+                    .expect("couldn't create test server");
+                let envelope = xous_kernel::receive_message(sid).expect("couldn't receive message");
+                if let Message::BlockingScalar(msg) = envelope.body {
+                    xous_kernel::return_scalar(envelope.sender, msg.arg1 + 1)
+                        .expect("couldn't return scalar");
+                } else {
+                    panic!("unexpected message type");
+                }
+            },
+        ))
+        .expect("couldn't spawn server process")
+    }
+
+    fn ask(cid: xous_kernel::CID, arg1: usize) -> Result<xous_kernel::Result, xous_kernel::Error> {
+        xous_kernel::send_message(
+            cid,
+            Message::BlockingScalar(ScalarMessage::from_usize(0, arg1, 0, 0, 0)),
+        )
+    }
+
+    let (stopped_send, stopped_recv) = channel();
+    let (checked_send, checked_recv) = channel();
+
+    let first_server = answer_once("process_restart_client_reconnects first server");
+    let client = xous_kernel::create_process_as_thread(xous_kernel::ProcessArgsAsThread::new(
+        "process_restart_client_reconnects client",
+        move || {
+            let sid = xous_kernel::SID::from_bytes(b"test_reconnect_s").unwrap();
+            let cid = xous_kernel::connect(sid).expect("couldn't connect to server");
+            assert_eq!(ask(cid, 1), Ok(xous_kernel::Result::Scalar1(2)));
+
+            // Once the server's process is gone, so is the server. Its thread
+            // can finish before the kernel has seen it exit, so wait for that.
+            stopped_recv.recv().unwrap();
+            while xous_kernel::try_connect(sid) != Err(xous_kernel::Error::ServerNotFound) {
+                xous_kernel::yield_slice();
+            }
+            assert_eq!(ask(cid, 1), Err(xous_kernel::Error::ServerNotFound));
+            checked_send.send(()).unwrap();
+
+            // Connecting waits for the new server to appear.
+            let cid = xous_kernel::connect(sid).expect("couldn't reconnect to server");
+            assert_eq!(ask(cid, 41), Ok(xous_kernel::Result::Scalar1(42)));
+        },
+    ))
+    .expect("couldn't start client process");
+
+    xous_kernel::wait_process_as_thread(first_server).expect("couldn't join first server");
+    stopped_send.send(()).unwrap();
+    checked_recv.recv().unwrap();
+
+    let second_server = answer_once("process_restart_client_reconnects second server");
+    xous_kernel::wait_process_as_thread(second_server).expect("couldn't join second server");
+    xous_kernel::wait_process_as_thread(client).expect("couldn't join client process");
+
+    shutdown_kernel();
+    main_thread.join().expect("couldn't join kernel process");
+}
+
+/// Test that a server that asks for it hears about it when one of its clients
+/// terminates, that other servers don't, and that clients can't send that
+/// notice themselves.
+#[test]
+fn client_disconnected_notice() {
+    use xous_kernel::{Message, ScalarMessage, CLIENT_DISCONNECTED_ID};
+    let main_thread = start_kernel(SERVER_SPEC);
+    let (server_addr_send, server_addr_recv) = channel();
+
+    let xous_server = xous_kernel::create_process_as_thread(xous_kernel::ProcessArgsAsThread::new(
+        "client_disconnected_notice server",
+        move || {
+            let sid = xous_kernel::create_server().expect("couldn't create test server");
+            xous_kernel::notify_disconnects(sid, true).expect("couldn't ask for notices");
+            let quiet_sid = xous_kernel::create_server().expect("couldn't create test server");
+            server_addr_send.send((sid, quiet_sid)).unwrap();
+
+            let envelope = xous_kernel::receive_message(sid).expect("couldn't receive message");
+            let client_pid = envelope.sender.pid().expect("message had no sender");
+            assert_eq!(envelope.body.id(), 1);
+
+            // Whatever the client queued arrives before the notice does.
+            let envelope = xous_kernel::receive_message(sid).expect("couldn't receive message");
+            assert_eq!(
+                envelope.body,
+                Message::Scalar(ScalarMessage {
+                    id: CLIENT_DISCONNECTED_ID,
+                    arg1: client_pid.get() as usize,
+                    arg2: 0,
+                    arg3: 0,
+                    arg4: 0
+                })
+            );
+            assert_eq!(xous_kernel::try_receive_message(quiet_sid), Ok(None));
+        },
+    ))
+    .expect("couldn't spawn server process");
+
+    let xous_client = xous_kernel::create_process_as_thread(xous_kernel::ProcessArgsAsThread::new(
+        "client_disconnected_notice client",
+        move || {
+            let (sid, quiet_sid) = server_addr_recv.recv().unwrap();
+            xous_kernel::connect(quiet_sid).expect("couldn't connect to server");
+            let conn = xous_kernel::connect(sid).expect("couldn't connect to server");
+            let forged = ScalarMessage {
+                id: CLIENT_DISCONNECTED_ID,
+                arg1: 1,
+                arg2: 0,
+                arg3: 0,
+                arg4: 0,
+            };
+            assert_eq!(
+                xous_kernel::send_message(conn, Message::Scalar(forged)),
+                Err(xous_kernel::Error::AccessDenied)
+            );
+            xous_kernel::send_message(conn, Message::Scalar(ScalarMessage { id: 1, ..forged }))
+                .expect("couldn't send message");
+        },
+    ))
+    .expect("couldn't spawn client process");
+
+    xous_kernel::wait_process_as_thread(xous_client).expect("couldn't join client process");
+    xous_kernel::wait_process_as_thread(xous_server).expect("couldn't join server process");
+
+    shutdown_kernel();
+    main_thread.join().expect("couldn't join kernel process");
+}
+
+/// Test that any process can find out which process owns a server, until the
+/// server is destroyed.
+#[test]
+fn server_owner() {
+    let main_thread = start_kernel(SERVER_SPEC);
+    let (server_addr_send, server_addr_recv) = channel();
+    let (checked_send, checked_recv) = channel();
+    let (destroyed_send, destroyed_recv) = channel();
+
+    let xous_server = xous_kernel::create_process_as_thread(xous_kernel::ProcessArgsAsThread::new(
+        "server_owner server",
+        move || {
+            let sid = xous_kernel::create_server().expect("couldn't create test server");
+            server_addr_send.send(sid).unwrap();
+            checked_recv.recv().unwrap();
+            xous_kernel::destroy_server(sid).expect("couldn't destroy server");
+            destroyed_send.send(()).unwrap();
+        },
+    ))
+    .expect("couldn't spawn server process");
+    let server_pid = xous_server.pid();
+
+    let sid = server_addr_recv.recv().unwrap();
+    assert_eq!(xous_kernel::server_owner(sid), Ok(server_pid));
+    checked_send.send(()).unwrap();
+    destroyed_recv.recv().unwrap();
+    assert_eq!(
+        xous_kernel::server_owner(sid),
+        Err(xous_kernel::Error::ServerNotFound)
+    );
+
+    xous_kernel::wait_process_as_thread(xous_server).expect("couldn't join server process");
+
+    shutdown_kernel();
+    main_thread.join().expect("couldn't join kernel process");
+}
+
+/// Test that a client waiting on a response is woken up with an error when
+/// the server's process terminates without answering.
+#[test]
+fn server_terminated_while_blocked() {
+    use xous_kernel::{Message, ScalarMessage};
+    let main_thread = start_kernel(SERVER_SPEC);
+    let (server_addr_send, server_addr_recv) = channel();
+
+    let xous_server = xous_kernel::create_process_as_thread(xous_kernel::ProcessArgsAsThread::new(
+        "server_terminated_while_blocked server",
+        move || {
+            let sid = xous_kernel::create_server().expect("couldn't create test server");
+            server_addr_send.send(sid).unwrap();
+
+            // Take the message, then exit without responding to it.
+            xous_kernel::receive_message(sid).expect("couldn't receive message");
+        },
+    ))
+    .expect("couldn't spawn server process");
+
+    let xous_client = xous_kernel::create_process_as_thread(xous_kernel::ProcessArgsAsThread::new(
+        "server_terminated_while_blocked client",
+        move || {
+            let sid = server_addr_recv.recv().unwrap();
+            let conn = xous_kernel::connect(sid).expect("couldn't connect to server");
+            let msg = ScalarMessage::from_usize(1, 0, 0, 0, 0);
+            assert_eq!(
+                xous_kernel::send_message(conn, Message::BlockingScalar(msg)),
+                Err(xous_kernel::Error::ProcessTerminated)
+            );
+
+            // The connection now leads nowhere.
+            assert_eq!(
+                xous_kernel::send_message(conn, Message::BlockingScalar(msg)),
+                Err(xous_kernel::Error::ServerNotFound)
+            );
+        },
+    ))
+    .expect("couldn't spawn client process");
+
+    xous_kernel::wait_process_as_thread(xous_server).expect("couldn't join server process");
+    xous_kernel::wait_process_as_thread(xous_client).expect("couldn't join client process");
+
+    shutdown_kernel();
+    main_thread.join().expect("couldn't join kernel process");
+}
+
+/// Test that the kernel reports how many pages a process has lent out and
+/// borrowed, and that both go back to zero once the memory is returned.
+#[test]
+fn memory_stats() {
+    use xous_kernel::MemoryFlags;
+    let main_thread = start_kernel(SERVER_SPEC);
+    let (server_addr_send, server_addr_recv) = channel();
+    let (server_pid_send, server_pid_recv) = channel();
+
+    let xous_server = xous_kernel::create_process_as_thread(xous_kernel::ProcessArgsAsThread::new(
+        "memory_stats server",
+        move || {
+            let sid = xous_kernel::create_server().expect("couldn't create test server");
+            server_addr_send.send(sid).unwrap();
+            let server_pid = server_pid_recv.recv().unwrap();
+
+            let envelope = xous_kernel::receive_message(sid).expect("couldn't receive message");
+            let client_pid = envelope.sender.pid().expect("message had no sender");
+            if let xous_kernel::Message::MutableBorrow(m) = envelope.body {
+                let client = xous_kernel::memory_stats(client_pid).expect("couldn't get stats");
+                assert_eq!(client.lent_pages, 2);
+                assert_eq!(client.lent_to_servers, 0);
+                let server = xous_kernel::memory_stats(server_pid).expect("couldn't get stats");
+                assert_eq!(server.lent_pages, 0);
+                assert_eq!(server.lent_to_servers, 2);
+                xous_kernel::return_memory(envelope.sender, m.buf).expect("couldn't return memory");
+            } else {
+                panic!("unexpected message type");
+            }
+
+            let server = xous_kernel::memory_stats(server_pid).expect("couldn't get stats");
+            assert_eq!(server.lent_to_servers, 0);
+        },
+    ))
+    .expect("couldn't start server");
+    server_pid_send.send(xous_server.pid()).unwrap();
+
+    let xous_client = xous_kernel::create_process_as_thread(xous_kernel::ProcessArgsAsThread::new(
+        "memory_stats client",
+        move || {
+            let sid = server_addr_recv.recv().unwrap();
+            let conn = xous_kernel::connect(sid).expect("couldn't connect to server");
+            let range = xous_kernel::map_memory(None, None, 8192, MemoryFlags::R | MemoryFlags::W)
+                .expect("couldn't allocate buffer");
+            xous_kernel::send_message(
+                conn,
+                xous_kernel::Message::MutableBorrow(xous_kernel::MemoryMessage {
+                    id: 0,
+                    buf: range,
+                    offset: None,
+                    valid: None,
+                }),
+            )
+            .expect("couldn't lend buffer");
+        },
+    ))
+    .expect("couldn't start client");
+    let client_pid = xous_client.pid();
+
+    xous_kernel::wait_process_as_thread(xous_client).expect("couldn't join client process");
+    xous_kernel::wait_process_as_thread(xous_server).expect("couldn't join server process");
+
+    // Neither process exists any more.
+    while xous_kernel::memory_stats(client_pid) != Err(xous_kernel::Error::ProcessNotFound) {
+        xous_kernel::yield_slice();
+    }
+
+    shutdown_kernel();
+    main_thread.join().expect("couldn't join kernel process");
+}
+
+/// Test that a process can share memory with another one, that each sees what
+/// the other writes, and that the memory stays around until both give it up.
+#[test]
+fn shared_memory() {
+    use xous_kernel::{MemoryFlags, Message, ScalarMessage};
+    let main_thread = start_kernel(SERVER_SPEC);
+    let (server_addr_send, server_addr_recv) = channel();
+
+    let xous_owner = xous_kernel::create_process_as_thread(xous_kernel::ProcessArgsAsThread::new(
+        "shared_memory owner",
+        move || {
+            let sid = xous_kernel::create_server().expect("couldn't create test server");
+            let (id, range) =
+                xous_kernel::create_shared_memory(4096, MemoryFlags::R | MemoryFlags::W)
+                    .expect("couldn't create shared memory");
+            let words = range.as_mut_ptr() as *mut usize;
+            unsafe { words.write_volatile(42) };
+            server_addr_send.send(sid).unwrap();
+
+            // Hand the region to whoever asks for it, but no more of it than
+            // we have ourselves.
+            let envelope = xous_kernel::receive_message(sid).expect("couldn't receive message");
+            let client_pid = envelope.sender.pid().expect("message had no sender");
+            assert_eq!(
+                xous_kernel::grant_shared_memory(id, client_pid, MemoryFlags::X),
+                Err(xous_kernel::Error::AccessDenied)
+            );
+            xous_kernel::grant_shared_memory(id, client_pid, MemoryFlags::R | MemoryFlags::W)
+                .expect("couldn't grant shared memory");
+            assert_eq!(
+                xous_kernel::grant_shared_memory(id, client_pid, MemoryFlags::R),
+                Err(xous_kernel::Error::MemoryInUse)
+            );
+            xous_kernel::return_scalar(envelope.sender, id).expect("couldn't return scalar");
+
+            // The client writes its answer before asking again.
+            let envelope = xous_kernel::receive_message(sid).expect("couldn't receive message");
+            assert_eq!(unsafe { words.add(1).read_volatile() }, 43);
+
+            // Shared memory can't be unmapped like ordinary memory.
+            assert_eq!(
+                xous_kernel::unmap_memory(range),
+                Err(xous_kernel::Error::MemoryInUse)
+            );
+            xous_kernel::unmap_shared_memory(id).expect("couldn't unmap shared memory");
+            assert_eq!(
+                xous_kernel::map_shared_memory(id),
+                Err(xous_kernel::Error::AccessDenied)
+            );
+            xous_kernel::return_scalar(envelope.sender, 0).expect("couldn't return scalar");
+        },
+    ))
+    .expect("couldn't spawn owner process");
+
+    let xous_client = xous_kernel::create_process_as_thread(xous_kernel::ProcessArgsAsThread::new(
+        "shared_memory client",
+        move || {
+            let sid = server_addr_recv.recv().unwrap();
+            let conn = xous_kernel::connect(sid).expect("couldn't connect to server");
+            let ask = || {
+                match xous_kernel::send_message(
+                    conn,
+                    Message::BlockingScalar(ScalarMessage::from_usize(0, 0, 0, 0, 0)),
+                ) {
+                    Ok(xous_kernel::Result::Scalar1(value)) => value,
+                    other => panic!("unexpected response: {:?}", other),
+                }
+            };
+
+            let id = ask();
+            let range = xous_kernel::map_shared_memory(id).expect("couldn't map shared memory");
+            assert_eq!(
+                xous_kernel::map_shared_memory(id),
+                Err(xous_kernel::Error::MemoryInUse)
+            );
+            let words = range.as_mut_ptr() as *mut usize;
+            assert_eq!(unsafe { words.read_volatile() }, 42);
+            unsafe { words.add(1).write_volatile(43) };
+
+            // The owner gives up its reference, but the memory is still ours.
+            ask();
+            assert_eq!(unsafe { words.read_volatile() }, 42);
+            xous_kernel::unmap_shared_memory(id).expect("couldn't unmap shared memory");
+            assert_eq!(
+                xous_kernel::unmap_shared_memory(id),
+                Err(xous_kernel::Error::AccessDenied)
+            );
+        },
+    ))
+    .expect("couldn't spawn client process");
+
+    xous_kernel::wait_process_as_thread(xous_client).expect("couldn't join client process");
+    xous_kernel::wait_process_as_thread(xous_owner).expect("couldn't join owner process");
+
+    shutdown_kernel();
+    main_thread.join().expect("couldn't join kernel process");
+}
+
+/// Test that shared memory outlives the process that created it, as long as
+/// another process still holds it, and is freed along with the last holder.
+#[test]
+fn shared_memory_outlives_creator() {
+    use xous_kernel::{MemoryFlags, Message, ScalarMessage};
+    let main_thread = start_kernel(SERVER_SPEC);
+    let (server_addr_send, server_addr_recv) = channel();
+    let (stopped_send, stopped_recv) = channel();
+
+    let xous_owner = xous_kernel::create_process_as_thread(xous_kernel::ProcessArgsAsThread::new(
+        "shared_memory_outlives_creator owner",
+        move || {
+            let sid = xous_kernel::create_server().expect("couldn't create test server");
+            let (id, range) =
+                xous_kernel::create_shared_memory(8192, MemoryFlags::R | MemoryFlags::W)
+                    .expect("couldn't create shared memory");
+            unsafe { (range.as_mut_ptr().add(4096) as *mut usize).write_volatile(42) };
+            server_addr_send.send(sid).unwrap();
+
+            // Exit without giving up the region.
+            let envelope = xous_kernel::receive_message(sid).expect("couldn't receive message");
+            let client_pid = envelope.sender.pid().expect("message had no sender");
+            xous_kernel::grant_shared_memory(id, client_pid, MemoryFlags::R)
+                .expect("couldn't grant shared memory");
+            xous_kernel::return_scalar(envelope.sender, id).expect("couldn't return scalar");
+        },
+    ))
+    .expect("couldn't spawn owner process");
+
+    let xous_client = xous_kernel::create_process_as_thread(xous_kernel::ProcessArgsAsThread::new(
+        "shared_memory_outlives_creator client",
+        move || {
+            let sid = server_addr_recv.recv().unwrap();
+            let conn = xous_kernel::connect(sid).expect("couldn't connect to server");
+            let id = match xous_kernel::send_message(
+                conn,
+                Message::BlockingScalar(ScalarMessage::from_usize(0, 0, 0, 0, 0)),
+            ) {
+                Ok(xous_kernel::Result::Scalar1(id)) => id,
+                other => panic!("unexpected response: {:?}", other),
+            };
+
+            stopped_recv.recv().unwrap();
+            let range = xous_kernel::map_shared_memory(id).expect("couldn't map shared memory");
+            assert_eq!(range.len(), 8192);
+            assert_eq!(
+                unsafe { (range.as_ptr().add(4096) as *const usize).read_volatile() },
+                42
+            );
+
+            // Ours was the last reference, so the region is gone.
+            xous_kernel::unmap_shared_memory(id).expect("couldn't unmap shared memory");
+            assert_eq!(
+                xous_kernel::map_shared_memory(id),
+                Err(xous_kernel::Error::AccessDenied)
+            );
+        },
+    ))
+    .expect("couldn't spawn client process");
+
+    xous_kernel::wait_process_as_thread(xous_owner).expect("couldn't join owner process");
+    stopped_send.send(()).unwrap();
+    xous_kernel::wait_process_as_thread(xous_client).expect("couldn't join client process");
+
+    shutdown_kernel();
+    main_thread.join().expect("couldn't join kernel process");
+}
+
+/// Test that the scheduler prefers higher-priority processes and takes turns
+/// among processes that share a priority.
+#[test]
+fn scheduler_priority() {
+    use crate::services::SystemServices;
+    use xous_kernel::{ProcessInit, ProcessKey, ThreadInit, DEFAULT_PRIORITY};
+
+    // This thread gets its own copy of SystemServices, so none of these
+    // processes are visible to any kernel started by another test.
+    let pids: Vec<xous_kernel::PID> = SystemServices::with_mut(|ss| {
+        // The first process created is PID1, which owns the rest.
+        ss.create_process(ProcessInit {
+            key: ProcessKey::new([0u8; 16]),
+        })
+        .unwrap();
+        let pids: Vec<_> = (0..3)
+            .map(|_| {
+                ss.create_process(ProcessInit {
+                    key: ProcessKey::new([0u8; 16]),
+                })
+                .unwrap()
+            })
+            .collect();
+        for pid in &pids {
+            ss.create_thread(*pid, ThreadInit {}).unwrap();
+        }
+        pids
+    });
+    let pid1 = xous_kernel::pid_from_usize(1).unwrap();
+
+    // With equal priorities, each process gets a turn.
+    assert_eq!(crate::next_pid_to_run(Some(pids[0])), Some(pids[1]));
+    assert_eq!(crate::next_pid_to_run(Some(pids[1])), Some(pids[2]));
+    assert_eq!(crate::next_pid_to_run(Some(pids[2])), Some(pids[0]));
+
+    // A higher-priority process is always picked first.
+    SystemServices::with_mut(|ss| ss.set_priority(pid1, pids[2], DEFAULT_PRIORITY + 1)).unwrap();
+    for pid in &pids {
+        assert_eq!(crate::next_pid_to_run(Some(*pid)), Some(pids[2]));
+    }
+
+    // Processes at the same, highest priority still take turns.
+    SystemServices::with_mut(|ss| ss.set_priority(pid1, pids[0], DEFAULT_PRIORITY + 1)).unwrap();
+    assert_eq!(crate::next_pid_to_run(Some(pids[0])), Some(pids[2]));
+    assert_eq!(crate::next_pid_to_run(Some(pids[2])), Some(pids[0]));
+
+    // Only the process itself or its parent may change its priority.
+    assert_eq!(
+        SystemServices::with_mut(|ss| ss.set_priority(pids[0], pids[1], 0)),
+        Err(xous_kernel::Error::ProcessNotChild)
+    );
+    assert_eq!(
+        SystemServices::with_mut(|ss| ss.set_priority(pids[1], pids[1], 0)),
+        Ok(())
+    );
+
+    // A process can't raise itself above the priority it was started with,
+    // but can go back up to it.
+    assert_eq!(
+        SystemServices::with_mut(|ss| ss.set_priority(pids[1], pids[1], DEFAULT_PRIORITY + 1)),
+        Err(xous_kernel::Error::AccessDenied)
+    );
+    assert_eq!(
+        SystemServices::with_mut(|ss| ss.set_priority(pids[1], pids[1], DEFAULT_PRIORITY)),
+        Ok(())
+    );
+
+    // Once its parent has raised it, it may return to that priority.
+    SystemServices::with_mut(|ss| ss.set_priority(pid1, pids[1], DEFAULT_PRIORITY + 2)).unwrap();
+    SystemServices::with_mut(|ss| ss.set_priority(pids[1], pids[1], 0)).unwrap();
+    assert_eq!(
+        SystemServices::with_mut(|ss| ss.set_priority(pids[1], pids[1], DEFAULT_PRIORITY + 2)),
+        Ok(())
+    );
+    assert_eq!(
+        SystemServices::with_mut(|ss| ss.set_priority(pids[1], pids[1], DEFAULT_PRIORITY + 3)),
+        Err(xous_kernel::Error::AccessDenied)
+    );
+}
+
+/// Test that preempting a thread puts only that thread back on its
+/// process' ready list.
+#[test]
+fn preempt_requeues_thread() {
+    use crate::services::SystemServices;
+    use xous_kernel::{ProcessInit, ProcessKey, ThreadInit};
+
+    // This thread gets its own copy of SystemServices, so none of these
+    // processes are visible to any kernel started by another test.
+    SystemServices::with_mut(|ss| {
+        ss.create_process(ProcessInit {
+            key: ProcessKey::new([0u8; 16]),
+        })
+        .unwrap();
+        let pid = ss
+            .create_process(ProcessInit {
+                key: ProcessKey::new([0u8; 16]),
+            })
+            .unwrap();
+        crate::arch::process::set_current_pid(pid);
+        let running = ss.create_thread(pid, ThreadInit {}).unwrap();
+        ss.switch_to_thread(pid, Some(running)).unwrap();
+        let waiting = ss.create_thread(pid, ThreadInit {}).unwrap();
+
+        // The preempted thread is queued behind the one already waiting,
+        // which stays queued.
+        ss.preempt_thread(pid, running).unwrap();
+        assert_eq!(
+            ss.preempt_thread(pid, running),
+            Err(xous_kernel::Error::InvalidThread)
+        );
+        ss.switch_to_thread(pid, Some(running)).unwrap();
+        ss.switch_to_thread(pid, Some(waiting)).unwrap();
+    });
+
+    // Hosted threads can't be stopped, so a preempted thread keeps running.
+    let pid = xous_kernel::pid_from_usize(2).unwrap();
+    let tid = crate::arch::process::running_tid().unwrap();
+    crate::syscall::preempt(pid, tid).unwrap();
+    crate::syscall::preempt(pid, tid).unwrap();
+}
+
+/// Test that a thread waiting with a timeout is woken up while another
+/// thread of its process is stuck in a busy loop.
+#[test]
+fn timeout_during_busy_loop() {
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::sync::Arc;
+
+    let main_thread = start_kernel(SERVER_SPEC);
+
+    let (result_send, result_recv) = channel();
+    let process = xous_kernel::create_process_as_thread(xous_kernel::ProcessArgsAsThread::new(
+        "timeout_during_busy_loop process",
+        move || {
+            let stop = Arc::new(AtomicBool::new(false));
+            let spinner_stop = stop.clone();
+            let spinner = xous_kernel::create_thread(move || {
+                while !spinner_stop.load(Ordering::Relaxed) {
+                    core::hint::spin_loop();
+                }
+            })
+            .expect("couldn't spawn spinner thread");
+
+            let sid = xous_kernel::create_server().expect("couldn't create test server");
+            result_send
+                .send(xous_kernel::receive_message_timeout(sid, 20).map(|_| ()))
+                .unwrap();
+            stop.store(true, Ordering::Relaxed);
+            xous_kernel::wait_thread(spinner).expect("couldn't join spinner thread");
+        },
+    ))
+    .expect("couldn't spawn process");
+
+    // Only the timer can expire the timeout, so without it this would hang.
+    assert_eq!(
+        result_recv
+            .recv_timeout(std::time::Duration::from_secs(5))
+            .expect("timeout never expired"),
+        Err(xous_kernel::Error::Timeout)
+    );
+
+    crate::wait_process_as_thread(process).expect("couldn't join process");
+    shutdown_kernel();
+
+    main_thread.join().expect("couldn't join kernel process");
+}
+
+/// Test that a process terminated for running out of memory reports
+/// `Error::OutOfMemory` to its parent. Memory limits are only enforced on
+/// hardware, where the trap handler calls `terminate_out_of_memory()` once a
+/// process faults past its `MLim` limit, so call it directly here.
+#[test]
+fn out_of_memory_exit_code() {
+    use crate::services::SystemServices;
+    use xous_kernel::{ProcessInit, ProcessKey, ThreadInit};
+
+    // This thread gets its own copy of SystemServices, so none of these
+    // processes are visible to any kernel started by another test.
+    let (parent, parent_tid, child, child_tid) = SystemServices::with_mut(|ss| {
+        ss.create_process(ProcessInit {
+            key: ProcessKey::new([0u8; 16]),
+        })
+        .unwrap();
+        let parent = ss
+            .create_process(ProcessInit {
+                key: ProcessKey::new([0u8; 16]),
+            })
+            .unwrap();
+        crate::arch::process::set_current_pid(parent);
+        let parent_tid = ss.create_thread(parent, ThreadInit {}).unwrap();
+        ss.switch_to_thread(parent, Some(parent_tid)).unwrap();
+
+        let child = ss
+            .create_process(ProcessInit {
+                key: ProcessKey::new([0u8; 16]),
+            })
+            .unwrap();
+        crate::arch::process::set_current_pid(child);
+        let child_tid = ss.create_thread(child, ThreadInit {}).unwrap();
+        ss.switch_to_thread(child, Some(child_tid)).unwrap();
+        (parent, parent_tid, child, child_tid)
+    });
+
+    // A process can't be terminated from its interrupt handler, which the
+    // trap handler has to deal with itself.
+    assert_eq!(
+        crate::syscall::terminate_out_of_memory(child, child_tid, true),
+        Err(xous_kernel::Error::InvalidSyscall)
+    );
+
+    assert_eq!(
+        crate::syscall::terminate_out_of_memory(child, child_tid, false),
+        Ok(xous_kernel::Result::Ok)
+    );
+    crate::arch::process::set_current_pid(parent);
+    assert_eq!(
+        crate::syscall::handle(parent, parent_tid, false, SysCall::WaitProcess(child)),
+        Ok(xous_kernel::Result::Scalar1(
+            xous_kernel::Error::OutOfMemory.to_usize()
+        ))
+    );
+}
+
+/// Test that processes started by other processes get scheduled. The
+/// `wait_process` test checks that they run and exit.
+#[test]
+fn spawned_process_is_scheduled() {
+    use crate::services::SystemServices;
+    use xous_kernel::{ProcessInit, ProcessKey, ThreadInit};
+
+    // This thread gets its own copy of SystemServices, so none of these
+    // processes are visible to any kernel started by another test.
+    let (parent, child) = SystemServices::with_mut(|ss| {
+        ss.create_process(ProcessInit {
+            key: ProcessKey::new([0u8; 16]),
+        })
+        .unwrap();
+        let parent = ss
+            .create_process(ProcessInit {
+                key: ProcessKey::new([0u8; 16]),
+            })
+            .unwrap();
+        ss.create_thread(parent, ThreadInit {}).unwrap();
+
+        crate::arch::process::set_current_pid(parent);
+        let child = ss
+            .create_process(ProcessInit {
+                key: ProcessKey::new([0u8; 16]),
+            })
+            .unwrap();
+        ss.create_thread(child, ThreadInit {}).unwrap();
+        (parent, child)
+    });
+
+    // The child takes turns with its parent.
+    assert_eq!(crate::next_pid_to_run(Some(parent)), Some(child));
+    assert_eq!(crate::next_pid_to_run(Some(child)), Some(parent));
+
+    // Only its parent may change its priority.
+    let pid1 = xous_kernel::pid_from_usize(1).unwrap();
+    assert_eq!(
+        SystemServices::with_mut(|ss| ss.set_priority(pid1, child, 0)),
+        Err(xous_kernel::Error::ProcessNotChild)
+    );
+    SystemServices::with_mut(|ss| ss.set_priority(parent, child, 0)).unwrap();
+}
+
+/// Test that the `SetPriority` syscall makes it to the kernel
+#[test]
+fn set_priority() {
+    let main_thread = start_kernel(SERVER_SPEC);
+
+    let process = xous_kernel::create_process_as_thread(xous_kernel::ProcessArgsAsThread::new(
+        "set_priority process",
+        move || {
+            assert_eq!(
+                xous_kernel::set_priority(None, 200),
+                Err(xous_kernel::Error::AccessDenied)
+            );
+            xous_kernel::set_priority(None, xous_kernel::DEFAULT_PRIORITY - 1)
+                .expect("couldn't lower our own priority");
+            xous_kernel::set_priority(None, xous_kernel::DEFAULT_PRIORITY)
+                .expect("couldn't restore our own priority");
+            assert_eq!(
+                xous_kernel::set_priority(xous_kernel::pid_from_usize(1).ok(), 200),
+                Err(xous_kernel::Error::ProcessNotChild)
+            );
+        },
+    ))
+    .expect("couldn't spawn set_priority process");
+
+    xous_kernel::wait_process_as_thread(process).expect("couldn't join set_priority process");
+    shutdown_kernel();
+
+    main_thread.join().expect("couldn't join kernel process");
+}
+
+/// Test that a client gives up on a server that doesn't respond in time,
+/// whether or not the server has received the message yet, and that the
+/// server can carry on afterwards.
+#[test]
+fn send_message_timeout() {
+    let main_thread = start_kernel(SERVER_SPEC);
+    let (server_addr_send, server_addr_recv) = channel();
+    let (timed_out_send, timed_out_recv) = channel();
+    let test_bytes = b"Hello, world!";
+
+    let xous_server = xous_kernel::create_process_as_thread(xous_kernel::ProcessArgsAsThread::new(
+        "send_message_timeout server",
+        move || {
+            let sid = xous_kernel::create_server_with_address(b"send_message_tmo")
+                .expect("couldn't create test server");
+            server_addr_send.send(sid).unwrap();
+
+            // Don't look at the queue until the first message has timed out,
+            // after which it should have been withdrawn.
+            timed_out_recv.recv().unwrap();
+            let envelope = xous_kernel::receive_message(sid).expect("couldn't receive message");
+            assert_eq!(
+                envelope.body,
+                xous_kernel::Message::BlockingScalar(xous_kernel::ScalarMessage {
+                    id: 2,
+                    arg1: 0,
+                    arg2: 0,
+                    arg3: 0,
+                    arg4: 0,
+                })
+            );
+
+            // Responding after the client has given up is not an error.
+            timed_out_recv.recv().unwrap();
+            xous_kernel::return_scalar(envelope.sender, 1).expect("couldn't return scalar");
+
+            let envelope = xous_kernel::receive_message(sid).expect("couldn't receive message");
+            if let xous_kernel::Message::MutableBorrow(m) = envelope.body {
+                let bt =
+                    unsafe { core::slice::from_raw_parts_mut(m.buf.as_mut_ptr(), m.buf.len()) };
+                for letter in bt.iter_mut() {
+                    *letter += 1;
+                }
+                timed_out_recv.recv().unwrap();
+
+                // The client has given up, but the buffer stays lent until
+                // it's returned.
+                let client_pid = envelope.sender.pid().expect("message had no sender");
+                let stats = xous_kernel::memory_stats(client_pid).expect("couldn't get stats");
+                assert_eq!(stats.lent_pages, 1);
+                xous_kernel::return_memory(envelope.sender, m.buf).expect("couldn't return memory");
+                let stats = xous_kernel::memory_stats(client_pid).expect("couldn't get stats");
+                assert_eq!(stats.lent_pages, 0);
+            } else {
+                panic!("unexpected message type");
+            }
+
+            let envelope = xous_kernel::receive_message(sid).expect("couldn't receive message");
+            xous_kernel::return_scalar(envelope.sender, 42).expect("couldn't return scalar");
+        },
+    ))
+    .expect("couldn't spawn server process");
+
+    let xous_client = xous_kernel::create_process_as_thread(xous_kernel::ProcessArgsAsThread::new(
+        "send_message_timeout client",
+        move || {
+            let sid = server_addr_recv.recv().unwrap();
+            let conn = xous_kernel::connect(sid).expect("couldn't connect to server");
+            let scalar = |id| {
+                xous_kernel::Message::BlockingScalar(xous_kernel::ScalarMessage {
+                    id,
+                    arg1: 0,
+                    arg2: 0,
+                    arg3: 0,
+                    arg4: 0,
+                })
+            };
+
+            // The server isn't receiving yet, so this sits in its queue.
+            assert_eq!(
+                xous_kernel::send_message_timeout(conn, scalar(1), 20),
+                Err(xous_kernel::Error::Timeout)
+            );
+            timed_out_send.send(()).unwrap();
+
+            // The server receives this one, but doesn't respond in time.
+            assert_eq!(
+                xous_kernel::send_message_timeout(conn, scalar(2), 20),
+                Err(xous_kernel::Error::Timeout)
+            );
+            timed_out_send.send(()).unwrap();
+
+            // The server has received this lend, so it keeps the buffer until
+            // it responds, and its changes never reach the client.
+            let carton = xous_kernel::carton::Carton::from_bytes(test_bytes);
+            let range: &xous_kernel::MemoryRange = carton.as_ref();
+            let msg = xous_kernel::MemoryMessage {
+                id: 3,
+                buf: *range,
+                offset: None,
+                valid: None,
+            };
+            assert_eq!(
+                xous_kernel::send_message_timeout(conn, xous_kernel::Message::MutableBorrow(msg), 20),
+                Err(xous_kernel::Error::Timeout)
+            );
+            let returned_bytes: &[u8] = carton.as_ref();
+            assert_eq!(&test_bytes[..], returned_bytes);
+            timed_out_send.send(()).unwrap();
+
+            // Timeouts that don't fit next to the message type are refused.
+            assert_eq!(
+                xous_kernel::send_message_timeout(conn, scalar(5), usize::MAX),
+                Err(xous_kernel::Error::InvalidSyscall)
+            );
+
+            // Servers that respond in time are unaffected.
+            assert_eq!(
+                xous_kernel::send_message_timeout(conn, scalar(4), 10_000),
+                Ok(xous_kernel::Result::Scalar1(42))
+            );        },
+    ))
+    .expect("couldn't spawn client process");
+
+    xous_kernel::wait_process_as_thread(xous_server).expect("couldn't join server process");
+    xous_kernel::wait_process_as_thread(xous_client).expect("couldn't join client process");
     shutdown_kernel();
 
     main_thread.join().expect("couldn't join kernel process");
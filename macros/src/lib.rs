@@ -9,12 +9,16 @@ extern crate proc_macro2;
 #[macro_use]
 extern crate syn;
 
-use proc_macro2::Span;
+use proc_macro2::{Span, TokenStream as TokenStream2};
 use rand::Rng;
 use rand::SeedableRng;
 use std::sync::atomic::{AtomicUsize, Ordering};
 use std::time::{SystemTime, UNIX_EPOCH};
-use syn::{parse, spanned::Spanned, Ident, ItemFn, ReturnType, Type, Visibility};
+use syn::ext::IdentExt;
+use syn::{
+    parse, spanned::Spanned, Data, DeriveInput, Expr, ExprLit, Fields, Ident, ItemFn, Lit,
+    ReturnType, Type, Variant, Visibility,
+};
 
 static CALL_COUNT: AtomicUsize = AtomicUsize::new(0);
 
@@ -103,6 +107,341 @@ pub fn xous_main(args: TokenStream, input: TokenStream) -> TokenStream {
     r.into()
 }
 
+/// Derive conversions between an enum of opcodes and `xous::Message`
+///
+/// Every variant must be tagged with `#[opcode(<kind>, id = <expr>)]`, where `<kind>` is one of
+/// `scalar`, `blocking_scalar`, `borrow`, `mutable_borrow` or `move`, and `<expr>` is a constant
+/// `usize` expression that gives the message ID. This implements `TryFrom<&xous::Message>` for
+/// the enum, and converts the enum into a `xous::Message` so it can be passed to
+/// `send_message()` with `.into()`.
+///
+/// Scalar variants may have up to four fields, which must be `usize`. They are packed into
+/// `arg1` through `arg4` in the order they are declared, and any unused arguments are sent as 0.
+/// Memory variants have exactly one field, an `xous::MemoryMessage`, whose `id` is replaced with
+/// the variant's ID when it's turned into a `Message`.
+///
+/// Two variants of the same kind may not have the same ID, and this is checked when the enum is
+/// compiled. Variants of different kinds may share an ID, since the kind of message is enough
+/// to tell them apart.
+///
+/// # Examples
+///
+/// ``` ignore
+/// #[derive(Debug, xous::Opcode)]
+/// pub enum Opcode {
+///     #[opcode(scalar, id = 1)]
+///     Reset,
+///
+///     #[opcode(blocking_scalar, id = 3)]
+///     SleepMs(usize),
+///
+///     #[opcode(mutable_borrow, id = 4)]
+///     Lookup(xous::MemoryMessage),
+/// }
+/// ```
+#[proc_macro_derive(Opcode, attributes(opcode))]
+pub fn derive_opcode(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    match opcode_impls(&input) {
+        Ok(tokens) => tokens.into(),
+        Err(e) => e.to_compile_error().into(),
+    }
+}
+
+/// The kind of `xous::Message` that an opcode is sent as
+#[derive(Copy, Clone, PartialEq)]
+enum OpcodeKind {
+    Scalar,
+    BlockingScalar,
+    Borrow,
+    MutableBorrow,
+    Move,
+}
+
+const OPCODE_KINDS: [OpcodeKind; 5] = [
+    OpcodeKind::Scalar,
+    OpcodeKind::BlockingScalar,
+    OpcodeKind::Borrow,
+    OpcodeKind::MutableBorrow,
+    OpcodeKind::Move,
+];
+
+impl OpcodeKind {
+    fn from_ident(ident: &Ident) -> Option<OpcodeKind> {
+        match ident.to_string().as_str() {
+            "scalar" => Some(OpcodeKind::Scalar),
+            "blocking_scalar" => Some(OpcodeKind::BlockingScalar),
+            "borrow" => Some(OpcodeKind::Borrow),
+            "mutable_borrow" => Some(OpcodeKind::MutableBorrow),
+            "move" => Some(OpcodeKind::Move),
+            _ => None,
+        }
+    }
+
+    /// The name of the matching `xous::Message` variant
+    fn message_variant(self) -> Ident {
+        let name = match self {
+            OpcodeKind::Scalar => "Scalar",
+            OpcodeKind::BlockingScalar => "BlockingScalar",
+            OpcodeKind::Borrow => "Borrow",
+            OpcodeKind::MutableBorrow => "MutableBorrow",
+            OpcodeKind::Move => "Move",
+        };
+        Ident::new(name, Span::call_site())
+    }
+
+    fn is_scalar(self) -> bool {
+        self == OpcodeKind::Scalar || self == OpcodeKind::BlockingScalar
+    }
+}
+
+/// One variant of an enum deriving `Opcode`, along with what its attribute says
+struct OpcodeVariant<'a> {
+    variant: &'a Variant,
+    kind: OpcodeKind,
+    id: Expr,
+}
+
+impl<'a> OpcodeVariant<'a> {
+    fn parse(variant: &'a Variant) -> parse::Result<OpcodeVariant<'a>> {
+        let attr = variant
+            .attrs
+            .iter()
+            .find(|attr| attr.path.is_ident("opcode"))
+            .ok_or_else(|| {
+                parse::Error::new(
+                    variant.span(),
+                    "variant needs an `#[opcode(<kind>, id = <expr>)]` attribute",
+                )
+            })?;
+        let (kind, id) = attr.parse_args_with(|input: parse::ParseStream| {
+            // `move` is a keyword, so it can't be parsed as a plain `Ident`
+            let kind_ident = Ident::parse_any(input)?;
+            let kind = OpcodeKind::from_ident(&kind_ident).ok_or_else(|| {
+                parse::Error::new(
+                    kind_ident.span(),
+                    "expected `scalar`, `blocking_scalar`, `borrow`, `mutable_borrow` or `move`",
+                )
+            })?;
+            input.parse::<Token![,]>()?;
+            let id_ident: Ident = input.parse()?;
+            if id_ident != "id" {
+                return Err(parse::Error::new(id_ident.span(), "expected `id = <expr>`"));
+            }
+            input.parse::<Token![=]>()?;
+            let id: Expr = input.parse()?;
+            input.parse::<Option<Token![,]>>()?;
+            Ok((kind, id))
+        })?;
+
+        let field_count = variant.fields.len();
+        if kind.is_scalar() && field_count > 4 {
+            return Err(parse::Error::new(
+                variant.fields.span(),
+                "scalar opcodes may have at most four `usize` fields",
+            ));
+        }
+        if !kind.is_scalar() && field_count != 1 {
+            return Err(parse::Error::new(
+                variant.span(),
+                "memory opcodes must have exactly one field, an `xous::MemoryMessage`",
+            ));
+        }
+
+        Ok(OpcodeVariant { variant, kind, id })
+    }
+
+    /// The integer value of the ID, if it was given as a literal
+    fn literal_id(&self) -> Option<u128> {
+        match &self.id {
+            Expr::Lit(ExprLit {
+                lit: Lit::Int(lit), ..
+            }) => lit.base10_parse().ok(),
+            _ => None,
+        }
+    }
+
+    /// Names to bind each field of the variant to
+    fn bindings(&self) -> Vec<Ident> {
+        self.variant
+            .fields
+            .iter()
+            .enumerate()
+            .map(|(i, field)| match &field.ident {
+                Some(ident) => ident.clone(),
+                None => Ident::new(&format!("field{}", i), Span::call_site()),
+            })
+            .collect()
+    }
+
+    /// A pattern that binds each field of the variant to the names from `bindings()`
+    fn pattern(&self, enum_name: &Ident) -> TokenStream2 {
+        let name = &self.variant.ident;
+        let bindings = self.bindings();
+        match &self.variant.fields {
+            Fields::Unit => quote!(#enum_name::#name),
+            Fields::Unnamed(_) => quote!(#enum_name::#name(#(#bindings),*)),
+            Fields::Named(_) => quote!(#enum_name::#name { #(#bindings),* }),
+        }
+    }
+
+    /// Construct the variant, with each field taken from `values`
+    fn build<T: quote::ToTokens>(&self, enum_name: &Ident, values: &[T]) -> TokenStream2 {
+        let name = &self.variant.ident;
+        match &self.variant.fields {
+            Fields::Unit => quote!(#enum_name::#name),
+            Fields::Unnamed(_) => quote!(#enum_name::#name(#(#values),*)),
+            Fields::Named(_) => {
+                let idents = self.bindings();
+                quote!(#enum_name::#name { #(#idents: #values),* })
+            }
+        }
+    }
+}
+
+fn opcode_impls(input: &DeriveInput) -> parse::Result<TokenStream2> {
+    let name = &input.ident;
+    let data = match &input.data {
+        Data::Enum(data) => data,
+        _ => {
+            return Err(parse::Error::new(
+                input.span(),
+                "`#[derive(Opcode)]` may only be used on an enum",
+            ))
+        }
+    };
+    if !input.generics.params.is_empty() {
+        return Err(parse::Error::new(
+            input.generics.span(),
+            "`#[derive(Opcode)]` doesn't support generic enums",
+        ));
+    }
+
+    let variants = data
+        .variants
+        .iter()
+        .map(OpcodeVariant::parse)
+        .collect::<parse::Result<Vec<_>>>()?;
+
+    // Catch duplicate literal IDs here so the error is easy to read. Anything
+    // else is caught by the compiler as an unreachable pattern below.
+    for (i, variant) in variants.iter().enumerate() {
+        let id = match variant.literal_id() {
+            Some(id) => id,
+            None => continue,
+        };
+        if variants[..i]
+            .iter()
+            .any(|other| other.kind == variant.kind && other.literal_id() == Some(id))
+        {
+            return Err(parse::Error::new(
+                variant.id.span(),
+                format!("another opcode of the same kind already uses ID {}", id),
+            ));
+        }
+    }
+
+    let mut try_from_arms = vec![];
+    for kind in OPCODE_KINDS.iter().copied() {
+        let of_kind: Vec<&OpcodeVariant> = variants.iter().filter(|v| v.kind == kind).collect();
+        if of_kind.is_empty() {
+            continue;
+        }
+        let message_variant = kind.message_variant();
+        let mut consts = vec![];
+        let mut arms = vec![];
+        for (i, variant) in of_kind.iter().enumerate() {
+            let const_name = Ident::new(&format!("ID_{}", i), variant.id.span());
+            let id = &variant.id;
+            consts.push(quote!(const #const_name: usize = #id;));
+            let value = if kind.is_scalar() {
+                let args: Vec<TokenStream2> = (1..=variant.variant.fields.len())
+                    .map(|n| {
+                        let arg = Ident::new(&format!("arg{}", n), Span::call_site());
+                        quote!(m.#arg)
+                    })
+                    .collect();
+                variant.build(name, &args)
+            } else {
+                variant.build(
+                    name,
+                    &[quote!(xous::MemoryMessage {
+                        id: m.id,
+                        buf: m.buf,
+                        offset: m.offset,
+                        valid: m.valid,
+                    })],
+                )
+            };
+            arms.push(quote_spanned!(variant.id.span()=> #const_name => Ok(#value),));
+        }
+        try_from_arms.push(quote!(
+            xous::Message::#message_variant(m) => {
+                #(#consts)*
+                match m.id {
+                    #(#arms)*
+                    _ => Err("unrecognized opcode"),
+                }
+            }
+        ));
+    }
+    if try_from_arms.len() < OPCODE_KINDS.len() {
+        try_from_arms.push(quote!(_ => Err("unhandled message type"),));
+    }
+
+    let into_arms = variants.iter().map(|variant| {
+        let bindings = variant.bindings();
+        let pattern = variant.pattern(name);
+        let message_variant = variant.kind.message_variant();
+        let id = &variant.id;
+        if variant.kind.is_scalar() {
+            let args: Vec<TokenStream2> = (0..4)
+                .map(|n| match bindings.get(n) {
+                    Some(binding) => quote!(#binding),
+                    None => quote!(0),
+                })
+                .collect();
+            let (arg1, arg2, arg3, arg4) = (&args[0], &args[1], &args[2], &args[3]);
+            quote!(#pattern => xous::Message::#message_variant(xous::ScalarMessage {
+                id: #id,
+                arg1: #arg1,
+                arg2: #arg2,
+                arg3: #arg3,
+                arg4: #arg4,
+            }),)
+        } else {
+            let memory = &bindings[0];
+            quote!(#pattern => xous::Message::#message_variant(xous::MemoryMessage {
+                id: #id,
+                ..#memory
+            }),)
+        }
+    });
+
+    Ok(quote!(
+        impl<'a> core::convert::TryFrom<&'a xous::Message> for #name {
+            type Error = &'static str;
+
+            // Two opcodes of the same kind with the same ID make one of these
+            // patterns unreachable.
+            #[deny(unreachable_patterns)]
+            fn try_from(message: &'a xous::Message) -> core::result::Result<Self, Self::Error> {
+                match message {
+                    #(#try_from_arms)*
+                }
+            }
+        }
+
+        impl core::convert::From<#name> for xous::Message {
+            fn from(opcode: #name) -> xous::Message {
+                match opcode {
+                    #(#into_arms)*
+                }
+            }
+        }
+    ))
+}
+
 // Creates a random identifier
 fn random_ident() -> Ident {
     let secs = SystemTime::now()
@@ -135,3 +474,98 @@ fn random_ident() -> Ident {
         Span::call_site(),
     )
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn error_of(input: DeriveInput) -> String {
+        match opcode_impls(&input) {
+            Ok(_) => panic!("expected the derive to fail"),
+            Err(e) => e.to_string(),
+        }
+    }
+
+    #[test]
+    fn duplicate_ids_are_rejected() {
+        let message = error_of(parse_quote! {
+            enum Opcode {
+                #[opcode(scalar, id = 1)]
+                Reset,
+                #[opcode(scalar, id = 1)]
+                Restart(usize),
+            }
+        });
+        assert!(message.contains("already uses ID 1"), "{}", message);
+
+        let message = error_of(parse_quote! {
+            enum Opcode {
+                #[opcode(borrow, id = 0x10)]
+                Write(xous::MemoryMessage),
+                #[opcode(borrow, id = 16)]
+                Print(xous::MemoryMessage),
+            }
+        });
+        assert!(message.contains("already uses ID 16"), "{}", message);
+    }
+
+    #[test]
+    fn ids_may_repeat_across_kinds() {
+        let input: DeriveInput = parse_quote! {
+            enum Opcode {
+                #[opcode(scalar, id = 1)]
+                StatsNb,
+                #[opcode(blocking_scalar, id = 1)]
+                Stats,
+                #[opcode(mutable_borrow, id = 1)]
+                Lookup(xous::MemoryMessage),
+            }
+        };
+        assert!(opcode_impls(&input).is_ok());
+    }
+
+    #[test]
+    fn memory_variants_have_one_field() {
+        let message = error_of(parse_quote! {
+            enum Opcode {
+                #[opcode(move, id = 1)]
+                Store,
+            }
+        });
+        assert!(message.contains("exactly one field"), "{}", message);
+
+        let message = error_of(parse_quote! {
+            enum Opcode {
+                #[opcode(mutable_borrow, id = 1)]
+                Lookup(xous::MemoryMessage, usize),
+            }
+        });
+        assert!(message.contains("exactly one field"), "{}", message);
+
+        let input: DeriveInput = parse_quote! {
+            enum Opcode {
+                #[opcode(move, id = 1)]
+                Store { message: xous::MemoryMessage },
+            }
+        };
+        assert!(opcode_impls(&input).is_ok());
+    }
+
+    #[test]
+    fn variants_need_an_attribute() {
+        let message = error_of(parse_quote! {
+            enum Opcode {
+                Reset,
+            }
+        });
+        assert!(message.contains("needs an `#[opcode"), "{}", message);
+
+        let message = error_of(parse_quote! {
+            enum Opcode {
+                #[opcode(scalar, id = 1)]
+                Sleep(usize, usize, usize, usize, usize),
+            }
+        });
+        assert!(message.contains("at most four"), "{}", message);
+    }
+}
@@ -1,10 +1,33 @@
-use xous::{Message, ScalarMessage};
-
 // NOTE: the use of ComState "verbs" as commands is not meant as a 1:1 mapping of commands
 // It's just a convenient abuse of already-defined constants. However, it's intended that
 // the COM server on the SoC side abstracts much of the EC bus complexity away.
 use com_rs::*;
-#[derive(Debug, Default)]
+
+use serde::{Deserialize, Serialize};
+
+/// The name this server registers with `xous-names`
+pub const SERVER_NAME_COM: &str = "com";
+
+/// The name of the server that is told about asynchronous responses, such as
+/// `EVENT_BATT_STATS`
+pub const SERVER_NAME_SHELL: &str = "shell";
+
+/// Signalled on the shell's server once a `BattStatsNb` request has been
+/// carried out. The new stats can then be fetched with `BattStatsLatest`.
+pub const EVENT_BATT_STATS: usize = 0b1;
+
+/// Signalled on the shell's server once the COM server has sent it the key
+/// for `ClaimShipMode` in a `SHIP_MODE_KEY` message.
+pub const EVENT_SHIP_MODE_KEY: usize = 0b10;
+
+/// The ID of the `Scalar` message that the COM server sends to the shell's
+/// server when it starts. `arg1` through `arg4` hold the key for
+/// `ClaimShipMode`.
+pub const SHIP_MODE_KEY: usize = 1;
+
+/// Sent back as the reply to `BattStats` and `BattStatsLatest`, which lend a
+/// buffer that is encoded with `xous::ipc`
+#[derive(Debug, Default, Copy, Clone, Serialize, Deserialize)]
 pub struct BattStats {
     /// instantaneous voltage in mV
     pub voltage: u16,
@@ -16,139 +39,52 @@ pub struct BattStats {
     pub remaining_capacity: u16,
 }
 
-impl From<[usize; 2]> for BattStats {
-    fn from(a: [usize; 2]) -> BattStats {
-        BattStats {
-            voltage: (a[0] & 0xFFFF) as u16,
-            soc: ((a[0] >> 16) & 0xFF) as u8,
-            current: ((a[1] >> 16) & 0xFFFF) as i16,
-            remaining_capacity: (a[1] & 0xFFFF) as u16,
-        }
-    }
-}
-
-impl Into<[usize; 2]> for BattStats {
-    fn into(self) -> [usize; 2] {
-        [ (self.voltage as usize & 0xffff) | ((self.soc as usize) << 16) & 0xFF_0000,
-          (self.remaining_capacity as usize & 0xffff) | ((self.current as usize) << 16) & 0xffff_0000 ]
-    }
-}
-
-#[derive(Debug)]
+/// Requests that the COM server carries out. The IDs are the EC's own verbs.
+///
+/// Other EC commands, such as programming its FLASH or scanning for SSIDs,
+/// have no opcode until the COM server can carry them out.
+#[derive(Debug, xous::Opcode)]
 pub enum Opcode {
-    /// Battery stats
-    BattStats,
+    /// Battery stats, written into the lent buffer
+    #[opcode(mutable_borrow, id = ComState::STAT.verb as usize)]
+    BattStats(xous::MemoryMessage),
 
     /// Battery stats, non-blocking
+    #[opcode(scalar, id = ComState::STAT.verb as usize)]
     BattStatsNb,
 
-    /// Battery stats return
-    BattStatsReturn(BattStats),
-
-    /// Query Full charge capacity of the battery
-    BattFullCapacity,
+    /// The battery stats that were read by the last `BattStatsNb` request,
+    /// written into the lent buffer
+    #[opcode(mutable_borrow, id = ComState::STAT_RETURN.verb as usize)]
+    BattStatsLatest(xous::MemoryMessage),
 
     /// Turn Boost Mode On
+    #[opcode(scalar, id = ComState::CHG_BOOST_ON.verb as usize)]
     BoostOn,
 
     /// Turn Boost Mode Off
+    #[opcode(scalar, id = ComState::CHG_BOOST_OFF.verb as usize)]
     BoostOff,
 
     /// Read the current accelerations off the IMU
+    #[opcode(blocking_scalar, id = ComState::GYRO_READ.verb as usize)]
     ImuAccelRead,
 
     /// Power off the SoC
+    #[opcode(scalar, id = ComState::POWER_OFF.verb as usize)]
     PowerOffSoc,
 
-    /// Ship mode (battery disconnect)
+    /// Ship mode (battery disconnect). Only the process that has claimed it
+    /// with `ClaimShipMode` may send this.
+    #[opcode(scalar, id = ComState::POWER_SHIPMODE.verb as usize)]
     ShipMode,
 
-    /// Is the battery charging?
-    IsCharging,
-
-    /// Set the backlight brightness
-    SetBackLight,
-
-    /// Request charging
-    RequestCharging,
-
-    /// Erase a region of EC FLASH
-    FlashErase,
-
-    /// Program a page of FLASH
-    FlashProgram,
-
-    /// Update the SSID list
-    SsidScan,
-
-    /// Return the latest SSID list
-    SsidFetch,
-}
-
-impl<'a> core::convert::TryFrom<&'a Message> for Opcode {
-    type Error = &'static str;
-    fn try_from(message: &'a Message) -> Result<Self, Self::Error> {
-        match message {
-            Message::Scalar(m) => {
-                if m.id as u16 == ComState::CHG_BOOST_ON.verb {
-                    Ok(Opcode::BoostOn)
-                } else if m.id as u16 == ComState::CHG_BOOST_OFF.verb {
-                    Ok(Opcode::BoostOff)
-                } else if m.id as u16 == ComState::POWER_SHIPMODE.verb {
-                    Ok(Opcode::ShipMode)
-                } else if m.id as u16 == ComState::POWER_OFF.verb {
-                    Ok(Opcode::PowerOffSoc)
-                } else if m.id as u16 == ComState::STAT.verb {
-                    Ok(Opcode::BattStatsNb)
-                } else if m.id as u16 == ComState::STAT_RETURN.verb {
-                    let raw_stats: [usize; 2] = [m.arg1, m.arg2];
-                    Ok(Opcode::BattStatsReturn(raw_stats.into()))
-                } else {
-                    Err("unrecognized command")
-                }
-            },
-            Message::BlockingScalar(m) => {
-                if m.id as u16 == ComState::STAT.verb {
-                    Ok(Opcode::BattStats)
-                } else if m.id as u16 == ComState::GYRO_READ.verb {
-                    Ok(Opcode::ImuAccelRead)
-                } else {
-                    Err("unrecognized opcode")
-                }
-            },
-            _ => Err("unhandled message type"),
-        }
-    }
-}
-
-impl Into<Message> for Opcode {
-    fn into(self) -> Message {
-        match self {
-            Opcode::BoostOn => Message::Scalar(ScalarMessage {
-                id: ComState::CHG_BOOST_ON.verb as _, arg1: 0, arg2: 0, arg3: 0, arg4: 0 }),
-            Opcode::BoostOff => Message::Scalar(ScalarMessage {
-                id: ComState::CHG_BOOST_OFF.verb as _, arg1: 0, arg2: 0, arg3: 0, arg4: 0 }),
-            Opcode::ShipMode => Message::Scalar(ScalarMessage {
-                id: ComState::POWER_SHIPMODE.verb as _, arg1: 0, arg2: 0, arg3: 0, arg4: 0 }),
-            Opcode::PowerOffSoc => Message::Scalar(ScalarMessage {
-                id: ComState::POWER_OFF.verb as _, arg1: 0, arg2: 0, arg3: 0, arg4: 0 }),
-            Opcode::BattStats => Message::BlockingScalar(ScalarMessage {
-                id: ComState::STAT.verb as _, arg1: 0, arg2: 0, arg3: 0, arg4: 0 }),
-            Opcode::BattStatsNb => Message::Scalar(ScalarMessage {
-                id: ComState::STAT.verb as _, arg1: 0, arg2: 0, arg3: 0, arg4: 0 }),
-            Opcode::ImuAccelRead => Message::BlockingScalar(ScalarMessage {
-                id: ComState::GYRO_READ.verb as _, arg1: 0, arg2: 0, arg3: 0, arg4: 0 }),
-            Opcode::BattStatsReturn(stats) => {
-                let raw_stats: [usize; 2] = stats.into();
-                Message::Scalar(ScalarMessage {
-                    id: ComState::STAT_RETURN.verb as usize,
-                    arg1: raw_stats[0],
-                    arg2: raw_stats[1],
-                    arg3: 0,
-                    arg4: 0,
-                })
-            }
-            _ => todo!("message type not yet implemented")
-        }
-    }
+    /// Allow the sender to use `ShipMode`, by passing back the key that was
+    /// sent to the shell's server. Returns 1 if the key was right and 0 if it
+    /// wasn't. Only the first right key is accepted.
+    ///
+    /// This isn't an EC verb. The ID fits in a `u16` so that connection
+    /// rights can cover it.
+    #[opcode(blocking_scalar, id = 0xffff)]
+    ClaimShipMode(usize, usize, usize, usize),
 }
@@ -6,22 +6,52 @@
 pub mod api;
 
 use api::BattStats;
+use com_rs::ComState;
+use xous::ipc::Buffer;
 use xous::{send_message, Error, CID};
 
 pub fn power_off_soc(cid: CID) -> Result<(), xous::Error> {
     send_message(cid, api::Opcode::PowerOffSoc.into()).map(|_| ())
 }
 
-pub fn get_batt_stats(cid: CID) -> Result<BattStats, xous::Error> {
-    let response = send_message(cid, api::Opcode::BattStats.into())?;
-    if let xous::Result::Scalar2(upper, lower) = response {
-        let raw_stats: [usize; 2] = [lower, upper];
-        Ok(raw_stats.into())
-    } else {
-        panic!("unexpected return value: {:#?}", response);
+/// Disconnect the battery. This fails with `AccessDenied` unless this process
+/// has claimed it with `claim_ship_mode()`.
+pub fn ship_mode(cid: CID) -> Result<(), xous::Error> {
+    send_message(cid, api::Opcode::ShipMode.into()).map(|_| ())
+}
+
+/// Pass back the key from a `SHIP_MODE_KEY` message, which allows this process
+/// to use `ship_mode()`.
+///
+/// # Errors
+///
+/// * **AccessDenied**: The key was wrong, or it has already been claimed
+pub fn claim_ship_mode(cid: CID, key: [usize; 4]) -> Result<(), xous::Error> {
+    let claim = api::Opcode::ClaimShipMode(key[0], key[1], key[2], key[3]);
+    match send_message(cid, claim.into())? {
+        xous::Result::Scalar1(1) => Ok(()),
+        xous::Result::Scalar1(_) => Err(Error::AccessDenied),
+        _ => Err(Error::InternalError),
     }
 }
 
+/// Lend a buffer that the COM server writes the battery stats into.
+fn lend_batt_stats(cid: CID, id: u16) -> Result<BattStats, xous::Error> {
+    let mut buffer = Buffer::into_buf(&BattStats::default()).or(Err(Error::OutOfMemory))?;
+    buffer.lend_mut(cid, id as usize)?;
+    buffer.to_original().or(Err(Error::InternalError))
+}
+
+pub fn get_batt_stats(cid: CID) -> Result<BattStats, xous::Error> {
+    lend_batt_stats(cid, ComState::STAT.verb)
+}
+
 pub fn get_batt_stats_nb(cid: CID) -> Result<(), xous::Error> {
     send_message(cid, api::Opcode::BattStatsNb.into()).map(|_|())
 }
+
+/// Fetch the stats that were read by the last `get_batt_stats_nb()`, once
+/// `EVENT_BATT_STATS` has been signalled.
+pub fn get_batt_stats_latest(cid: CID) -> Result<BattStats, xous::Error> {
+    lend_batt_stats(cid, ComState::STAT_RETURN.verb)
+}
@@ -17,8 +17,42 @@ pub struct WorkRequest {
     sender: CID,
 }
 
-fn return_battstats(cid: CID, stats: api::BattStats)  -> Result<(), xous::Error> {
-    xous::send_message(cid, crate::api::Opcode::BattStatsReturn(stats).into()).map(|_| ())
+/// Write `stats` into the buffer that was lent with a `BattStats` or
+/// `BattStatsLatest` request. The requester gets it back when the envelope
+/// is dropped.
+fn reply_battstats(envelope: &mut xous::MessageEnvelope, stats: &api::BattStats) {
+    if let xous::Message::MutableBorrow(m) = &mut envelope.body {
+        xous::ipc::replace_memory_message(m, stats).expect("COM: couldn't encode batt stats");
+    }
+}
+
+/// Tell the requester that new battery stats are ready, without waiting for it
+/// to take them.
+fn notify_battstats(cid: CID) -> Result<(), xous::Error> {
+    xous::signal_events(cid, api::EVENT_BATT_STATS)
+}
+
+/// Send the shell a new key for `ClaimShipMode` and return it. Only the
+/// process that owns the server registered as the shell receives it, so
+/// whoever passes it back is the shell. A fresh SID is the only source of
+/// randomness here, so the key is made from one.
+fn send_ship_mode_key(shell_conn: CID) -> Result<[usize; 4], xous::Error> {
+    let sid = xous::create_server()?;
+    xous::destroy_server(sid)?;
+    let (a0, a1, a2, a3) = sid.to_u32();
+    let key = [a0 as usize, a1 as usize, a2 as usize, a3 as usize];
+    xous::send_message(
+        shell_conn,
+        xous::Message::Scalar(xous::ScalarMessage {
+            id: api::SHIP_MODE_KEY,
+            arg1: key[0],
+            arg2: key[1],
+            arg3: key[2],
+            arg4: key[3],
+        }),
+    )?;
+    xous::signal_events(shell_conn, api::EVENT_SHIP_MODE_KEY)?;
+    Ok(key)
 }
 
 #[cfg(target_os = "none")]
@@ -30,7 +64,7 @@ mod implementation {
     use utralib::generated::*;
     use xous::CID;
     use log::{error, info};
-    use crate::return_battstats;
+    use crate::notify_battstats;
 
     #[macro_use]
     use heapless::Vec;
@@ -47,6 +81,7 @@ mod implementation {
         csr: utralib::CSR<u32>,
         ticktimer: CID,
         pub workqueue: Vec<WorkRequest, U64>,
+        pub latest_stats: BattStats,
         busy: bool,
     }
 
@@ -66,13 +101,16 @@ mod implementation {
             )
             .expect("couldn't map COM CSR range");
 
-            let ticktimer_server_id = xous::SID::from_bytes(b"ticktimer-server").unwrap();
-            let ticktimer_conn = xous::connect(ticktimer_server_id).unwrap();
+            let ticktimer_conn = xous_names::request_connection_blocking(
+                ticktimer_server::api::SERVER_NAME_TICKTIMER,
+            )
+            .unwrap();
 
             let mut xc = XousCom {
                 csr: CSR::new(csr.as_mut_ptr() as *mut u32),
                 ticktimer: ticktimer_conn,
                 workqueue: Vec::new(),
+                latest_stats: BattStats::default(),
                 busy: false,
                 //tx_queue: Vec::new(),
                 //rx_queue: Vec::new(),
@@ -127,8 +165,8 @@ mod implementation {
                 self.busy = true;
                 let work_descriptor = self.workqueue.swap_remove(0); // not quite FIFO, but Vec does not support FIFO (best we can do with "heapless")
                 if work_descriptor.work.verb == ComState::STAT.verb {
-                    let stats = self.get_battstats();
-                    return_battstats(work_descriptor.sender, stats).expect("Could not return BattStatsNb value");
+                    self.latest_stats = self.get_battstats();
+                    notify_battstats(work_descriptor.sender).expect("Could not signal BattStatsNb completion");
                 } else {
                     error!("unimplemented work queue responder 0x{:x}", work_descriptor.work.verb);
                 }
@@ -162,7 +200,7 @@ mod implementation {
     use crate::WorkRequest;
     use log::{error, info};
     use com_rs::*;
-    use crate::return_battstats;
+    use crate::notify_battstats;
 
     #[macro_use]
     use heapless::Vec;
@@ -170,6 +208,7 @@ mod implementation {
 
     pub struct XousCom {
         pub workqueue: Vec<WorkRequest, U64>,
+        pub latest_stats: BattStats,
         busy: bool,
     }
 
@@ -177,6 +216,7 @@ mod implementation {
         pub fn new() -> XousCom {
             XousCom {
                 workqueue: Vec::new(),
+                latest_stats: BattStats::default(),
                 busy: false,
             }
         }
@@ -199,8 +239,8 @@ mod implementation {
                 self.busy = true;
                 let work_descriptor = self.workqueue.swap_remove(0); // not quite FIFO, but Vec does not support FIFO (best we can do with "heapless")
                 if work_descriptor.work.verb == ComState::STAT.verb {
-                    let stats = self.get_battstats();
-                    return_battstats(work_descriptor.sender, stats).expect("Could not return BattStatsNb value");
+                    self.latest_stats = self.get_battstats();
+                    notify_battstats(work_descriptor.sender).expect("Could not signal BattStatsNb completion");
                 } else {
                     error!("unimplemented work queue responder 0x{:x}", work_descriptor.work.verb);
                 }
@@ -217,17 +257,27 @@ fn xmain() -> ! {
     log_server::init_wait().unwrap();
 
     let com_server =
-        xous::create_server_with_address(b"com             ").expect("Couldn't create COM server");
+        xous_names::register_name(api::SERVER_NAME_COM).expect("Couldn't create COM server");
 
-    let shell_id =      xous::SID::from_bytes(b"shell           ").unwrap();
-    let shell_conn = xous::connect(shell_id).unwrap();
+    // Only the shell may disconnect the battery. Everyone else is connected
+    // without `ShipMode`, and the shell is given it once it claims it.
+    xous::set_connection_rights(
+        com_server,
+        None,
+        xous::ConnectionRights::excluding_id(ComState::POWER_SHIPMODE.verb),
+    )
+    .expect("Couldn't set COM connection rights");
+
+    let shell_conn = xous_names::request_connection_blocking(api::SERVER_NAME_SHELL).unwrap();
+    let mut ship_mode_key =
+        Some(send_ship_mode_key(shell_conn).expect("Couldn't send the ship mode key"));
 
     // Create a new com object
     let mut com = XousCom::new();
 
     loop {
         info!("COM: waiting for message");
-        let envelope = xous::receive_message(com_server).unwrap();
+        let mut envelope = xous::receive_message(com_server).unwrap();
         info!("COM: Message: {:?}", envelope);
         if let Ok(opcode) = Opcode::try_from(&envelope.body) {
             info!("COM: Opcode: {:?}", opcode);
@@ -236,25 +286,46 @@ fn xmain() -> ! {
                     info!("COM: power off called");
                     com.txrx(ComState::POWER_OFF.verb);
                 }
-                Opcode::BattStats => {
+                Opcode::BattStats(_) => {
                     info!("COM: batt stats request received");
                     let stats = com.get_battstats();
-                    let raw_stats: [usize; 2] = stats.into();
-                    xous::return_scalar2(
-                        envelope.sender,
-                        raw_stats[1],
-                        raw_stats[0]
-                    ).expect("COM: couldn't return batt stats request");
+                    reply_battstats(&mut envelope, &stats);
                     info!("COM: done returning batt stats request");
                 }
+                Opcode::BattStatsLatest(_) => {
+                    reply_battstats(&mut envelope, &com.latest_stats);
+                }
                 Opcode::BattStatsNb => {
                     com.workqueue.push(WorkRequest { work: ComState::STAT, sender: shell_conn }).unwrap();
+                }
+                Opcode::ShipMode => {
+                    info!("COM: ship mode called");
+                    com.txrx(ComState::POWER_SHIPMODE.verb);
+                }
+                Opcode::ClaimShipMode(k0, k1, k2, k3) => {
+                    let claimed = ship_mode_key == Some([k0, k1, k2, k3])
+                        && envelope.sender.pid().map_or(false, |pid| {
+                            xous::set_connection_rights(
+                                com_server,
+                                Some(pid),
+                                xous::ConnectionRights::ALL,
+                            )
+                            .is_ok()
+                        });
+                    if claimed {
+                        info!("COM: ship mode claimed by {:?}", envelope.sender.pid());
+                        ship_mode_key = None;
+                    }
+                    xous::return_scalar(envelope.sender, claimed as usize)
+                        .expect("COM: couldn't answer ship mode claim");
                 }
                     _ => error!("unknown opcode"),
             }
         } else {
             error!("couldn't convert opcode");
         }
+        // return any lent buffer before working through the queue
+        drop(envelope);
 
         com.process_queue();
     }
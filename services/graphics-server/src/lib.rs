@@ -6,65 +6,55 @@ pub use api::{Point, PixelColor, Rectangle, DrawStyle, Line, Circle};
 pub use blitstr::{Cursor, GlyphStyle, ClipRect};
 use xous::String;
 pub mod op;
+pub mod opcode;
 
-use xous::{send_message, CID};
+use xous::CID;
 
 pub fn draw_line(cid: CID, line: Line) -> Result<(), xous::Error> {
-    send_message(cid, api::Opcode::Line(line).into()).map(|_| ())
+    opcode::draw_line(cid, line.start.into(), line.end.into(), line.style.into())
 }
 
 pub fn draw_circle(cid: CID, circ: Circle) -> Result<(), xous::Error> {
-    send_message(cid, api::Opcode::Circle(circ).into()).map(|_| ())
+    opcode::draw_circle(cid, circ.center.into(), circ.radius as usize, circ.style.into())
 }
 
 pub fn draw_rectangle(cid: CID, rect: Rectangle) -> Result<(), xous::Error> {
-    send_message(cid, api::Opcode::Rectangle(rect).into()).map(|_| ())
+    opcode::draw_rectangle(cid, rect.tl.into(), rect.br.into(), rect.style.into())
 }
 
 pub fn flush(cid: CID) -> Result<(), xous::Error> {
-    send_message(cid, api::Opcode::Flush.into()).map(|_| ())
+    opcode::flush(cid)
 }
 
 pub fn set_string_clipping(cid: CID, r: ClipRect) -> Result<(), xous::Error> {
-    send_message(cid, api::Opcode::SetStringClipping(r).into()).map(|_| ())
+    opcode::set_string_clipping(cid, r.min.x as _, r.min.y as _, r.max.x as _, r.max.y as _)
 }
 
 pub fn set_cursor(cid: CID, c: Cursor) -> Result<(), xous::Error> {
-    send_message(cid, api::Opcode::SetCursor(c).into()).map(|_| ())
+    opcode::set_cursor(cid, c.pt.x, c.pt.y, c.line_height)
 }
 
 pub fn get_cursor(cid: CID) -> Result<Cursor, xous::Error> {
-    let response = send_message(cid, api::Opcode::GetCursor.into())?;
-    if let xous::Result::Scalar2(pt_as_usize, h) = response {
-        let p: Point = pt_as_usize.into();
-        Ok(Cursor::new(p.x as usize, p.y as usize, h as _))
-    } else {
-        panic!("unexpected return value: {:#?}", response);
-    }
+    let (pt_as_usize, h) = opcode::get_cursor(cid)?;
+    let p: Point = pt_as_usize.into();
+    Ok(Cursor::new(p.x as usize, p.y as usize, h as _))
 }
 
+/// Lend `s` to the server, which draws it with `opcode::Opcode::DrawString`.
 pub fn draw_string(cid: CID, s: &String) -> Result<(), xous::Error> {
     s.lend(cid, 1).map(|_| ())
 }
 
 pub fn set_glyph_style(cid: CID, glyph: GlyphStyle) -> Result<(), xous::Error> {
-    send_message(cid, api::Opcode::SetGlyphStyle(glyph).into()).map( |_| ())
+    opcode::set_glyph_style(cid, glyph as usize)
 }
 
 pub fn screen_size(cid: CID) -> Result<Point, xous::Error> {
-    let response = send_message(cid, api::Opcode::ScreenSize.into())?;
-    if let xous::Result::Scalar2(x, y) = response {
-        Ok(Point::new(x as _, y as _))
-    } else {
-        panic!("unexpected return value: {:#?}", response);
-    }
+    let (x, y) = opcode::screen_size(cid)?;
+    Ok(Point::new(x as _, y as _))
 }
 
 pub fn query_glyph(cid: CID) -> Result<(GlyphStyle, usize), xous::Error> {
-    let response = send_message(cid, api::Opcode::QueryGlyphStyle.into())?;
-    if let xous::Result::Scalar2(glyph, h) = response {
-        Ok((GlyphStyle::from(glyph), h))
-    } else {
-        panic!("unexpected return value: {:#?}", response);
-    }
-}
\ No newline at end of file
+    let (glyph, h) = opcode::query_glyph_style(cid)?;
+    Ok((GlyphStyle::from(glyph), h))
+}
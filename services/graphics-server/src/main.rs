@@ -7,21 +7,119 @@ mod backend;
 use backend::XousDisplay;
 
 mod api;
-use api::Opcode;
 
 mod op;
 
-use core::convert::TryFrom;
+mod opcode;
 
 mod logo;
 
 use blitstr;
-use api::{PixelColor, Rectangle, DrawStyle};
+use api::{PixelColor, Rectangle, DrawStyle, Line, Circle, Point};
 
 fn draw_boot_logo(display: &mut XousDisplay) {
     display.blit_screen(logo::LOGO_MAP);
 }
 
+/// Everything the server draws with, and the string settings that clients choose
+struct Gfx {
+    display: XousDisplay,
+    current_glyph: blitstr::GlyphStyle,
+    current_string_clip: blitstr::ClipRect,
+    current_cursor: blitstr::Cursor,
+
+    /// The process that draws on the screen. Only one process may be
+    /// connected at a time, and another may connect once it terminates.
+    client: Option<xous::PID>,
+}
+
+impl Gfx {
+    /// Decide whether `pid` may connect, and make it the client if there isn't
+    /// one yet.
+    fn approve_client(&mut self, pid: xous::PID) -> bool {
+        match self.client {
+            Some(client) => client == pid,
+            None => {
+                self.client = Some(pid);
+                true
+            }
+        }
+    }
+}
+
+impl opcode::Server for Gfx {
+    fn flush(&mut self) {
+        self.display.update();
+        self.display.redraw();
+    }
+
+    fn clear(&mut self) {
+        let mut r = Rectangle::full_screen();
+        r.style = DrawStyle::new(PixelColor::Light, PixelColor::Light, 0);
+        op::rectangle(self.display.native_buffer(), r)
+    }
+
+    fn draw_line(&mut self, start: usize, end: usize, style: usize) {
+        let l = Line::new_with_style(Point::from(start), Point::from(end), DrawStyle::from(style));
+        op::line(self.display.native_buffer(), l);
+    }
+
+    fn draw_rectangle(&mut self, tl: usize, br: usize, style: usize) {
+        let r = Rectangle::new_with_style(Point::from(tl), Point::from(br), DrawStyle::from(style));
+        op::rectangle(self.display.native_buffer(), r);
+    }
+
+    fn draw_circle(&mut self, center: usize, radius: usize, style: usize) {
+        let c = Circle::new_with_style(Point::from(center), radius as _, DrawStyle::from(style));
+        op::circle(self.display.native_buffer(), c);
+    }
+
+    fn screen_size(&mut self) -> (usize, usize) {
+        (336, 536)
+    }
+
+    fn set_glyph_style(&mut self, glyph: usize) {
+        self.current_glyph = blitstr::GlyphStyle::from(glyph);
+    }
+
+    fn query_glyph_style(&mut self) -> (usize, usize) {
+        (self.current_glyph.into(), blitstr::glyph_to_height_hint(self.current_glyph))
+    }
+
+    fn set_string_clipping(&mut self, min_x: usize, min_y: usize, max_x: usize, max_y: usize) {
+        self.current_string_clip = blitstr::ClipRect::new(min_x as _, min_y as _, max_x as _, max_y as _);
+    }
+
+    fn set_cursor(&mut self, x: usize, y: usize, line_height: usize) {
+        self.current_cursor = blitstr::Cursor::new(x as _, y as _, line_height as _);
+    }
+
+    fn get_cursor(&mut self) -> (usize, usize) {
+        let pt: Point = Point::new(self.current_cursor.pt.x as i16, self.current_cursor.pt.y as i16);
+        (pt.into(), self.current_cursor.line_height)
+    }
+
+    fn draw_string(&mut self, _sender: xous::PID, message: &xous::MemoryMessage) {
+        let len = message.valid.map(|x| x.get()).unwrap_or_else(|| message.buf.len());
+        let s = unsafe {
+            core::slice::from_raw_parts(message.buf.as_ptr(), len.min(message.buf.len()))
+        };
+        match core::str::from_utf8(s) {
+            Ok(s) => {
+                blitstr::paint_str(self.display.native_buffer(), self.current_string_clip.into(), &mut self.current_cursor, self.current_glyph.into(), s);
+            }
+            Err(_) => error!("GFX: string isn't valid UTF-8"),
+        }
+    }
+
+    fn client_disconnected(&mut self, pid: xous::PID) {
+        if self.client == Some(pid) {
+            info!("GFX: client {} has gone away", pid);
+            self.client = None;
+        }
+    }
+}
+
 #[xous::xous_main]
 fn xmain() -> ! {
     log_server::init_wait().unwrap();
@@ -31,80 +129,44 @@ fn xmain() -> ! {
 
     draw_boot_logo(&mut display);
 
-    let mut current_glyph = blitstr::GlyphStyle::Regular;
-    let mut current_string_clip = blitstr::ClipRect::full_screen();
-    let mut current_cursor = blitstr::Cursor::from_top_left_of(current_string_clip);
-
+    let current_string_clip = blitstr::ClipRect::full_screen();
     display.redraw();
 
-    let sid = xous::create_server_with_address(b"graphics-server ").unwrap();
+    let mut gfx = Gfx {
+        display,
+        current_glyph: blitstr::GlyphStyle::Regular,
+        current_string_clip,
+        current_cursor: blitstr::Cursor::from_top_left_of(current_string_clip),
+        client: None,
+    };
+
+    let sid = xous_names::register_name(api::SERVER_NAME_GFX).expect("GFX: couldn't register name");
+    xous::require_connect_approval(sid, true).expect("GFX: couldn't require connect approval");
+    xous::notify_disconnects(sid, true).expect("GFX: couldn't ask for disconnect notices");
     // info!("GFX: Server listening on address {:?}", sid);
     // ::debug_here::debug_here!();
     loop {
-        let msg = xous::receive_message(sid).unwrap();
-        // info!("GFX: Message: {:?}", msg);
-        if let Ok(opcode) = Opcode::try_from(&msg.body) {
-            // info!("GFX: Opcode: {:?}", opcode);
-            match opcode {
-                Opcode::Flush => {
-                    display.update();
-                    display.redraw();
-                },
-                Opcode::Clear => {
-                    let mut r = Rectangle::full_screen();
-                    r.style = DrawStyle::new(PixelColor::Light, PixelColor::Light, 0);
-                    op::rectangle(display.native_buffer(), r)
-                }
-                Opcode::Line(l) => {
-                    op::line(display.native_buffer(), l);
-                }
-                Opcode::Rectangle(r) => {
-                    op::rectangle(display.native_buffer(), r);
-                }
-                Opcode::Circle(c) => {
-                    op::circle(display.native_buffer(), c);
-                }
-                Opcode::String(s) => {
-                    blitstr::paint_str(display.native_buffer(), current_string_clip.into(), &mut current_cursor, current_glyph.into(), s);
-                }
-                Opcode::SetGlyphStyle(glyph) => {
-                    current_glyph = glyph;
-                }
-                Opcode::SetCursor(c) => {
-                    current_cursor = c;
-                }
-                Opcode::GetCursor => {
-                    let pt: api::Point = api::Point::new(current_cursor.pt.x as i16, current_cursor.pt.y as i16);
-                    xous::return_scalar2(
-                        msg.sender,
-                        pt.into(),
-                        current_cursor.line_height,
-                    )
-                    .expect("GFX: could not return GetCursor request");
-                }
-                Opcode::SetStringClipping(r) => {
-                    current_string_clip = r;
-                }
-                Opcode::ScreenSize => {
-                    xous::return_scalar2(
-                        msg.sender,
-                        336 as usize,
-                        536 as usize,
-                    )
-                    .expect("GFX: couldn't return ScreenSize request");
-                }
-                Opcode::QueryGlyphStyle => {
-                    xous::return_scalar2(
-                        msg.sender,
-                        current_glyph.into(),
-                        blitstr::glyph_to_height_hint(current_glyph),
-                    )
-                    .expect("GFX: could not return QueryGlyph request");
-                }
+        let mut envelope = xous::receive_message(sid).unwrap();
+        // info!("GFX: Message: {:?}", envelope);
+        if let xous::Message::BlockingScalar(xous::ScalarMessage {
+            id: xous::CONNECT_REQUEST_ID,
+            arg1,
+            ..
+        }) = envelope.body
+        {
+            let approved = xous::pid_from_usize(arg1).map_or(false, |pid| gfx.approve_client(pid));
+            if !approved {
+                info!("GFX: turned down a connection from PID {}", arg1);
             }
-        } else {
-            error!("Couldn't convert opcode");
+            xous::approve_connection(envelope.sender, approved)
+                .expect("GFX: couldn't answer connect request");
+            continue;
+        }
+        if let Err(e) = opcode::dispatch(&mut gfx, &mut envelope) {
+            error!("GFX: couldn't handle message: {}", e);
         }
-        display.update();
+        // return the string before updating the display
+        drop(envelope);
+        gfx.display.update();
     }
 }
@@ -0,0 +1,258 @@
+// Generated by `idl-gen` from opcode.idl. Don't edit this file: change the IDL
+// and run `cargo xtask generate-idl` instead.
+#![allow(dead_code)]
+
+use core::convert::TryFrom;
+
+#[derive(Debug, xous::Opcode)]
+pub enum Opcode {
+    /// Flush the buffer to the screen
+    #[opcode(scalar, id = 1)]
+    Flush,
+
+    /// Clear the buffer to "light" colored pixels
+    #[opcode(scalar, id = 2)]
+    Clear,
+
+    /// Draw a line at the specified area
+    #[opcode(scalar, id = 3)]
+    DrawLine(usize, usize, usize),
+
+    /// Draw a rectangle or square at the specified coordinates
+    #[opcode(scalar, id = 4)]
+    DrawRectangle(usize, usize, usize),
+
+    /// Draw a circle with a specified radius
+    #[opcode(scalar, id = 5)]
+    DrawCircle(usize, usize, usize),
+
+    /// Retrieve the X and Y dimensions of the screen
+    #[opcode(blocking_scalar, id = 8)]
+    ScreenSize,
+
+    /// Set the current string glyph set for strings
+    #[opcode(scalar, id = 9)]
+    SetGlyphStyle(usize),
+
+    /// Retrieve the current glyph style, and the height of its lines
+    #[opcode(blocking_scalar, id = 10)]
+    QueryGlyphStyle,
+
+    /// Set the clipping region for the string.
+    #[opcode(scalar, id = 11)]
+    SetStringClipping(usize, usize, usize, usize),
+
+    /// Set the cursor point for the current string clipping region
+    #[opcode(scalar, id = 12)]
+    SetCursor(usize, usize, usize),
+
+    /// Retrieve the current cursor point, packed like any other point, and the
+    /// height of its line
+    #[opcode(blocking_scalar, id = 13)]
+    GetCursor,
+
+    /// Render the UTF-8 string in the lent buffer inside the clipping region.
+    #[opcode(borrow, id = 1)]
+    DrawString(xous::MemoryMessage),
+}
+
+/// Flush the buffer to the screen
+pub fn flush(cid: xous::CID) -> Result<(), xous::Error> {
+    xous::send_message(cid, Opcode::Flush.into()).map(|_| ())
+}
+
+/// Clear the buffer to "light" colored pixels
+pub fn clear(cid: xous::CID) -> Result<(), xous::Error> {
+    xous::send_message(cid, Opcode::Clear.into()).map(|_| ())
+}
+
+/// Draw a line at the specified area
+pub fn draw_line(
+    cid: xous::CID,
+    start: usize,
+    end: usize,
+    style: usize,
+) -> Result<(), xous::Error> {
+    xous::send_message(cid, Opcode::DrawLine(start, end, style).into()).map(|_| ())
+}
+
+/// Draw a rectangle or square at the specified coordinates
+pub fn draw_rectangle(
+    cid: xous::CID,
+    tl: usize,
+    br: usize,
+    style: usize,
+) -> Result<(), xous::Error> {
+    xous::send_message(cid, Opcode::DrawRectangle(tl, br, style).into()).map(|_| ())
+}
+
+/// Draw a circle with a specified radius
+pub fn draw_circle(
+    cid: xous::CID,
+    center: usize,
+    radius: usize,
+    style: usize,
+) -> Result<(), xous::Error> {
+    xous::send_message(cid, Opcode::DrawCircle(center, radius, style).into()).map(|_| ())
+}
+
+/// Retrieve the X and Y dimensions of the screen
+pub fn screen_size(cid: xous::CID) -> Result<(usize, usize), xous::Error> {
+    let response = xous::send_message(cid, Opcode::ScreenSize.into())?;
+    if let xous::Result::Scalar2(value1, value2) = response {
+        Ok((value1, value2))
+    } else {
+        panic!("unexpected return value: {:#?}", response);
+    }
+}
+
+/// Set the current string glyph set for strings
+pub fn set_glyph_style(cid: xous::CID, glyph: usize) -> Result<(), xous::Error> {
+    xous::send_message(cid, Opcode::SetGlyphStyle(glyph).into()).map(|_| ())
+}
+
+/// Retrieve the current glyph style, and the height of its lines
+pub fn query_glyph_style(cid: xous::CID) -> Result<(usize, usize), xous::Error> {
+    let response = xous::send_message(cid, Opcode::QueryGlyphStyle.into())?;
+    if let xous::Result::Scalar2(value1, value2) = response {
+        Ok((value1, value2))
+    } else {
+        panic!("unexpected return value: {:#?}", response);
+    }
+}
+
+/// Set the clipping region for the string.
+pub fn set_string_clipping(
+    cid: xous::CID,
+    min_x: usize,
+    min_y: usize,
+    max_x: usize,
+    max_y: usize,
+) -> Result<(), xous::Error> {
+    xous::send_message(
+        cid,
+        Opcode::SetStringClipping(min_x, min_y, max_x, max_y).into(),
+    )
+    .map(|_| ())
+}
+
+/// Set the cursor point for the current string clipping region
+pub fn set_cursor(
+    cid: xous::CID,
+    x: usize,
+    y: usize,
+    line_height: usize,
+) -> Result<(), xous::Error> {
+    xous::send_message(cid, Opcode::SetCursor(x, y, line_height).into()).map(|_| ())
+}
+
+/// Retrieve the current cursor point, packed like any other point, and the
+/// height of its line
+pub fn get_cursor(cid: xous::CID) -> Result<(usize, usize), xous::Error> {
+    let response = xous::send_message(cid, Opcode::GetCursor.into())?;
+    if let xous::Result::Scalar2(value1, value2) = response {
+        Ok((value1, value2))
+    } else {
+        panic!("unexpected return value: {:#?}", response);
+    }
+}
+
+/// Render the UTF-8 string in the lent buffer inside the clipping region.
+pub fn draw_string(cid: xous::CID, message: xous::MemoryMessage) -> Result<(), xous::Error> {
+    xous::send_message(cid, Opcode::DrawString(message).into()).map(|_| ())
+}
+
+/// The methods a server provides to handle each opcode. `dispatch()` calls
+/// them, and sends whatever they return back to the client.
+pub trait Server {
+    /// Flush the buffer to the screen
+    fn flush(&mut self);
+
+    /// Clear the buffer to "light" colored pixels
+    fn clear(&mut self);
+
+    /// Draw a line at the specified area
+    fn draw_line(&mut self, start: usize, end: usize, style: usize);
+
+    /// Draw a rectangle or square at the specified coordinates
+    fn draw_rectangle(&mut self, tl: usize, br: usize, style: usize);
+
+    /// Draw a circle with a specified radius
+    fn draw_circle(&mut self, center: usize, radius: usize, style: usize);
+
+    /// Retrieve the X and Y dimensions of the screen
+    fn screen_size(&mut self) -> (usize, usize);
+
+    /// Set the current string glyph set for strings
+    fn set_glyph_style(&mut self, glyph: usize);
+
+    /// Retrieve the current glyph style, and the height of its lines
+    fn query_glyph_style(&mut self) -> (usize, usize);
+
+    /// Set the clipping region for the string.
+    fn set_string_clipping(&mut self, min_x: usize, min_y: usize, max_x: usize, max_y: usize);
+
+    /// Set the cursor point for the current string clipping region
+    fn set_cursor(&mut self, x: usize, y: usize, line_height: usize);
+
+    /// Retrieve the current cursor point, packed like any other point, and the
+    /// height of its line
+    fn get_cursor(&mut self) -> (usize, usize);
+
+    /// Render the UTF-8 string in the lent buffer inside the clipping region.
+    fn draw_string(&mut self, sender: xous::PID, message: &xous::MemoryMessage);
+
+    /// A process that was connected to the server has terminated. This is
+    /// only called once the server has asked for it with `xous::notify_disconnects()`.
+    fn client_disconnected(&mut self, _pid: xous::PID) {}
+}
+
+/// Call the method of `server` that handles the message in `envelope`, and
+/// send its reply if the message expects one. Lent memory is returned when
+/// `envelope` is dropped.
+pub fn dispatch<S: Server>(
+    server: &mut S,
+    envelope: &mut xous::MessageEnvelope,
+) -> Result<(), &'static str> {
+    if let xous::Message::Scalar(xous::ScalarMessage {
+        id: xous::CLIENT_DISCONNECTED_ID,
+        arg1,
+        ..
+    }) = envelope.body
+    {
+        let pid = xous::pid_from_usize(arg1).or(Err("invalid PID"))?;
+        server.client_disconnected(pid);
+        return Ok(());
+    }
+    match Opcode::try_from(&envelope.body)? {
+        Opcode::Flush => server.flush(),
+        Opcode::Clear => server.clear(),
+        Opcode::DrawLine(start, end, style) => server.draw_line(start, end, style),
+        Opcode::DrawRectangle(tl, br, style) => server.draw_rectangle(tl, br, style),
+        Opcode::DrawCircle(center, radius, style) => server.draw_circle(center, radius, style),
+        Opcode::ScreenSize => {
+            let (value1, value2) = server.screen_size();
+            xous::return_scalar2(envelope.sender, value1, value2).or(Err("couldn't send reply"))?;
+        }
+        Opcode::SetGlyphStyle(glyph) => server.set_glyph_style(glyph),
+        Opcode::QueryGlyphStyle => {
+            let (value1, value2) = server.query_glyph_style();
+            xous::return_scalar2(envelope.sender, value1, value2).or(Err("couldn't send reply"))?;
+        }
+        Opcode::SetStringClipping(min_x, min_y, max_x, max_y) => {
+            server.set_string_clipping(min_x, min_y, max_x, max_y)
+        }
+        Opcode::SetCursor(x, y, line_height) => server.set_cursor(x, y, line_height),
+        Opcode::GetCursor => {
+            let (value1, value2) = server.get_cursor();
+            xous::return_scalar2(envelope.sender, value1, value2).or(Err("couldn't send reply"))?;
+        }
+        Opcode::DrawString(_) => {
+            let sender = envelope.sender.pid().ok_or("no sender")?;
+            if let xous::Message::Borrow(message) = &envelope.body {
+                server.draw_string(sender, message);
+            }
+        }
+    }
+    Ok(())
+}
@@ -1,21 +1,17 @@
 #![cfg_attr(target_os = "none", no_std)]
 use core::fmt::Write;
-use core::sync::atomic::{AtomicBool, Ordering};
+use xous::sync::Mutex;
 use xous::String;
 
-static XOUS_LOGGER: XousLogger = XousLogger {
-    locked: AtomicBool::new(false),
-};
+static XOUS_LOGGER: XousLogger = XousLogger {};
 
-struct XousLogger {
-    locked: AtomicBool,
-}
+struct XousLogger {}
 
-static mut XOUS_LOGGER_BACKING: XousLoggerBacking = XousLoggerBacking {
+static XOUS_LOGGER_BACKING: Mutex<XousLoggerBacking> = Mutex::new(XousLoggerBacking {
     conn: 0,
     initialized: false,
     buffer: None,
-};
+});
 
 struct XousLoggerBacking {
     conn: xous::CID,
@@ -52,18 +48,7 @@ impl log::Log for XousLogger {
     }
 
     fn log(&self, record: &log::Record) {
-        while self
-            .locked
-            .compare_exchange(false, true, Ordering::SeqCst, Ordering::Acquire)
-            .is_err()
-        {
-            xous::yield_slice();
-        }
-
-        unsafe { XOUS_LOGGER_BACKING.log_impl(record) };
-        self.locked
-            .compare_exchange(true, false, Ordering::SeqCst, Ordering::Acquire)
-            .expect("LOG: logger became unlocked somehow");
+        XOUS_LOGGER_BACKING.lock().log_impl(record);
     }
     fn flush(&self) {}
 }
@@ -77,10 +62,8 @@ pub fn init() -> Result<(), log::SetLoggerError> {
 pub fn init_wait() -> Result<(), log::SetLoggerError> {
     log::set_logger(&XOUS_LOGGER)?;
     log::set_max_level(log::LevelFilter::Info);
-    unsafe {
-        while XOUS_LOGGER_BACKING.init().is_err() {
-            xous::yield_slice();
-        }
+    while XOUS_LOGGER_BACKING.lock().init().is_err() {
+        xous::yield_slice();
     }
     Ok(())
 }
@@ -8,8 +8,6 @@ use blitstr::{Cursor, GlyphStyle};
 use log::{error, info};
 use xous::String;
 
-use core::convert::TryFrom;
-
 fn move_lfsr(mut lfsr: u32) -> u32 {
     lfsr ^= lfsr >> 7;
     lfsr ^= lfsr << 9;
@@ -95,35 +93,83 @@ impl Bounce {
     }
 }
 
-use core::sync::atomic::{AtomicI16, AtomicU16, AtomicU8, Ordering};
-
-// need atomic global constants to pass data between threads
-// as we do not yet have a "Mutex" in Xous
-static BATT_STATS_VOLTAGE: AtomicU16 = AtomicU16::new(3700);
-static BATT_STATS_CURRENT: AtomicI16 = AtomicI16::new(-150);
-static BATT_STATS_SOC: AtomicU8 = AtomicU8::new(50);
-static BATT_STATS_REMAINING: AtomicU16 = AtomicU16::new(750);
+use xous::sync::Mutex;
+
+// the most recent battery stats, shared with the COM response thread
+static BATT_STATS: Mutex<com::api::BattStats> = Mutex::new(com::api::BattStats {
+    voltage: 3700,
+    soc: 50,
+    current: -150,
+    remaining_capacity: 750,
+});
+
+// PIDs above this are never handed out, as the kernel runs at most this many processes
+const MAX_PROCESS_COUNT: u8 = 32;
+
+/// Write a table of how much memory each process is using, the way `top` would,
+/// with the processes that own the most memory first.
+fn write_memory_stats(buffer: &mut String) -> core::fmt::Result {
+    let mut processes = [(0u8, xous::MemoryStats::default()); MAX_PROCESS_COUNT as usize];
+    let mut count = 0;
+    // there's no way to list the processes, so ask about every PID in turn
+    for pid in 1..=MAX_PROCESS_COUNT {
+        if let Ok(stats) = xous::memory_stats(xous::PID::new(pid).unwrap()) {
+            processes[count] = (pid, stats);
+            count += 1;
+        }
+    }
+    let processes = &mut processes[..count];
+    processes.sort_unstable_by(|a, b| {
+        (b.1.owned_pages, b.1.heap_size).cmp(&(a.1.owned_pages, a.1.heap_size))
+    });
+
+    let free_pages = processes.first().map(|(_, stats)| stats.free_pages).unwrap_or(0);
+    writeln!(buffer, "{} processes, {} pages free", count, free_pages)?;
+    writeln!(buffer, "PID  OWNED  HEAP  LENT  LENT TO")?;
+    for (pid, stats) in processes.iter() {
+        writeln!(
+            buffer,
+            "{:>3}  {:>5}  {:>4}K  {:>4}  {:>7}",
+            pid,
+            stats.owned_pages,
+            stats.heap_size / 1024,
+            stats.lent_pages,
+            stats.lent_to_servers
+        )?;
+    }
+    Ok(())
+}
 
-fn com_thread(_arg: Option<u32>) {
-    let shell_server =
-        xous::create_server_with_address(b"shell           ").expect("Couldn't create Shell server");
-    info!("SHELL|com_thread: starting COM response handler thread");
+fn com_thread(com_conn: xous::CID) {
+    let shell_server = xous_names::register_name(com::api::SERVER_NAME_SHELL)
+        .expect("Couldn't create Shell server");
+    info!("SHELL|com_thread: starting COM event handler thread");
     loop {
-        let envelope =
-            xous::syscall::receive_message(shell_server).expect("couldn't get address");
-        info!("SHELL|com_thread: got message {:?}", envelope);
-        if let Ok(opcode) = com::api::Opcode::try_from(&envelope.body) {
-            match opcode {
-                com::api::Opcode::BattStatsReturn(stats) => {
-                    BATT_STATS_VOLTAGE.store(stats.voltage, Ordering::Relaxed);
-                    BATT_STATS_CURRENT.store(stats.current, Ordering::Relaxed);
-                    BATT_STATS_SOC.store(stats.soc, Ordering::Relaxed);
-                    BATT_STATS_REMAINING.store(stats.remaining_capacity, Ordering::Relaxed);
+        let events = xous::wait_events(shell_server).expect("couldn't wait for events");
+        info!("SHELL|com_thread: got events {:x}", events);
+        if events & com::api::EVENT_BATT_STATS != 0 {
+            match get_batt_stats_latest(com_conn) {
+                Ok(stats) => *BATT_STATS.lock() = stats,
+                Err(e) => error!("couldn't fetch battery stats: {:?}", e),
+            }
+        }
+        if events & com::api::EVENT_SHIP_MODE_KEY != 0 {
+            take_ship_mode_key(shell_server, com_conn);
+        }
+    }
+}
+
+/// Take the key that COM sent to our server and hand it back, so that this
+/// process is allowed to put the battery into ship mode.
+fn take_ship_mode_key(shell_server: xous::SID, com_conn: xous::CID) {
+    while let Ok(Some(envelope)) = xous::try_receive_message(shell_server) {
+        if let xous::Message::Scalar(m) = &envelope.body {
+            if m.id == com::api::SHIP_MODE_KEY {
+                match com::claim_ship_mode(com_conn, [m.arg1, m.arg2, m.arg3, m.arg4]) {
+                    Ok(()) => info!("SHELL|com_thread: claimed ship mode"),
+                    Err(e) => error!("couldn't claim ship mode: {:?}", e),
                 }
-                _ => error!("shell received an opcode that wasn't expected"),
             }
-        } else {
-            error!("couldn't convert opcode");
         }
     }
 }
@@ -132,16 +178,17 @@ fn com_thread(_arg: Option<u32>) {
 fn shell_main() -> ! {
     log_server::init_wait().unwrap();
 
-    // let log_server_id = xous::SID::from_bytes(b"xous-logs-output").unwrap();
-    let graphics_server_id = xous::SID::from_bytes(b"graphics-server ").unwrap();
-    let ticktimer_server_id = xous::SID::from_bytes(b"ticktimer-server").unwrap();
+    // The log server has a well-known address, since it must be reachable before
+    // name resolution is up. Everything else is looked up by name.
     let log_server_id = xous::SID::from_bytes(b"xous-log-server ").unwrap();
-    let com_id = xous::SID::from_bytes(b"com             ").unwrap();
 
     let log_conn = xous::connect(log_server_id).unwrap();
-    let graphics_conn = xous::connect(graphics_server_id).unwrap();
-    let ticktimer_conn = xous::connect(ticktimer_server_id).unwrap();
-    let com_conn = xous::connect(com_id).unwrap();
+    let graphics_conn =
+        xous_names::request_connection_blocking(graphics_server::api::SERVER_NAME_GFX).unwrap();
+    let ticktimer_conn =
+        xous_names::request_connection_blocking(ticktimer_server::api::SERVER_NAME_TICKTIMER)
+            .unwrap();
+    let com_conn = xous_names::request_connection_blocking(com::api::SERVER_NAME_COM).unwrap();
 
     info!(
         "SHELL: Connected to Log server: {}  Graphics server: {}  Ticktimer server: {} Com: {}",
@@ -158,9 +205,9 @@ fn shell_main() -> ! {
         "SHELL: graphics and ticktimer connections are the same!"
     );
 
-    // make a thread to catch responses from the COM
-    xous::create_thread_simple(com_thread, None).unwrap();
-    info!("SHELL: COM responder thread started");
+    // make a thread to catch events from the COM
+    xous::create_thread_simple(com_thread, com_conn).unwrap();
+    info!("SHELL: COM event thread started");
 
     let screensize = graphics_server::screen_size(graphics_conn).expect("Couldn't get screen size");
 
@@ -206,6 +253,9 @@ fn shell_main() -> ! {
     graphics_server::draw_rectangle(graphics_conn, work_clipregion)
             .expect("unable to clear region");
 
+    // memory view, between the work area and the ball
+    let top_clipregion = Rectangle::new_coords_with_style(4, font_h as i16 * 8 + 20, screensize.x, 18 * 21 - 2, style_light);
+
     let mut firsttime = true;
     loop {
         // status bar
@@ -215,24 +265,26 @@ fn shell_main() -> ! {
             .expect("unable to clear region");
         graphics_server::set_string_clipping(graphics_conn, status_clipregion.into())
             .expect("unable to set string clip region");
+        // take one consistent copy, rather than holding the lock while drawing
+        let stats = *BATT_STATS.lock();
         string_buffer.clear();
-        write!(&mut string_buffer, "{}mV", BATT_STATS_VOLTAGE.load(Ordering::Relaxed)).expect("Can't write");
+        write!(&mut string_buffer, "{}mV", stats.voltage).expect("Can't write");
         status_cursor = Cursor::from_top_left_of(status_clipregion.into());
         graphics_server::set_cursor(graphics_conn, status_cursor).expect("can't set cursor");
         graphics_server::draw_string(graphics_conn, &string_buffer).expect("unable to draw string");
         status_cursor.pt.x = 95;
         string_buffer.clear();
-        write!(&mut string_buffer, "{}mA", BATT_STATS_CURRENT.load(Ordering::Relaxed)).expect("Can't write");
+        write!(&mut string_buffer, "{}mA", stats.current).expect("Can't write");
         graphics_server::set_cursor(graphics_conn, status_cursor).expect("can't set cursor");
         graphics_server::draw_string(graphics_conn, &string_buffer).expect("unable to draw string");
         status_cursor.pt.x = 190;
         string_buffer.clear();
-        write!(&mut string_buffer, "{}mA", BATT_STATS_REMAINING.load(Ordering::Relaxed)).expect("Can't write");
+        write!(&mut string_buffer, "{}mA", stats.remaining_capacity).expect("Can't write");
         graphics_server::set_cursor(graphics_conn, status_cursor).expect("can't set cursor");
         graphics_server::draw_string(graphics_conn, &string_buffer).expect("unable to draw string");
         status_cursor.pt.x = 280;
         string_buffer.clear();
-        write!(&mut string_buffer, "{}%", BATT_STATS_SOC.load(Ordering::Relaxed)).expect("Can't write");
+        write!(&mut string_buffer, "{}%", stats.soc).expect("Can't write");
         graphics_server::set_cursor(graphics_conn, status_cursor).expect("can't set cursor");
         graphics_server::draw_string(graphics_conn, &string_buffer).expect("unable to draw string");
 
@@ -297,6 +349,17 @@ fn shell_main() -> ! {
                 last_time = elapsed_time;
                 info!("Requesting batt stats from COM");
                 get_batt_stats_nb(com_conn).expect("Can't get battery stats from COM");
+
+                string_buffer.clear();
+                write_memory_stats(&mut string_buffer).expect("Can't write");
+                graphics_server::draw_rectangle(graphics_conn, top_clipregion)
+                    .expect("unable to clear region");
+                graphics_server::set_string_clipping(graphics_conn, top_clipregion.into())
+                    .expect("unable to set string clip region");
+                graphics_server::set_cursor(graphics_conn, Cursor::from_top_left_of(top_clipregion.into()))
+                    .expect("can't set cursor");
+                graphics_server::set_glyph_style(graphics_conn, GlyphStyle::Small).expect("unable to set glyph");
+                graphics_server::draw_string(graphics_conn, &string_buffer).expect("unable to draw string");
             }
         } else {
             error!("error requesting ticktimer!")
@@ -0,0 +1,53 @@
+use std::env;
+use std::fmt::Write;
+use std::path::PathBuf;
+
+fn main() {
+    let target = env::var("TARGET").unwrap();
+    let target_os = target.split('-').nth(2).unwrap_or("none");
+
+    // `XOUS_SUPERVISED` lists the services to start as soon as the supervisor
+    // is running, separated by `;`. Each one is given as `name,priority,program`,
+    // where the program is the path to a MiniElf image on hardware, or the
+    // command to run when hosted.
+    let mut services = String::new();
+    if let Ok(list) = env::var("XOUS_SUPERVISED") {
+        for service in list.split(';').filter(|service| !service.is_empty()) {
+            let fields: Vec<&str> = service.splitn(3, ',').collect();
+            if fields.len() != 3 {
+                panic!(
+                    "supervised service {:?} isn't name,priority,program",
+                    service
+                );
+            }
+            let priority: u8 = fields[1]
+                .parse()
+                .unwrap_or_else(|_| panic!("invalid priority for {}", fields[0]));
+            let program = if target_os == "none" {
+                println!("cargo:rerun-if-changed={}", fields[2]);
+                format!("include_bytes!({:?})", fields[2])
+            } else {
+                format!("{:?}.as_bytes()", fields[2])
+            };
+            writeln!(
+                services,
+                "    ({:?}, {}, {}),",
+                fields[0], priority, program
+            )
+            .unwrap();
+        }
+    }
+
+    let out = PathBuf::from(env::var("OUT_DIR").unwrap()).join("builtin_services.rs");
+    std::fs::write(
+        out,
+        format!(
+            "const BUILTIN_SERVICES: &[(&str, xous::Priority, &[u8])] = &[\n{}];\n",
+            services
+        ),
+    )
+    .unwrap();
+
+    println!("cargo:rerun-if-env-changed=XOUS_SUPERVISED");
+    println!("cargo:rerun-if-changed=build.rs");
+}
@@ -0,0 +1,99 @@
+use xous::Message;
+use xous_names::api::XousServerName;
+
+/// The name this server registers with `xous-names`
+pub const SERVER_NAME_SUPERVISOR: &str = "supervisor";
+
+/// The most services that may be supervised at once
+pub const MAX_SERVICES: usize = 16;
+
+/// What to do when a supervised service exits
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum RestartPolicy {
+    /// Leave the service stopped
+    Never = 0,
+
+    /// Restart the service if it exited with a nonzero code or crashed
+    OnFailure = 1,
+
+    /// Restart the service whenever it exits
+    Always = 2,
+}
+
+impl RestartPolicy {
+    pub fn from_usize(value: usize) -> Option<RestartPolicy> {
+        match value {
+            0 => Some(RestartPolicy::Never),
+            1 => Some(RestartPolicy::OnFailure),
+            2 => Some(RestartPolicy::Always),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Opcode {
+    /// Start a service and keep it running. The lent buffer begins with a
+    /// `Supervise` header, and the program follows immediately after it.
+    Supervise = 1,
+
+    /// One or more supervised services have exited. Only sent by the
+    /// supervisor to itself.
+    ServiceExited = 2,
+}
+
+impl<'a> core::convert::TryFrom<&'a Message> for Opcode {
+    type Error = &'static str;
+    fn try_from(message: &'a Message) -> Result<Self, Self::Error> {
+        match message {
+            Message::MutableBorrow(m) => match m.id {
+                1 => Ok(Opcode::Supervise),
+                _ => Err("unrecognized opcode"),
+            },
+            Message::Scalar(m) => match m.id {
+                2 => Ok(Opcode::ServiceExited),
+                _ => Err("unrecognized opcode"),
+            },
+            _ => Err("unhandled message type"),
+        }
+    }
+}
+
+/// Sent to have a service started and supervised. `program_length` bytes of
+/// program follow this header: a MiniElf image on hardware, or a shell command
+/// when running hosted. `result` is filled in by the supervisor with the
+/// `usize` form of an `xous::Error`, where `NoError` means success.
+#[repr(C)]
+#[derive(Debug, Copy, Clone)]
+pub struct Supervise {
+    /// The name of the service, used for logging and for naming the process.
+    /// The supervisor keeps this name in `xous-names`, so only the processes
+    /// it starts may register it.
+    pub name: XousServerName,
+
+    /// A `RestartPolicy`, as a `usize`
+    pub policy: usize,
+
+    /// How many times the service may be restarted before the supervisor gives up
+    pub max_restarts: u32,
+
+    pub program_length: u32,
+    pub result: usize,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn restart_policy_round_trip() {
+        for policy in &[
+            RestartPolicy::Never,
+            RestartPolicy::OnFailure,
+            RestartPolicy::Always,
+        ] {
+            assert_eq!(RestartPolicy::from_usize(*policy as usize), Some(*policy));
+        }
+        assert_eq!(RestartPolicy::from_usize(3), None);
+    }
+}
@@ -0,0 +1,65 @@
+#![cfg_attr(target_os = "none", no_std)]
+
+//! This is the API that other processes use to hand services to the supervisor.
+//! Read this code as if you are calling these functions inside a different process.
+
+pub mod api;
+
+use api::{RestartPolicy, Supervise};
+use xous::{Error, MemoryFlags, MemoryMessage, Message, CID};
+
+/// Start `program` as a new process named `name`, and have the supervisor
+/// restart it according to `policy` whenever it exits, up to `max_restarts`
+/// times. On hardware `program` is a MiniElf image, and when running hosted it
+/// is the command line to run.
+///
+/// A restarted service registers its name with `xous-names` again, so clients
+/// that connect through `xous_names::NamedConnection` find the new server.
+///
+/// # Errors
+///
+/// * **InvalidString**: The name is too long, or the hosted command isn't UTF-8
+/// * **OutOfMemory**: The supervisor is full, or has no room for the program
+///
+/// Any error from `xous::create_process()` is also returned if the service
+/// can't be started the first time.
+pub fn supervise(
+    cid: CID,
+    name: &str,
+    policy: RestartPolicy,
+    max_restarts: u32,
+    program: &[u8],
+) -> Result<(), Error> {
+    let header_length = core::mem::size_of::<Supervise>();
+    let request = Supervise {
+        name: name.parse()?,
+        policy: policy as usize,
+        max_restarts,
+        program_length: program.len() as u32,
+        result: Error::InternalError.to_usize(),
+    };
+
+    let length = (header_length + program.len() + 4095) & !4095;
+    let buf = xous::map_memory(None, None, length, MemoryFlags::R | MemoryFlags::W)?;
+    unsafe {
+        (buf.as_mut_ptr() as *mut Supervise).write(request);
+        core::ptr::copy_nonoverlapping(
+            program.as_ptr(),
+            buf.as_mut_ptr().add(header_length),
+            program.len(),
+        );
+    }
+    let msg = MemoryMessage {
+        id: api::Opcode::Supervise as usize,
+        buf,
+        offset: None,
+        valid: None,
+    };
+    let result = xous::send_message(cid, Message::MutableBorrow(msg))
+        .map(|_| unsafe { (buf.as_ptr() as *const Supervise).read().result });
+    xous::unmap_memory(buf)?;
+    match Error::from_usize(result?) {
+        Error::NoError => Ok(()),
+        e => Err(e),
+    }
+}
@@ -0,0 +1,344 @@
+#![cfg_attr(target_os = "none", no_std)]
+#![cfg_attr(target_os = "none", no_main)]
+
+mod api;
+use api::{Opcode, RestartPolicy, Supervise, MAX_SERVICES};
+
+use core::convert::TryFrom;
+use core::sync::atomic::{AtomicBool, Ordering};
+
+use heapless::consts::*;
+use heapless::Vec;
+
+use log::{error, info};
+
+use xous_names::api::XousServerName;
+
+// The services to start as soon as the supervisor is running, as their name,
+// priority, and program. They are listed in `XOUS_SUPERVISED` when the
+// supervisor is built, and are restarted according to `BUILTIN_POLICY`.
+include!(concat!(env!("OUT_DIR"), "/builtin_services.rs"));
+
+/// How services that the supervisor starts by itself are restarted
+const BUILTIN_POLICY: RestartPolicy = RestartPolicy::OnFailure;
+const BUILTIN_MAX_RESTARTS: u32 = 8;
+
+/// Set by a service's waiter thread once its process has exited, just before
+/// the waiter sends `ServiceExited`, which only tells the main loop that at
+/// least one flag has been set.
+static EXITED: [AtomicBool; MAX_SERVICES] = {
+    #[allow(clippy::declare_interior_mutable_const)]
+    const RUNNING: AtomicBool = AtomicBool::new(false);
+    [RUNNING; MAX_SERVICES]
+};
+
+struct Service {
+    name: XousServerName,
+    policy: RestartPolicy,
+    max_restarts: u32,
+    restarts: u32,
+
+    /// The priority to start the service at, if not the default
+    priority: Option<xous::Priority>,
+
+    /// Our own copy of the program, so the service can be started again
+    /// after the process that asked for it has gone away
+    program: xous::MemoryRange,
+    program_length: usize,
+
+    /// The thread waiting for the current process to exit. Its return value is
+    /// the process' exit code.
+    waiter: Option<xous::arch::WaitHandle<usize>>,
+}
+
+impl Service {
+    fn program(&self) -> &[u8] {
+        unsafe { core::slice::from_raw_parts(self.program.as_ptr(), self.program_length) }
+    }
+
+    /// Decide whether a service that exited with `exit_code` gets started again.
+    fn should_restart(&self, exit_code: usize) -> bool {
+        let wanted = match self.policy {
+            RestartPolicy::Never => false,
+            RestartPolicy::OnFailure => exit_code != 0,
+            RestartPolicy::Always => true,
+        };
+        wanted && self.restarts < self.max_restarts
+    }
+}
+
+#[cfg(not(target_os = "none"))]
+fn process_args(service: &Service) -> Result<xous::ProcessArgs, xous::Error> {
+    let command = core::str::from_utf8(service.program()).or(Err(xous::Error::InvalidString))?;
+    Ok(xous::ProcessArgs::new(
+        service.name.as_str()?,
+        command.to_owned(),
+    ))
+}
+
+#[cfg(target_os = "none")]
+fn process_args(service: &Service) -> Result<xous::ProcessArgs, xous::Error> {
+    Ok(xous::ProcessArgs::new(xous::MemoryRange::new(
+        service.program.as_ptr() as usize,
+        service.program_length,
+    )?))
+}
+
+/// Start the service in slot `index`, along with a thread that lets the main
+/// loop know when it exits. Only the new process may register the name of the
+/// service, which the supervisor keeps.
+fn start(service: &mut Service, index: usize, self_cid: xous::CID) -> Result<(), xous::Error> {
+    let process = xous::create_process(process_args(service)?)?;
+    let pid = process.pid();
+    if let Some(priority) = service.priority {
+        if let Err(e) = xous::set_priority(Some(pid), priority) {
+            error!(
+                "SUPERVISOR: couldn't set priority of {:?}: {:?}",
+                service.name, e
+            );
+        }
+    }
+    if let Err(e) = xous_names::hand_over_name(service.name.as_str()?, Some(pid)) {
+        error!(
+            "SUPERVISOR: couldn't hand {:?} its name: {:?}",
+            service.name, e
+        );
+    }
+    EXITED[index].store(false, Ordering::SeqCst);
+    let waiter = xous::create_thread(move || {
+        // A process that can't be waited on is treated as having crashed.
+        let exit_code = xous::wait_process(process).unwrap_or(usize::MAX);
+        EXITED[index].store(true, Ordering::SeqCst);
+        let wakeup = xous::ScalarMessage::from_usize(Opcode::ServiceExited as usize, 0, 0, 0, 0);
+        while let Err(xous::Error::ServerQueueFull) =
+            xous::send_message(self_cid, xous::Message::Scalar(wakeup))
+        {
+            xous::yield_slice();
+        }
+        exit_code
+    })?;
+    service.waiter = Some(waiter);
+    info!("SUPERVISOR: started {:?}", service.name);
+    Ok(())
+}
+
+/// Keep a copy of `program`, become the keeper of the name of the service,
+/// and start it for the first time.
+fn supervise(
+    services: &mut Vec<Service, U16>,
+    name: XousServerName,
+    policy: RestartPolicy,
+    max_restarts: u32,
+    priority: Option<xous::Priority>,
+    program: &[u8],
+    self_cid: xous::CID,
+) -> Result<(), xous::Error> {
+    if program.is_empty() {
+        return Err(xous::Error::BadAddress);
+    }
+    if services.len() >= MAX_SERVICES {
+        return Err(xous::Error::OutOfMemory);
+    }
+    xous_names::hand_over_name(name.as_str()?, None)?;
+
+    let copy = xous::map_memory(
+        None,
+        None,
+        (program.len() + 4095) & !4095,
+        xous::MemoryFlags::R | xous::MemoryFlags::W,
+    )?;
+    unsafe { core::ptr::copy_nonoverlapping(program.as_ptr(), copy.as_mut_ptr(), program.len()) };
+
+    let mut service = Service {
+        name,
+        policy,
+        max_restarts,
+        restarts: 0,
+        priority,
+        program: copy,
+        program_length: program.len(),
+        waiter: None,
+    };
+    let index = services.len();
+    if let Err(e) = start(&mut service, index, self_cid) {
+        xous::unmap_memory(copy).ok();
+        return Err(e);
+    }
+    services.push(service).ok();
+    Ok(())
+}
+
+/// Collect every service whose process has exited, and start again those
+/// that their restart policy calls for.
+fn restart_exited(services: &mut Vec<Service, U16>, self_cid: xous::CID) {
+    for (index, service) in services.iter_mut().enumerate() {
+        if !EXITED[index].swap(false, Ordering::SeqCst) {
+            continue;
+        }
+        let exit_code = match service.waiter.take().map(xous::wait_thread) {
+            Some(Ok(exit_code)) => exit_code,
+            _ => usize::MAX,
+        };
+        if !service.should_restart(exit_code) {
+            info!(
+                "SUPERVISOR: {:?} exited with code {}, leaving it stopped",
+                service.name, exit_code
+            );
+            continue;
+        }
+        service.restarts += 1;
+        info!(
+            "SUPERVISOR: {:?} exited with code {}, restarting ({} of {})",
+            service.name, exit_code, service.restarts, service.max_restarts
+        );
+        if let Err(e) = start(service, index, self_cid) {
+            error!("SUPERVISOR: couldn't restart {:?}: {:?}", service.name, e);
+        }
+    }
+}
+
+#[xous::xous_main]
+fn xmain() -> ! {
+    log_server::init_wait().unwrap();
+
+    let supervisor_server = xous_names::register_name(api::SERVER_NAME_SUPERVISOR)
+        .expect("Couldn't create supervisor server");
+    // Only the supervisor itself may report that a service has exited.
+    xous::set_connection_rights(
+        supervisor_server,
+        None,
+        xous::ConnectionRights::excluding_id(Opcode::ServiceExited as u16),
+    )
+    .expect("couldn't restrict supervisor rights");
+
+    // Connect to our own server so waiter threads can report exits
+    let supervisor_client = xous::connect(supervisor_server).expect("couldn't connect to self");
+
+    // Services are never removed, so their index doubles as their `EXITED` slot.
+    let mut services: Vec<Service, U16> = Vec::new();
+
+    // The supervisor may be started at a higher priority than it needs, so
+    // that it can start services at that priority.
+    xous::set_priority(None, xous::DEFAULT_PRIORITY).expect("couldn't lower supervisor priority");
+    for (name, priority, program) in BUILTIN_SERVICES {
+        let result = name.parse().and_then(|name| {
+            supervise(
+                &mut services,
+                name,
+                BUILTIN_POLICY,
+                BUILTIN_MAX_RESTARTS,
+                Some(*priority),
+                program,
+                supervisor_client,
+            )
+        });
+        if let Err(e) = result {
+            error!("SUPERVISOR: couldn't start {}: {:?}", name, e);
+        }
+    }
+
+    loop {
+        let mut envelope = xous::receive_message(supervisor_server).unwrap();
+        let opcode = match Opcode::try_from(&envelope.body) {
+            Ok(opcode) => opcode,
+            Err(e) => {
+                error!("SUPERVISOR: couldn't convert opcode: {}", e);
+                continue;
+            }
+        };
+        match opcode {
+            Opcode::Supervise => {
+                let mem = match &mut envelope.body {
+                    xous::Message::MutableBorrow(mem) => mem,
+                    _ => continue,
+                };
+                let header_length = core::mem::size_of::<Supervise>();
+                if mem.buf.len() < header_length {
+                    error!("SUPERVISOR: request buffer is too small");
+                    continue;
+                }
+                let request = unsafe { &mut *(mem.buf.as_mut_ptr() as *mut Supervise) };
+                let program_length = request.program_length as usize;
+                request.result = if mem.buf.len() - header_length < program_length {
+                    xous::Error::BadAddress.to_usize()
+                } else {
+                    let program = unsafe {
+                        core::slice::from_raw_parts(
+                            mem.buf.as_ptr().add(header_length),
+                            program_length,
+                        )
+                    };
+                    let result = RestartPolicy::from_usize(request.policy)
+                        .ok_or(xous::Error::InternalError)
+                        .and_then(|policy| {
+                            supervise(
+                                &mut services,
+                                request.name,
+                                policy,
+                                request.max_restarts,
+                                None,
+                                program,
+                                supervisor_client,
+                            )
+                        });
+                    match result {
+                        Ok(()) => xous::Error::NoError.to_usize(),
+                        Err(e) => {
+                            error!("SUPERVISOR: couldn't supervise {:?}: {:?}", request.name, e);
+                            e.to_usize()
+                        }
+                    }
+                };
+            }
+            Opcode::ServiceExited => restart_exited(&mut services, supervisor_client),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn service(policy: RestartPolicy, max_restarts: u32, restarts: u32) -> Service {
+        Service {
+            name: "test".parse().unwrap(),
+            policy,
+            max_restarts,
+            restarts,
+            priority: None,
+            program: xous::MemoryRange::new(4096, 4096).unwrap(),
+            program_length: 0,
+            waiter: None,
+        }
+    }
+
+    #[test]
+    fn never_restarts() {
+        let service = service(RestartPolicy::Never, 8, 0);
+        assert!(!service.should_restart(0));
+        assert!(!service.should_restart(1));
+        assert!(!service.should_restart(usize::MAX));
+    }
+
+    #[test]
+    fn on_failure_restarts_failed_services() {
+        let service = service(RestartPolicy::OnFailure, 8, 0);
+        assert!(!service.should_restart(0));
+        assert!(service.should_restart(1));
+        assert!(service.should_restart(usize::MAX));
+    }
+
+    #[test]
+    fn always_restarts() {
+        let service = service(RestartPolicy::Always, 8, 0);
+        assert!(service.should_restart(0));
+        assert!(service.should_restart(1));
+    }
+
+    #[test]
+    fn restarts_are_limited() {
+        assert!(service(RestartPolicy::Always, 8, 7).should_restart(0));
+        assert!(!service(RestartPolicy::Always, 8, 8).should_restart(0));
+        assert!(!service(RestartPolicy::OnFailure, 0, 0).should_restart(1));
+    }
+}
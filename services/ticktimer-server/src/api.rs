@@ -1,70 +1,208 @@
-use xous::{Message, ScalarMessage};
+// Generated by `idl-gen` from api.idl. Don't edit this file: change the IDL
+// and run `cargo xtask generate-idl` instead.
+#![allow(dead_code)]
 
-#[derive(Debug)]
+use core::convert::TryFrom;
+
+/// The name this server registers with `xous-names`
+pub const SERVER_NAME_TICKTIMER: &str = "ticktimer-server";
+
+#[derive(Debug, xous::Opcode)]
 pub enum Opcode {
     /// Reset the timer
+    #[opcode(scalar, id = 1)]
     Reset,
 
     /// Get the elapsed time in milliseconds
+    #[opcode(blocking_scalar, id = 4919)]
     ElapsedMs,
 
-    /// Sleep for the specified numer of milliseconds
+    /// Sleep for the specified number of milliseconds
+    #[opcode(blocking_scalar, id = 3)]
     SleepMs(usize),
 
     /// Recalculate the sleep time
+    #[opcode(scalar, id = 131072)]
     RecalculateSleep,
+
+    /// Connect to the server whose SID is given in four parts, so that it can be
+    /// woken by `SignalAfterMs`. Replies with an error code, and the waker to pass
+    /// to `SignalAfterMs`.
+    #[opcode(blocking_scalar, id = 5)]
+    ConnectWaker(usize, usize, usize, usize),
+
+    /// Set `events` on the server behind `waker` once `ms` milliseconds have
+    /// passed. Replies with an error code.
+    #[opcode(blocking_scalar, id = 6)]
+    SignalAfterMs(usize, usize, usize),
+
+    /// Disconnect from the server behind `waker`, and forget any signals that are
+    /// still due. Replies with an error code.
+    #[opcode(blocking_scalar, id = 7)]
+    DisconnectWaker(usize),
 }
 
-impl<'a> core::convert::TryFrom<&'a Message> for Opcode {
-    type Error = &'static str;
-    fn try_from(message: &'a Message) -> Result<Self, Self::Error> {
-        match message {
-            Message::Scalar(m) => match m.id {
-                1 => Ok(Opcode::Reset),
-                131072 => Ok(Opcode::RecalculateSleep),
-                _ => Err("unrecognized opcode"),
-            },
-            Message::BlockingScalar(m) => match m.id {
-                4919 => Ok(Opcode::ElapsedMs),
-                3 => Ok(Opcode::SleepMs(m.arg1)),
-                _ => Err("unrecognized opcode"),
-            },
-            _ => Err("unhandled message type"),
-        }
+/// Reset the timer
+pub fn reset(cid: xous::CID) -> Result<(), xous::Error> {
+    xous::send_message(cid, Opcode::Reset.into()).map(|_| ())
+}
+
+/// Get the elapsed time in milliseconds
+pub fn elapsed_ms(cid: xous::CID) -> Result<u64, xous::Error> {
+    let response = xous::send_message(cid, Opcode::ElapsedMs.into())?;
+    if let xous::Result::Scalar2(lower, upper) = response {
+        Ok(lower as u64 | ((upper as u64) << 32))
+    } else {
+        panic!("unexpected return value: {:#?}", response);
+    }
+}
+
+/// Sleep for the specified number of milliseconds
+pub fn sleep_ms(cid: xous::CID, ms: usize) -> Result<(), xous::Error> {
+    xous::send_message(cid, Opcode::SleepMs(ms).into()).map(|_| ())
+}
+
+/// Recalculate the sleep time
+pub fn recalculate_sleep(cid: xous::CID) -> Result<(), xous::Error> {
+    xous::send_message(cid, Opcode::RecalculateSleep.into()).map(|_| ())
+}
+
+/// Connect to the server whose SID is given in four parts, so that it can be
+/// woken by `SignalAfterMs`. Replies with an error code, and the waker to pass
+/// to `SignalAfterMs`.
+pub fn connect_waker(
+    cid: xous::CID,
+    sid0: usize,
+    sid1: usize,
+    sid2: usize,
+    sid3: usize,
+) -> Result<(usize, usize), xous::Error> {
+    let response = xous::send_message(cid, Opcode::ConnectWaker(sid0, sid1, sid2, sid3).into())?;
+    if let xous::Result::Scalar2(value1, value2) = response {
+        Ok((value1, value2))
+    } else {
+        panic!("unexpected return value: {:#?}", response);
+    }
+}
+
+/// Set `events` on the server behind `waker` once `ms` milliseconds have
+/// passed. Replies with an error code.
+pub fn signal_after_ms(
+    cid: xous::CID,
+    ms: usize,
+    waker: usize,
+    events: usize,
+) -> Result<usize, xous::Error> {
+    let response = xous::send_message(cid, Opcode::SignalAfterMs(ms, waker, events).into())?;
+    if let xous::Result::Scalar1(value) = response {
+        Ok(value)
+    } else {
+        panic!("unexpected return value: {:#?}", response);
     }
 }
 
-impl Into<Message> for Opcode {
-    fn into(self) -> Message {
-        match self {
-            Opcode::Reset => Message::Scalar(ScalarMessage {
-                id: 1,
-                arg1: 0,
-                arg2: 0,
-                arg3: 0,
-                arg4: 0,
-            }),
-            Opcode::RecalculateSleep => Message::Scalar(ScalarMessage {
-                id: 131072,
-                arg1: 0,
-                arg2: 0,
-                arg3: 0,
-                arg4: 0,
-            }),
-            Opcode::ElapsedMs => Message::BlockingScalar(ScalarMessage {
-                id: 4919,
-                arg1: 0,
-                arg2: 0,
-                arg3: 0,
-                arg4: 0,
-            }),
-            Opcode::SleepMs(ms) => Message::BlockingScalar(ScalarMessage {
-                id: 3,
-                arg1: ms,
-                arg2: 0,
-                arg3: 0,
-                arg4: 0,
-            }),
+/// Disconnect from the server behind `waker`, and forget any signals that are
+/// still due. Replies with an error code.
+pub fn disconnect_waker(cid: xous::CID, waker: usize) -> Result<usize, xous::Error> {
+    let response = xous::send_message(cid, Opcode::DisconnectWaker(waker).into())?;
+    if let xous::Result::Scalar1(value) = response {
+        Ok(value)
+    } else {
+        panic!("unexpected return value: {:#?}", response);
+    }
+}
+
+/// The methods a server provides to handle each opcode. `dispatch()` calls
+/// them, and sends whatever they return back to the client.
+pub trait Server {
+    /// Reset the timer
+    fn reset(&mut self);
+
+    /// Get the elapsed time in milliseconds
+    fn elapsed_ms(&mut self) -> u64;
+
+    /// Sleep for the specified number of milliseconds
+    ///
+    /// The reply isn't sent when this returns. It must be sent to `sender` later.
+    fn sleep_ms(&mut self, sender: xous::MessageSender, ms: usize);
+
+    /// Recalculate the sleep time
+    fn recalculate_sleep(&mut self);
+
+    /// Connect to the server whose SID is given in four parts, so that it can be
+    /// woken by `SignalAfterMs`. Replies with an error code, and the waker to pass
+    /// to `SignalAfterMs`.
+    ///
+    /// The reply isn't sent when this returns. It must be sent to `sender` later.
+    fn connect_waker(
+        &mut self,
+        sender: xous::MessageSender,
+        sid0: usize,
+        sid1: usize,
+        sid2: usize,
+        sid3: usize,
+    );
+
+    /// Set `events` on the server behind `waker` once `ms` milliseconds have
+    /// passed. Replies with an error code.
+    ///
+    /// The reply isn't sent when this returns. It must be sent to `sender` later.
+    fn signal_after_ms(
+        &mut self,
+        sender: xous::MessageSender,
+        ms: usize,
+        waker: usize,
+        events: usize,
+    );
+
+    /// Disconnect from the server behind `waker`, and forget any signals that are
+    /// still due. Replies with an error code.
+    ///
+    /// The reply isn't sent when this returns. It must be sent to `sender` later.
+    fn disconnect_waker(&mut self, sender: xous::MessageSender, waker: usize);
+
+    /// A process that was connected to the server has terminated. This is
+    /// only called once the server has asked for it with `xous::notify_disconnects()`.
+    fn client_disconnected(&mut self, _pid: xous::PID) {}
+}
+
+/// Call the method of `server` that handles the message in `envelope`, and
+/// send its reply if the message expects one. Lent memory is returned when
+/// `envelope` is dropped.
+pub fn dispatch<S: Server>(
+    server: &mut S,
+    envelope: &mut xous::MessageEnvelope,
+) -> Result<(), &'static str> {
+    if let xous::Message::Scalar(xous::ScalarMessage {
+        id: xous::CLIENT_DISCONNECTED_ID,
+        arg1,
+        ..
+    }) = envelope.body
+    {
+        let pid = xous::pid_from_usize(arg1).or(Err("invalid PID"))?;
+        server.client_disconnected(pid);
+        return Ok(());
+    }
+    match Opcode::try_from(&envelope.body)? {
+        Opcode::Reset => server.reset(),
+        Opcode::ElapsedMs => {
+            let value = server.elapsed_ms();
+            xous::return_scalar2(
+                envelope.sender,
+                (value & 0xffff_ffff) as usize,
+                (value >> 32) as usize,
+            )
+            .or(Err("couldn't send reply"))?;
+        }
+        Opcode::SleepMs(ms) => server.sleep_ms(envelope.sender, ms),
+        Opcode::RecalculateSleep => server.recalculate_sleep(),
+        Opcode::ConnectWaker(sid0, sid1, sid2, sid3) => {
+            server.connect_waker(envelope.sender, sid0, sid1, sid2, sid3)
+        }
+        Opcode::SignalAfterMs(ms, waker, events) => {
+            server.signal_after_ms(envelope.sender, ms, waker, events)
         }
+        Opcode::DisconnectWaker(waker) => server.disconnect_waker(envelope.sender, waker),
     }
+    Ok(())
 }
@@ -2,21 +2,107 @@
 
 pub mod api;
 
-use xous::{send_message, Error, CID};
-
-pub fn elapsed_ms(cid: CID) -> Result<u64, Error> {
-    let response = send_message(cid, api::Opcode::ElapsedMs.into())?;
-    if let xous::Result::Scalar2(upper, lower) = response {
-        Ok(upper as u64 | ((lower as u64) << 32))
-    } else {
-        panic!("unexpected return value: {:#?}", response);
+pub use api::{elapsed_ms, reset, sleep_ms};
+
+use core::future::Future;
+use core::marker::PhantomData;
+use core::pin::Pin;
+use core::task::{Context, Poll};
+use xous::executor::Executor;
+
+/// Turn an error code from the ticktimer into a `Result`.
+fn check(error: usize) -> Result<(), xous::Error> {
+    match xous::Error::from_usize(error) {
+        xous::Error::NoError => Ok(()),
+        e => Err(e),
+    }
+}
+
+/// Lets futures running on an `Executor` sleep without holding up its thread.
+/// The ticktimer sets an event on the executor's server once each sleep is
+/// over, so no thread has to wait for it.
+pub struct Timer<'a> {
+    cid: xous::CID,
+
+    /// The ticktimer's connection to the executor's server
+    waker: usize,
+    _executor: PhantomData<&'a Executor>,
+}
+
+impl<'a> Timer<'a> {
+    /// Have the ticktimer on `cid` connect to the server of `executor`.
+    ///
+    /// # Errors
+    ///
+    /// * **OutOfMemory**: The ticktimer is connected to too many executors
+    pub fn new(cid: xous::CID, executor: &'a Executor) -> Result<Timer<'a>, xous::Error> {
+        let (sid0, sid1, sid2, sid3) = executor.server().to_u32();
+        let (error, waker) = api::connect_waker(
+            cid,
+            sid0 as usize,
+            sid1 as usize,
+            sid2 as usize,
+            sid3 as usize,
+        )?;
+        check(error)?;
+        Ok(Timer {
+            cid,
+            waker,
+            _executor: PhantomData,
+        })
+    }
+
+    /// Resolve once `ms` milliseconds have passed since the future was first
+    /// polled.
+    pub fn sleep_ms(&self, ms: usize) -> Sleep<'_> {
+        Sleep {
+            timer: self,
+            ms,
+            deadline: None,
+        }
+    }
+}
+
+impl Drop for Timer<'_> {
+    fn drop(&mut self) {
+        api::disconnect_waker(self.cid, self.waker).ok();
     }
 }
 
-pub fn reset(cid: CID) -> Result<(), xous::Error> {
-    send_message(cid, api::Opcode::Reset.into()).map(|_| ())
+/// A future that resolves once a number of milliseconds have passed. It is
+/// made by `Timer::sleep_ms()`.
+pub struct Sleep<'a> {
+    timer: &'a Timer<'a>,
+    ms: usize,
+
+    /// When the sleep is over, by the ticktimer's clock
+    deadline: Option<u64>,
 }
 
-pub fn sleep_ms(cid: CID, ms: usize) -> Result<(), xous::Error> {
-    send_message(cid, api::Opcode::SleepMs(ms).into()).map(|_| ())
+impl Future for Sleep<'_> {
+    type Output = Result<(), xous::Error>;
+
+    fn poll(mut self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let now = match api::elapsed_ms(self.timer.cid) {
+            Ok(now) => now,
+            Err(e) => return Poll::Ready(Err(e)),
+        };
+        let ms = self.ms as u64;
+        let deadline = *self.deadline.get_or_insert(now + ms);
+        if now >= deadline {
+            return Poll::Ready(Ok(()));
+        }
+        // The ticktimer only keeps the earliest signal for each executor, and
+        // that may have belonged to another sleep, so ask every time.
+        let result = api::signal_after_ms(
+            self.timer.cid,
+            (deadline - now) as usize,
+            self.timer.waker,
+            xous::executor::WAKE,
+        );
+        match result.and_then(check) {
+            Ok(()) => Poll::Pending,
+            Err(e) => Poll::Ready(Err(e)),
+        }
+    }
 }
@@ -2,26 +2,56 @@
 #![cfg_attr(target_os = "none", no_main)]
 
 mod api;
-use api::Opcode;
-
-mod os_timer;
-
-use core::convert::TryFrom;
 
 use heapless::binary_heap::{BinaryHeap, Min};
 use heapless::consts::*;
+use heapless::Vec;
 
 use log::{error, info};
 
+/// Who to tell once a sleep is over
+#[derive(Eq, PartialEq, Debug, Copy, Clone)]
+pub enum Waiter {
+    /// A client blocked in `SleepMs`, which is woken by the reply
+    Sleeper(xous::MessageSender),
+
+    /// A server that the given process connected with `ConnectWaker`, which
+    /// is woken by setting events on it
+    Server(xous::PID, xous::CID, usize /* events */),
+}
+
+impl Waiter {
+    fn pid(&self) -> Option<xous::PID> {
+        match self {
+            Waiter::Sleeper(sender) => sender.pid(),
+            Waiter::Server(pid, _, _) => Some(*pid),
+        }
+    }
+
+    pub fn wake(self) {
+        match self {
+            Waiter::Sleeper(sender) => {
+                xous::return_scalar(sender, 0).expect("couldn't send response")
+            }
+            // The server's owner may have destroyed it already, which is no
+            // concern of ours.
+            Waiter::Server(_, cid, events) => {
+                xous::signal_events(cid, events).ok();
+            }
+        }
+    }
+}
+
 #[derive(Eq, Debug)]
 pub struct SleepResponse {
-    msec: usize,
-    sender: xous::MessageSender,
+    /// When the sleep is over, in milliseconds since the timer was reset
+    deadline: u64,
+    waiter: Waiter,
 }
 
 impl core::cmp::Ord for SleepResponse {
     fn cmp(&self, other: &Self) -> core::cmp::Ordering {
-        self.msec.cmp(&other.msec)
+        self.deadline.cmp(&other.deadline)
     }
 }
 
@@ -33,7 +63,7 @@ impl core::cmp::PartialOrd for SleepResponse {
 
 impl core::cmp::PartialEq for SleepResponse {
     fn eq(&self, other: &Self) -> bool {
-        self.msec == other.msec && self.sender == other.sender
+        self.deadline == other.deadline && self.waiter == other.waiter
     }
 }
 
@@ -47,7 +77,6 @@ mod implementation {
         csr: utralib::CSR<u32>,
         wdt: utralib::CSR<u32>,
         current_response: Option<SleepResponse>,
-        response_start: u64,
         connection: xous::CID,
     }
 
@@ -58,7 +87,7 @@ mod implementation {
         // Safe because we're in an interrupt, and this interrupt is only
         // enabled when this value is not None.
         let response = xtt.current_response.take().unwrap();
-        xous::return_scalar(response.sender, 0).expect("couldn't send response");
+        response.waiter.wake();
 
         xtt.csr.wo(utra::ticktimer::EV_ENABLE, 0); // Disable the interrupt
 
@@ -90,7 +119,6 @@ mod implementation {
                 csr: CSR::new(csr.as_mut_ptr() as *mut u32),
                 wdt: CSR::new(wdt.as_mut_ptr() as *mut u32),
                 current_response: None,
-                response_start: 0,
                 connection,
             };
 
@@ -123,28 +151,16 @@ mod implementation {
 
         pub fn stop_interrupt(&mut self) -> Option<SleepResponse> {
             self.csr.wfo(utra::ticktimer::EV_ENABLE_ALARM, 0); // Disable the timer
-            let current_value = self.elapsed_ms();
-            if let Some(sr) = self.current_response.take() {
-                Some(SleepResponse {
-                    msec: (current_value - self.response_start) as _,
-                    sender: sr.sender,
-                })
-            } else {
-                None
-            }
+            self.current_response.take()
         }
 
-        pub fn schedule_response(&mut self, milliseconds: usize, sender: xous::MessageSender) {
-            self.current_response = Some(SleepResponse {
-                sender,
-                msec: milliseconds,
-            });
-            self.response_start = self.elapsed_ms();
-            let irq_target = self.response_start + (milliseconds as u64);
+        pub fn schedule_response(&mut self, response: SleepResponse) {
+            let irq_target = response.deadline;
+            self.current_response = Some(response);
             log::info!(
                 "setting a response at {} ms (current time: {} ms)",
                 irq_target,
-                self.response_start
+                self.elapsed_ms()
             );
             self.csr.wfo(utra::ticktimer::EV_PENDING_ALARM, 1); // Clear previous interrupt (if any)
             self.csr
@@ -182,7 +198,7 @@ mod implementation {
     #[derive(Debug)]
     enum SleepComms {
         InterruptSleep,
-        StartSleep(xous::MessageSender, u64 /* ms */),
+        StartSleep(SleepResponse, u64 /* ms */),
     }
     pub struct XousTickTimer {
         start: std::time::Instant,
@@ -196,9 +212,8 @@ mod implementation {
             let (time_remaining_sender, time_remaining_receiver) = std::sync::mpsc::channel();
             xous::create_thread(move || {
                 let mut timeout = None;
-                let mut sender = Default::default();
+                let mut current: Option<SleepResponse> = None;
                 loop {
-                    let start_time = std::time::Instant::now();
                     let result = match timeout {
                         None => sleep_receiver
                             .recv()
@@ -207,7 +222,9 @@ mod implementation {
                     };
                     match result {
                         Err(std::sync::mpsc::RecvTimeoutError::Timeout) => {
-                            xous::return_scalar(sender, 0).expect("couldn't send response");
+                            if let Some(response) = current.take() {
+                                response.waiter.wake();
+                            }
 
                             // This is dangerous and may panic if the queue is full.
                             xous::try_send_message(
@@ -220,19 +237,13 @@ mod implementation {
                         Err(std::sync::mpsc::RecvTimeoutError::Disconnected) => {
                             return;
                         }
-                        Ok(SleepComms::InterruptSleep) => time_remaining_sender
-                            .send(if timeout.is_some() {
-                                Some(SleepResponse {
-                                    sender,
-                                    msec: start_time.elapsed().as_millis() as _,
-                                })
-                            } else {
-                                None
-                            })
-                            .unwrap(),
-                        Ok(SleepComms::StartSleep(new_sender, duration)) => {
+                        Ok(SleepComms::InterruptSleep) => {
+                            timeout = None;
+                            time_remaining_sender.send(current.take()).unwrap()
+                        }
+                        Ok(SleepComms::StartSleep(response, duration)) => {
                             timeout = Some(std::time::Duration::from_millis(duration));
-                            sender = new_sender;
+                            current = Some(response);
                         }
                     }
                 }
@@ -259,9 +270,10 @@ mod implementation {
             self.time_remaining_receiver.recv().unwrap()
         }
 
-        pub fn schedule_response(&mut self, milliseconds: usize, sender: xous::MessageSender) {
+        pub fn schedule_response(&mut self, response: SleepResponse) {
+            let milliseconds = response.deadline.saturating_sub(self.elapsed_ms());
             self.sleep_comms
-                .send(SleepComms::StartSleep(sender, milliseconds as _))
+                .send(SleepComms::StartSleep(response, milliseconds))
                 .unwrap();
         }
 
@@ -288,70 +300,215 @@ fn recalculate_sleep(
             .expect("couldn't push new sleep to heap");
     }
     if let Some(next_response) = sleep_heap.pop() {
-        info!("scheduling a response at {}", next_response.msec);
-        ticktimer.schedule_response(next_response.msec, next_response.sender);
+        info!("scheduling a response at {}", next_response.deadline);
+        ticktimer.schedule_response(next_response);
+    }
+}
+
+struct TickTimerServer {
+    ticktimer: XousTickTimer,
+
+    /// "Sleep" commands get put in here and are ordered as necessary
+    sleep_heap: BinaryHeap<SleepResponse, U32, Min>,
+
+    /// The servers that the ticktimer has connected to for itself. Connecting
+    /// to one of these as a waker would hand back the same connection, which
+    /// a client could then have it disconnect.
+    own_servers: [xous::SID; 3],
+
+    /// Each waker, along with the process that connected it. Connections to
+    /// the same server are shared, so one may appear more than once.
+    wakers: Vec<(xous::PID, xous::CID), U16>,
+}
+
+impl TickTimerServer {
+    /// Forget every sleep that `keep` turns down, including the one in
+    /// progress. Nothing is scheduled until `recalculate_sleep()` is called.
+    fn retain_sleeps<F: FnMut(&SleepResponse) -> bool>(&mut self, mut keep: F) {
+        let mut sleep_heap = BinaryHeap::new();
+        let mut current = self.ticktimer.stop_interrupt();
+        while let Some(response) = current.take().or_else(|| self.sleep_heap.pop()) {
+            if keep(&response) {
+                sleep_heap.push(response).expect("couldn't push to heap");
+            }
+        }
+        self.sleep_heap = sleep_heap;
+    }
+
+    fn connect_waker(&mut self, pid: xous::PID, sid: xous::SID) -> Result<xous::CID, xous::Error> {
+        if self.own_servers.contains(&sid) {
+            return Err(xous::Error::AccessDenied);
+        }
+        if self.wakers.len() == self.wakers.capacity() {
+            return Err(xous::Error::OutOfMemory);
+        }
+        // Never wait here, or every other client would wait too.
+        let cid = xous::try_connect(sid)?;
+        self.wakers.push((pid, cid)).ok();
+        Ok(cid)
+    }
+
+    /// Disconnect from `cid` unless another waker still uses it.
+    fn release_waker(&mut self, cid: xous::CID) {
+        if self.wakers.iter().all(|&(_, other)| other != cid) {
+            xous::disconnect(cid).ok();
+        }
+    }
+}
+
+impl api::Server for TickTimerServer {
+    fn reset(&mut self) {
+        info!("TickTimer: reset called");
+        self.ticktimer.reset();
+    }
+
+    fn elapsed_ms(&mut self) -> u64 {
+        self.ticktimer.elapsed_ms()
+    }
+
+    fn sleep_ms(&mut self, sender: xous::MessageSender, ms: usize) {
+        let deadline = self.ticktimer.elapsed_ms() + ms as u64;
+        recalculate_sleep(
+            &mut self.ticktimer,
+            &mut self.sleep_heap,
+            Some(SleepResponse {
+                deadline,
+                waiter: Waiter::Sleeper(sender),
+            }),
+        );
+    }
+
+    fn recalculate_sleep(&mut self) {
+        recalculate_sleep(&mut self.ticktimer, &mut self.sleep_heap, None);
+    }
+
+    fn connect_waker(
+        &mut self,
+        sender: xous::MessageSender,
+        sid0: usize,
+        sid1: usize,
+        sid2: usize,
+        sid3: usize,
+    ) {
+        let sid = xous::SID::from_u32(sid0 as _, sid1 as _, sid2 as _, sid3 as _);
+        let result = sender
+            .pid()
+            .ok_or(xous::Error::ProcessNotFound)
+            .and_then(|pid| self.connect_waker(pid, sid));
+        let (error, waker) = match result {
+            Ok(cid) => (xous::Error::NoError, cid),
+            Err(e) => (e, 0),
+        };
+        xous::return_scalar2(sender, error.to_usize(), waker).expect("couldn't send response");
+    }
+
+    fn signal_after_ms(
+        &mut self,
+        sender: xous::MessageSender,
+        ms: usize,
+        waker: usize,
+        events: usize,
+    ) {
+        let error = match sender.pid() {
+            Some(pid) if self.wakers.contains(&(pid, waker as xous::CID)) => {
+                // Only the earliest signal for each waker is kept, so that
+                // sleeps that are given up on don't pile up.
+                let cid = waker as xous::CID;
+                let mut deadline = self.ticktimer.elapsed_ms() + ms as u64;
+                let mut events = events;
+                self.retain_sleeps(|response| match response.waiter {
+                    Waiter::Server(p, c, e) if p == pid && c == cid => {
+                        deadline = deadline.min(response.deadline);
+                        events |= e;
+                        false
+                    }
+                    _ => true,
+                });
+                recalculate_sleep(
+                    &mut self.ticktimer,
+                    &mut self.sleep_heap,
+                    Some(SleepResponse {
+                        deadline,
+                        waiter: Waiter::Server(pid, cid, events),
+                    }),
+                );
+                xous::Error::NoError
+            }
+            _ => xous::Error::AccessDenied,
+        };
+        xous::return_scalar(sender, error.to_usize()).expect("couldn't send response");
+    }
+
+    fn disconnect_waker(&mut self, sender: xous::MessageSender, waker: usize) {
+        let entry = sender.pid().map(|pid| (pid, waker as xous::CID));
+        let error = match self.wakers.iter().position(|&w| Some(w) == entry) {
+            Some(index) => {
+                let (pid, cid) = self.wakers.swap_remove(index);
+                if !self.wakers.contains(&(pid, cid)) {
+                    self.retain_sleeps(|response| match response.waiter {
+                        Waiter::Server(p, c, _) => (p, c) != (pid, cid),
+                        _ => true,
+                    });
+                    recalculate_sleep(&mut self.ticktimer, &mut self.sleep_heap, None);
+                }
+                self.release_waker(cid);
+                xous::Error::NoError
+            }
+            None => xous::Error::AccessDenied,
+        };
+        xous::return_scalar(sender, error.to_usize()).expect("couldn't send response");
+    }
+
+    fn client_disconnected(&mut self, pid: xous::PID) {
+        // Nobody is left to wake up, and returning to a process that has gone
+        // away fails.
+        self.retain_sleeps(|response| response.waiter.pid() != Some(pid));
+        recalculate_sleep(&mut self.ticktimer, &mut self.sleep_heap, None);
+
+        let mut index = 0;
+        while index < self.wakers.len() {
+            if self.wakers[index].0 == pid {
+                let (_, cid) = self.wakers.swap_remove(index);
+                self.release_waker(cid);
+            } else {
+                index += 1;
+            }
+        }
     }
 }
 
 #[xous::xous_main]
 fn xmain() -> ! {
-    // Start the OS timer which is responsible for setting up preemption.
-    os_timer::init();
-
     log_server::init_wait().unwrap();
 
-    // "Sleep" commands get put in here and are ordered as necessary
-    let mut sleep_heap: BinaryHeap<SleepResponse, U32, Min> = BinaryHeap::new();
+    let ticktimer_server = xous_names::register_name(api::SERVER_NAME_TICKTIMER)
+        .expect("Couldn't create Ticktimer server");
 
-    let ticktimer_server =
-        xous::create_server_with_address(b"ticktimer-server").expect("Couldn't create Ticktimer server");
+    // Sleeping clients that terminate must be forgotten.
+    xous::notify_disconnects(ticktimer_server, true).expect("couldn't ask for disconnect notices");
 
     // Connect to our own server so we can send the "Recalculate" message
-    let ticktimer_client = xous::connect(xous::SID::from_bytes(b"ticktimer-server").unwrap())
-        .expect("couldn't connect to self");
-
-    // Create a new ticktimer object
-    let mut ticktimer = XousTickTimer::new(ticktimer_client);
+    let ticktimer_client = xous::connect(ticktimer_server).expect("couldn't connect to self");
+
+    let mut server = TickTimerServer {
+        ticktimer: XousTickTimer::new(ticktimer_client),
+        sleep_heap: BinaryHeap::new(),
+        own_servers: [
+            ticktimer_server,
+            xous::SID::from_bytes(b"xous-log-server ").unwrap(),
+            xous::SID::from_bytes(xous_names::api::SID_BYTES).unwrap(),
+        ],
+        wakers: Vec::new(),
+    };
 
     loop {
-        ticktimer.reset_wdt();
+        server.ticktimer.reset_wdt();
 
         //info!("TickTimer: waiting for message");
-        let envelope = xous::receive_message(ticktimer_server).unwrap();
+        let mut envelope = xous::receive_message(ticktimer_server).unwrap();
         //info!("TickTimer: Message: {:?}", envelope);
-        if let Ok(opcode) = Opcode::try_from(&envelope.body) {
-            //info!("TickTimer: Opcode: {:?}", opcode);
-            match opcode {
-                Opcode::Reset => {
-                    info!("TickTimer: reset called");
-                    ticktimer.reset();
-                }
-                Opcode::ElapsedMs => {
-                    let time = ticktimer.elapsed_ms();
-                    //info!("TickTimer: returning time of {:?}", time);
-                    xous::return_scalar2(
-                        envelope.sender,
-                        (time & 0xFFFF_FFFFu64) as usize,
-                        ((time >> 32) & 0xFFF_FFFFu64) as usize,
-                    )
-                    .expect("TickTimer: couldn't return time request");
-                    //info!("TickTimer: done returning value");
-                }
-                Opcode::SleepMs(ms) => recalculate_sleep(
-                    &mut ticktimer,
-                    &mut sleep_heap,
-                    Some(SleepResponse {
-                        msec: ms,
-                        sender: envelope.sender,
-                    }),
-                ),
-                Opcode::RecalculateSleep => {
-                    recalculate_sleep(&mut ticktimer, &mut sleep_heap, None);
-                    //info!("TickTimer: Done recalculating");
-                }
-            }
-        } else {
-            error!("couldn't convert opcode");
+        if let Err(e) = api::dispatch(&mut server, &mut envelope) {
+            error!("couldn't handle message: {}", e);
         }
     }
 }
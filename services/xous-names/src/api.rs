@@ -0,0 +1,136 @@
+use xous::{Message, CID};
+
+/// The well-known address of `xous-names`. Every other server gets a random
+/// SID, so this is the one address processes must know in advance.
+pub const SID_BYTES: &[u8; 16] = b"xous-name-server";
+
+/// The longest name that may be registered, in bytes.
+pub const NAME_MAX_LENGTH: usize = 64;
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Opcode {
+    /// Associate a name with the sender's SID
+    Register = 1,
+
+    /// Create a connection to the server with the given name
+    Lookup = 2,
+
+    /// Choose which process may register a name next
+    HandOver = 3,
+}
+
+impl<'a> core::convert::TryFrom<&'a Message> for Opcode {
+    type Error = &'static str;
+    fn try_from(message: &'a Message) -> Result<Self, Self::Error> {
+        match message {
+            Message::MutableBorrow(m) => match m.id {
+                1 => Ok(Opcode::Register),
+                2 => Ok(Opcode::Lookup),
+                3 => Ok(Opcode::HandOver),
+                _ => Err("unrecognized opcode"),
+            },
+            _ => Err("unhandled message type"),
+        }
+    }
+}
+
+/// A UTF-8 name stored in a fixed-size buffer so it can be shipped across
+/// the kernel boundary inside a `MutableBorrow` message.
+#[repr(C)]
+#[derive(Copy, Clone)]
+pub struct XousServerName {
+    length: u32,
+    value: [u8; NAME_MAX_LENGTH],
+}
+
+impl core::str::FromStr for XousServerName {
+    type Err = xous::Error;
+
+    fn from_str(name: &str) -> Result<XousServerName, xous::Error> {
+        if name.len() > NAME_MAX_LENGTH {
+            return Err(xous::Error::InvalidString);
+        }
+        let mut value = [0u8; NAME_MAX_LENGTH];
+        value[..name.len()].copy_from_slice(name.as_bytes());
+        Ok(XousServerName {
+            length: name.len() as u32,
+            value,
+        })
+    }
+}
+
+impl XousServerName {
+    /// Return the name as a `str`. Since the contents may have come from
+    /// another process, both the length and the encoding are checked.
+    pub fn as_str(&self) -> Result<&str, xous::Error> {
+        let length = self.length as usize;
+        if length > NAME_MAX_LENGTH {
+            return Err(xous::Error::InvalidString);
+        }
+        core::str::from_utf8(&self.value[..length]).or(Err(xous::Error::InvalidString))
+    }
+}
+
+impl PartialEq for XousServerName {
+    fn eq(&self, other: &Self) -> bool {
+        self.length == other.length
+            && self.value[..(self.length as usize).min(NAME_MAX_LENGTH)]
+                == other.value[..(other.length as usize).min(NAME_MAX_LENGTH)]
+    }
+}
+
+impl core::fmt::Debug for XousServerName {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self.as_str() {
+            Ok(s) => write!(f, "{}", s),
+            Err(_) => write!(f, "<invalid name>"),
+        }
+    }
+}
+
+/// Sent by a server to claim a name. `result` is filled in by `xous-names`
+/// with the `usize` form of an `xous::Error`, where `NoError` means success.
+/// The server `sid` must belong to the process that sends this, or the
+/// registration fails with `AccessDenied`.
+#[repr(C)]
+#[derive(Debug, Copy, Clone)]
+pub struct Registration {
+    pub name: XousServerName,
+    pub sid: [u32; 4],
+    pub result: usize,
+}
+
+/// Sent by a client to connect to a named server. On success `cid` holds the
+/// connection ID, which `xous-names` creates on the client's behalf.
+///
+/// A server that approves its clients could keep `xous-names` waiting for as
+/// long as it likes, so for those servers `cid` is left at 0 and `sid` is
+/// filled in instead. The client then connects by itself and waits for the
+/// server's answer on its own.
+#[repr(C)]
+#[derive(Debug, Copy, Clone)]
+pub struct Lookup {
+    pub name: XousServerName,
+    pub cid: CID,
+    pub sid: [u32; 4],
+    pub result: usize,
+}
+
+/// Sent by the keeper of a name to choose which process registers it next,
+/// replacing whichever server has it now. The first process to send this for
+/// a name that isn't registered yet becomes its keeper, and nobody else may
+/// send it for that name afterwards. A name without a keeper stays with the
+/// first server to register it.
+///
+/// Until the keeper names a `successor`, any process that tries to register
+/// the name is kept waiting.
+#[repr(C)]
+#[derive(Debug, Copy, Clone)]
+pub struct HandOver {
+    pub name: XousServerName,
+
+    /// The PID of the process that may register the name next, or 0 to have
+    /// every registration wait
+    pub successor: usize,
+    pub result: usize,
+}
@@ -0,0 +1,361 @@
+#![cfg_attr(target_os = "none", no_std)]
+
+//! This is the API that other servers use to call `xous-names`. Read this code as if you
+//! are calling these functions inside a different process.
+
+pub mod api;
+
+use api::{HandOver, Lookup, Registration, XousServerName};
+use core::sync::atomic::{AtomicUsize, Ordering};
+use xous::{Error, MemoryFlags, MemoryMessage, Message, CID, SID};
+
+/// This process' connection to `xous-names`, or 0 until the first request is
+/// made. Connection IDs start at 2, so 0 is never a real one.
+static XOUS_NAMES_CID: AtomicUsize = AtomicUsize::new(0);
+
+/// Connect to `xous-names` the first time it's needed, and reuse that
+/// connection for every request after it.
+fn xous_names_cid() -> Result<CID, Error> {
+    let cid = XOUS_NAMES_CID.load(Ordering::Relaxed);
+    if cid != 0 {
+        return Ok(cid);
+    }
+    // Two threads that get here at once are given the same connection.
+    let cid = xous::connect(SID::from_bytes(api::SID_BYTES).unwrap())?;
+    XOUS_NAMES_CID.store(cid, Ordering::Relaxed);
+    Ok(cid)
+}
+
+/// Copy `request` into a page of its own, lend it to `xous-names`, and copy the
+/// response back out once the server returns it.
+fn lend_request<T: Copy>(request: &mut T, opcode: api::Opcode) -> Result<(), Error> {
+    assert!(core::mem::size_of::<T>() <= 4096);
+    let conn = xous_names_cid()?;
+
+    let buf = xous::map_memory(None, None, 4096, MemoryFlags::R | MemoryFlags::W)?;
+    unsafe { (buf.as_mut_ptr() as *mut T).write(*request) };
+    let msg = MemoryMessage {
+        id: opcode as usize,
+        buf,
+        offset: None,
+        valid: None,
+    };
+    let result = xous::send_message(conn, Message::MutableBorrow(msg));
+    if result.is_ok() {
+        *request = unsafe { (buf.as_ptr() as *const T).read() };
+    }
+    xous::unmap_memory(buf)?;
+    result.map(|_| ())
+}
+
+/// Create a new server with a random SID and register it under `name`.
+/// Other processes can then reach it with `request_connection()` without
+/// ever learning the SID.
+///
+/// # Errors
+///
+/// * **InvalidString**: The name is too long
+/// * **ServerExists**: Another server has already registered that name
+/// * **OutOfMemory**: No more servers may be created, or the name table is full
+pub fn register_name(name: &str) -> Result<SID, Error> {
+    let name: XousServerName = name.parse()?;
+    let sid = xous::create_server()?;
+    let (a0, a1, a2, a3) = sid.to_u32();
+    let mut registration = Registration {
+        name,
+        sid: [a0, a1, a2, a3],
+        result: Error::InternalError.to_usize(),
+    };
+    let result = match lend_request(&mut registration, api::Opcode::Register) {
+        Ok(()) => match Error::from_usize(registration.result) {
+            Error::NoError => Ok(sid),
+            e => Err(e),
+        },
+        Err(e) => Err(e),
+    };
+    if result.is_err() {
+        // Nobody can find a server that has no name, so don't leave it behind.
+        xous::destroy_server(sid).ok();
+    }
+    result
+}
+
+/// Ask `xous-names` to connect this process to the server registered as `name`.
+/// If the server approves its clients, this waits for it to decide.
+///
+/// # Errors
+///
+/// * **InvalidString**: The name is too long
+/// * **ServerNotFound**: No running server has registered that name
+/// * **AccessDenied**: The server turned down the connection
+pub fn request_connection(name: &str) -> Result<CID, Error> {
+    let mut lookup = Lookup {
+        name: name.parse()?,
+        cid: 0,
+        sid: [0; 4],
+        result: Error::InternalError.to_usize(),
+    };
+    lend_request(&mut lookup, api::Opcode::Lookup)?;
+    match Error::from_usize(lookup.result) {
+        Error::NoError if lookup.cid != 0 => Ok(lookup.cid),
+        Error::NoError => {
+            let [a0, a1, a2, a3] = lookup.sid;
+            let sid = SID::from_u32(a0, a1, a2, a3);
+            // `connect()` waits for a server that doesn't exist yet, but this
+            // one may have just gone away for good, so check that first.
+            match xous::try_connect(sid) {
+                Err(Error::AccessDenied) => xous::connect(sid),
+                other => other,
+            }
+        }
+        e => Err(e),
+    }
+}
+
+/// Choose the process that registers `name` next. Lookups are sent to the
+/// server it registers, even if another server has the name now. Pass `None`
+/// to have every process that tries to register it wait until a successor is
+/// chosen.
+///
+/// The first process to do this for a name that nobody has registered yet
+/// becomes its keeper, and is the only one allowed to do it again. This is
+/// how a supervisor makes sure that a service it restarts gets its old name
+/// back, and that nobody else can take it.
+///
+/// # Errors
+///
+/// * **InvalidString**: The name is too long
+/// * **AccessDenied**: Another process keeps the name, or a server registered
+///   it without a keeper
+/// * **OutOfMemory**: The name table is full
+pub fn hand_over_name(name: &str, successor: Option<xous::PID>) -> Result<(), Error> {
+    let mut hand_over = HandOver {
+        name: name.parse()?,
+        successor: successor.map_or(0, |pid| pid.get() as usize),
+        result: Error::InternalError.to_usize(),
+    };
+    lend_request(&mut hand_over, api::Opcode::HandOver)?;
+    match Error::from_usize(hand_over.result) {
+        Error::NoError => Ok(()),
+        e => Err(e),
+    }
+}
+
+/// Like `request_connection()`, but keep retrying until a server registers
+/// `name`. Useful during boot, when servers come up in no particular order.
+pub fn request_connection_blocking(name: &str) -> Result<CID, Error> {
+    loop {
+        match request_connection(name) {
+            Err(Error::ServerNotFound) => xous::yield_slice(),
+            other => return other,
+        }
+    }
+}
+
+/// A connection to a named server that survives the server being restarted.
+/// When the server has gone away, the name is looked up again and the message
+/// is sent to whichever server has registered it since.
+pub struct NamedConnection {
+    name: XousServerName,
+    cid: CID,
+}
+
+impl NamedConnection {
+    /// Connect to the server registered as `name`, waiting for it to appear
+    /// if necessary.
+    ///
+    /// # Errors
+    ///
+    /// * **InvalidString**: The name is too long
+    pub fn new(name: &str) -> Result<NamedConnection, Error> {
+        let name: XousServerName = name.parse()?;
+        let cid = request_connection_blocking(name.as_str()?)?;
+        Ok(NamedConnection { name, cid })
+    }
+
+    /// The connection currently in use. This changes if the server is
+    /// restarted.
+    pub fn cid(&self) -> CID {
+        self.cid
+    }
+
+    /// Send `message` to the named server. If the server has terminated, wait
+    /// for a new server to register the name, then send it there.
+    ///
+    /// The kernel refuses a message for a server that has gone away before
+    /// touching any memory it lends, so lent memory can be sent again. Memory
+    /// that is moved may already have been released by the failed attempt, so
+    /// a `Move` is not retried: the connection is renewed and **ServerNotFound**
+    /// is returned.
+    ///
+    /// If the server terminated while it was handling the message, the request
+    /// may have been partly carried out, so it isn't sent again either. The
+    /// connection is renewed and **ProcessTerminated** is returned.
+    pub fn send_message(&mut self, message: Message) -> Result<xous::Result, Error> {
+        let name = self.name.as_str()?;
+        let cid = core::cell::Cell::new(self.cid);
+        let result = send_with_reconnect(
+            &message,
+            |message| xous::send_message(cid.get(), message),
+            || {
+                // Drop the connection to the server that has gone away, and
+                // connect to whichever server registers the name next.
+                xous::disconnect(cid.get()).ok();
+                cid.set(request_connection_blocking(name)?);
+                Ok(())
+            },
+        );
+        self.cid = cid.get();
+        result
+    }
+}
+
+/// Send `message` with `send`, calling `reconnect` and trying again if the
+/// server has gone away. See `NamedConnection::send_message()`.
+fn send_with_reconnect<S, R>(
+    message: &Message,
+    mut send: S,
+    mut reconnect: R,
+) -> Result<xous::Result, Error>
+where
+    S: FnMut(Message) -> Result<xous::Result, Error>,
+    R: FnMut() -> Result<(), Error>,
+{
+    loop {
+        match send(duplicate_message(message)) {
+            Err(Error::ServerNotFound) => {
+                reconnect()?;
+                if let Message::Move(_) = message {
+                    return Err(Error::ServerNotFound);
+                }
+            }
+            Err(Error::ProcessTerminated) => {
+                reconnect()?;
+                return Err(Error::ProcessTerminated);
+            }
+            other => return other,
+        }
+    }
+}
+
+/// Make a second copy of `message`, which refers to the same memory.
+fn duplicate_message(message: &Message) -> Message {
+    let copy_memory = |mem: &MemoryMessage| MemoryMessage {
+        id: mem.id,
+        buf: mem.buf,
+        offset: mem.offset,
+        valid: mem.valid,
+    };
+    match message {
+        Message::MutableBorrow(mem) => Message::MutableBorrow(copy_memory(mem)),
+        Message::Borrow(mem) => Message::Borrow(copy_memory(mem)),
+        Message::Move(mem) => Message::Move(copy_memory(mem)),
+        Message::Scalar(scalar) => Message::Scalar(*scalar),
+        Message::BlockingScalar(scalar) => Message::BlockingScalar(*scalar),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn memory_message() -> MemoryMessage {
+        MemoryMessage {
+            id: 1,
+            buf: xous::MemoryRange::new(0x1000_0000, 4096).unwrap(),
+            offset: None,
+            valid: None,
+        }
+    }
+
+    /// Send `message` to a server that answers with each of `answers` in
+    /// turn. Returns the result, how many times the message was sent, and how
+    /// many times the connection was renewed.
+    fn send_to(
+        message: Message,
+        answers: Vec<Result<xous::Result, Error>>,
+    ) -> (Result<xous::Result, Error>, usize, usize) {
+        let mut answers = answers.into_iter();
+        let mut sent = 0;
+        let mut reconnected = 0;
+        let result = send_with_reconnect(
+            &message,
+            |_| {
+                sent += 1;
+                answers.next().unwrap()
+            },
+            || {
+                reconnected += 1;
+                Ok(())
+            },
+        );
+        (result, sent, reconnected)
+    }
+
+    #[test]
+    fn lent_memory_is_sent_to_new_server() {
+        let answers = || {
+            vec![
+                Err(Error::ServerNotFound),
+                Err(Error::ServerNotFound),
+                Ok(xous::Result::Ok),
+            ]
+        };
+        assert_eq!(
+            send_to(Message::MutableBorrow(memory_message()), answers()),
+            (Ok(xous::Result::Ok), 3, 2)
+        );
+        assert_eq!(
+            send_to(Message::Borrow(memory_message()), answers()),
+            (Ok(xous::Result::Ok), 3, 2)
+        );
+    }
+
+    #[test]
+    fn scalars_are_sent_to_new_server() {
+        let scalar = xous::ScalarMessage::from_usize(1, 2, 3, 4, 5);
+        let answers = vec![Err(Error::ServerNotFound), Ok(xous::Result::Scalar1(6))];
+        assert_eq!(
+            send_to(Message::BlockingScalar(scalar), answers),
+            (Ok(xous::Result::Scalar1(6)), 2, 1)
+        );
+    }
+
+    #[test]
+    fn moved_memory_is_not_sent_again() {
+        let answers = vec![Err(Error::ServerNotFound), Ok(xous::Result::Ok)];
+        assert_eq!(
+            send_to(Message::Move(memory_message()), answers),
+            (Err(Error::ServerNotFound), 1, 1)
+        );
+    }
+
+    #[test]
+    fn interrupted_requests_are_not_sent_again() {
+        let answers = vec![Err(Error::ProcessTerminated), Ok(xous::Result::Ok)];
+        assert_eq!(
+            send_to(Message::MutableBorrow(memory_message()), answers),
+            (Err(Error::ProcessTerminated), 1, 1)
+        );
+    }
+
+    #[test]
+    fn other_errors_keep_the_connection() {
+        let answers = vec![Err(Error::AccessDenied)];
+        assert_eq!(
+            send_to(Message::Borrow(memory_message()), answers),
+            (Err(Error::AccessDenied), 1, 0)
+        );
+    }
+
+    #[test]
+    fn reconnect_errors_are_returned() {
+        let message = Message::Borrow(memory_message());
+        let result = send_with_reconnect(
+            &message,
+            |_| Err(Error::ServerNotFound),
+            || Err(Error::InvalidString),
+        );
+        assert_eq!(result, Err(Error::InvalidString));
+    }
+}
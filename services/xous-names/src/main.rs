@@ -0,0 +1,217 @@
+#![cfg_attr(target_os = "none", no_std)]
+#![cfg_attr(target_os = "none", no_main)]
+
+mod api;
+use api::{HandOver, Lookup, Opcode, Registration};
+
+mod table;
+use table::{NameTable, Outcome};
+
+use core::convert::TryFrom;
+
+use heapless::consts::*;
+use heapless::Vec;
+
+use log::{error, info};
+
+/// The most registrations that may wait for the keeper of their name at once
+type MaxPending = U8;
+
+/// Get at the request that `envelope` lends, if it is large enough to hold one.
+fn request<T>(envelope: &mut xous::MessageEnvelope) -> Option<&mut T> {
+    match &mut envelope.body {
+        xous::Message::MutableBorrow(mem) if mem.buf.len() >= core::mem::size_of::<T>() => {
+            Some(unsafe { &mut *(mem.buf.as_mut_ptr() as *mut T) })
+        }
+        _ => None,
+    }
+}
+
+fn register_name(
+    name_table: &mut NameTable,
+    sender: xous::MessageSender,
+    registration: &Registration,
+) -> Result<Outcome, xous::Error> {
+    let name = registration.name.as_str()?;
+    let sid = xous::SID::from_u32(
+        registration.sid[0],
+        registration.sid[1],
+        registration.sid[2],
+        registration.sid[3],
+    );
+    let pid = sender.pid().ok_or(xous::Error::ProcessNotFound)?;
+    // Otherwise anyone who learned a SID could put a name on it, and have that
+    // name's clients sent to somebody else's server.
+    if xous::server_owner(sid)? != pid {
+        info!(
+            "NAMES: {} isn't the owner of the server it registered as {}",
+            pid, name
+        );
+        return Err(xous::Error::AccessDenied);
+    }
+    let outcome = name_table.register(registration.name, sid, pid);
+    match &outcome {
+        Ok(Outcome::Registered) => info!("NAMES: registered {}", name),
+        Ok(Outcome::Deferred) => info!("NAMES: {} waits for its keeper", name),
+        Err(e) => info!("NAMES: couldn't register {}: {:?}", name, e),
+    }
+    outcome
+}
+
+fn hand_over_name(
+    name_table: &mut NameTable,
+    sender: xous::MessageSender,
+    hand_over: &HandOver,
+) -> Result<(), xous::Error> {
+    let name = hand_over.name.as_str()?;
+    let keeper = sender.pid().ok_or(xous::Error::ProcessNotFound)?;
+    let successor = match hand_over.successor {
+        0 => None,
+        pid => Some(
+            u8::try_from(pid)
+                .ok()
+                .and_then(xous::PID::new)
+                .ok_or(xous::Error::ProcessNotFound)?,
+        ),
+    };
+    name_table.hand_over(hand_over.name, keeper, successor)?;
+    info!("NAMES: {} is handed over to {:?}", name, successor);
+    Ok(())
+}
+
+/// Try every registration that is waiting again, now that a keeper may have
+/// chosen who registers its name. Those that are done are answered, which
+/// returns their memory.
+fn retry_pending(name_table: &mut NameTable, pending: &mut Vec<xous::MessageEnvelope, MaxPending>) {
+    let mut index = 0;
+    while index < pending.len() {
+        let sender = pending[index].sender;
+        let outcome = match request::<Registration>(&mut pending[index]) {
+            Some(registration) => register_name(name_table, sender, registration),
+            None => Err(xous::Error::InternalError),
+        };
+        if outcome == Ok(Outcome::Deferred) {
+            index += 1;
+            continue;
+        }
+        answer_registration(&mut pending.swap_remove(index), outcome);
+    }
+}
+
+/// Fill in the result of the registration that `envelope` lends.
+fn answer_registration(
+    envelope: &mut xous::MessageEnvelope,
+    outcome: Result<Outcome, xous::Error>,
+) {
+    if let Some(registration) = request::<Registration>(envelope) {
+        registration.result = match outcome {
+            Ok(_) => xous::Error::NoError,
+            Err(e) => e,
+        }
+        .to_usize();
+    }
+}
+
+fn lookup_name(
+    name_table: &NameTable,
+    sender: xous::MessageSender,
+    lookup: &mut Lookup,
+) -> Result<(), xous::Error> {
+    lookup.name.as_str()?;
+    let sid = name_table.lookup(&lookup.name)?;
+    let pid = sender.pid().ok_or(xous::Error::ProcessNotFound)?;
+
+    // Never wait here, or every other lookup would wait too. A server that has
+    // gone away will only come back under a new SID, so the client retries
+    // once the service has registered its name again. A server that approves
+    // its clients is connected to by the client itself.
+    match xous::try_connect_for_process(pid, sid) {
+        Ok(cid) => {
+            lookup.cid = cid;
+            Ok(())
+        }
+        Err(xous::Error::AccessDenied) => {
+            let (a0, a1, a2, a3) = sid.to_u32();
+            lookup.sid = [a0, a1, a2, a3];
+            Ok(())
+        }
+        Err(e) => Err(e),
+    }
+}
+
+#[xous::xous_main]
+fn xmain() -> ! {
+    log_server::init_wait().unwrap();
+
+    let name_server = xous::create_server_with_address(api::SID_BYTES)
+        .expect("Couldn't create xous-names server");
+
+    let mut name_table = NameTable::new();
+
+    // Registrations waiting for the keeper of their name. They are answered
+    // once the keeper chooses who may register it.
+    let mut pending: Vec<xous::MessageEnvelope, MaxPending> = Vec::new();
+
+    loop {
+        let mut envelope = xous::receive_message(name_server).unwrap();
+        let sender = envelope.sender;
+        let opcode = match Opcode::try_from(&envelope.body) {
+            Ok(opcode) => opcode,
+            Err(e) => {
+                error!("NAMES: couldn't convert opcode: {}", e);
+                continue;
+            }
+        };
+        match opcode {
+            Opcode::Register => {
+                let outcome = match request::<Registration>(&mut envelope) {
+                    Some(registration) => register_name(&mut name_table, sender, registration),
+                    None => {
+                        error!("NAMES: registration buffer is too small");
+                        continue;
+                    }
+                };
+                // A deferred registration is answered by `retry_pending()`.
+                let outcome = match outcome {
+                    Ok(Outcome::Deferred) => match pending.push(envelope) {
+                        Ok(()) => continue,
+                        Err(rejected) => {
+                            envelope = rejected;
+                            Err(xous::Error::OutOfMemory)
+                        }
+                    },
+                    other => other,
+                };
+                answer_registration(&mut envelope, outcome);
+            }
+            Opcode::Lookup => {
+                let lookup = match request::<Lookup>(&mut envelope) {
+                    Some(lookup) => lookup,
+                    None => {
+                        error!("NAMES: lookup buffer is too small");
+                        continue;
+                    }
+                };
+                lookup.result = match lookup_name(&name_table, sender, lookup) {
+                    Ok(()) => xous::Error::NoError.to_usize(),
+                    Err(e) => e.to_usize(),
+                };
+            }
+            Opcode::HandOver => {
+                let hand_over = match request::<HandOver>(&mut envelope) {
+                    Some(hand_over) => hand_over,
+                    None => {
+                        error!("NAMES: hand-over buffer is too small");
+                        continue;
+                    }
+                };
+                hand_over.result = match hand_over_name(&mut name_table, sender, hand_over) {
+                    Ok(()) => xous::Error::NoError.to_usize(),
+                    Err(e) => e.to_usize(),
+                };
+                drop(envelope);
+                retry_pending(&mut name_table, &mut pending);
+            }
+        }
+    }
+}
@@ -0,0 +1,216 @@
+use crate::api::XousServerName;
+
+use heapless::consts::*;
+use heapless::Vec;
+
+use xous::{Error, PID, SID};
+
+/// A name, and who decides which server it belongs to.
+struct Entry {
+    name: XousServerName,
+
+    /// The server registered under this name, if any
+    sid: Option<SID>,
+
+    /// The process that chooses who registers the name next, if any
+    keeper: Option<PID>,
+
+    /// The process that the keeper has chosen to register the name next
+    successor: Option<PID>,
+}
+
+/// What became of a request to register a name.
+#[derive(Debug, PartialEq)]
+pub enum Outcome {
+    /// The name now points at the new server
+    Registered,
+
+    /// The keeper of the name hasn't chosen who may register it yet, so the
+    /// request has to wait until it does
+    Deferred,
+}
+
+/// Every name that has been registered or handed over. Names are never
+/// removed, so the table only needs to hold one entry per name. A name that
+/// is registered again simply points at the new server.
+pub struct NameTable {
+    entries: Vec<Entry, U64>,
+}
+
+impl NameTable {
+    pub fn new() -> NameTable {
+        NameTable {
+            entries: Vec::new(),
+        }
+    }
+
+    fn entry_mut(&mut self, name: &XousServerName) -> Option<&mut Entry> {
+        self.entries.iter_mut().find(|entry| entry.name == *name)
+    }
+
+    /// Have `name` point at the server `sid`, which `pid` created.
+    ///
+    /// # Errors
+    ///
+    /// * **ServerExists**: The name belongs to another server, and has no keeper
+    /// * **AccessDenied**: The keeper chose a different process to register the name
+    /// * **OutOfMemory**: The table is full
+    pub fn register(&mut self, name: XousServerName, sid: SID, pid: PID) -> Result<Outcome, Error> {
+        let entry = match self.entry_mut(&name) {
+            Some(entry) => entry,
+            None => {
+                self.entries
+                    .push(Entry {
+                        name,
+                        sid: Some(sid),
+                        keeper: None,
+                        successor: None,
+                    })
+                    .or(Err(Error::OutOfMemory))?;
+                return Ok(Outcome::Registered);
+            }
+        };
+
+        // Without a keeper to vouch for it, a new server can't tell a service
+        // that was restarted apart from a process that wants to pose as it.
+        if entry.keeper.is_none() {
+            return Err(Error::ServerExists);
+        }
+        match entry.successor {
+            None => Ok(Outcome::Deferred),
+            Some(successor) if successor == pid => {
+                entry.sid = Some(sid);
+                entry.successor = None;
+                Ok(Outcome::Registered)
+            }
+            Some(_) => Err(Error::AccessDenied),
+        }
+    }
+
+    /// Have `keeper` choose `successor` to register `name` next.
+    ///
+    /// # Errors
+    ///
+    /// * **AccessDenied**: Somebody else keeps the name, or it was registered
+    ///   without a keeper
+    /// * **OutOfMemory**: The table is full
+    pub fn hand_over(
+        &mut self,
+        name: XousServerName,
+        keeper: PID,
+        successor: Option<PID>,
+    ) -> Result<(), Error> {
+        let entry = match self.entry_mut(&name) {
+            Some(entry) => entry,
+            None => {
+                return self
+                    .entries
+                    .push(Entry {
+                        name,
+                        sid: None,
+                        keeper: Some(keeper),
+                        successor,
+                    })
+                    .or(Err(Error::OutOfMemory));
+            }
+        };
+        if entry.keeper != Some(keeper) {
+            return Err(Error::AccessDenied);
+        }
+        entry.successor = successor;
+        Ok(())
+    }
+
+    /// Find the server registered as `name`.
+    ///
+    /// # Errors
+    ///
+    /// * **ServerNotFound**: No server has registered that name yet
+    pub fn lookup(&self, name: &XousServerName) -> Result<SID, Error> {
+        self.entries
+            .iter()
+            .find(|entry| entry.name == *name)
+            .and_then(|entry| entry.sid)
+            .ok_or(Error::ServerNotFound)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn name(name: &str) -> XousServerName {
+        name.parse().unwrap()
+    }
+
+    fn pid(pid: u8) -> PID {
+        PID::new(pid).unwrap()
+    }
+
+    fn sid(id: u32) -> SID {
+        SID::from_u32(id, 0, 0, 0)
+    }
+
+    #[test]
+    fn names_without_keeper_stay_with_first_server() {
+        let mut table = NameTable::new();
+        assert_eq!(
+            table.register(name("com"), sid(1), pid(2)),
+            Ok(Outcome::Registered)
+        );
+        assert_eq!(
+            table.register(name("com"), sid(2), pid(3)),
+            Err(Error::ServerExists)
+        );
+        assert_eq!(table.lookup(&name("com")), Ok(sid(1)));
+        assert_eq!(table.lookup(&name("gfx")), Err(Error::ServerNotFound));
+
+        // A name can't be taken over by becoming its keeper afterwards.
+        assert_eq!(
+            table.hand_over(name("com"), pid(3), Some(pid(3))),
+            Err(Error::AccessDenied)
+        );
+    }
+
+    #[test]
+    fn keeper_chooses_successor() {
+        let mut table = NameTable::new();
+        assert_eq!(table.hand_over(name("com"), pid(2), None), Ok(()));
+        assert_eq!(table.lookup(&name("com")), Err(Error::ServerNotFound));
+
+        // Until the keeper has chosen, registrations have to wait.
+        assert_eq!(
+            table.register(name("com"), sid(1), pid(3)),
+            Ok(Outcome::Deferred)
+        );
+
+        assert_eq!(table.hand_over(name("com"), pid(2), Some(pid(3))), Ok(()));
+        assert_eq!(
+            table.register(name("com"), sid(9), pid(4)),
+            Err(Error::AccessDenied)
+        );
+        assert_eq!(
+            table.register(name("com"), sid(1), pid(3)),
+            Ok(Outcome::Registered)
+        );
+        assert_eq!(table.lookup(&name("com")), Ok(sid(1)));
+
+        // The successor only gets to register once.
+        assert_eq!(
+            table.register(name("com"), sid(2), pid(3)),
+            Ok(Outcome::Deferred)
+        );
+
+        // Only the keeper may choose the next server.
+        assert_eq!(
+            table.hand_over(name("com"), pid(4), Some(pid(4))),
+            Err(Error::AccessDenied)
+        );
+        assert_eq!(table.hand_over(name("com"), pid(2), Some(pid(5))), Ok(()));
+        assert_eq!(
+            table.register(name("com"), sid(5), pid(5)),
+            Ok(Outcome::Registered)
+        );
+        assert_eq!(table.lookup(&name("com")), Ok(sid(5)));
+    }
+}
@@ -3,13 +3,15 @@ extern crate clap;
 
 extern crate crc;
 
-use std::convert::TryInto;
+use std::convert::{TryFrom, TryInto};
 use std::fs::File;
 
 use tools::elf::{read_minielf, read_program};
 use tools::tags::bflg::Bflg;
 use tools::tags::inie::IniE;
 use tools::tags::memory::{MemoryRegion, MemoryRegions};
+use tools::tags::mlim::MemoryLimits;
+use tools::tags::ppri::InitPriorities;
 use tools::tags::xkrn::XousKernel;
 use tools::utils::{parse_csr_csv, parse_u32};
 use tools::xous_arguments::XousArguments;
@@ -122,6 +124,31 @@ fn main() {
                 .takes_value(false)
                 .help("Reduce kernel-userspace security and enable debugging programs"),
         )
+        .arg(
+            Arg::with_name("memory-limit")
+                .long("memory-limit")
+                .takes_value(true)
+                .value_name("BYTES")
+                .help("Most memory that any one process may use, or 0 for no limit"),
+        )
+        .arg(
+            Arg::with_name("init-memory-limit")
+                .long("init-memory-limit")
+                .takes_value(true)
+                .multiple(true)
+                .number_of_values(1)
+                .value_name("BYTES")
+                .help("Most memory that an initial program may use, in the same order as --init"),
+        )
+        .arg(
+            Arg::with_name("init-priority")
+                .long("init-priority")
+                .takes_value(true)
+                .multiple(true)
+                .number_of_values(1)
+                .value_name("PRIORITY")
+                .help("Scheduling priority that an initial program starts with, in the same order as --init"),
+        )
         .arg(
             Arg::with_name("output")
                 .value_name("OUTPUT")
@@ -215,6 +242,31 @@ fn main() {
         args.add(Bflg::new().debug());
     }
 
+    if matches.is_present("memory-limit") || matches.is_present("init-memory-limit") {
+        let parse_limit = |val: &str| {
+            parse_u32(val).unwrap_or_else(|e| panic!("Unable to parse {}: {:?}", val, e))
+        };
+        let mut limits = MemoryLimits::new(matches.value_of("memory-limit").map_or(0, parse_limit));
+        if let Some(init_limits) = matches.values_of("init-memory-limit") {
+            for limit in init_limits {
+                limits.add_init(parse_limit(limit));
+            }
+        }
+        args.add(limits);
+    }
+
+    if let Some(init_priorities) = matches.values_of("init-priority") {
+        let mut priorities = InitPriorities::new();
+        for priority in init_priorities {
+            let priority = parse_u32(priority)
+                .ok()
+                .and_then(|p| u8::try_from(p).ok())
+                .unwrap_or_else(|| panic!("Priority {} isn't between 0 and 255", priority));
+            priorities.add_init(priority);
+        }
+        args.add(priorities);
+    }
+
     let kernel = read_program(
         matches
             .value_of("kernel")
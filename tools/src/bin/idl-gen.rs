@@ -0,0 +1,51 @@
+use std::env;
+use std::fs;
+use std::path::Path;
+use std::process;
+use tools::idl;
+
+fn main() {
+    let args: Vec<String> = env::args().collect();
+    if args.len() < 2 {
+        println!(
+            "Usage: {} input.idl [output.rs]",
+            args.first().unwrap_or(&"idl-gen".to_owned())
+        );
+        return;
+    }
+
+    let input_filename = Path::new(args.get(1).unwrap()).to_path_buf();
+    let output_filename = args
+        .get(2)
+        .map(|x| Path::new(x).to_path_buf())
+        .unwrap_or_else(|| {
+            let mut output_filename = input_filename.clone();
+            output_filename.set_extension("rs");
+            output_filename
+        });
+    if output_filename == input_filename {
+        eprintln!(
+            "Input and output filename are the same: {}",
+            output_filename.display()
+        );
+        eprintln!("Specify an output path, or change the suffix of your input file from \".rs\"");
+        process::exit(1);
+    }
+
+    let source = fs::read_to_string(&input_filename).unwrap_or_else(|e| {
+        eprintln!("Unable to read input file: {}", e);
+        process::exit(1);
+    });
+    let interface = idl::parse(&source).unwrap_or_else(|e| {
+        eprintln!("{}: {}", input_filename.display(), e);
+        process::exit(1);
+    });
+    let source_name = input_filename
+        .file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    fs::write(&output_filename, interface.generate(&source_name)).unwrap_or_else(|e| {
+        eprintln!("Unable to write {}: {}", output_filename.display(), e);
+        process::exit(1);
+    });
+}
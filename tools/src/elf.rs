@@ -73,6 +73,35 @@ pub struct MiniElf {
     pub program: Vec<u8>,
 }
 
+impl MiniElf {
+    /// Lay this file out as a single image that the kernel can load into a new
+    /// process. Sections are described the same way as in an `IniE` tag, but
+    /// there is no load offset. Instead, the entrypoint is followed by the
+    /// number of sections, and the program data comes right after them.
+    ///
+    /// The flags of each section take up the top byte of its size, so no
+    /// section may be 16 MiB or larger.
+    pub fn to_image(&self) -> Result<Vec<u8>, ElfReadError> {
+        let mut image = vec![];
+        image.extend_from_slice(&self.entry_point.to_le_bytes());
+        image.extend_from_slice(&(self.sections.len() as u32).to_le_bytes());
+        for section in &self.sections {
+            if section.size > 0x00ff_ffff {
+                return Err(ElfReadError::SectionTooLarge(
+                    section.name.clone(),
+                    section.size as usize,
+                ));
+            }
+            image.extend_from_slice(&section.virt.to_le_bytes());
+            let mut word2 = section.size.to_le_bytes();
+            word2[3] = section.flags.bits();
+            image.extend_from_slice(&word2);
+        }
+        image.extend_from_slice(&self.program);
+        Ok(image)
+    }
+}
+
 #[derive(Debug)]
 pub enum ElfReadError {
     /// Read an unexpected number of bytes
@@ -101,6 +130,9 @@ pub enum ElfReadError {
 
     /// Couldn't write the section to the file
     WriteSectionError(std::io::Error),
+
+    /// Section was too large to describe in an image
+    SectionTooLarge(String /* section name */, usize /* section size */),
 }
 
 impl fmt::Display for ElfReadError {
@@ -118,6 +150,7 @@ impl fmt::Display for ElfReadError {
             SectionNotAligned(s, a) => write!(f, "elf section {} had unaligned length {}", s, a),
             FileSeekError(e) => write!(f, "couldn't seek in the output file: {}", e),
             WriteSectionError(e) => write!(f, "couldn't write a section to the output file: {}", e),
+            SectionTooLarge(s, a) => write!(f, "elf section {} was too large ({} bytes)", s, a),
         }
     }
 }
@@ -357,3 +390,96 @@ pub fn read_minielf<P: AsRef<Path>>(filename: P) -> Result<MiniElf, ElfReadError
         program: program_data.into_inner(),
     })
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn section(name: &str, virt: u32, size: u32, flags: MiniElfFlags) -> MiniElfSection {
+        MiniElfSection {
+            virt,
+            size,
+            name: name.to_owned(),
+            flags,
+        }
+    }
+
+    fn word(image: &[u8], offset: usize) -> u32 {
+        let mut bytes = [0u8; 4];
+        bytes.copy_from_slice(&image[offset..offset + 4]);
+        u32::from_le_bytes(bytes)
+    }
+
+    /// Read an image back the way the kernel does. Section names aren't part
+    /// of the image, so they're left empty.
+    fn from_image(image: &[u8]) -> MiniElf {
+        let section_count = word(image, 4) as usize;
+        let sections = (0..section_count)
+            .map(|index| {
+                let size_and_flags = word(image, 12 + index * 8);
+                section(
+                    "",
+                    word(image, 8 + index * 8),
+                    size_and_flags & 0x00ff_ffff,
+                    MiniElfFlags::from_bits(size_and_flags.to_le_bytes()[3]).unwrap(),
+                )
+            })
+            .collect();
+        MiniElf {
+            entry_point: word(image, 0),
+            sections,
+            program: image[8 + section_count * 8..].to_vec(),
+        }
+    }
+
+    #[test]
+    fn image_round_trip() {
+        let elf = MiniElf {
+            entry_point: 0x2000_0124,
+            sections: vec![
+                section(".text", 0x2000_0000, 0x1234, MiniElfFlags::EXECUTE),
+                section(".data", 0x2000_2000, 0x10, MiniElfFlags::WRITE),
+                section(
+                    ".bss",
+                    0x2000_2010,
+                    0x00ff_ffff,
+                    MiniElfFlags::WRITE | MiniElfFlags::NOCOPY,
+                ),
+            ],
+            program: (0..0x1244).map(|i| i as u8).collect(),
+        };
+        let image = elf.to_image().unwrap();
+        assert_eq!(image.len(), 8 + 3 * 8 + elf.program.len());
+
+        let decoded = from_image(&image);
+        assert_eq!(decoded.entry_point, elf.entry_point);
+        assert_eq!(decoded.program, elf.program);
+        assert_eq!(decoded.sections.len(), elf.sections.len());
+        for (decoded, original) in decoded.sections.iter().zip(elf.sections.iter()) {
+            assert_eq!(decoded.virt, original.virt);
+            assert_eq!(decoded.size, original.size);
+            assert_eq!(decoded.flags, original.flags);
+        }
+    }
+
+    #[test]
+    fn image_rejects_large_sections() {
+        let elf = MiniElf {
+            entry_point: 0x2000_0000,
+            sections: vec![section(
+                ".bss",
+                0x2000_0000,
+                0x0100_0000,
+                MiniElfFlags::WRITE | MiniElfFlags::NOCOPY,
+            )],
+            program: vec![],
+        };
+        match elf.to_image() {
+            Err(ElfReadError::SectionTooLarge(name, size)) => {
+                assert_eq!(name, ".bss");
+                assert_eq!(size, 0x0100_0000);
+            }
+            other => panic!("large section was accepted: {:?}", other.map(|i| i.len())),
+        }
+    }
+}
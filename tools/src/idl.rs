@@ -0,0 +1,940 @@
+//! A small interface definition language for services. Each service describes
+//! its opcodes in an `.idl` file, and `idl-gen` turns that into a Rust module
+//! with the `Opcode` enum, a client function for every opcode, and a `Server`
+//! trait along with a `dispatch()` function that calls it.
+//!
+//! A file looks like this:
+//!
+//! ```text
+//! # Comments start with `#`, and doc comments with `///`.
+//!
+//! /// The name this server registers with `xous-names`
+//! server SERVER_NAME_TICKTIMER = "ticktimer-server"
+//!
+//! /// Reset the timer
+//! scalar Reset = 1
+//!
+//! /// Get the elapsed time in milliseconds
+//! blocking_scalar ElapsedMs = 2 -> u64
+//!
+//! /// Sleep for the specified number of milliseconds
+//! deferred blocking_scalar SleepMs(ms: usize) = 3
+//!
+//! /// Look up a name, which is in the lent buffer
+//! mutable_borrow Lookup = 4
+//! ```
+//!
+//! Opcodes are one of `scalar`, `blocking_scalar`, `borrow`, `mutable_borrow`
+//! or `move`. Scalar opcodes take up to four `usize` arguments. Memory opcodes
+//! carry an `xous::MemoryMessage` instead. Their handlers are given the PID of
+//! the sender along with the message in the envelope, and the memory goes back
+//! when the envelope is dropped, along with any change to `offset` and `valid`.
+//! A `blocking_scalar` may reply with `usize`, `(usize, usize)` or `u64`, and
+//! otherwise replies with 0. If it's `deferred`, its handler is given the
+//! sender instead of returning the reply, so that it can reply later.
+//!
+//! Opcode and argument names become Rust identifiers, so they may not be
+//! keywords once they're in `snake_case`.
+
+use std::fmt;
+use std::fmt::Write;
+
+#[derive(Debug)]
+pub struct IdlError {
+    /// The line the error was found on, starting from 1
+    pub line: usize,
+    pub message: String,
+}
+
+impl fmt::Display for IdlError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "line {}: {}", self.line, self.message)
+    }
+}
+
+impl std::error::Error for IdlError {}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Kind {
+    Scalar,
+    BlockingScalar,
+    Borrow,
+    MutableBorrow,
+    Move,
+}
+
+impl Kind {
+    fn from_str(s: &str) -> Option<Kind> {
+        match s {
+            "scalar" => Some(Kind::Scalar),
+            "blocking_scalar" => Some(Kind::BlockingScalar),
+            "borrow" => Some(Kind::Borrow),
+            "mutable_borrow" => Some(Kind::MutableBorrow),
+            "move" => Some(Kind::Move),
+            _ => None,
+        }
+    }
+
+    /// The name of this kind in an IDL file and in `#[opcode(...)]`
+    fn name(self) -> &'static str {
+        match self {
+            Kind::Scalar => "scalar",
+            Kind::BlockingScalar => "blocking_scalar",
+            Kind::Borrow => "borrow",
+            Kind::MutableBorrow => "mutable_borrow",
+            Kind::Move => "move",
+        }
+    }
+
+    fn is_scalar(self) -> bool {
+        self == Kind::Scalar || self == Kind::BlockingScalar
+    }
+}
+
+/// What a `blocking_scalar` opcode replies with
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Reply {
+    /// Nothing, which is sent as a 0
+    Nothing,
+
+    /// One `usize`, sent with `return_scalar()`
+    Usize,
+
+    /// Two `usize`s, sent with `return_scalar2()`
+    Usize2,
+
+    /// A `u64`, sent with `return_scalar2()` as the low and then the high 32 bits
+    U64,
+}
+
+impl Reply {
+    fn from_str(s: &str) -> Option<Reply> {
+        let s: String = s.chars().filter(|c| !c.is_whitespace()).collect();
+        match s.as_str() {
+            "()" => Some(Reply::Nothing),
+            "usize" => Some(Reply::Usize),
+            "(usize,usize)" => Some(Reply::Usize2),
+            "u64" => Some(Reply::U64),
+            _ => None,
+        }
+    }
+
+    fn rust_type(self) -> &'static str {
+        match self {
+            Reply::Nothing => "()",
+            Reply::Usize => "usize",
+            Reply::Usize2 => "(usize, usize)",
+            Reply::U64 => "u64",
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct Opcode {
+    pub doc: Vec<String>,
+    pub name: String,
+    pub kind: Kind,
+    pub id: u64,
+    pub args: Vec<String>,
+    pub reply: Reply,
+    pub deferred: bool,
+}
+
+impl Opcode {
+    /// The name of the client function and the `Server` method
+    pub fn function_name(&self) -> String {
+        snake_case(&self.name)
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct Interface {
+    /// The doc comment, constant name and value of the server's name
+    pub server_name: Option<(Vec<String>, String, String)>,
+    pub opcodes: Vec<Opcode>,
+}
+
+/// Words that can't be used as identifiers, including those reserved for later
+const KEYWORDS: &[&str] = &[
+    "Self", "abstract", "as", "async", "await", "become", "box", "break", "const", "continue",
+    "crate", "do", "dyn", "else", "enum", "extern", "false", "final", "fn", "for", "if", "impl",
+    "in", "let", "loop", "macro", "match", "mod", "move", "mut", "override", "priv", "pub", "ref",
+    "return", "self", "static", "struct", "super", "trait", "true", "try", "type", "typeof",
+    "unsafe", "unsized", "use", "virtual", "where", "while", "yield",
+];
+
+/// Names that the generated code already uses for its own parameters
+const RESERVED_ARGS: &[&str] = &["cid", "envelope", "server"];
+
+fn is_identifier(s: &str) -> bool {
+    let mut chars = s.chars();
+    match chars.next() {
+        Some(c) if c.is_ascii_alphabetic() || c == '_' => {}
+        _ => return false,
+    }
+    chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+/// Turn a `CamelCase` name into `snake_case`, keeping acronyms together.
+fn snake_case(name: &str) -> String {
+    let chars: Vec<char> = name.chars().collect();
+    let mut out = String::new();
+    for (i, &c) in chars.iter().enumerate() {
+        if c.is_ascii_uppercase() && i > 0 {
+            let after_lower = !chars[i - 1].is_ascii_uppercase() && chars[i - 1] != '_';
+            let ends_acronym = chars[i - 1].is_ascii_uppercase()
+                && matches!(chars.get(i + 1), Some(n) if n.is_ascii_lowercase());
+            if after_lower || ends_acronym {
+                out.push('_');
+            }
+        }
+        out.push(c.to_ascii_lowercase());
+    }
+    out
+}
+
+fn parse_id(s: &str) -> Option<u64> {
+    let s = s.replace('_', "");
+    if let Some(hex) = s.strip_prefix("0x") {
+        u64::from_str_radix(hex, 16).ok()
+    } else {
+        s.parse().ok()
+    }
+}
+
+fn parse_server(rest: &str, doc: Vec<String>) -> Result<(Vec<String>, String, String), String> {
+    let mut parts = rest.splitn(2, '=');
+    let name = parts.next().unwrap_or("").trim();
+    let value = parts.next().unwrap_or("").trim();
+    if !is_identifier(name) {
+        return Err(format!("expected a constant name, not `{}`", name));
+    }
+    if value.len() < 2 || !value.starts_with('"') || !value.ends_with('"') {
+        return Err("expected the server name as a quoted string".to_owned());
+    }
+    Ok((doc, name.to_owned(), value[1..value.len() - 1].to_owned()))
+}
+
+fn parse_opcode(line: &str, doc: Vec<String>) -> Result<Opcode, String> {
+    let (decl, reply) = match line.find("->") {
+        Some(arrow) => (&line[..arrow], Some(line[arrow + 2..].trim())),
+        None => (line, None),
+    };
+    let equals = decl
+        .rfind('=')
+        .ok_or_else(|| "expected `= <id>` after the opcode".to_owned())?;
+    let id_str = decl[equals + 1..].trim();
+    let id = parse_id(id_str).ok_or_else(|| format!("`{}` isn't a valid ID", id_str))?;
+
+    let mut signature = decl[..equals].trim();
+    let deferred = signature.starts_with("deferred ");
+    if deferred {
+        signature = signature["deferred ".len()..].trim_start();
+    }
+    let kind_end = signature
+        .find(char::is_whitespace)
+        .ok_or_else(|| "expected an opcode kind followed by a name".to_owned())?;
+    let kind_str = &signature[..kind_end];
+    let kind = Kind::from_str(kind_str).ok_or_else(|| {
+        format!(
+            "unknown opcode kind `{}`: expected `scalar`, `blocking_scalar`, `borrow`, `mutable_borrow` or `move`",
+            kind_str
+        )
+    })?;
+
+    let rest = signature[kind_end..].trim();
+    let (name, args) = match rest.find('(') {
+        Some(paren) => {
+            if !rest.ends_with(')') {
+                return Err("expected `)` at the end of the arguments".to_owned());
+            }
+            (rest[..paren].trim(), Some(&rest[paren + 1..rest.len() - 1]))
+        }
+        None => (rest, None),
+    };
+    if !is_identifier(name) || !name.starts_with(|c: char| c.is_ascii_uppercase()) {
+        return Err(format!("`{}` isn't a valid opcode name", name));
+    }
+    let function = snake_case(name);
+    if KEYWORDS.contains(&name) || KEYWORDS.contains(&function.as_str()) {
+        return Err(format!(
+            "`{}` can't be an opcode name, as `{}` is a keyword",
+            name, function
+        ));
+    }
+
+    let mut arg_names = vec![];
+    for arg in args.unwrap_or("").split(',').map(str::trim) {
+        if arg.is_empty() {
+            continue;
+        }
+        let mut parts = arg.splitn(2, ':').map(str::trim);
+        let arg_name = parts.next().unwrap_or("");
+        if !is_identifier(arg_name) {
+            return Err(format!("`{}` isn't a valid argument name", arg_name));
+        }
+        if KEYWORDS.contains(&arg_name) {
+            return Err(format!("`{}` is a keyword", arg_name));
+        }
+        if RESERVED_ARGS.contains(&arg_name) {
+            return Err(format!(
+                "`{}` is used by the generated code, and can't be an argument",
+                arg_name
+            ));
+        }
+        if parts.next() != Some("usize") {
+            return Err(format!("argument `{}` must be a `usize`", arg_name));
+        }
+        if arg_names.iter().any(|a| a == arg_name) {
+            return Err(format!(
+                "there's more than one argument called `{}`",
+                arg_name
+            ));
+        }
+        arg_names.push(arg_name.to_owned());
+    }
+
+    let reply = match reply {
+        Some(r) => Reply::from_str(r).ok_or_else(|| {
+            format!(
+                "unknown reply type `{}`: expected `usize`, `(usize, usize)` or `u64`",
+                r
+            )
+        })?,
+        None => Reply::Nothing,
+    };
+
+    if kind.is_scalar() && arg_names.len() > 4 {
+        return Err("scalar opcodes may have at most four arguments".to_owned());
+    }
+    if !kind.is_scalar() && args.is_some() {
+        return Err("memory opcodes carry a `MemoryMessage`, and can't have arguments".to_owned());
+    }
+    if kind != Kind::BlockingScalar && reply != Reply::Nothing {
+        return Err("only `blocking_scalar` opcodes can reply".to_owned());
+    }
+    if kind != Kind::BlockingScalar && deferred {
+        return Err("only `blocking_scalar` opcodes can be `deferred`".to_owned());
+    }
+
+    Ok(Opcode {
+        doc,
+        name: name.to_owned(),
+        kind,
+        id,
+        args: arg_names,
+        reply,
+        deferred,
+    })
+}
+
+/// Parse the text of an IDL file.
+pub fn parse(source: &str) -> Result<Interface, IdlError> {
+    let mut interface = Interface::default();
+    let mut doc = vec![];
+    for (index, line) in source.lines().enumerate() {
+        let line = line.trim();
+        let error = |message: String| IdlError {
+            line: index + 1,
+            message,
+        };
+        if let Some(text) = line.strip_prefix("///") {
+            doc.push(text.strip_prefix(' ').unwrap_or(text).to_owned());
+            continue;
+        }
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        if let Some(rest) = line.strip_prefix("server ") {
+            if interface.server_name.is_some() {
+                return Err(error("the server name was already given".to_owned()));
+            }
+            interface.server_name = Some(parse_server(rest, doc).map_err(error)?);
+            doc = vec![];
+            continue;
+        }
+
+        let opcode = parse_opcode(line, doc).map_err(error)?;
+        doc = vec![];
+        if interface.opcodes.iter().any(|o| o.name == opcode.name) {
+            return Err(error(format!(
+                "there's already an opcode called `{}`",
+                opcode.name
+            )));
+        }
+        if let Some(other) = interface
+            .opcodes
+            .iter()
+            .find(|o| o.kind == opcode.kind && o.id == opcode.id)
+        {
+            return Err(error(format!(
+                "`{}` has the same kind and ID as `{}`",
+                opcode.name, other.name
+            )));
+        }
+        interface.opcodes.push(opcode);
+    }
+    Ok(interface)
+}
+
+/// The longest line rustfmt leaves alone
+const MAX_WIDTH: usize = 100;
+
+/// Write a function signature the way rustfmt lays it out: on one line if it
+/// fits, and otherwise with one parameter per line.
+fn write_signature(out: &mut String, indent: &str, start: &str, params: &[String], end: &str) {
+    let line = format!("{}{}({}){}", indent, start, params.join(", "), end);
+    if line.len() <= MAX_WIDTH {
+        writeln!(out, "{}", line).unwrap();
+        return;
+    }
+    writeln!(out, "{}{}(", indent, start).unwrap();
+    for param in params {
+        writeln!(out, "{}    {},", indent, param).unwrap();
+    }
+    writeln!(out, "{}){}", indent, end).unwrap();
+}
+
+/// Write a call to `xous::send_message()` that sends `value`, preceded by
+/// `start` and followed by `end`, the way rustfmt lays it out.
+fn write_send(out: &mut String, start: &str, value: &str, end: &str) {
+    let line = format!(
+        "    {}xous::send_message(cid, {}.into()){}",
+        start, value, end
+    );
+    if line.len() <= MAX_WIDTH {
+        writeln!(out, "{}", line).unwrap();
+        return;
+    }
+    writeln!(out, "    {}xous::send_message(\n        cid,", start).unwrap();
+    writeln!(out, "        {}.into(),", value).unwrap();
+    match end.strip_prefix('.') {
+        Some(method) => writeln!(out, "    )\n    .{}", method).unwrap(),
+        None => writeln!(out, "    ){}", end).unwrap(),
+    }
+}
+
+fn write_doc(out: &mut String, doc: &[String], indent: &str) {
+    for line in doc {
+        if line.is_empty() {
+            writeln!(out, "{}///", indent).unwrap();
+        } else {
+            writeln!(out, "{}/// {}", indent, line).unwrap();
+        }
+    }
+}
+
+impl Interface {
+    /// Generate the Rust module for this interface. `source_name` is the
+    /// name of the IDL file, which is mentioned in the header.
+    pub fn generate(&self, source_name: &str) -> String {
+        let mut out = String::new();
+        writeln!(
+            out,
+            "// Generated by `idl-gen` from {}. Don't edit this file: change the IDL\n\
+             // and run `cargo xtask generate-idl` instead.",
+            source_name
+        )
+        .unwrap();
+        // Client functions go unused in the server, and `Server` in clients.
+        writeln!(out, "#![allow(dead_code)]\n").unwrap();
+        writeln!(out, "use core::convert::TryFrom;\n").unwrap();
+
+        if let Some((doc, name, value)) = &self.server_name {
+            write_doc(&mut out, doc, "");
+            writeln!(out, "pub const {}: &str = {:?};\n", name, value).unwrap();
+        }
+
+        self.generate_opcode(&mut out);
+        for opcode in &self.opcodes {
+            Self::generate_client(&mut out, opcode);
+        }
+        self.generate_server(&mut out);
+        self.generate_dispatch(&mut out);
+
+        // Drop the blank line after the last item
+        out.pop();
+        out
+    }
+
+    fn generate_opcode(&self, out: &mut String) {
+        writeln!(out, "#[derive(Debug, xous::Opcode)]").unwrap();
+        writeln!(out, "pub enum Opcode {{").unwrap();
+        for (i, opcode) in self.opcodes.iter().enumerate() {
+            if i > 0 {
+                writeln!(out).unwrap();
+            }
+            write_doc(out, &opcode.doc, "    ");
+            writeln!(
+                out,
+                "    #[opcode({}, id = {})]",
+                opcode.kind.name(),
+                opcode.id
+            )
+            .unwrap();
+            if !opcode.kind.is_scalar() {
+                writeln!(out, "    {}(xous::MemoryMessage),", opcode.name).unwrap();
+            } else if opcode.args.is_empty() {
+                writeln!(out, "    {},", opcode.name).unwrap();
+            } else {
+                let fields = vec!["usize"; opcode.args.len()].join(", ");
+                writeln!(out, "    {}({}),", opcode.name, fields).unwrap();
+            }
+        }
+        writeln!(out, "}}\n").unwrap();
+    }
+
+    fn generate_client(out: &mut String, opcode: &Opcode) {
+        let function = opcode.function_name();
+        let mut params = vec!["cid: xous::CID".to_owned()];
+        let value = if opcode.kind.is_scalar() {
+            params.extend(opcode.args.iter().map(|a| format!("{}: usize", a)));
+            if opcode.args.is_empty() {
+                format!("Opcode::{}", opcode.name)
+            } else {
+                format!("Opcode::{}({})", opcode.name, opcode.args.join(", "))
+            }
+        } else {
+            params.push("message: xous::MemoryMessage".to_owned());
+            format!("Opcode::{}(message)", opcode.name)
+        };
+
+        write_doc(out, &opcode.doc, "");
+        write_signature(
+            out,
+            "",
+            &format!("pub fn {}", function),
+            &params,
+            &format!(" -> Result<{}, xous::Error> {{", opcode.reply.rust_type()),
+        );
+        let (pattern, result) = match opcode.reply {
+            Reply::Nothing => {
+                write_send(out, "", &value, ".map(|_| ())");
+                writeln!(out, "}}\n").unwrap();
+                return;
+            }
+            Reply::Usize => ("Scalar1(value)", "value"),
+            Reply::Usize2 => ("Scalar2(value1, value2)", "(value1, value2)"),
+            Reply::U64 => (
+                "Scalar2(lower, upper)",
+                "lower as u64 | ((upper as u64) << 32)",
+            ),
+        };
+        write_send(out, "let response = ", &value, "?;");
+        writeln!(out, "    if let xous::Result::{} = response {{", pattern).unwrap();
+        writeln!(out, "        Ok({})", result).unwrap();
+        writeln!(out, "    }} else {{").unwrap();
+        writeln!(
+            out,
+            "        panic!(\"unexpected return value: {{:#?}}\", response);"
+        )
+        .unwrap();
+        writeln!(out, "    }}\n}}\n").unwrap();
+    }
+
+    fn generate_server(&self, out: &mut String) {
+        writeln!(
+            out,
+            "/// The methods a server provides to handle each opcode. `dispatch()` calls\n\
+             /// them, and sends whatever they return back to the client."
+        )
+        .unwrap();
+        writeln!(out, "pub trait Server {{").unwrap();
+        for (i, opcode) in self.opcodes.iter().enumerate() {
+            if i > 0 {
+                writeln!(out).unwrap();
+            }
+            write_doc(out, &opcode.doc, "    ");
+            if opcode.deferred {
+                if !opcode.doc.is_empty() {
+                    writeln!(out, "    ///").unwrap();
+                }
+                writeln!(
+                    out,
+                    "    /// The reply isn't sent when this returns. It must be sent to `sender` later."
+                )
+                .unwrap();
+            }
+            let mut params = vec!["&mut self".to_owned()];
+            if opcode.deferred {
+                params.push("sender: xous::MessageSender".to_owned());
+            } else if !opcode.kind.is_scalar() {
+                params.push("sender: xous::PID".to_owned());
+            }
+            match opcode.kind {
+                Kind::Borrow => params.push("message: &xous::MemoryMessage".to_owned()),
+                Kind::MutableBorrow | Kind::Move => {
+                    params.push("message: &mut xous::MemoryMessage".to_owned())
+                }
+                Kind::Scalar | Kind::BlockingScalar => {
+                    params.extend(opcode.args.iter().map(|a| format!("{}: usize", a)));
+                }
+            }
+            let ret = if opcode.deferred || opcode.reply == Reply::Nothing {
+                String::new()
+            } else {
+                format!(" -> {}", opcode.reply.rust_type())
+            };
+            write_signature(
+                out,
+                "    ",
+                &format!("fn {}", opcode.function_name()),
+                &params,
+                &format!("{};", ret),
+            );
+        }
+        writeln!(
+            out,
+            "\n    \
+             /// A process that was connected to the server has terminated. This is\n    \
+             /// only called once the server has asked for it with `xous::notify_disconnects()`.\n    \
+             fn client_disconnected(&mut self, _pid: xous::PID) {{}}"
+        )
+        .unwrap();
+        writeln!(out, "}}\n").unwrap();
+    }
+
+    fn generate_dispatch(&self, out: &mut String) {
+        writeln!(
+            out,
+            "/// Call the method of `server` that handles the message in `envelope`, and\n\
+             /// send its reply if the message expects one. Lent memory is returned when\n\
+             /// `envelope` is dropped.\n\
+             pub fn dispatch<S: Server>(\n    \
+                 server: &mut S,\n    \
+                 envelope: &mut xous::MessageEnvelope,\n\
+             ) -> Result<(), &'static str> {{"
+        )
+        .unwrap();
+        writeln!(
+            out,
+            "    if let xous::Message::Scalar(xous::ScalarMessage {{\n        \
+                     id: xous::CLIENT_DISCONNECTED_ID,\n        \
+                     arg1,\n        \
+                     ..\n    \
+                 }}) = envelope.body\n    \
+             {{\n        \
+                 let pid = xous::pid_from_usize(arg1).or(Err(\"invalid PID\"))?;\n        \
+                 server.client_disconnected(pid);\n        \
+                 return Ok(());\n    \
+             }}"
+        )
+        .unwrap();
+        writeln!(out, "    match Opcode::try_from(&envelope.body)? {{").unwrap();
+        for opcode in &self.opcodes {
+            let function = opcode.function_name();
+            let (pattern, args) = match opcode.kind {
+                // The handler works on the message in the envelope rather than
+                // the copy in the opcode, so that whatever it changes is sent
+                // back with the memory.
+                Kind::Borrow | Kind::MutableBorrow | Kind::Move => {
+                    let (variant, reference) = match opcode.kind {
+                        Kind::Borrow => ("Borrow", "&"),
+                        Kind::MutableBorrow => ("MutableBorrow", "&mut "),
+                        _ => ("Move", "&mut "),
+                    };
+                    writeln!(out, "        Opcode::{}(_) => {{", opcode.name).unwrap();
+                    writeln!(
+                        out,
+                        "            let sender = envelope.sender.pid().ok_or(\"no sender\")?;"
+                    )
+                    .unwrap();
+                    writeln!(
+                        out,
+                        "            if let xous::Message::{}(message) = {}envelope.body {{",
+                        variant, reference
+                    )
+                    .unwrap();
+                    writeln!(out, "                server.{}(sender, message);", function).unwrap();
+                    writeln!(out, "            }}\n        }}").unwrap();
+                    continue;
+                }
+                Kind::Scalar | Kind::BlockingScalar => {
+                    let pattern = if opcode.args.is_empty() {
+                        format!("Opcode::{}", opcode.name)
+                    } else {
+                        format!("Opcode::{}({})", opcode.name, opcode.args.join(", "))
+                    };
+                    let mut args = opcode.args.clone();
+                    if opcode.deferred {
+                        args.insert(0, "envelope.sender".to_owned());
+                    }
+                    (pattern, args.join(", "))
+                }
+            };
+            let call = format!("server.{}({})", function, args);
+
+            if opcode.kind != Kind::BlockingScalar || opcode.deferred {
+                let arm = format!("        {} => {},", pattern, call);
+                if arm.len() <= MAX_WIDTH {
+                    writeln!(out, "{}", arm).unwrap();
+                } else {
+                    writeln!(
+                        out,
+                        "        {} => {{\n            {}\n        }}",
+                        pattern, call
+                    )
+                    .unwrap();
+                }
+                continue;
+            }
+            let reply = match opcode.reply {
+                Reply::Nothing => {
+                    writeln!(out, "        {} => {{", pattern).unwrap();
+                    writeln!(out, "            {};", call).unwrap();
+                    "xous::return_scalar(envelope.sender, 0)".to_owned()
+                }
+                Reply::Usize => {
+                    writeln!(out, "        {} => {{", pattern).unwrap();
+                    writeln!(out, "            let value = {};", call).unwrap();
+                    "xous::return_scalar(envelope.sender, value)".to_owned()
+                }
+                Reply::Usize2 => {
+                    writeln!(out, "        {} => {{", pattern).unwrap();
+                    writeln!(out, "            let (value1, value2) = {};", call).unwrap();
+                    "xous::return_scalar2(envelope.sender, value1, value2)".to_owned()
+                }
+                Reply::U64 => {
+                    writeln!(out, "        {} => {{", pattern).unwrap();
+                    writeln!(out, "            let value = {};", call).unwrap();
+                    "xous::return_scalar2(\n                \
+                         envelope.sender,\n                \
+                         (value & 0xffff_ffff) as usize,\n                \
+                         (value >> 32) as usize,\n            \
+                     )"
+                    .to_owned()
+                }
+            };
+            // Lay the call out the way rustfmt would, so the output is left alone.
+            let or_err = ".or(Err(\"couldn't send reply\"))?;";
+            if reply.contains('\n') {
+                writeln!(out, "            {}\n            {}", reply, or_err).unwrap();
+            } else if 12 + reply.len() + or_err.len() <= MAX_WIDTH {
+                writeln!(out, "            {}{}", reply, or_err).unwrap();
+            } else {
+                writeln!(out, "            {}\n                {}", reply, or_err).unwrap();
+            }
+            writeln!(out, "        }}").unwrap();
+        }
+        writeln!(out, "    }}\n    Ok(())\n}}\n").unwrap();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn error_of(source: &str) -> IdlError {
+        match parse(source) {
+            Ok(interface) => panic!("expected an error, got {:?}", interface),
+            Err(e) => e,
+        }
+    }
+
+    #[test]
+    fn parses_every_kind_of_opcode() {
+        let interface = parse(
+            "# A comment\n\
+             /// The name\n\
+             server SERVER_NAME_TEST = \"test-server\"\n\
+             \n\
+             /// Reset it\n\
+             ///\n\
+             /// Really\n\
+             scalar Reset = 1\n\
+             blocking_scalar ElapsedMs = 0x1337 -> u64\n\
+             deferred blocking_scalar SleepMs(ms: usize) = 3\n\
+             blocking_scalar Add(a: usize, b : usize) = 5 -> ( usize , usize )\n\
+             borrow Print = 1_000\n\
+             mutable_borrow Lookup = 4\n\
+             move Store = 4\n",
+        )
+        .unwrap();
+
+        let (doc, name, value) = interface.server_name.unwrap();
+        assert_eq!(doc, ["The name"]);
+        assert_eq!(
+            (name.as_str(), value.as_str()),
+            ("SERVER_NAME_TEST", "test-server")
+        );
+
+        let opcodes = &interface.opcodes;
+        assert_eq!(opcodes.len(), 7);
+        assert_eq!(opcodes[0].doc, ["Reset it", "", "Really"]);
+        assert_eq!((opcodes[0].kind, opcodes[0].id), (Kind::Scalar, 1));
+        assert_eq!((opcodes[1].id, opcodes[1].reply), (0x1337, Reply::U64));
+        assert!(opcodes[1].doc.is_empty());
+        assert!(opcodes[2].deferred);
+        assert_eq!(opcodes[2].args, ["ms"]);
+        assert_eq!(opcodes[3].args, ["a", "b"]);
+        assert_eq!(opcodes[3].reply, Reply::Usize2);
+        assert_eq!((opcodes[4].kind, opcodes[4].id), (Kind::Borrow, 1000));
+        assert_eq!(opcodes[5].kind, Kind::MutableBorrow);
+        assert_eq!(opcodes[6].kind, Kind::Move);
+    }
+
+    #[test]
+    fn snake_case_keeps_acronyms_together() {
+        assert_eq!(snake_case("Reset"), "reset");
+        assert_eq!(snake_case("ElapsedMs"), "elapsed_ms");
+        assert_eq!(snake_case("GetSSIDList"), "get_ssid_list");
+        assert_eq!(snake_case("ReadIMU"), "read_imu");
+        assert_eq!(snake_case("Flash2Program"), "flash2_program");
+    }
+
+    #[test]
+    fn rejects_invalid_opcodes() {
+        let cases = [
+            ("scalar Reset", "expected `= <id>`"),
+            ("scalar Reset = one", "isn't a valid ID"),
+            ("blocking Reset = 1", "unknown opcode kind"),
+            ("scalar = 1", "expected an opcode kind followed by a name"),
+            ("scalar reset = 1", "isn't a valid opcode name"),
+            ("scalar Reset(a: usize = 1", "expected `)`"),
+            ("scalar Reset(a: u32) = 1", "must be a `usize`"),
+            ("scalar Reset(2a: usize) = 1", "isn't a valid argument name"),
+            (
+                "scalar Reset(a: usize, a: usize) = 1",
+                "more than one argument",
+            ),
+            (
+                "scalar Reset(a: usize, b: usize, c: usize, d: usize, e: usize) = 1",
+                "at most four",
+            ),
+            ("borrow Print(a: usize) = 1", "can't have arguments"),
+            (
+                "scalar Reset = 1 -> usize",
+                "only `blocking_scalar` opcodes can reply",
+            ),
+            ("blocking_scalar Reset = 1 -> u32", "unknown reply type"),
+            ("deferred scalar Reset = 1", "can be `deferred`"),
+            ("server 1NAME = \"name\"", "expected a constant name"),
+            ("server NAME = name", "quoted string"),
+        ];
+        for (source, message) in cases.iter() {
+            let error = error_of(source);
+            assert_eq!(error.line, 1, "{}", source);
+            assert!(error.message.contains(message), "{}: {}", source, error);
+        }
+    }
+
+    #[test]
+    fn rejects_duplicates() {
+        let error = error_of("scalar Reset = 1\nblocking_scalar Reset = 2");
+        assert_eq!(error.line, 2);
+        assert!(error.message.contains("already an opcode called `Reset`"));
+
+        let error = error_of("scalar Reset = 1\n\nscalar Clear = 1");
+        assert_eq!(error.line, 3);
+        assert!(error.message.contains("same kind and ID as `Reset`"));
+
+        let error = error_of("server A = \"a\"\nserver B = \"b\"");
+        assert_eq!(error.line, 2);
+        assert!(error.message.contains("already given"));
+    }
+
+    #[test]
+    fn rejects_keywords() {
+        for name in ["Move", "Loop", "Type", "Self", "Async"].iter() {
+            let error = error_of(&format!("scalar {} = 1", name));
+            assert!(
+                error.message.contains("is a keyword"),
+                "{}: {}",
+                name,
+                error
+            );
+        }
+        assert!(parse("scalar Moved = 1\nscalar SelfTest = 2").is_ok());
+
+        let error = error_of("scalar Reset(type: usize) = 1");
+        assert!(error.message.contains("`type` is a keyword"), "{}", error);
+        for arg in RESERVED_ARGS.iter() {
+            let error = error_of(&format!("scalar Reset({}: usize) = 1", arg));
+            assert!(
+                error.message.contains("used by the generated code"),
+                "{}",
+                error
+            );
+        }
+    }
+
+    #[test]
+    fn long_lines_are_wrapped() {
+        let code = parse(
+            "scalar SetStringClipping(min_x: usize, min_y: usize, max_x: usize, max_y: usize) = 1\n\
+             blocking_scalar QueryTheWholeThing(first: usize, second: usize) = 2 -> (usize, usize)\n\
+             deferred blocking_scalar WaitForAllOfIt(first: usize, second: usize, third: usize) = 3\n",
+        )
+        .unwrap()
+        .generate("test.idl");
+        assert!(code.lines().all(|line| line.len() <= MAX_WIDTH), "{}", code);
+    }
+
+    #[test]
+    fn dispatch_lends_the_message_in_the_envelope() {
+        let code = parse(
+            "borrow Print = 1\n\
+             mutable_borrow Lookup = 2\n\
+             move Store = 3\n\
+             deferred blocking_scalar Wait = 4\n",
+        )
+        .unwrap()
+        .generate("test.idl");
+
+        assert!(code.contains("envelope: &mut xous::MessageEnvelope,"));
+        assert!(
+            code.contains("fn print(&mut self, sender: xous::PID, message: &xous::MemoryMessage);")
+        );
+        assert!(code.contains(
+            "fn lookup(&mut self, sender: xous::PID, message: &mut xous::MemoryMessage);"
+        ));
+        assert!(code.contains("fn wait(&mut self, sender: xous::MessageSender);"));
+        assert!(code.contains("if let xous::Message::Borrow(message) = &envelope.body {"));
+        assert!(
+            code.contains("if let xous::Message::MutableBorrow(message) = &mut envelope.body {")
+        );
+        assert!(code.contains("if let xous::Message::Move(message) = &mut envelope.body {"));
+        assert!(code.contains("Opcode::Wait => server.wait(envelope.sender),"));
+        // Nothing replies to a memory message except the envelope.
+        assert!(!code.contains("return_memory"));
+    }
+
+    #[test]
+    fn disconnected_pid_is_not_truncated() {
+        let code = parse("scalar Reset = 1").unwrap().generate("test.idl");
+        assert!(code.contains("let pid = xous::pid_from_usize(arg1).or(Err(\"invalid PID\"))?;"));
+        assert!(!code.contains("as u8"));
+    }
+
+    /// The generated code is checked in, so make sure nobody has changed an
+    /// IDL file without running `cargo xtask generate-idl` afterwards.
+    #[test]
+    fn checked_in_code_matches_idl() {
+        let services = std::path::Path::new(env!("CARGO_MANIFEST_DIR")).join("../services");
+        let mut checked = 0;
+        for service in std::fs::read_dir(services).unwrap() {
+            let src = service.unwrap().path().join("src");
+            if !src.is_dir() {
+                continue;
+            }
+            for file in std::fs::read_dir(src).unwrap() {
+                let idl = file.unwrap().path();
+                if idl.extension().and_then(|ext| ext.to_str()) != Some("idl") {
+                    continue;
+                }
+                let source = std::fs::read_to_string(&idl).unwrap();
+                let name = idl.file_name().unwrap().to_string_lossy().into_owned();
+                let code = parse(&source).unwrap().generate(&name);
+                let checked_in = std::fs::read_to_string(idl.with_extension("rs")).unwrap();
+                assert!(
+                    code == checked_in,
+                    "{} is out of date, run `cargo xtask generate-idl`",
+                    idl.with_extension("rs").display()
+                );
+                checked += 1;
+            }
+        }
+        assert!(checked > 0, "no IDL files were found");
+    }
+}
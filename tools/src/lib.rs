@@ -4,3 +4,4 @@ extern crate log;
 pub mod tags;
 pub mod utils;
 pub mod elf;
+pub mod idl;
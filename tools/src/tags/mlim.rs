@@ -0,0 +1,54 @@
+use crate::xous_arguments::{XousArgument, XousArgumentCode, XousSize};
+use std::fmt;
+use std::io;
+
+/// Limits on how much memory each process may own, in bytes. A limit of 0
+/// means that the process may use as much memory as is free.
+#[derive(Debug, Default)]
+pub struct MemoryLimits {
+    /// Applies to every process that isn't given a limit of its own
+    default: u32,
+
+    /// Limits for the initial processes, in the same order as their `IniE` tags
+    init: Vec<u32>,
+}
+
+impl fmt::Display for MemoryLimits {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "    Memory limits: default {}", self.default)?;
+        for (index, limit) in self.init.iter().enumerate() {
+            write!(f, ", init {} {}", index, limit)?;
+        }
+        writeln!(f)
+    }
+}
+
+impl MemoryLimits {
+    pub fn new(default: u32) -> MemoryLimits {
+        MemoryLimits {
+            default,
+            init: vec![],
+        }
+    }
+
+    /// Set the limit of the next initial process.
+    pub fn add_init(&mut self, limit: u32) {
+        self.init.push(limit);
+    }
+}
+
+impl XousArgument for MemoryLimits {
+    fn code(&self) -> XousArgumentCode {
+        u32::from_le_bytes(*b"MLim")
+    }
+    fn length(&self) -> XousSize {
+        4 + self.init.len() as XousSize * 4
+    }
+    fn serialize(&self, output: &mut dyn io::Write) -> io::Result<usize> {
+        let mut written = output.write(&self.default.to_le_bytes())?;
+        for limit in &self.init {
+            written += output.write(&limit.to_le_bytes())?;
+        }
+        Ok(written)
+    }
+}
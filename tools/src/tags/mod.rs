@@ -1,4 +1,6 @@
 pub mod bflg;
 pub mod inie;
 pub mod memory;
+pub mod mlim;
+pub mod ppri;
 pub mod xkrn;
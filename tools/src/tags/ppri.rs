@@ -0,0 +1,48 @@
+use crate::xous_arguments::{XousArgument, XousArgumentCode, XousSize};
+use std::fmt;
+use std::io;
+
+/// The scheduling priority that each initial process starts with. A process
+/// can't raise itself any higher than this.
+#[derive(Debug, Default)]
+pub struct InitPriorities {
+    /// Priorities for the initial processes, in the same order as their `IniE` tags
+    init: Vec<u8>,
+}
+
+impl fmt::Display for InitPriorities {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "    Priorities:")?;
+        for (index, priority) in self.init.iter().enumerate() {
+            write!(f, " init {} {}", index, priority)?;
+        }
+        writeln!(f)
+    }
+}
+
+impl InitPriorities {
+    pub fn new() -> InitPriorities {
+        Default::default()
+    }
+
+    /// Set the priority of the next initial process.
+    pub fn add_init(&mut self, priority: u8) {
+        self.init.push(priority);
+    }
+}
+
+impl XousArgument for InitPriorities {
+    fn code(&self) -> XousArgumentCode {
+        u32::from_le_bytes(*b"PPri")
+    }
+    fn length(&self) -> XousSize {
+        self.init.len() as XousSize * 4
+    }
+    fn serialize(&self, output: &mut dyn io::Write) -> io::Result<usize> {
+        let mut written = 0;
+        for priority in &self.init {
+            written += output.write(&(*priority as u32).to_le_bytes())?;
+        }
+        Ok(written)
+    }
+}
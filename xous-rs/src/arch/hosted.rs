@@ -4,6 +4,7 @@ use std::convert::TryInto;
 use std::io::{Read, Write};
 use std::mem::size_of;
 use std::net::{IpAddr, Ipv4Addr, SocketAddr, TcpStream, ToSocketAddrs};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
 use std::sync::{Arc, Mutex};
 use std::thread_local;
 
@@ -44,7 +45,18 @@ where
         }
     }
 }
-pub struct ProcessHandleAsThread(std::thread::JoinHandle<()>);
+pub struct ProcessHandleAsThread(std::thread::JoinHandle<()>, PID);
+
+impl ProcessHandleAsThread {
+    /// The PID that the kernel gave this process.
+    pub fn pid(&self) -> PID {
+        self.1
+    }
+}
+
+/// Set once this program starts running processes as threads, at which point
+/// a process can no longer end by exiting the whole program.
+static PROCESSES_AS_THREADS: AtomicBool = AtomicBool::new(false);
 
 /// If no connection exists, create a new connection to the server. This means
 /// our parent PID will be PID1. Otherwise, reuse the same connection.
@@ -75,14 +87,17 @@ where
     F: FnOnce() + Send + 'static,
 {
     let server_address = xous_address();
+    PROCESSES_AS_THREADS.store(true, Ordering::Relaxed);
 
     let f = args.main;
-    let thread_main = std::thread::Builder::new()
+    let mut thread_main = std::thread::Builder::new()
         .name(args.name)
         .spawn(move || {
             set_xous_address(server_address);
             THREAD_ID.with(|tid| *tid.borrow_mut() = 1);
             PROCESS_ID.with(|p| *p.borrow_mut() = pid);
+            // Any children this process creates will connect with the same key.
+            PROCESS_KEY.with(|pk| *pk.borrow_mut() = Some(init.key));
             XOUS_SERVER_CONNECTION.with(|xsc| {
                 let mut xsc = xsc.borrow_mut();
                 match xous_connect_impl(server_address, &init.key) {
@@ -101,14 +116,20 @@ where
         .unwrap()
         .unwrap();
 
-    Ok(ProcessHandleAsThread(thread_main.0))
+    // The process is waited for as a whole, so its main thread isn't detached.
+    let handle = thread_main.handle.take().unwrap();
+    Ok(ProcessHandleAsThread(handle, pid))
 }
 
 pub fn wait_process_as_thread(joiner: ProcessHandleAsThread) -> crate::SysCallResult {
-    joiner.0.join().map(|_| Result::Ok).map_err(|_x| {
-        // panic!("wait error: {:?}", x);
-        crate::Error::InternalError
-    })
+    match joiner.0.join() {
+        Ok(()) => Ok(Result::Ok),
+        Err(payload) if payload.is::<ProcessExit>() => Ok(Result::Ok),
+        Err(_x) => {
+            // panic!("wait error: {:?}", x);
+            Err(crate::Error::InternalError)
+        }
+    }
 }
 
 pub struct ProcessArgs {
@@ -126,7 +147,14 @@ impl ProcessArgs {
 }
 
 #[derive(Debug)]
-pub struct ProcessHandle(std::process::Child);
+pub struct ProcessHandle(std::process::Child, PID);
+
+impl ProcessHandle {
+    /// The PID that the kernel gave this process.
+    pub fn pid(&self) -> PID {
+        self.1
+    }
+}
 
 /// If no connection exists, create a new connection to the server. This means
 /// our parent PID will be PID1. Otherwise, reuse the same connection.
@@ -169,28 +197,48 @@ pub fn create_process_post(
         .env("XOUS_PROCESS_NAME", process_name_env)
         .env("XOUS_PROCESS_KEY", process_key_env)
         .spawn()
-        .map(ProcessHandle)
+        .map(|child| ProcessHandle(child, pid))
         .map_err(|_| {
             // eprintln!("couldn't start command: {}", e);
             crate::Error::InternalError
         })
 }
 
-pub fn wait_process(mut joiner: ProcessHandle) -> crate::SysCallResult {
+pub fn wait_process(mut joiner: ProcessHandle) -> core::result::Result<usize, crate::Error> {
     joiner
         .0
         .wait()
         .or(Err(crate::Error::InternalError))
         .and_then(|e| {
-            if e.success() {
-                Ok(crate::Result::Ok)
-            } else {
-                Err(crate::Error::UnknownError)
-            }
+            // There's no exit code if the process was killed by a signal.
+            e.code()
+                .map(|code| code as usize)
+                .ok_or(crate::Error::UnknownError)
         })
 }
 
-pub struct WaitHandle<T>(std::thread::JoinHandle<T>);
+pub struct WaitHandle<T> {
+    handle: Option<std::thread::JoinHandle<T>>,
+    tid: TID,
+}
+
+/// Nobody is going to wait for the thread, so let the kernel free its slot as
+/// soon as it exits. Dropping the `JoinHandle` detaches the `std` thread.
+impl<T> Drop for WaitHandle<T> {
+    fn drop(&mut self) {
+        if self.handle.is_some() {
+            crate::detach_thread(self.tid, None).ok();
+        }
+    }
+}
+
+/// The payload that `exit_thread()` unwinds with. Unwinding is the only way to
+/// stop a `std` thread, and it lets the thread's destructors run.
+struct ThreadExit(usize);
+
+/// The payload that `terminate_process()` unwinds with when processes are
+/// running as threads. Only the thread that called it is stopped.
+struct ProcessExit;
 
 #[derive(Clone)]
 struct ServerConnection {
@@ -311,13 +359,14 @@ where
 pub fn create_thread_simple_post<T, U>(
     f: fn(T) -> U,
     arg: T,
+    thread_init: ThreadInit,
     thread_id: TID,
 ) -> core::result::Result<WaitHandle<U>, crate::Error>
 where
     T: Send + 'static,
     U: Send + 'static,
 {
-    create_thread_post(move || f(arg), thread_id)
+    create_thread_post(move || f(arg), thread_init, thread_id)
 }
 
 pub fn create_thread_pre<F, T>(_f: &F) -> core::result::Result<ThreadInit, crate::Error>
@@ -331,6 +380,7 @@ where
 
 pub fn create_thread_post<F, U>(
     f: F,
+    _thread_init: ThreadInit,
     thread_id: TID,
 ) -> core::result::Result<WaitHandle<U>, crate::Error>
 where
@@ -342,26 +392,63 @@ where
     let server_connection =
         XOUS_SERVER_CONNECTION.with(|xsc| xsc.borrow().as_ref().unwrap().clone());
     let process_id = PROCESS_ID.with(|pid| *pid.borrow());
+    let process_key = PROCESS_KEY.with(|pk| *pk.borrow());
     let call_for_thread = CALL_FOR_THREAD.with(|cft| cft.borrow().clone());
     Ok(std::thread::Builder::new()
         .spawn(move || {
             set_xous_address(server_address);
             THREAD_ID.with(|tid| *tid.borrow_mut() = thread_id);
             PROCESS_ID.with(|pid| *pid.borrow_mut() = process_id);
+            PROCESS_KEY.with(|pk| *pk.borrow_mut() = process_key);
             XOUS_SERVER_CONNECTION.with(|xsc| *xsc.borrow_mut() = Some(server_connection));
             CALL_FOR_THREAD.with(|cft| *cft.borrow_mut() = call_for_thread);
-            f()
+            crate::tls::thread_started(thread_id);
+
+            // Tell the kernel the thread has exited, unless it panicked.
+            let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(f));
+            let code = match &result {
+                Ok(_) => Some(0),
+                Err(payload) => payload.downcast_ref::<ThreadExit>().map(|exit| exit.0),
+            };
+            if let Some(code) = code {
+                crate::rsyscall(crate::SysCall::ExitThread(code)).ok();
+            }
+            result.unwrap_or_else(|payload| std::panic::resume_unwind(payload))
+        })
+        .map(|handle| WaitHandle {
+            handle: Some(handle),
+            tid: thread_id,
         })
-        .map(WaitHandle)
         .map_err(|_| crate::Error::InternalError)?)
 }
 
-pub fn wait_thread<T>(joiner: WaitHandle<T>) -> crate::SysCallResult {
-    joiner
-        .0
-        .join()
-        .map(|_| Result::Ok)
-        .map_err(|_| crate::Error::InternalError)
+pub fn create_thread_failed(_thread_init: ThreadInit) {}
+
+pub fn wait_thread<T>(mut joiner: WaitHandle<T>) -> core::result::Result<T, crate::Error> {
+    let handle = joiner.handle.take().unwrap();
+    match handle.join() {
+        Ok(result) => {
+            crate::join_thread(joiner.tid)?;
+            Ok(result)
+        }
+        Err(payload) if payload.is::<ThreadExit>() => {
+            crate::join_thread(joiner.tid)?;
+            Err(crate::Error::ThreadNotAvailable)
+        }
+        Err(_) => Err(crate::Error::InternalError),
+    }
+}
+
+pub fn exit_thread(code: usize) -> ! {
+    std::panic::resume_unwind(Box::new(ThreadExit(code)))
+}
+
+pub fn terminate_process(exit_code: usize) -> ! {
+    crate::rsyscall(crate::SysCall::TerminateProcess(exit_code)).ok();
+    if PROCESSES_AS_THREADS.load(Ordering::Relaxed) {
+        std::panic::resume_unwind(Box::new(ProcessExit))
+    }
+    std::process::exit(exit_code as i32)
 }
 
 pub fn ensure_connection() -> core::result::Result<(), crate::Error> {
@@ -443,18 +530,32 @@ pub fn _xous_syscall(
             let mut xsc_borrowed = xsc.borrow_mut();
             let xsc_asmut = xsc_borrowed.as_mut().expect("not connected to server (did you forget to create a thread with xous::create_thread()?)");
             loop {
-                _xous_syscall_to(
-                    nr,
-                    a1,
-                    a2,
-                    a3,
-                    a4,
-                    a5,
-                    a6,
-                    a7,
-                    &call,
-                    &mut xsc_asmut.send.lock().unwrap(),
-                );
+                let mut send = xsc_asmut.send.lock().unwrap();
+
+                // The kernel can't see our memory, so the futex is compared here
+                // instead. Doing so while holding the send lock ensures that a
+                // wake from another thread can't reach the kernel first.
+                if let crate::SysCall::FutexWait(addr, expected) = &call {
+                    let futex = unsafe { &*(addr.get() as *const AtomicUsize) };
+                    if futex.load(Ordering::SeqCst) != *expected {
+                        CALL_FOR_THREAD.with(|cft| {
+                            cft.borrow().lock().unwrap().remove(&*tid.borrow());
+                        });
+                        *ret = Result::Ok;
+                        return;
+                    }
+                }
+
+                _xous_syscall_to(nr, a1, a2, a3, a4, a5, a6, a7, &call, &mut send);
+                drop(send);
+
+                // A thread or process that has exited is never sent a response.
+                if let crate::SysCall::ExitThread(_) | crate::SysCall::TerminateProcess(_) = &call {
+                    CALL_FOR_THREAD.with(|cft| {
+                        cft.borrow().lock().unwrap().remove(&*tid.borrow());
+                    });
+                    return;
+                }
                 _xous_syscall_result(ret, *tid.borrow(), xsc_asmut);
                 if *ret != Result::WouldBlock {
                     return;
@@ -585,7 +686,10 @@ fn _xous_syscall_result(ret: &mut Result, thread_id: TID, server_connection: &Se
 
         // If the original call contained memory, then ensure the memory we get back is correct.
         if let Some(mem) = call.memory() {
-            if call.is_borrow() || call.is_mutableborrow() {
+            // A borrow that failed (for example, because it timed out) doesn't send
+            // the buffer back, and the original contents are left as they were.
+            let returned_data = !matches!(response, Result::Error(_));
+            if returned_data && (call.is_borrow() || call.is_mutableborrow()) {
                 // Read the buffer back from the remote host.
                 use core::slice;
                 let mut data = unsafe { slice::from_raw_parts_mut(mem.as_mut_ptr(), mem.len()) };
@@ -676,6 +780,11 @@ fn _xous_syscall_to(
     }
 
     if let Err(e) = xsc.write_all(&pkt) {
+        // Threads may still be exiting after the system has been shut down,
+        // and there's nobody left to tell.
+        if let crate::SysCall::ExitThread(_) = call {
+            return;
+        }
         eprintln!("Server shut down: {}", e);
         std::process::exit(0);
     }
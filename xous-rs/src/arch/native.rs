@@ -1,12 +1,21 @@
 use crate::{MemoryAddress, MemoryRange, PID, TID};
-use core::convert::TryInto;
 
 mod mem;
 pub use mem::*;
 
+/// Describes a program to start with `create_process()`.
 #[derive(Debug, Clone, Copy, PartialEq)]
 pub struct ProcessArgs {
-    name: [u8; 16],
+    elf: MemoryRange,
+}
+
+impl ProcessArgs {
+    /// Start the program held in `elf`, which is a MiniElf image as described
+    /// in `ProcessInit`. The kernel copies what it needs out of the image, so
+    /// it may be reused as soon as `create_process()` returns.
+    pub fn new(elf: MemoryRange) -> ProcessArgs {
+        ProcessArgs { elf }
+    }
 }
 
 #[derive(Debug, Clone, Copy, PartialEq)]
@@ -52,13 +61,58 @@ impl ProcessKey {
     }
 }
 
+/// A MiniElf image that the kernel loads into a new address space. The image
+/// is lent to the kernel for the duration of the `CreateProcess` call, and is
+/// laid out as a series of little-endian words:
+///
+/// * The virtual address of the entrypoint
+/// * The number of sections
+/// * For each section, its virtual address, followed by its size with the
+///   section flags in the top byte. Sections must be sorted by address and
+///   must not overlap.
+/// * The contents of every section that doesn't have the `NOCOPY` flag, one
+///   after another
+///
+/// The flags are `WRITE` (1), `NOCOPY` (2) and `EXECUTE` (4), and sections
+/// with the `NOCOPY` flag are filled with zeroes.
 #[derive(Copy, Clone, Debug, PartialEq)]
 pub struct ProcessInit {
-    pub key: ProcessKey,
+    pub elf: MemoryRange,
+}
+
+/// How much stack each new thread is given.
+const THREAD_STACK_SIZE: usize = 131_072;
+
+/// Everything a new thread needs in order to start, along with somewhere for it
+/// to leave its return value. This lives at the top of the thread's stack. The
+/// result comes first so that its offset doesn't depend on `F`.
+#[repr(C)]
+struct ThreadStart<F, T> {
+    result: Option<T>,
+    main: Option<F>,
 }
 
-pub struct WaitHandle<T>(core::marker::PhantomData<T>);
-pub struct ProcessHandle(());
+pub struct WaitHandle<T> {
+    tid: TID,
+    stack: MemoryRange,
+    result: *mut Option<T>,
+}
+
+/// Nobody is going to wait for the thread, so let the kernel free its slot and
+/// unmap its stack once it exits. Whatever it returns is never dropped.
+impl<T> Drop for WaitHandle<T> {
+    fn drop(&mut self) {
+        crate::detach_thread(self.tid, Some(self.stack)).ok();
+    }
+}
+pub struct ProcessHandle(PID);
+
+impl ProcessHandle {
+    /// The PID that the kernel gave this process.
+    pub fn pid(&self) -> PID {
+        self.0
+    }
+}
 
 pub fn thread_to_args(call: usize, init: &ThreadInit) -> [usize; 8] {
     [
@@ -96,58 +150,108 @@ pub fn args_to_thread(
     })
 }
 
-pub fn create_thread_pre<F, T>(_f: &F) -> core::result::Result<ThreadInit, crate::Error>
+/// The entrypoint of every thread created by `create_thread()`. Returning
+/// from here exits the thread with a code of 0.
+fn thread_start<F, T>(data: usize) -> usize
+where
+    F: FnOnce() -> T,
+{
+    if let Ok(tid) = crate::current_tid() {
+        crate::tls::thread_started(tid);
+    }
+    let start = unsafe { &mut *(data as *mut ThreadStart<F, T>) };
+    let main = start.main.take().unwrap();
+    start.result = Some(main());
+    0
+}
+
+pub fn create_thread_pre<F, T>(f: &F) -> core::result::Result<ThreadInit, crate::Error>
 where
     F: FnOnce() -> T,
     F: Send + 'static,
     T: Send + 'static,
 {
-    todo!()
-    // Ok(ThreadInit {})
+    let stack = crate::map_memory(
+        None,
+        None,
+        THREAD_STACK_SIZE,
+        crate::MemoryFlags::R | crate::MemoryFlags::W | crate::MemoryFlags::RESERVE,
+    )?;
+
+    // Keep the stack pointer 16-byte aligned, as the ABI requires.
+    let start_size = (core::mem::size_of::<ThreadStart<F, T>>() + 15) & !15;
+    let start = (stack.as_ptr() as usize + stack.len() - start_size) as *mut ThreadStart<F, T>;
+
+    // The new thread may start running before the syscall returns, so it gets
+    // its copy of `f` now. The original is forgotten in `create_thread_post()`.
+    unsafe {
+        start.write(ThreadStart {
+            result: None,
+            main: Some(core::ptr::read(f)),
+        })
+    };
+    Ok(ThreadInit::new(
+        thread_start::<F, T>,
+        MemoryRange::new(stack.as_ptr() as usize, stack.len() - start_size)?,
+        MemoryAddress::new(start as usize),
+        [0; 12],
+    ))
 }
 
 pub fn create_thread_post<F, T>(
-    _f: F,
-    _thread_id: TID,
+    f: F,
+    thread_init: ThreadInit,
+    thread_id: TID,
 ) -> core::result::Result<WaitHandle<T>, crate::Error>
 where
     F: FnOnce() -> T,
     F: Send + 'static,
     T: Send + 'static,
 {
-    todo!()
-    // let server_address = xous_address();
-    // let server_connection =
-    //     XOUS_SERVER_CONNECTION.with(|xsc| xsc.borrow().as_ref().unwrap().clone());
-    // let process_id = PROCESS_ID.with(|pid| *pid.borrow());
-    // Ok(std::thread::Builder::new()
-    //     .spawn(move || {
-    //         set_xous_address(server_address);
-    //         THREAD_ID.with(|tid| *tid.borrow_mut() = thread_id);
-    //         PROCESS_ID.with(|pid| *pid.borrow_mut() = process_id);
-    //         XOUS_SERVER_CONNECTION.with(|xsc| *xsc.borrow_mut() = Some(server_connection));
-    //         f()
-    //     })
-    //     .map(WaitHandle)
-    //     .map_err(|_| crate::Error::InternalError)?)
-}
-
-pub fn wait_thread<T>(_joiner: WaitHandle<T>) -> crate::SysCallResult {
-    todo!()
-    // joiner
-    //     .0
-    //     .join()
-    //     .map(|_| Result::Ok)
-    //     .map_err(|_| crate::Error::InternalError)
+    core::mem::forget(f);
+    Ok(WaitHandle {
+        tid: thread_id,
+        stack: MemoryRange::new(thread_init.stack.as_ptr() as usize, THREAD_STACK_SIZE)?,
+        result: thread_init.arg.map(|x| x.get()).unwrap_or_default() as *mut Option<T>,
+    })
+}
+
+/// Free the stack that `create_thread_pre()` set up for a thread that the
+/// kernel didn't create. The copy of the closure on it is forgotten, since the
+/// caller still owns the original.
+pub fn create_thread_failed(thread_init: ThreadInit) {
+    if let Ok(stack) = MemoryRange::new(thread_init.stack.as_ptr() as usize, THREAD_STACK_SIZE) {
+        crate::unmap_memory(stack).ok();
+    }
+}
+
+/// Join the thread, then take its return value and free its stack.
+pub fn wait_thread<T>(joiner: WaitHandle<T>) -> core::result::Result<T, crate::Error> {
+    // The thread is joined here, so it must not be detached as well.
+    let mut joiner = core::mem::ManuallyDrop::new(joiner);
+    crate::join_thread(joiner.tid)?;
+    let result = unsafe { (*joiner.result).take() };
+    crate::unmap_memory(joiner.stack)?;
+    result.ok_or(crate::Error::ThreadNotAvailable)
+}
+
+pub fn exit_thread(code: usize) -> ! {
+    crate::rsyscall(crate::SysCall::ExitThread(code)).ok();
+    panic!("thread was resumed after exiting");
+}
+
+pub fn terminate_process(exit_code: usize) -> ! {
+    crate::rsyscall(crate::SysCall::TerminateProcess(exit_code)).ok();
+    panic!("process was resumed after terminating");
 }
 
 pub fn process_to_args(call: usize, init: &ProcessInit) -> [usize; 8] {
     [
         call,
-        u32::from_le_bytes(init.key.0[0..4].try_into().unwrap()) as _,
-        u32::from_le_bytes(init.key.0[4..8].try_into().unwrap()) as _,
-        u32::from_le_bytes(init.key.0[8..12].try_into().unwrap()) as _,
-        u32::from_le_bytes(init.key.0[12..16].try_into().unwrap()) as _,
+        init.elf.as_ptr() as usize,
+        init.elf.len(),
+        0,
+        0,
         0,
         0,
         0,
@@ -155,15 +259,17 @@ pub fn process_to_args(call: usize, init: &ProcessInit) -> [usize; 8] {
 }
 
 pub fn args_to_process(
-    _a1: usize,
-    _a2: usize,
+    a1: usize,
+    a2: usize,
     _a3: usize,
     _a4: usize,
     _a5: usize,
     _a6: usize,
     _a7: usize,
 ) -> core::result::Result<ProcessInit, crate::Error> {
-    todo!()
+    Ok(ProcessInit {
+        elf: MemoryRange::new(a1, a2)?,
+    })
 }
 
 pub fn create_thread_simple_pre<T, U>(
@@ -174,45 +280,46 @@ where
     T: Send + 'static,
     U: Send + 'static,
 {
-    let stack = crate::map_memory(
-        None,
-        None,
-        131_072,
-        crate::MemoryFlags::R | crate::MemoryFlags::W | crate::MemoryFlags::RESERVE,
-    )?;
-    let start = unsafe { core::mem::transmute(*f) };
-    let arg = unsafe { core::mem::transmute(arg) };
-    Ok(ThreadInit::new(start, stack, Some(arg), [0; 12]))
+    // As with `create_thread_pre()`, the new thread gets its own copy of
+    // `arg`, and the original is forgotten in `create_thread_simple_post()`.
+    let f = *f;
+    let arg = unsafe { core::ptr::read(arg) };
+    let main = move || f(arg);
+    let thread_init = create_thread_pre(&main);
+    core::mem::forget(main);
+    thread_init
 }
 
 pub fn create_thread_simple_post<T, U>(
     _f: fn(T) -> U,
-    _arg: T,
-    _thread_id: TID,
+    arg: T,
+    thread_init: ThreadInit,
+    thread_id: TID,
 ) -> core::result::Result<WaitHandle<U>, crate::Error>
 where
     T: Send + 'static,
     U: Send + 'static,
 {
-    Ok(WaitHandle(core::marker::PhantomData))
+    core::mem::forget(arg);
+    Ok(WaitHandle {
+        tid: thread_id,
+        stack: MemoryRange::new(thread_init.stack.as_ptr() as usize, THREAD_STACK_SIZE)?,
+        result: thread_init.arg.map(|x| x.get()).unwrap_or_default() as *mut Option<U>,
+    })
 }
 
-/// If no connection exists, create a new connection to the server. This means
-/// our parent PID will be PID1. Otherwise, reuse the same connection.
-pub fn create_process_pre(_args: &ProcessArgs) -> core::result::Result<ProcessInit, crate::Error> {
-    todo!()
+pub fn create_process_pre(args: &ProcessArgs) -> core::result::Result<ProcessInit, crate::Error> {
+    Ok(ProcessInit { elf: args.elf })
 }
 
 pub fn create_process_post(
     _args: ProcessArgs,
     _init: ProcessInit,
-    _pid: PID,
+    pid: PID,
 ) -> core::result::Result<ProcessHandle, crate::Error> {
-    todo!()
+    Ok(ProcessHandle(pid))
 }
 
-pub fn wait_process(_joiner: ProcessHandle) -> crate::SysCallResult {
-    loop {
-        crate::wait_event();
-    }
+pub fn wait_process(joiner: ProcessHandle) -> core::result::Result<usize, crate::Error> {
+    crate::join_process(joiner.0)
 }
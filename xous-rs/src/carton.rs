@@ -1,14 +1,27 @@
 //! A Carton is an object that wraps another object for shipping across the kernel
 //! boundary. Structs that are stored in Cartons can be sent as messages.
+//!
+//! `from_bytes()` copies its data into freshly-allocated pages. A buffer that
+//! is already made of whole pages can instead be wrapped with `from_buffer()`
+//! or `from_buffer_mut()`, and is then lent to the server without any copy.
+//!
+//! Lending isn't copy-on-write. Pages that are lent read-only can't be written
+//! by the lender until every borrower has returned them, which the `Carton`
+//! borrowing the buffer enforces for the thread that made it.
 
 use crate::{Error, MemoryMessage, MemoryRange, MemorySize, Message, CID};
+use core::marker::PhantomData;
 
 #[derive(Debug)]
 pub struct Carton<'a> {
     range: MemoryRange,
     valid: MemoryRange,
-    slice: &'a [u8],
+    /// Holds on to the buffer that was wrapped, if any. The bytes are only
+    /// ever reached through `valid`, since a server may write to them while
+    /// they are mutably lent, and that isn't allowed behind a `&[u8]`.
+    buffer: PhantomData<&'a mut [u8]>,
     should_drop: bool,
+    writable: bool,
 }
 
 impl<'a> Carton<'a> {
@@ -35,13 +48,64 @@ impl<'a> Carton<'a> {
         valid.size = MemorySize::new(bytes.len()).unwrap();
         Carton {
             range: new_mem,
-            slice: unsafe { core::slice::from_raw_parts_mut(new_mem.as_mut_ptr(), bytes.len()) },
             valid,
+            buffer: PhantomData,
             should_drop: true,
+            writable: true,
         }
     }
 
+    /// Wrap an existing buffer so that it can be lent without being copied.
+    /// While it is lent, the buffer can be read but not written, and it may
+    /// be lent to several servers at once.
+    ///
+    /// # Errors
+    ///
+    /// * **BadAlignment**: The buffer is empty, doesn't start on a page boundary, or isn't a whole number of pages
+    pub fn from_buffer(bytes: &'a [u8]) -> Result<Self, Error> {
+        let range = Self::page_range(bytes.as_ptr() as usize, bytes.len())?;
+        Ok(Carton {
+            range,
+            valid: range,
+            buffer: PhantomData,
+            should_drop: false,
+            writable: false,
+        })
+    }
+
+    /// Wrap an existing buffer so that it can be lent, either mutably or
+    /// immutably, without being copied. Anything the server writes while
+    /// it is mutably lent ends up in `bytes`.
+    ///
+    /// # Errors
+    ///
+    /// * **BadAlignment**: The buffer is empty, doesn't start on a page boundary, or isn't a whole number of pages
+    pub fn from_buffer_mut(bytes: &'a mut [u8]) -> Result<Self, Error> {
+        let range = Self::page_range(bytes.as_ptr() as usize, bytes.len())?;
+        Ok(Carton {
+            range,
+            valid: range,
+            buffer: PhantomData,
+            should_drop: false,
+            writable: true,
+        })
+    }
+
+    fn page_range(addr: usize, len: usize) -> Result<MemoryRange, Error> {
+        if len == 0 || addr & 4095 != 0 || len & 4095 != 0 {
+            return Err(Error::BadAlignment);
+        }
+        MemoryRange::new(addr, len)
+    }
+
+    /// Turn this Carton into a message that moves its memory to the server.
+    ///
+    /// # Panics
+    ///
+    /// If the Carton was made with `from_buffer()` or `from_buffer_mut()`,
+    /// since the buffer isn't the Carton's to give away.
     pub fn into_message(mut self, id: usize) -> MemoryMessage {
+        assert!(self.should_drop, "can't move a borrowed buffer");
         // Leak the memory buffer, since it will be taken care of
         // when the MemoryMessage is dropped.
         self.should_drop = false;
@@ -66,7 +130,14 @@ impl<'a> Carton<'a> {
     }
 
     /// Perform a mutable lend of this Carton to the server.
+    ///
+    /// # Errors
+    ///
+    /// * **ShareViolation**: The Carton was made with `from_buffer()`, and so may not be written
     pub fn lend_mut(&mut self, connection: CID, id: usize) -> Result<crate::Result, Error> {
+        if !self.writable {
+            return Err(Error::ShareViolation);
+        }
         let msg = MemoryMessage {
             id,
             buf: self.valid,
@@ -85,7 +156,8 @@ impl<'a> AsRef<MemoryRange> for Carton<'a> {
 
 impl<'a> AsRef<[u8]> for Carton<'a> {
     fn as_ref(&self) -> &[u8] {
-        &self.slice
+        // Nothing can be lending the buffer mutably while `self` is borrowed.
+        unsafe { core::slice::from_raw_parts(self.valid.as_ptr(), self.valid.len()) }
     }
 }
 
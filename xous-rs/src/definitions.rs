@@ -88,9 +88,20 @@ pub type CID = usize;
 /// Context ID
 pub type TID = usize;
 
+/// Shared memory region ID
+pub type SharedMemoryId = usize;
+
 /// Equivalent to a RISC-V Hart ID
 pub type CpuID = usize;
 
+/// Scheduling priority of a process. When more than one process is ready to
+/// run, the one with the highest priority is picked. Processes that share a
+/// priority take turns.
+pub type Priority = u8;
+
+/// The priority that every process starts out with
+pub const DEFAULT_PRIORITY: Priority = 128;
+
 #[derive(Debug, PartialEq, Copy, Clone)]
 pub struct MemoryRange {
     pub addr: MemoryAddress,
@@ -154,6 +165,7 @@ pub enum Error {
     InvalidThread = 20,
     InvalidPID = 21,
     UnknownError = 22,
+    AccessDenied = 23,
 }
 
 impl Error {
@@ -182,6 +194,7 @@ impl Error {
             19 => ShareViolation,
             20 => InvalidThread,
             21 => InvalidPID,
+            23 => AccessDenied,
             _ => UnknownError,
         }
     }
@@ -211,6 +224,7 @@ impl Error {
             InvalidThread => 20,
             InvalidPID => 21,
             UnknownError => usize::MAX,
+            AccessDenied => 23,
         }
     }
 }
@@ -310,6 +324,22 @@ impl ScalarMessage {
     }
 }
 
+/// The ID of the `BlockingScalar` message the kernel sends to a server that
+/// approves its own connections, whenever a process asks to connect to it.
+/// `arg1` holds the PID that would be connected, and the sender is the thread
+/// waiting on the answer. Respond with `approve_connection()`. Processes may
+/// not send messages with this ID themselves.
+pub const CONNECT_REQUEST_ID: MessageId = usize::MAX;
+
+/// The ID of the `Scalar` message the kernel sends to a server whenever a
+/// process that is connected to it terminates, if the server has asked for it
+/// with `notify_disconnects()`. `arg1` holds the PID of that process, so the
+/// server can forget anything it kept on the client's behalf. A notice that
+/// doesn't fit in the server's queue is dropped, and `arg2` holds the number
+/// of notices dropped since the last one was delivered. Processes may not send
+/// messages with this ID themselves.
+pub const CLIENT_DISCONNECTED_ID: MessageId = usize::MAX - 1;
+
 #[repr(usize)]
 #[derive(Debug, PartialEq)]
 pub enum Message {
@@ -346,6 +376,25 @@ impl Message {
         }
     }
 
+    /// The user-assigned ID of the message
+    pub fn id(&self) -> MessageId {
+        match self {
+            Message::MutableBorrow(mem) | Message::Borrow(mem) | Message::Move(mem) => mem.id,
+            Message::BlockingScalar(scalar) | Message::Scalar(scalar) => scalar.id,
+        }
+    }
+
+    /// The single `MessageKinds` flag that describes this message
+    pub fn kind(&self) -> MessageKinds {
+        match *self {
+            Message::MutableBorrow(_) => MessageKinds::MUTABLE_BORROW,
+            Message::Borrow(_) => MessageKinds::BORROW,
+            Message::Move(_) => MessageKinds::MOVE,
+            Message::Scalar(_) => MessageKinds::SCALAR,
+            Message::BlockingScalar(_) => MessageKinds::BLOCKING_SCALAR,
+        }
+    }
+
     pub fn message_type(&self) -> usize {
         match *self {
             Message::MutableBorrow(_) => 1,
@@ -357,6 +406,108 @@ impl Message {
     }
 }
 
+bitflags! {
+    /// The kinds of message that a connection is allowed to carry.
+    pub struct MessageKinds: u8 {
+        const SCALAR            = 0b0000_0001;
+        const BLOCKING_SCALAR   = 0b0000_0010;
+        const BORROW            = 0b0000_0100;
+        const MUTABLE_BORROW    = 0b0000_1000;
+        const MOVE              = 0b0001_0000;
+        /// Setting event bits with `signal_events()`
+        const EVENTS            = 0b0010_0000;
+    }
+}
+
+/// What a client may send over its connection to a server.  The kernel checks
+/// every message against these before it is delivered, and fails the send with
+/// `AccessDenied` if the message isn't allowed.
+///
+/// Message IDs are allowed if they fall within `first_id..=last_id`.  If
+/// `first_id` is greater than `last_id` the range wraps around, which makes it
+/// possible to allow everything except a block of IDs in the middle.  IDs
+/// larger than `u16::MAX` are only allowed if every ID is.
+#[derive(Debug, PartialEq, Eq, Copy, Clone)]
+pub struct ConnectionRights {
+    pub kinds: MessageKinds,
+    pub first_id: u16,
+    pub last_id: u16,
+}
+
+impl ConnectionRights {
+    /// Any kind of message with any ID.  This is what clients get unless the
+    /// server says otherwise.
+    pub const ALL: ConnectionRights = ConnectionRights {
+        kinds: MessageKinds::all(),
+        first_id: 0,
+        last_id: u16::MAX,
+    };
+
+    /// Allow only the given kinds of message, with any ID.
+    pub fn kinds(kinds: MessageKinds) -> ConnectionRights {
+        ConnectionRights {
+            kinds,
+            ..ConnectionRights::ALL
+        }
+    }
+
+    /// Allow any kind of message, except for those with an ID of `id`.
+    pub fn excluding_id(id: u16) -> ConnectionRights {
+        ConnectionRights {
+            kinds: MessageKinds::all(),
+            first_id: id.wrapping_add(1),
+            last_id: id.wrapping_sub(1),
+        }
+    }
+
+    fn allows_id(&self, id: MessageId) -> bool {
+        let span = self.last_id.wrapping_sub(self.first_id);
+        if id > u16::MAX as MessageId {
+            return span == u16::MAX;
+        }
+        (id as u16).wrapping_sub(self.first_id) <= span
+    }
+
+    /// Determine whether `message` may be sent over a connection with these rights
+    pub fn allows(&self, message: &Message) -> bool {
+        self.kinds.contains(message.kind()) && self.allows_id(message.id())
+    }
+
+    /// Determine whether event bits may be set over a connection with these
+    /// rights.  Events don't carry an ID, so only the kind is checked.
+    pub fn allows_events(&self) -> bool {
+        self.kinds.contains(MessageKinds::EVENTS)
+    }
+
+    /// Determine whether everything allowed by `other` is also allowed by
+    /// these rights.  A process may only hand out rights it holds itself.
+    pub fn includes(&self, other: &ConnectionRights) -> bool {
+        let span = self.last_id.wrapping_sub(self.first_id);
+        let other_span = other.last_id.wrapping_sub(other.first_id);
+        let first = other.first_id.wrapping_sub(self.first_id);
+        self.kinds.contains(other.kinds)
+            && (span == u16::MAX || (first <= span && other_span <= span - first))
+    }
+
+    pub fn to_usize(&self) -> [usize; 2] {
+        [
+            self.kinds.bits() as usize,
+            self.first_id as usize | (self.last_id as usize) << 16,
+        ]
+    }
+
+    pub fn from_usize(kinds: usize, ids: usize) -> Option<ConnectionRights> {
+        if kinds > u8::MAX as usize {
+            return None;
+        }
+        Some(ConnectionRights {
+            kinds: MessageKinds::from_bits(kinds as u8)?,
+            first_id: ids as u16,
+            last_id: (ids >> 16) as u16,
+        })
+    }
+}
+
 #[repr(C)]
 #[derive(Debug, PartialEq)]
 pub struct MessageEnvelope {
@@ -388,13 +539,18 @@ impl MessageEnvelope {
 #[cfg(not(feature = "forget-memory-messages"))]
 /// When a MessageEnvelope goes out of scope, return the memory.  It must either
 /// go to the kernel (in the case of a Move), or back to the borrowed process
-/// (in the case of a Borrow).  Ignore Scalar messages.
+/// (in the case of a Borrow).  A MutableBorrow also hands back its `offset` and
+/// `valid` fields.  Ignore Scalar messages.
 impl Drop for MessageEnvelope {
     fn drop(&mut self) {
         match &self.body {
-            Message::Borrow(x) | Message::MutableBorrow(x) => {
+            Message::Borrow(x) => {
                 crate::syscall::return_memory(self.sender, x.buf).expect("couldn't return memory")
             }
+            Message::MutableBorrow(x) => {
+                crate::syscall::return_memory_offset_valid(self.sender, x.buf, x.offset, x.valid)
+                    .expect("couldn't return memory")
+            }
             Message::Move(msg) => {
                 crate::syscall::unmap_memory(msg.buf).expect("couldn't free memory message")
             }
@@ -464,6 +620,29 @@ impl From<usize> for MemoryType {
     }
 }
 
+/// How much memory a process is using, as returned by `memory_stats()`.
+/// In hosted mode memory comes from the host rather than from the kernel, so
+/// `owned_pages` and `free_pages` are always 0.
+#[derive(Debug, PartialEq, Copy, Clone, Default)]
+pub struct MemoryStats {
+    /// Pages of RAM that belong to the process, including the pages the
+    /// kernel uses to keep track of it
+    pub owned_pages: usize,
+
+    /// The size of the process' heap, in bytes
+    pub heap_size: usize,
+
+    /// Pages that the process has lent to servers and not yet got back
+    pub lent_pages: usize,
+
+    /// Pages that clients have lent to the process' servers and not yet got
+    /// back, whether or not the servers have received them
+    pub lent_to_servers: usize,
+
+    /// Pages of RAM that don't belong to any process, across the whole system
+    pub free_pages: usize,
+}
+
 #[repr(C)]
 #[derive(Debug, PartialEq)]
 pub enum Result {
@@ -509,6 +688,16 @@ pub enum Result {
     /// The message was successful but no value was returned.
     None,
 
+    /// How much memory a process is using
+    MemoryStats(MemoryStats),
+
+    /// Memory that was mutably lent has come back, along with the offset and
+    /// the number of valid bytes that the server set
+    MemoryReturned(
+        Option<MemoryAddress>, /* offset */
+        Option<MemorySize>,    /* valid */
+    ),
+
     UnknownResult(usize, usize, usize, usize, usize, usize, usize),
 }
 
@@ -546,6 +735,26 @@ impl Result {
             }
             Result::WouldBlock => [16, 0, 0, 0, 0, 0, 0, 0],
             Result::None => [17, 0, 0, 0, 0, 0, 0, 0],
+            Result::MemoryStats(stats) => [
+                18,
+                stats.owned_pages,
+                stats.heap_size,
+                stats.lent_pages,
+                stats.lent_to_servers,
+                stats.free_pages,
+                0,
+                0,
+            ],
+            Result::MemoryReturned(offset, valid) => [
+                19,
+                offset.map(|o| o.get()).unwrap_or(0),
+                valid.map(|v| v.get()).unwrap_or(0),
+                0,
+                0,
+                0,
+                0,
+                0,
+            ],
             Result::UnknownResult(arg1, arg2, arg3, arg4, arg5, arg6, arg7) => {
                 [usize::MAX, *arg1, *arg2, *arg3, *arg4, *arg5, *arg6, *arg7]
             }
@@ -621,6 +830,14 @@ impl Result {
             ),
             16 => Result::WouldBlock,
             17 => Result::None,
+            18 => Result::MemoryStats(MemoryStats {
+                owned_pages: src[1],
+                heap_size: src[2],
+                lent_pages: src[3],
+                lent_to_servers: src[4],
+                free_pages: src[5],
+            }),
+            19 => Result::MemoryReturned(MemoryAddress::new(src[1]), MemorySize::new(src[2])),
             _ => Result::UnknownResult(src[0], src[1], src[2], src[3], src[4], src[5], src[6]),
         }
     }
@@ -0,0 +1,626 @@
+//! A small executor that runs `async` code on a single thread, so that one
+//! thread can wait on several servers, replies and timers at the same time.
+//!
+//! An `Executor` sleeps in `wait_events()` on a server of its own, and its
+//! wakers signal that server. `Executor::receive_message()` asks the kernel to
+//! signal it whenever a message is queued on the server being received from.
+//! Other processes may be asked to signal `Executor::server()` as well, which
+//! is how the ticktimer lets a sleep end without a thread waiting on it.
+//!
+//! A blocking message still holds up the thread that sends it until the reply
+//! comes back, so `Executor::send_message()` hands those to one of a few
+//! helper threads. A helper is started the first time it's needed, and then
+//! sends one message after another until the executor goes away. While every
+//! helper is busy, further blocking messages wait their turn.
+//!
+//! What the executor shares with its wakers and helpers lives in a page of its
+//! own. That page and the executor's server are only freed once the last of
+//! them has let go, so a waker that outlives its executor signals a server
+//! that nobody waits on rather than whichever one takes over its connection.
+//!
+//! ```ignore
+//! let executor = xous::executor::Executor::new()?;
+//! let timer = ticktimer_server::Timer::new(ticktimer, &executor)?;
+//! executor.block_on(xous::executor::join(
+//!     async {
+//!         loop {
+//!             let envelope = executor.receive_message(server).await?;
+//!             // ...
+//!         }
+//!     },
+//!     async {
+//!         loop {
+//!             timer.sleep_ms(1000).await?;
+//!             // ...
+//!         }
+//!     },
+//! ));
+//! ```
+
+use crate::sync::Mutex;
+use crate::{Error, MemoryFlags, MemoryRange, Message, MessageEnvelope, CID, SID};
+use core::future::Future;
+use core::pin::Pin;
+use core::sync::atomic::{fence, AtomicBool, AtomicUsize, Ordering};
+use core::task::{Context, Poll, RawWaker, RawWakerVTable, Waker};
+
+/// The event that wakes an executor up to poll its future again. Any other
+/// event set on `Executor::server()` wakes it just the same.
+pub const WAKE: usize = 1;
+
+/// How many blocking messages an executor can wait on at once
+const HELPER_COUNT: usize = 4;
+
+/// The size of the page that `Shared` lives in
+const PAGE_SIZE: usize = 4096;
+
+// A waker holds a reference to its executor's `Shared`.
+static VTABLE: RawWakerVTable = RawWakerVTable::new(clone_waker, wake, wake_by_ref, drop_waker);
+
+unsafe fn clone_waker(shared: *const ()) -> RawWaker {
+    (*(shared as *const Shared)).acquire();
+    RawWaker::new(shared, &VTABLE)
+}
+
+unsafe fn wake(shared: *const ()) {
+    wake_by_ref(shared);
+    drop_waker(shared);
+}
+
+unsafe fn wake_by_ref(shared: *const ()) {
+    (*(shared as *const Shared)).wake();
+}
+
+unsafe fn drop_waker(shared: *const ()) {
+    Shared::release(shared as *const Shared);
+}
+
+/// How far a blocking message handed to a helper thread has got
+enum State {
+    /// The helper has nothing to do
+    Idle,
+
+    /// The message is waiting to be sent, or waiting for its reply. The waker
+    /// is woken once the reply is in.
+    Sending(Option<Waker>),
+
+    /// The reply, waiting for the `SendMessage` to collect it
+    Replied(Result<crate::Result, Error>),
+
+    /// The `SendMessage` went away before the reply came back
+    Abandoned,
+}
+
+struct Job {
+    /// Whether the helper's thread has been started
+    started: bool,
+    connection: CID,
+
+    /// The message, until the helper takes it to send
+    message: Option<Message>,
+    state: State,
+}
+
+struct Helper {
+    /// Bumped whenever the helper is given a message, or the executor goes
+    /// away, so that its thread can sleep on it in between.
+    work: AtomicUsize,
+    job: Mutex<Job>,
+}
+
+impl Helper {
+    const fn new() -> Helper {
+        Helper {
+            work: AtomicUsize::new(0),
+            job: Mutex::new(Job {
+                started: false,
+                connection: 0,
+                message: None,
+                state: State::Idle,
+            }),
+        }
+    }
+
+    /// Wake the helper's thread, so that it looks at its job again.
+    fn notify(&self) {
+        self.work.fetch_add(1, Ordering::Release);
+        crate::futex_wake(&self.work, 1).ok();
+    }
+}
+
+/// What an executor shares with its wakers and helper threads
+struct Shared {
+    /// How many executors, wakers and helper threads refer to this. The last
+    /// one to let go frees it.
+    refs: AtomicUsize,
+
+    /// The page that this lives in
+    range: MemoryRange,
+    server: SID,
+    connection: CID,
+
+    /// Set once the `Executor` has been dropped, which lets the helpers exit
+    closed: AtomicBool,
+
+    /// Set when a blocking message found every helper busy
+    turned_away: AtomicBool,
+    helpers: [Helper; HELPER_COUNT],
+}
+
+impl Shared {
+    /// Set up a `Shared` in a page of its own, with one reference to it.
+    fn new(server: SID, connection: CID) -> Result<*const Shared, Error> {
+        debug_assert!(core::mem::size_of::<Shared>() <= PAGE_SIZE);
+        let range = crate::map_memory(None, None, PAGE_SIZE, MemoryFlags::R | MemoryFlags::W)?;
+        let shared = range.as_mut_ptr() as *mut Shared;
+        // Safe because the page is new, and large enough.
+        unsafe {
+            shared.write(Shared {
+                refs: AtomicUsize::new(1),
+                range,
+                server,
+                connection,
+                closed: AtomicBool::new(false),
+                turned_away: AtomicBool::new(false),
+                helpers: [Helper::new(), Helper::new(), Helper::new(), Helper::new()],
+            })
+        };
+        Ok(shared)
+    }
+
+    fn acquire(&self) {
+        self.refs.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Let go of a reference, and free everything once the last one is gone.
+    ///
+    /// # Safety
+    ///
+    /// The caller must hold a reference to `shared`, and not use it again.
+    unsafe fn release(shared: *const Shared) {
+        if (*shared).refs.fetch_sub(1, Ordering::Release) != 1 {
+            return;
+        }
+        fence(Ordering::Acquire);
+        let shared = (shared as *mut Shared).read();
+        crate::disconnect(shared.connection).ok();
+        crate::destroy_server(shared.server).ok();
+        crate::unmap_memory(shared.range).ok();
+    }
+
+    fn waker(&self) -> Waker {
+        self.acquire();
+        unsafe { Waker::from_raw(RawWaker::new(self as *const Shared as *const (), &VTABLE)) }
+    }
+
+    fn wake(&self) {
+        crate::signal_events(self.connection, WAKE).ok();
+    }
+
+    /// A helper has become free, so wake the executor if a message was left
+    /// waiting for one.
+    fn helper_freed(&self) {
+        if self.turned_away.swap(false, Ordering::SeqCst) {
+            self.wake();
+        }
+    }
+
+    /// Have a free helper send `message` on `connection`, and return its
+    /// index. If every helper is busy, `message` is left where it is and the
+    /// executor is woken once one of them is free.
+    fn hand_over(
+        &self,
+        connection: CID,
+        message: &mut Option<Message>,
+        waker: &Waker,
+    ) -> Result<Option<usize>, Error> {
+        if let Some(index) = self.try_hand_over(connection, message, waker)? {
+            return Ok(Some(index));
+        }
+        // Any helper freed from here on sees this, so look once more in case
+        // one was freed in the meantime.
+        self.turned_away.store(true, Ordering::SeqCst);
+        self.try_hand_over(connection, message, waker)
+    }
+
+    fn try_hand_over(
+        &self,
+        connection: CID,
+        message: &mut Option<Message>,
+        waker: &Waker,
+    ) -> Result<Option<usize>, Error> {
+        for (index, helper) in self.helpers.iter().enumerate() {
+            let mut job = helper.job.lock();
+            if !matches!(job.state, State::Idle) {
+                continue;
+            }
+            if !job.started {
+                self.start_helper(index)?;
+                job.started = true;
+            }
+            job.connection = connection;
+            job.message = message.take();
+            job.state = State::Sending(Some(waker.clone()));
+            drop(job);
+            helper.notify();
+            return Ok(Some(index));
+        }
+        Ok(None)
+    }
+
+    fn start_helper(&self, index: usize) -> Result<(), Error> {
+        self.acquire();
+        let shared = self as *const Shared as usize;
+        // The handle is dropped straight away, which detaches the thread.
+        match crate::create_thread(move || unsafe { run_helper(shared as *const Shared, index) }) {
+            Ok(_) => Ok(()),
+            Err(e) => {
+                // Safe because the caller still holds its own reference.
+                unsafe { Shared::release(self) };
+                Err(e)
+            }
+        }
+    }
+}
+
+/// Send each message that the helper at `index` is given, until the executor
+/// goes away.
+///
+/// # Safety
+///
+/// `shared` must hold a reference for this thread, which is let go of when it
+/// returns.
+unsafe fn run_helper(shared: *const Shared, index: usize) {
+    let helper = &(*shared).helpers[index];
+    loop {
+        let seen = helper.work.load(Ordering::Acquire);
+        let taken = {
+            let mut job = helper.job.lock();
+            let connection = job.connection;
+            job.message.take().map(|message| (connection, message))
+        };
+        let (connection, message) = match taken {
+            Some(taken) => taken,
+            None if (*shared).closed.load(Ordering::Acquire) => break,
+            None => {
+                if crate::futex_wait(&helper.work, seen).is_err() {
+                    crate::yield_slice();
+                }
+                continue;
+            }
+        };
+
+        let result = crate::send_message(connection, message);
+        let mut job = helper.job.lock();
+        match core::mem::replace(&mut job.state, State::Idle) {
+            State::Sending(waker) => {
+                job.state = State::Replied(result);
+                drop(job);
+                if let Some(waker) = waker {
+                    waker.wake();
+                }
+            }
+            // Nobody is waiting for the reply any more.
+            _ => {
+                drop(job);
+                (*shared).helper_freed();
+            }
+        }
+    }
+    Shared::release(shared);
+}
+
+/// Runs futures on the current thread, sleeping until something they are
+/// waiting on is ready.
+pub struct Executor {
+    shared: *const Shared,
+}
+
+impl Executor {
+    /// Create an executor, along with the server that it waits on.
+    ///
+    /// # Errors
+    ///
+    /// Anything that `create_server()`, `connect()` or `map_memory()` can
+    /// return
+    pub fn new() -> Result<Executor, Error> {
+        let server = crate::create_server()?;
+        let connection = match crate::connect(server) {
+            Ok(connection) => connection,
+            Err(e) => {
+                crate::destroy_server(server).ok();
+                return Err(e);
+            }
+        };
+        match Shared::new(server, connection) {
+            Ok(shared) => Ok(Executor { shared }),
+            Err(e) => {
+                crate::disconnect(connection).ok();
+                crate::destroy_server(server).ok();
+                Err(e)
+            }
+        }
+    }
+
+    fn shared(&self) -> &Shared {
+        // Safe because the executor holds a reference until it's dropped.
+        unsafe { &*self.shared }
+    }
+
+    /// The server that this executor waits on. Setting events on it wakes the
+    /// executor, so another process that is given this can wake it too.
+    pub fn server(&self) -> SID {
+        self.shared().server
+    }
+
+    /// Run `future` until it completes, and return its output.
+    pub fn block_on<F: Future>(&self, future: F) -> F::Output {
+        let mut future = future;
+        // Safe because `future` is never moved again. It's dropped in place
+        // when this function returns.
+        let mut future = unsafe { Pin::new_unchecked(&mut future) };
+        let waker = self.shared().waker();
+        let mut cx = Context::from_waker(&waker);
+        loop {
+            if let Poll::Ready(output) = future.as_mut().poll(&mut cx) {
+                return output;
+            }
+            // Wakers that fired while the future was being polled leave their
+            // event set, so this returns straight away rather than missing them.
+            crate::wait_events(self.shared().server).expect("executor server has gone away");
+        }
+    }
+
+    /// Wait for a message to arrive at `server`, which must belong to this
+    /// process. Only one `ReceiveMessage` should wait on a server at a time.
+    pub fn receive_message(&self, server: SID) -> ReceiveMessage<'_> {
+        ReceiveMessage {
+            executor: self,
+            server,
+            watching: false,
+        }
+    }
+
+    /// Send `message` on `connection` without blocking the executor. Messages
+    /// that don't block are sent the first time the future is polled.
+    /// Blocking ones are sent by a helper thread, and the future resolves to
+    /// the server's reply.
+    ///
+    /// Dropping the future before the reply comes back doesn't wait for it.
+    /// The helper throws the reply away, and any memory that the message lent
+    /// stays lent until then.
+    pub fn send_message(&self, connection: CID, message: Message) -> SendMessage<'_> {
+        SendMessage {
+            executor: self,
+            connection,
+            message: Some(message),
+            helper: None,
+        }
+    }
+}
+
+impl Drop for Executor {
+    fn drop(&mut self) {
+        let shared = self.shared();
+        shared.closed.store(true, Ordering::Release);
+        for helper in shared.helpers.iter() {
+            helper.notify();
+        }
+        // Safe because this is the executor's own reference.
+        unsafe { Shared::release(self.shared) };
+    }
+}
+
+/// A future that resolves to the next message sent to a server. It is made
+/// by `Executor::receive_message()`.
+pub struct ReceiveMessage<'a> {
+    executor: &'a Executor,
+    server: SID,
+
+    /// Whether the kernel has been asked to wake the executor when a message
+    /// arrives
+    watching: bool,
+}
+
+impl Future for ReceiveMessage<'_> {
+    type Output = Result<MessageEnvelope, Error>;
+
+    fn poll(mut self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<Self::Output> {
+        match crate::try_receive_message(self.server) {
+            Ok(Some(envelope)) => return Poll::Ready(Ok(envelope)),
+            Ok(None) => (),
+            Err(e) => return Poll::Ready(Err(e)),
+        }
+        if !self.watching {
+            // This also wakes the executor if a message has arrived since the
+            // queue was checked above.
+            let connection = self.executor.shared().connection;
+            if let Err(e) = crate::signal_on_message(self.server, connection, WAKE) {
+                return Poll::Ready(Err(e));
+            }
+            self.watching = true;
+        }
+        Poll::Pending
+    }
+}
+
+impl Drop for ReceiveMessage<'_> {
+    fn drop(&mut self) {
+        if self.watching {
+            crate::signal_on_message(self.server, self.executor.shared().connection, 0).ok();
+        }
+    }
+}
+
+/// A future that sends a message, and resolves to the reply. It is made by
+/// `Executor::send_message()`.
+pub struct SendMessage<'a> {
+    executor: &'a Executor,
+    connection: CID,
+
+    /// The message, until it's sent or handed to a helper
+    message: Option<Message>,
+
+    /// The helper that is sending a blocking message
+    helper: Option<usize>,
+}
+
+impl Future for SendMessage<'_> {
+    type Output = Result<crate::Result, Error>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let this = self.get_mut();
+        let shared = this.executor.shared();
+
+        let index = match this.helper {
+            Some(index) => index,
+            None => {
+                match this.message.take() {
+                    None => return Poll::Ready(Err(Error::InternalError)),
+                    Some(message) if !message.is_blocking() => {
+                        return Poll::Ready(crate::send_message(this.connection, message))
+                    }
+                    message => this.message = message,
+                }
+                match shared.hand_over(this.connection, &mut this.message, cx.waker()) {
+                    Ok(Some(index)) => this.helper = Some(index),
+                    Ok(None) => return Poll::Pending,
+                    Err(e) => return Poll::Ready(Err(e)),
+                }
+                return Poll::Pending;
+            }
+        };
+
+        let mut job = shared.helpers[index].job.lock();
+        let state = core::mem::replace(&mut job.state, State::Idle);
+        if let State::Sending(_) = state {
+            job.state = State::Sending(Some(cx.waker().clone()));
+            return Poll::Pending;
+        }
+        drop(job);
+        this.helper = None;
+        shared.helper_freed();
+        match state {
+            State::Replied(result) => Poll::Ready(result),
+            _ => Poll::Ready(Err(Error::InternalError)),
+        }
+    }
+}
+
+impl Drop for SendMessage<'_> {
+    fn drop(&mut self) {
+        let index = match self.helper {
+            Some(index) => index,
+            None => return,
+        };
+        let shared = self.executor.shared();
+        let mut job = shared.helpers[index].job.lock();
+        // A message that the helper hasn't taken yet is never sent.
+        if job.message.take().is_some() || matches!(job.state, State::Replied(_)) {
+            job.state = State::Idle;
+            drop(job);
+            shared.helper_freed();
+        } else {
+            job.state = State::Abandoned;
+        }
+    }
+}
+
+enum MaybeDone<F: Future> {
+    Running(F),
+    Done(F::Output),
+    Taken,
+}
+
+impl<F: Future> MaybeDone<F> {
+    /// Poll the future if it's still running, and return `true` once it has
+    /// finished.
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> bool {
+        // Safe because the future is only moved out of once it has finished.
+        let this = unsafe { self.get_unchecked_mut() };
+        if let MaybeDone::Running(future) = this {
+            match unsafe { Pin::new_unchecked(future) }.poll(cx) {
+                Poll::Ready(output) => *this = MaybeDone::Done(output),
+                Poll::Pending => return false,
+            }
+        }
+        true
+    }
+
+    fn take(self: Pin<&mut Self>) -> F::Output {
+        // Safe because the future has already finished.
+        let this = unsafe { self.get_unchecked_mut() };
+        match core::mem::replace(this, MaybeDone::Taken) {
+            MaybeDone::Done(output) => output,
+            _ => panic!("output taken before the future finished"),
+        }
+    }
+}
+
+/// Run two futures at the same time, and resolve to both of their outputs
+/// once they have both finished.
+pub fn join<A: Future, B: Future>(a: A, b: B) -> Join<A, B> {
+    Join {
+        a: MaybeDone::Running(a),
+        b: MaybeDone::Running(b),
+    }
+}
+
+/// A future that runs two others at once. It is made by `join()`.
+pub struct Join<A: Future, B: Future> {
+    a: MaybeDone<A>,
+    b: MaybeDone<B>,
+}
+
+impl<A: Future, B: Future> Future for Join<A, B> {
+    type Output = (A::Output, B::Output);
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        // Safe because neither future is ever moved out of `this`.
+        let this = unsafe { self.get_unchecked_mut() };
+        let mut a = unsafe { Pin::new_unchecked(&mut this.a) };
+        let mut b = unsafe { Pin::new_unchecked(&mut this.b) };
+        let a_done = a.as_mut().poll(cx);
+        let b_done = b.as_mut().poll(cx);
+        if a_done && b_done {
+            Poll::Ready((a.take(), b.take()))
+        } else {
+            Poll::Pending
+        }
+    }
+}
+
+/// The output of whichever future in a `select()` finished first
+#[derive(Debug, PartialEq)]
+pub enum Either<A, B> {
+    Left(A),
+    Right(B),
+}
+
+/// Run two futures at the same time, and resolve to the output of whichever
+/// finishes first. The other one is dropped along with the `Select`.
+pub fn select<A: Future, B: Future>(a: A, b: B) -> Select<A, B> {
+    Select { a, b }
+}
+
+/// A future that races two others. It is made by `select()`.
+pub struct Select<A, B> {
+    a: A,
+    b: B,
+}
+
+impl<A: Future, B: Future> Future for Select<A, B> {
+    type Output = Either<A::Output, B::Output>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        // Safe because neither future is ever moved out of `this`.
+        let this = unsafe { self.get_unchecked_mut() };
+        if let Poll::Ready(output) = unsafe { Pin::new_unchecked(&mut this.a) }.poll(cx) {
+            return Poll::Ready(Either::Left(output));
+        }
+        if let Poll::Ready(output) = unsafe { Pin::new_unchecked(&mut this.b) }.poll(cx) {
+            return Poll::Ready(Either::Right(output));
+        }
+        Poll::Pending
+    }
+}
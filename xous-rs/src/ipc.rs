@@ -0,0 +1,1096 @@
+//! Typed messages. A value of any type that implements `serde::Serialize` can
+//! be encoded into a `Buffer`, which is lent or moved to a server in the same
+//! way as a `Carton`, and decoded by the server with `from_memory_message()`.
+//! The number of bytes that were encoded is passed along in the message's
+//! `valid` field, and nothing past it is ever read.
+//!
+//! The encoding is compact and is not self-describing, so both sides must agree
+//! on the type being sent. Integers and floats are stored little-endian at their
+//! full width, `bool`s and `Option` tags take one byte, enum variants are a
+//! `u32` index, and strings, byte arrays, sequences and maps start with a `u32`
+//! length. Struct and tuple fields are stored one after another, with nothing
+//! in between.
+//!
+//! This module is only available with the `serde` feature.
+
+use core::convert::TryFrom;
+use core::fmt;
+
+use serde::{de, ser, Deserialize, Serialize};
+
+use crate::{MemoryFlags, MemoryMessage, MemoryRange, MemorySize, Message, CID};
+
+#[derive(Debug, PartialEq)]
+pub enum Error {
+    /// The encoded value doesn't fit in the buffer
+    BufferFull,
+
+    /// The data ended before the whole value had been decoded
+    UnexpectedEnd,
+
+    /// The data doesn't describe a value of the type being decoded
+    InvalidData,
+
+    /// The type needs something this encoding can't do, such as a sequence
+    /// longer than `u32::MAX`, or being decoded without knowing its type
+    Unsupported,
+
+    /// A `Serialize` or `Deserialize` implementation reported its own error
+    Custom,
+
+    /// The kernel couldn't provide memory for a buffer
+    Xous(crate::Error),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::BufferFull => write!(f, "buffer is full"),
+            Error::UnexpectedEnd => write!(f, "unexpected end of data"),
+            Error::InvalidData => write!(f, "invalid data"),
+            Error::Unsupported => write!(f, "unsupported by this encoding"),
+            Error::Custom => write!(f, "error while encoding or decoding"),
+            Error::Xous(e) => write!(f, "kernel error: {:?}", e),
+        }
+    }
+}
+
+impl From<crate::Error> for Error {
+    fn from(e: crate::Error) -> Self {
+        Error::Xous(e)
+    }
+}
+
+impl ser::StdError for Error {}
+
+impl ser::Error for Error {
+    fn custom<T: fmt::Display>(_msg: T) -> Self {
+        Error::Custom
+    }
+}
+
+impl de::Error for Error {
+    fn custom<T: fmt::Display>(_msg: T) -> Self {
+        Error::Custom
+    }
+}
+
+/// Encode `value` at the start of `buf`, and return the number of bytes used.
+pub fn to_slice<T: Serialize + ?Sized>(value: &T, buf: &mut [u8]) -> Result<usize, Error> {
+    let mut serializer = Serializer {
+        output: Some(buf),
+        len: 0,
+    };
+    value.serialize(&mut serializer)?;
+    Ok(serializer.len)
+}
+
+/// Work out how many bytes `value` takes up when it's encoded.
+pub fn serialized_len<T: Serialize + ?Sized>(value: &T) -> Result<usize, Error> {
+    let mut serializer = Serializer {
+        output: None,
+        len: 0,
+    };
+    value.serialize(&mut serializer)?;
+    Ok(serializer.len)
+}
+
+/// Decode a value from the start of `bytes`. Anything after the value is
+/// ignored. Strings and byte slices in the value borrow from `bytes`.
+pub fn from_slice<'de, T: Deserialize<'de>>(bytes: &'de [u8]) -> Result<T, Error> {
+    let mut deserializer = Deserializer { input: bytes };
+    T::deserialize(&mut deserializer)
+}
+
+/// Decode a value from a message that a server has received. If the sender
+/// said how many bytes are valid, none past that are read.
+///
+/// # Errors
+///
+/// * **InvalidData**: `valid` is larger than the buffer
+pub fn from_memory_message<'a, T: Deserialize<'a>>(message: &'a MemoryMessage) -> Result<T, Error> {
+    let len = match message.valid {
+        Some(valid) if valid.get() > message.buf.len() => return Err(Error::InvalidData),
+        Some(valid) => valid.get(),
+        None => message.buf.len(),
+    };
+    from_slice(unsafe { core::slice::from_raw_parts(message.buf.as_ptr(), len) })
+}
+
+/// Encode a reply into the buffer of a message that was mutably lent, in place
+/// of the request, and return the number of bytes used. The lender can decode
+/// it with `Buffer::to_original()` once the memory has been returned.
+pub fn replace_memory_message<T: Serialize + ?Sized>(
+    message: &mut MemoryMessage,
+    value: &T,
+) -> Result<usize, Error> {
+    let buf =
+        unsafe { core::slice::from_raw_parts_mut(message.buf.as_mut_ptr(), message.buf.len()) };
+    let len = to_slice(value, buf)?;
+    message.valid = MemorySize::new(len);
+    Ok(len)
+}
+
+/// Pages of memory holding an encoded value, ready to be sent to a server.
+#[derive(Debug)]
+pub struct Buffer {
+    range: MemoryRange,
+    valid: usize,
+}
+
+impl Buffer {
+    /// Allocate an empty buffer that can hold at least `len` bytes. The size
+    /// is rounded up to a whole number of pages.
+    pub fn new(len: usize) -> Result<Self, crate::Error> {
+        let size = (len.max(1) + 4095) & !4095;
+        let range = crate::map_memory(None, None, size, MemoryFlags::R | MemoryFlags::W)?;
+        Ok(Buffer { range, valid: 0 })
+    }
+
+    /// Allocate a buffer that is just big enough for `value`, and encode it there.
+    pub fn into_buf<T: Serialize + ?Sized>(value: &T) -> Result<Self, Error> {
+        let mut buffer = Buffer::new(serialized_len(value)?)?;
+        buffer.replace(value)?;
+        Ok(buffer)
+    }
+
+    /// Encode `value` into the buffer in place of whatever was there before.
+    pub fn replace<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), Error> {
+        self.valid = to_slice(value, self.as_mut())?;
+        Ok(())
+    }
+
+    /// Decode the value in the buffer. After a mutable lend, this is whatever
+    /// the server put there with `replace_memory_message()`. Only the bytes
+    /// that are valid are read, which the server may have changed.
+    pub fn to_original<'a, T: Deserialize<'a>>(&'a self) -> Result<T, Error> {
+        from_slice(&self.as_ref()[..self.valid])
+    }
+
+    /// The number of bytes used by the value that was last encoded, or that
+    /// the server reported when it returned a mutable lend.
+    pub fn len(&self) -> usize {
+        self.valid
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.valid == 0
+    }
+
+    fn message(&self, id: usize) -> MemoryMessage {
+        MemoryMessage {
+            id,
+            buf: self.range,
+            offset: None,
+            valid: MemorySize::new(self.valid),
+        }
+    }
+
+    /// Perform an immutable lend of this buffer to the specified server.
+    /// This function will block until the server returns.
+    pub fn lend(&self, connection: CID, id: usize) -> Result<crate::Result, crate::Error> {
+        crate::send_message(connection, Message::Borrow(self.message(id)))
+    }
+
+    /// Perform a mutable lend of this buffer to the specified server, which
+    /// may replace the value with a reply.
+    pub fn lend_mut(&mut self, connection: CID, id: usize) -> Result<crate::Result, crate::Error> {
+        let result = crate::send_message(connection, Message::MutableBorrow(self.message(id)))?;
+        if let crate::Result::MemoryReturned(_, valid) = result {
+            self.valid = valid.map(|v| v.get()).unwrap_or(0).min(self.range.len());
+        }
+        Ok(result)
+    }
+
+    /// Turn this buffer into a message that moves its memory to the server.
+    pub fn into_message(self, id: usize) -> MemoryMessage {
+        let message = self.message(id);
+        // Once the message is sent, the memory belongs to the server.
+        core::mem::forget(self);
+        message
+    }
+}
+
+impl AsRef<[u8]> for Buffer {
+    fn as_ref(&self) -> &[u8] {
+        unsafe { core::slice::from_raw_parts(self.range.as_ptr(), self.range.len()) }
+    }
+}
+
+impl AsMut<[u8]> for Buffer {
+    fn as_mut(&mut self) -> &mut [u8] {
+        unsafe { core::slice::from_raw_parts_mut(self.range.as_mut_ptr(), self.range.len()) }
+    }
+}
+
+impl Drop for Buffer {
+    fn drop(&mut self) {
+        crate::unmap_memory(self.range).unwrap();
+    }
+}
+
+/// Writes the encoding into a slice, or only counts its length if there is no
+/// slice to write into.
+struct Serializer<'a> {
+    output: Option<&'a mut [u8]>,
+    len: usize,
+}
+
+impl<'a> Serializer<'a> {
+    fn write(&mut self, bytes: &[u8]) -> Result<(), Error> {
+        let end = self.len.checked_add(bytes.len()).ok_or(Error::BufferFull)?;
+        if let Some(output) = self.output.as_deref_mut() {
+            output
+                .get_mut(self.len..end)
+                .ok_or(Error::BufferFull)?
+                .copy_from_slice(bytes);
+        }
+        self.len = end;
+        Ok(())
+    }
+
+    fn write_len(&mut self, len: usize) -> Result<(), Error> {
+        let len = u32::try_from(len).or(Err(Error::Unsupported))?;
+        self.write(&len.to_le_bytes())
+    }
+
+    /// Leave room for a length that isn't known yet, and return where it goes.
+    fn reserve_len(&mut self) -> Result<usize, Error> {
+        let at = self.len;
+        self.write(&0u32.to_le_bytes())?;
+        Ok(at)
+    }
+
+    /// Fill in a length that was reserved with `reserve_len()`.
+    fn patch_len(&mut self, at: usize, len: usize) -> Result<(), Error> {
+        let len = u32::try_from(len).or(Err(Error::Unsupported))?;
+        if let Some(output) = self.output.as_deref_mut() {
+            output[at..at + 4].copy_from_slice(&len.to_le_bytes());
+        }
+        Ok(())
+    }
+}
+
+impl<'a> fmt::Write for Serializer<'a> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        self.write(s.as_bytes()).or(Err(fmt::Error))
+    }
+}
+
+/// Serializes the parts of a compound value. Sequences and maps may not know
+/// how many elements they have until they're done, so their length is filled
+/// in at the end.
+struct Compound<'s, 'a> {
+    ser: &'s mut Serializer<'a>,
+    len_at: Option<usize>,
+    count: usize,
+}
+
+impl<'s, 'a> Compound<'s, 'a> {
+    fn element<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), Error> {
+        self.count += 1;
+        value.serialize(&mut *self.ser)
+    }
+
+    fn finish(self) -> Result<(), Error> {
+        match self.len_at {
+            Some(at) => self.ser.patch_len(at, self.count),
+            None => Ok(()),
+        }
+    }
+}
+
+impl<'s, 'a> ser::Serializer for &'s mut Serializer<'a> {
+    type Ok = ();
+    type Error = Error;
+    type SerializeSeq = Compound<'s, 'a>;
+    type SerializeTuple = Compound<'s, 'a>;
+    type SerializeTupleStruct = Compound<'s, 'a>;
+    type SerializeTupleVariant = Compound<'s, 'a>;
+    type SerializeMap = Compound<'s, 'a>;
+    type SerializeStruct = Compound<'s, 'a>;
+    type SerializeStructVariant = Compound<'s, 'a>;
+
+    fn serialize_bool(self, v: bool) -> Result<(), Error> {
+        self.write(&[v as u8])
+    }
+
+    fn serialize_i8(self, v: i8) -> Result<(), Error> {
+        self.write(&v.to_le_bytes())
+    }
+
+    fn serialize_i16(self, v: i16) -> Result<(), Error> {
+        self.write(&v.to_le_bytes())
+    }
+
+    fn serialize_i32(self, v: i32) -> Result<(), Error> {
+        self.write(&v.to_le_bytes())
+    }
+
+    fn serialize_i64(self, v: i64) -> Result<(), Error> {
+        self.write(&v.to_le_bytes())
+    }
+
+    fn serialize_i128(self, v: i128) -> Result<(), Error> {
+        self.write(&v.to_le_bytes())
+    }
+
+    fn serialize_u8(self, v: u8) -> Result<(), Error> {
+        self.write(&[v])
+    }
+
+    fn serialize_u16(self, v: u16) -> Result<(), Error> {
+        self.write(&v.to_le_bytes())
+    }
+
+    fn serialize_u32(self, v: u32) -> Result<(), Error> {
+        self.write(&v.to_le_bytes())
+    }
+
+    fn serialize_u64(self, v: u64) -> Result<(), Error> {
+        self.write(&v.to_le_bytes())
+    }
+
+    fn serialize_u128(self, v: u128) -> Result<(), Error> {
+        self.write(&v.to_le_bytes())
+    }
+
+    fn serialize_f32(self, v: f32) -> Result<(), Error> {
+        self.write(&v.to_bits().to_le_bytes())
+    }
+
+    fn serialize_f64(self, v: f64) -> Result<(), Error> {
+        self.write(&v.to_bits().to_le_bytes())
+    }
+
+    fn serialize_char(self, v: char) -> Result<(), Error> {
+        self.serialize_u32(v as u32)
+    }
+
+    fn serialize_str(self, v: &str) -> Result<(), Error> {
+        self.serialize_bytes(v.as_bytes())
+    }
+
+    fn serialize_bytes(self, v: &[u8]) -> Result<(), Error> {
+        self.write_len(v.len())?;
+        self.write(v)
+    }
+
+    fn serialize_none(self) -> Result<(), Error> {
+        self.write(&[0])
+    }
+
+    fn serialize_some<T: Serialize + ?Sized>(self, value: &T) -> Result<(), Error> {
+        self.write(&[1])?;
+        value.serialize(self)
+    }
+
+    fn serialize_unit(self) -> Result<(), Error> {
+        Ok(())
+    }
+
+    fn serialize_unit_struct(self, _name: &'static str) -> Result<(), Error> {
+        Ok(())
+    }
+
+    fn serialize_unit_variant(
+        self,
+        _name: &'static str,
+        variant_index: u32,
+        _variant: &'static str,
+    ) -> Result<(), Error> {
+        self.serialize_u32(variant_index)
+    }
+
+    fn serialize_newtype_struct<T: Serialize + ?Sized>(
+        self,
+        _name: &'static str,
+        value: &T,
+    ) -> Result<(), Error> {
+        value.serialize(self)
+    }
+
+    fn serialize_newtype_variant<T: Serialize + ?Sized>(
+        self,
+        _name: &'static str,
+        variant_index: u32,
+        _variant: &'static str,
+        value: &T,
+    ) -> Result<(), Error> {
+        self.serialize_u32(variant_index)?;
+        value.serialize(self)
+    }
+
+    fn serialize_seq(self, _len: Option<usize>) -> Result<Compound<'s, 'a>, Error> {
+        let len_at = Some(self.reserve_len()?);
+        Ok(Compound {
+            ser: self,
+            len_at,
+            count: 0,
+        })
+    }
+
+    fn serialize_tuple(self, _len: usize) -> Result<Compound<'s, 'a>, Error> {
+        Ok(Compound {
+            ser: self,
+            len_at: None,
+            count: 0,
+        })
+    }
+
+    fn serialize_tuple_struct(
+        self,
+        _name: &'static str,
+        len: usize,
+    ) -> Result<Compound<'s, 'a>, Error> {
+        self.serialize_tuple(len)
+    }
+
+    fn serialize_tuple_variant(
+        self,
+        _name: &'static str,
+        variant_index: u32,
+        _variant: &'static str,
+        len: usize,
+    ) -> Result<Compound<'s, 'a>, Error> {
+        self.serialize_u32(variant_index)?;
+        self.serialize_tuple(len)
+    }
+
+    fn serialize_map(self, len: Option<usize>) -> Result<Compound<'s, 'a>, Error> {
+        self.serialize_seq(len)
+    }
+
+    fn serialize_struct(self, _name: &'static str, len: usize) -> Result<Compound<'s, 'a>, Error> {
+        self.serialize_tuple(len)
+    }
+
+    fn serialize_struct_variant(
+        self,
+        _name: &'static str,
+        variant_index: u32,
+        _variant: &'static str,
+        len: usize,
+    ) -> Result<Compound<'s, 'a>, Error> {
+        self.serialize_u32(variant_index)?;
+        self.serialize_tuple(len)
+    }
+
+    fn collect_str<T: fmt::Display + ?Sized>(self, value: &T) -> Result<(), Error> {
+        let len_at = self.reserve_len()?;
+        let start = self.len;
+        fmt::write(self, format_args!("{}", value)).or(Err(Error::BufferFull))?;
+        let len = self.len - start;
+        self.patch_len(len_at, len)
+    }
+
+    fn is_human_readable(&self) -> bool {
+        false
+    }
+}
+
+impl<'s, 'a> ser::SerializeSeq for Compound<'s, 'a> {
+    type Ok = ();
+    type Error = Error;
+
+    fn serialize_element<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), Error> {
+        self.element(value)
+    }
+
+    fn end(self) -> Result<(), Error> {
+        self.finish()
+    }
+}
+
+impl<'s, 'a> ser::SerializeTuple for Compound<'s, 'a> {
+    type Ok = ();
+    type Error = Error;
+
+    fn serialize_element<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), Error> {
+        self.element(value)
+    }
+
+    fn end(self) -> Result<(), Error> {
+        self.finish()
+    }
+}
+
+impl<'s, 'a> ser::SerializeTupleStruct for Compound<'s, 'a> {
+    type Ok = ();
+    type Error = Error;
+
+    fn serialize_field<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), Error> {
+        self.element(value)
+    }
+
+    fn end(self) -> Result<(), Error> {
+        self.finish()
+    }
+}
+
+impl<'s, 'a> ser::SerializeTupleVariant for Compound<'s, 'a> {
+    type Ok = ();
+    type Error = Error;
+
+    fn serialize_field<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), Error> {
+        self.element(value)
+    }
+
+    fn end(self) -> Result<(), Error> {
+        self.finish()
+    }
+}
+
+impl<'s, 'a> ser::SerializeMap for Compound<'s, 'a> {
+    type Ok = ();
+    type Error = Error;
+
+    // Each entry counts once, when its key is written.
+    fn serialize_key<T: Serialize + ?Sized>(&mut self, key: &T) -> Result<(), Error> {
+        self.element(key)
+    }
+
+    fn serialize_value<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), Error> {
+        value.serialize(&mut *self.ser)
+    }
+
+    fn end(self) -> Result<(), Error> {
+        self.finish()
+    }
+}
+
+impl<'s, 'a> ser::SerializeStruct for Compound<'s, 'a> {
+    type Ok = ();
+    type Error = Error;
+
+    fn serialize_field<T: Serialize + ?Sized>(
+        &mut self,
+        _key: &'static str,
+        value: &T,
+    ) -> Result<(), Error> {
+        self.element(value)
+    }
+
+    fn end(self) -> Result<(), Error> {
+        self.finish()
+    }
+}
+
+impl<'s, 'a> ser::SerializeStructVariant for Compound<'s, 'a> {
+    type Ok = ();
+    type Error = Error;
+
+    fn serialize_field<T: Serialize + ?Sized>(
+        &mut self,
+        _key: &'static str,
+        value: &T,
+    ) -> Result<(), Error> {
+        self.element(value)
+    }
+
+    fn end(self) -> Result<(), Error> {
+        self.finish()
+    }
+}
+
+/// Reads values back out of the encoding, checking every access against the
+/// end of the input.
+struct Deserializer<'de> {
+    input: &'de [u8],
+}
+
+macro_rules! read_le {
+    ($de:expr, $t:ty) => {{
+        let mut raw = [0u8; core::mem::size_of::<$t>()];
+        raw.copy_from_slice($de.take(core::mem::size_of::<$t>())?);
+        <$t>::from_le_bytes(raw)
+    }};
+}
+
+impl<'de> Deserializer<'de> {
+    fn take(&mut self, len: usize) -> Result<&'de [u8], Error> {
+        if len > self.input.len() {
+            return Err(Error::UnexpectedEnd);
+        }
+        let (bytes, rest) = self.input.split_at(len);
+        self.input = rest;
+        Ok(bytes)
+    }
+
+    fn read_len(&mut self) -> Result<usize, Error> {
+        Ok(read_le!(self, u32) as usize)
+    }
+
+    fn read_bytes(&mut self) -> Result<&'de [u8], Error> {
+        let len = self.read_len()?;
+        self.take(len)
+    }
+}
+
+impl<'de> de::Deserializer<'de> for &mut Deserializer<'de> {
+    type Error = Error;
+
+    fn deserialize_any<V: de::Visitor<'de>>(self, _visitor: V) -> Result<V::Value, Error> {
+        Err(Error::Unsupported)
+    }
+
+    fn deserialize_bool<V: de::Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        match read_le!(self, u8) {
+            0 => visitor.visit_bool(false),
+            1 => visitor.visit_bool(true),
+            _ => Err(Error::InvalidData),
+        }
+    }
+
+    fn deserialize_i8<V: de::Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        visitor.visit_i8(read_le!(self, i8))
+    }
+
+    fn deserialize_i16<V: de::Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        visitor.visit_i16(read_le!(self, i16))
+    }
+
+    fn deserialize_i32<V: de::Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        visitor.visit_i32(read_le!(self, i32))
+    }
+
+    fn deserialize_i64<V: de::Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        visitor.visit_i64(read_le!(self, i64))
+    }
+
+    fn deserialize_i128<V: de::Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        visitor.visit_i128(read_le!(self, i128))
+    }
+
+    fn deserialize_u8<V: de::Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        visitor.visit_u8(read_le!(self, u8))
+    }
+
+    fn deserialize_u16<V: de::Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        visitor.visit_u16(read_le!(self, u16))
+    }
+
+    fn deserialize_u32<V: de::Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        visitor.visit_u32(read_le!(self, u32))
+    }
+
+    fn deserialize_u64<V: de::Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        visitor.visit_u64(read_le!(self, u64))
+    }
+
+    fn deserialize_u128<V: de::Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        visitor.visit_u128(read_le!(self, u128))
+    }
+
+    fn deserialize_f32<V: de::Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        visitor.visit_f32(f32::from_bits(read_le!(self, u32)))
+    }
+
+    fn deserialize_f64<V: de::Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        visitor.visit_f64(f64::from_bits(read_le!(self, u64)))
+    }
+
+    fn deserialize_char<V: de::Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        let c = core::char::from_u32(read_le!(self, u32)).ok_or(Error::InvalidData)?;
+        visitor.visit_char(c)
+    }
+
+    fn deserialize_str<V: de::Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        let s = core::str::from_utf8(self.read_bytes()?).or(Err(Error::InvalidData))?;
+        visitor.visit_borrowed_str(s)
+    }
+
+    fn deserialize_string<V: de::Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        self.deserialize_str(visitor)
+    }
+
+    fn deserialize_bytes<V: de::Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        visitor.visit_borrowed_bytes(self.read_bytes()?)
+    }
+
+    fn deserialize_byte_buf<V: de::Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        self.deserialize_bytes(visitor)
+    }
+
+    fn deserialize_option<V: de::Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        match read_le!(self, u8) {
+            0 => visitor.visit_none(),
+            1 => visitor.visit_some(self),
+            _ => Err(Error::InvalidData),
+        }
+    }
+
+    fn deserialize_unit<V: de::Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        visitor.visit_unit()
+    }
+
+    fn deserialize_unit_struct<V: de::Visitor<'de>>(
+        self,
+        _name: &'static str,
+        visitor: V,
+    ) -> Result<V::Value, Error> {
+        visitor.visit_unit()
+    }
+
+    fn deserialize_newtype_struct<V: de::Visitor<'de>>(
+        self,
+        _name: &'static str,
+        visitor: V,
+    ) -> Result<V::Value, Error> {
+        visitor.visit_newtype_struct(self)
+    }
+
+    fn deserialize_seq<V: de::Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        let remaining = self.read_len()?;
+        visitor.visit_seq(Elements {
+            de: self,
+            remaining,
+        })
+    }
+
+    fn deserialize_tuple<V: de::Visitor<'de>>(
+        self,
+        len: usize,
+        visitor: V,
+    ) -> Result<V::Value, Error> {
+        visitor.visit_seq(Elements {
+            de: self,
+            remaining: len,
+        })
+    }
+
+    fn deserialize_tuple_struct<V: de::Visitor<'de>>(
+        self,
+        _name: &'static str,
+        len: usize,
+        visitor: V,
+    ) -> Result<V::Value, Error> {
+        self.deserialize_tuple(len, visitor)
+    }
+
+    fn deserialize_map<V: de::Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        let remaining = self.read_len()?;
+        visitor.visit_map(Elements {
+            de: self,
+            remaining,
+        })
+    }
+
+    fn deserialize_struct<V: de::Visitor<'de>>(
+        self,
+        _name: &'static str,
+        fields: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, Error> {
+        self.deserialize_tuple(fields.len(), visitor)
+    }
+
+    fn deserialize_enum<V: de::Visitor<'de>>(
+        self,
+        _name: &'static str,
+        _variants: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, Error> {
+        visitor.visit_enum(self)
+    }
+
+    fn deserialize_identifier<V: de::Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        self.deserialize_u32(visitor)
+    }
+
+    fn deserialize_ignored_any<V: de::Visitor<'de>>(self, _visitor: V) -> Result<V::Value, Error> {
+        Err(Error::Unsupported)
+    }
+
+    fn is_human_readable(&self) -> bool {
+        false
+    }
+}
+
+/// Hands out the elements of a sequence, tuple or map, of which there are
+/// `remaining` left.
+struct Elements<'a, 'de> {
+    de: &'a mut Deserializer<'de>,
+    remaining: usize,
+}
+
+impl<'a, 'de> de::SeqAccess<'de> for Elements<'a, 'de> {
+    type Error = Error;
+
+    fn next_element_seed<T: de::DeserializeSeed<'de>>(
+        &mut self,
+        seed: T,
+    ) -> Result<Option<T::Value>, Error> {
+        if self.remaining == 0 {
+            return Ok(None);
+        }
+        self.remaining -= 1;
+        seed.deserialize(&mut *self.de).map(Some)
+    }
+
+    fn size_hint(&self) -> Option<usize> {
+        // The length came from the sender, so don't let it make the receiver
+        // reserve more than could possibly be there.
+        Some(self.remaining.min(self.de.input.len()))
+    }
+}
+
+impl<'a, 'de> de::MapAccess<'de> for Elements<'a, 'de> {
+    type Error = Error;
+
+    fn next_key_seed<K: de::DeserializeSeed<'de>>(
+        &mut self,
+        seed: K,
+    ) -> Result<Option<K::Value>, Error> {
+        if self.remaining == 0 {
+            return Ok(None);
+        }
+        self.remaining -= 1;
+        seed.deserialize(&mut *self.de).map(Some)
+    }
+
+    fn next_value_seed<V: de::DeserializeSeed<'de>>(&mut self, seed: V) -> Result<V::Value, Error> {
+        seed.deserialize(&mut *self.de)
+    }
+
+    fn size_hint(&self) -> Option<usize> {
+        Some(self.remaining.min(self.de.input.len()))
+    }
+}
+
+impl<'de> de::EnumAccess<'de> for &mut Deserializer<'de> {
+    type Error = Error;
+    type Variant = Self;
+
+    fn variant_seed<V: de::DeserializeSeed<'de>>(self, seed: V) -> Result<(V::Value, Self), Error> {
+        let index: de::value::U32Deserializer<Error> =
+            de::IntoDeserializer::into_deserializer(read_le!(self, u32));
+        let value = seed.deserialize(index)?;
+        Ok((value, self))
+    }
+}
+
+impl<'de> de::VariantAccess<'de> for &mut Deserializer<'de> {
+    type Error = Error;
+
+    fn unit_variant(self) -> Result<(), Error> {
+        Ok(())
+    }
+
+    fn newtype_variant_seed<T: de::DeserializeSeed<'de>>(self, seed: T) -> Result<T::Value, Error> {
+        seed.deserialize(self)
+    }
+
+    fn tuple_variant<V: de::Visitor<'de>>(self, len: usize, visitor: V) -> Result<V::Value, Error> {
+        de::Deserializer::deserialize_tuple(self, len, visitor)
+    }
+
+    fn struct_variant<V: de::Visitor<'de>>(
+        self,
+        fields: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, Error> {
+        de::Deserializer::deserialize_tuple(self, fields.len(), visitor)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::BTreeMap;
+
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    struct Point {
+        x: i16,
+        y: i16,
+    }
+
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    enum Shape {
+        Empty,
+        Circle(u32),
+        Line(Point, Point),
+        Rect { tl: Point, br: Point },
+    }
+
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    struct Drawing {
+        name: String,
+        shapes: Vec<Shape>,
+        scale: f32,
+        marker: Option<char>,
+    }
+
+    fn encode<T: Serialize + ?Sized>(value: &T) -> Vec<u8> {
+        let mut buf = [0u8; 512];
+        let len = to_slice(value, &mut buf).unwrap();
+        assert_eq!(len, serialized_len(value).unwrap());
+        buf[..len].to_vec()
+    }
+
+    fn round_trip<T>(value: T)
+    where
+        T: Serialize + for<'de> Deserialize<'de> + PartialEq + fmt::Debug,
+    {
+        assert_eq!(from_slice::<T>(&encode(&value)), Ok(value));
+    }
+
+    #[test]
+    fn structs_are_packed() {
+        assert_eq!(encode(&Point { x: 1, y: -1 }), [1, 0, 0xff, 0xff]);
+        round_trip(Point { x: 1, y: -1 });
+        round_trip(Drawing {
+            name: "a line".into(),
+            shapes: vec![Shape::Line(Point { x: 0, y: 0 }, Point { x: 335, y: 535 })],
+            scale: 0.5,
+            marker: Some('x'),
+        });
+    }
+
+    #[test]
+    fn enums() {
+        assert_eq!(encode(&Shape::Circle(3)), [1, 0, 0, 0, 3, 0, 0, 0]);
+        round_trip(Shape::Empty);
+        round_trip(Shape::Circle(u32::MAX));
+        round_trip(Shape::Rect {
+            tl: Point { x: -5, y: 2 },
+            br: Point { x: 10, y: 20 },
+        });
+    }
+
+    #[test]
+    fn maps() {
+        let mut map = BTreeMap::new();
+        round_trip(map.clone());
+        map.insert(3u8, "three".to_string());
+        map.insert(1u8, String::new());
+        map.insert(2u8, "zwei".to_string());
+        assert_eq!(&encode(&map)[..4], [3, 0, 0, 0]);
+        round_trip(map);
+    }
+
+    #[test]
+    fn floats() {
+        round_trip(1.5f32);
+        round_trip(-0.0f64);
+        round_trip(f64::INFINITY);
+        round_trip(f32::MIN_POSITIVE);
+        let nan: f64 = from_slice(&encode(&f64::NAN)).unwrap();
+        assert!(nan.is_nan());
+    }
+
+    #[test]
+    fn chars() {
+        round_trip('a');
+        round_trip('ö');
+        round_trip('😸');
+        round_trip(char::MAX);
+        assert_eq!(encode(&'😸'), 0x1f638u32.to_le_bytes());
+    }
+
+    #[test]
+    fn options() {
+        round_trip(None::<u8>);
+        round_trip(Some(7u8));
+        round_trip(Some(None::<u16>));
+        round_trip(Some(Some(Point { x: 1, y: 2 })));
+        assert_eq!(encode(&Some(7u8)), [1, 7]);
+    }
+
+    #[test]
+    fn nested_sequences() {
+        round_trip(Vec::<Vec<u16>>::new());
+        round_trip(vec![vec![1u16, 2], vec![], vec![3]]);
+        round_trip(vec![vec!["a".to_string()], vec!["b".into(), "".into()]]);
+        round_trip([[1u8, 2], [3, 4], [5, 6]]);
+        round_trip((1u8, vec![(2u16, [3u32; 2])], "four".to_string()));
+    }
+
+    #[test]
+    fn borrowed_strings_and_bytes() {
+        let bytes = encode(&("hello", BytesLike(&[1, 2, 3])));
+        let (s, b): (&str, &[u8]) = from_slice(&bytes).unwrap();
+        assert_eq!((s, b), ("hello", &[1u8, 2, 3][..]));
+    }
+
+    /// Encodes as bytes rather than as a sequence of `u8`s, the way `&[u8]`
+    /// is decoded.
+    struct BytesLike<'a>(&'a [u8]);
+
+    impl<'a> Serialize for BytesLike<'a> {
+        fn serialize<S: ser::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+            serializer.serialize_bytes(self.0)
+        }
+    }
+
+    #[test]
+    fn value_must_fit() {
+        let mut buf = [0u8; 3];
+        assert_eq!(
+            to_slice(&Point { x: 1, y: 2 }, &mut buf),
+            Err(Error::BufferFull)
+        );
+        assert_eq!(to_slice("abc", &mut buf), Err(Error::BufferFull));
+    }
+
+    #[test]
+    fn bad_bool() {
+        assert_eq!(from_slice::<bool>(&[1]), Ok(true));
+        assert_eq!(from_slice::<bool>(&[2]), Err(Error::InvalidData));
+        assert_eq!(from_slice::<Option<u8>>(&[2, 0]), Err(Error::InvalidData));
+    }
+
+    #[test]
+    fn bad_char() {
+        // A surrogate, and a value past the last code point
+        assert_eq!(
+            from_slice::<char>(&0xd800u32.to_le_bytes()),
+            Err(Error::InvalidData)
+        );
+        assert_eq!(
+            from_slice::<char>(&0x11_0000u32.to_le_bytes()),
+            Err(Error::InvalidData)
+        );
+    }
+
+    #[test]
+    fn bad_utf8() {
+        let bytes = [2, 0, 0, 0, 0xc3, 0x28];
+        assert_eq!(from_slice::<&str>(&bytes), Err(Error::InvalidData));
+        assert_eq!(from_slice::<String>(&bytes), Err(Error::InvalidData));
+    }
+
+    #[test]
+    fn truncated_input() {
+        let bytes = encode(&Drawing {
+            name: "shapes".into(),
+            shapes: vec![Shape::Circle(1), Shape::Empty],
+            scale: 2.0,
+            marker: None,
+        });
+        for len in 0..bytes.len() {
+            assert_eq!(
+                from_slice::<Drawing>(&bytes[..len]),
+                Err(Error::UnexpectedEnd),
+                "decoded {} of {} bytes",
+                len,
+                bytes.len()
+            );
+        }
+        assert!(from_slice::<Drawing>(&bytes).is_ok());
+    }
+
+    #[test]
+    fn oversize_length_prefix() {
+        // The length claims far more than is there, which must neither read
+        // past the end nor reserve room for every element up front.
+        let bytes = [0xff, 0xff, 0xff, 0xff, 1, 0, 0, 0, 2, 0, 0, 0];
+        assert_eq!(from_slice::<&[u8]>(&bytes), Err(Error::UnexpectedEnd));
+        assert_eq!(from_slice::<&str>(&bytes), Err(Error::UnexpectedEnd));
+        assert_eq!(from_slice::<Vec<u32>>(&bytes), Err(Error::UnexpectedEnd));
+        assert_eq!(
+            from_slice::<BTreeMap<u32, u32>>(&bytes),
+            Err(Error::UnexpectedEnd)
+        );
+    }
+}
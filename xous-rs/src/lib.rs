@@ -5,15 +5,20 @@ extern crate bitflags;
 
 extern crate xous_macros as macros;
 
-pub use macros::xous_main;
+pub use macros::{xous_main, Opcode};
 
 pub mod arch;
 
 pub mod carton;
 pub mod definitions;
+pub mod executor;
+#[cfg(feature = "serde")]
+pub mod ipc;
 mod messages;
 pub mod string;
+pub mod sync;
 pub mod syscall;
+pub mod tls;
 
 pub use arch::{ProcessArgs, ProcessInit, ProcessKey, ThreadInit};
 pub use definitions::*;
@@ -146,8 +151,8 @@ macro_rules! maybe_main {
                 try_send_message(conn, Message::Scalar(panic_start_msg)).ok();
             }
             wait_event();
-            terminate_process();
-            loop {}
+            // Exit the same way a panicking `std` program does.
+            terminate_process(101)
         }
 
         extern "Rust" {
@@ -7,6 +7,10 @@ pub struct String<'a> {
     raw_slice: &'a mut [u8],
     s: &'a str,
     len: usize,
+
+    /// The `valid` field of the message this String was made from, which
+    /// follows the length so that the sender finds out about it.
+    message_valid: Option<&'a mut Option<MemorySize>>,
 }
 
 impl<'a> String<'a> {
@@ -22,6 +26,7 @@ impl<'a> String<'a> {
                 core::str::from_utf8_unchecked(core::slice::from_raw_parts(mem.as_ptr(), 0))
             },
             len: 0,
+            message_valid: None,
         }
     }
 
@@ -36,11 +41,12 @@ impl<'a> String<'a> {
     /// Convert a `MemoryMessage` into a `String`
     pub fn from_message(message: &'a mut MemoryMessage) -> core::result::Result<String<'a>, core::str::Utf8Error> {
         let raw_slice = unsafe { core::slice::from_raw_parts_mut(message.buf.as_mut_ptr(), message.buf.len()) };
-        let starting_length = message.valid.map(|x| x.get()).unwrap_or(0);
+        let starting_length = message.valid.map(|x| x.get()).unwrap_or(0).min(raw_slice.len());
         Ok(String {
             raw_slice,
             s: core::str::from_utf8(unsafe { core::slice::from_raw_parts(message.buf.as_ptr(), starting_length) })?,
-            len: 0,
+            len: starting_length,
+            message_valid: Some(&mut message.valid),
         })
     }
 
@@ -58,6 +64,32 @@ impl<'a> String<'a> {
         send_message(connection, Message::Borrow(msg))
     }
 
+    /// Perform a mutable lend of this String to the specified server, which
+    /// may change its contents. This function will block until the server
+    /// returns, after which the length is the number of bytes that the server
+    /// marked as valid.
+    pub fn lend_mut(&mut self, connection: CID, id: crate::MessageId) -> core::result::Result<Result, Error> {
+        let memory_range =
+            MemoryRange::new(self.raw_slice.as_ptr() as _, self.raw_slice.len()).unwrap();
+        let msg = MemoryMessage {
+            id,
+            buf: memory_range,
+            offset: None,
+            valid: MemorySize::new(self.len),
+        };
+        let result = send_message(connection, Message::MutableBorrow(msg))?;
+        if let Result::MemoryReturned(_, valid) = result {
+            let len = valid.map_or(0, |x| x.get()).min(self.raw_slice.len());
+            // Keep as much as is valid UTF-8, in case the server cut a character in half.
+            let len = match core::str::from_utf8(&self.raw_slice[..len]) {
+                Ok(_) => len,
+                Err(e) => e.valid_up_to(),
+            };
+            self.set_len(len);
+        }
+        Ok(result)
+    }
+
     /// Move this string from the client into the server.
     pub fn send(self, connection: CID, id: crate::MessageId) -> core::result::Result<Result, Error> {
         let memory_range =
@@ -73,13 +105,21 @@ impl<'a> String<'a> {
 
     /// Clear the contents of this String and set the length to 0
     pub fn clear(&mut self) {
-        self.len = 0;
+        self.set_len(0);
+    }
+
+    /// Set the length, along with the `str` and the message that follow it.
+    fn set_len(&mut self, len: usize) {
+        self.len = len;
         self.s = unsafe {
             core::str::from_utf8_unchecked(core::slice::from_raw_parts(
                 self.raw_slice.as_ptr(),
                 self.len,
             ))
         };
+        if let Some(valid) = self.message_valid.as_deref_mut() {
+            *valid = MemorySize::new(len);
+        }
     }
 }
 
@@ -91,18 +131,14 @@ impl<'a> core::fmt::Display for String<'a> {
 
 impl<'a> core::fmt::Write for String<'a> {
     fn write_str(&mut self, s: &str) -> core::result::Result<(), core::fmt::Error> {
+        let mut len = self.len;
         for c in s.bytes() {
-            if self.len < self.raw_slice.len() {
-                self.raw_slice[self.len] = c;
-                self.len += 1;
+            if len < self.raw_slice.len() {
+                self.raw_slice[len] = c;
+                len += 1;
             }
         }
-        self.s = unsafe {
-            core::str::from_utf8_unchecked(core::slice::from_raw_parts(
-                self.raw_slice.as_ptr(),
-                self.len,
-            ))
-        };
+        self.set_len(len);
         Ok(())
     }
 }
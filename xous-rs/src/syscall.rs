@@ -1,8 +1,10 @@
 use crate::{
-    pid_from_usize, CpuID, Error, MemoryAddress, MemoryFlags, MemoryMessage, MemoryRange,
-    MemorySize, MemoryType, Message, MessageEnvelope, MessageSender, ProcessArgs, ProcessInit,
-    Result, ScalarMessage, SysCallResult, ThreadInit, CID, PID, SID,
+    pid_from_usize, ConnectionRights, CpuID, Error, MemoryAddress, MemoryFlags, MemoryMessage,
+    MemoryRange, MemorySize, MemoryStats, MemoryType, Message, MessageEnvelope, MessageSender,
+    Priority, ProcessArgs, ProcessInit, Result, ScalarMessage, SharedMemoryId, SysCallResult,
+    ThreadInit, CID, PID, SID, TID,
 };
+use core::sync::atomic::AtomicUsize;
 // use num_derive::FromPrimitive;
 // use num_traits::FromPrimitive;
 
@@ -229,11 +231,12 @@ pub enum SysCall {
     CreateServerWithAddress(SID /* server hash */),
 
     /// Connect to a server.   This turns a 128-bit Serever ID into a 32-bit
-    /// Connection ID. Blocks until the server is available.
+    /// Connection ID. Blocks until the server is available, and until the
+    /// server approves the connection if it requires approval.
     ///
     /// # Errors
     ///
-    /// None
+    /// * **AccessDenied**: The server turned down the connection
     Connect(SID /* server id */),
 
     /// Try to connect to a server.   This turns a 128-bit Serever ID into a 32-bit
@@ -242,6 +245,8 @@ pub enum SysCall {
     /// # Errors
     ///
     /// * **ServerNotFound**: The server could not be found.
+    /// * **AccessDenied**: The server requires approval for new connections,
+    ///                     which can't be waited for here
     TryConnect(SID /* server id */),
 
     /// Send a message to a server (blocking until it's ready)
@@ -263,11 +268,26 @@ pub enum SysCall {
     CreateThread(ThreadInit),
 
     /// Create a new process, setting the current process as the parent ID.
-    /// Does not start the process immediately.
+    /// Does not start the process immediately. On hardware, the `ProcessInit`
+    /// lends the kernel a MiniElf image, which is copied into a fresh address
+    /// space. The new process starts at the image's entrypoint the first time
+    /// it is scheduled.
+    ///
+    /// # Returns
+    ///
+    /// The PID of the new process
+    ///
+    /// # Errors
+    ///
+    /// * **BadAddress**: The image is truncated or can't be read, or it places
+    ///                   a section outside of userspace
+    /// * **OutOfMemory**: There wasn't enough memory to load the image
+    /// * **ProcessNotFound**: No more processes can be created
     CreateProcess(ProcessInit),
 
-    /// Terminate the current process, closing all server connections.
-    TerminateProcess,
+    /// Terminate the current process, closing all server connections. The
+    /// exit code is kept until the parent collects it with `WaitProcess`.
+    TerminateProcess(usize /* exit code */),
 
     /// Shut down the entire system
     Shutdown,
@@ -291,7 +311,316 @@ pub enum SysCall {
     /// Establish a connection in the given process to the given server. This
     /// call can be used by a nameserver to make server connections without
     /// disclosing SIDs.
-    ConnectForProcess(PID, SID),
+    ///
+    /// If rights are given, they replace whatever rights the process had on
+    /// the server. The caller may only grant rights that it holds itself, and
+    /// the owner of a server holds every right on it.
+    ///
+    /// # Errors
+    ///
+    /// * **ServerNotFound**: The server does not exist
+    /// * **AccessDenied**: The rights go beyond what the caller holds, or the
+    ///                     server turned down the connection
+    ConnectForProcess(PID, SID, Option<ConnectionRights>),
+
+    /// Set the scheduling priority of a process. If no PID is specified, the
+    /// priority of the calling process is changed.  A process may only change
+    /// its own priority or the priority of one of its children, and may not
+    /// raise either above the priority it was started with unless its own
+    /// parent has raised it.
+    ///
+    /// # Errors
+    ///
+    /// * **ProcessNotFound**: The requested process does not exist
+    /// * **ProcessNotChild**: The given process is neither the caller nor one
+    ///                        of its children
+    /// * **AccessDenied**: The priority is higher than the caller may use
+    SetPriority(Option<PID>, Priority),
+
+    /// Send a message to a server, giving up if a blocking message has not
+    /// been responded to within the given number of milliseconds. The timeout
+    /// shares an argument with the message type, so it may be at most
+    /// `usize::MAX >> 8` milliseconds.
+    ///
+    /// Memory lent with a message that the server has already received stays
+    /// lent after a timeout, and must not be used or unmapped until the server
+    /// responds. A server that never responds keeps it for good.
+    ///
+    /// # Errors
+    ///
+    /// * **ServerNotFound**: The server could not be found.
+    /// * **ServerQueueFull**: The server's queue is full
+    /// * **Timeout**: The server did not respond in time. Memory that was lent
+    ///                as part of a message the server hadn't received yet has
+    ///                been returned. Otherwise it stays lent until the server
+    ///                responds.
+    SendMessageTimeout(CID, Message, usize /* timeout in ms */),
+
+    /// Wait for a message with the given server ID, as with `ReceiveMessage`,
+    /// but give up after the given number of milliseconds.
+    ///
+    /// # Errors
+    ///
+    /// * **ServerNotFound**: The server does not exist or belongs to another process
+    /// * **Timeout**: No message arrived in time
+    ReceiveMessageTimeout(SID, usize /* timeout in ms */),
+
+    /// Release a connection to a server, freeing its connection ID so that it
+    /// may be reused by a later call to `Connect`.
+    ///
+    /// # Errors
+    ///
+    /// * **ServerNotFound**: The connection ID is not in use
+    Disconnect(CID),
+
+    /// Tear down a server owned by this process. Any clients waiting on a
+    /// response are woken with `ServerNotFound` and have their lent memory
+    /// returned, as are any of this process' threads waiting for a message.
+    /// Existing connections to the server fail with `ServerNotFound`.
+    ///
+    /// # Errors
+    ///
+    /// * **ServerNotFound**: The server does not exist or belongs to another process
+    DestroyServer(SID),
+
+    /// Restrict what clients may send to a server owned by this process. If a
+    /// PID is given, the rights apply to that process alone, including any
+    /// connection it already holds. Otherwise they become the default for
+    /// every process that hasn't been given rights of its own. Messages that
+    /// are not allowed fail with `AccessDenied` before they reach the server.
+    ///
+    /// # Errors
+    ///
+    /// * **ServerNotFound**: The server does not exist or belongs to another process
+    SetConnectionRights(SID, Option<PID>, ConnectionRights),
+
+    /// Choose whether a server owned by this process must approve new
+    /// connections. While enabled, a process that connects for the first time
+    /// is blocked, and the server receives a `BlockingScalar` message with the
+    /// ID `CONNECT_REQUEST_ID` and the PID being connected in `arg1`. The
+    /// connection is made if the server returns a nonzero value, and fails
+    /// with `AccessDenied` otherwise. The server's own process never needs
+    /// approval.
+    ///
+    /// # Errors
+    ///
+    /// * **ServerNotFound**: The server does not exist or belongs to another process
+    RequireConnectApproval(SID, bool),
+
+    /// Set event bits on the server that the given connection goes to. Bits
+    /// that are already set stay set, and if one of the server's threads is
+    /// waiting in `WaitEvents` it is woken up with them. This never blocks,
+    /// so it may be used to notify a server without a message round-trip.
+    ///
+    /// # Errors
+    ///
+    /// * **ServerNotFound**: The connection is not valid
+    /// * **AccessDenied**: The connection's rights don't include `MessageKinds::EVENTS`
+    SignalEvents(CID, usize),
+
+    /// Wait until at least one event bit has been set on a server owned by
+    /// this process, then return every bit that is set and clear them all.
+    ///
+    /// # Errors
+    ///
+    /// * **ServerNotFound**: The server does not exist or belongs to another process
+    WaitEvents(SID),
+
+    /// Return and clear the event bits that are set on a server owned by
+    /// this process. Returns zero rather than blocking if none are set.
+    ///
+    /// # Errors
+    ///
+    /// * **ServerNotFound**: The server does not exist or belongs to another process
+    TryWaitEvents(SID),
+
+    /// Block the current thread until the futex at the given address is woken
+    /// by `FutexWake`, unless the futex no longer holds the expected value,
+    /// in which case this returns immediately. Futexes are private to a
+    /// process, and may wake up spuriously.
+    ///
+    /// # Errors
+    ///
+    /// * **BadAlignment**: The address isn't word-aligned
+    /// * **BadAddress**: The address isn't readable by this process
+    /// * **OutOfMemory**: Too many threads are waiting on futexes
+    FutexWait(MemoryAddress, usize),
+
+    /// Wake up to the given number of threads that are waiting on the futex at
+    /// the given address, and return how many were woken.
+    FutexWake(MemoryAddress, usize),
+
+    /// End the current thread with the given exit code. A thread that returns
+    /// from its entrypoint exits with its return value as the code. The exit
+    /// code is kept until another thread in this process collects it with
+    /// `JoinThread`, unless the thread has been detached.
+    ExitThread(usize),
+
+    /// Block until the given thread in this process exits, then free it and
+    /// return its exit code.
+    ///
+    /// # Errors
+    ///
+    /// * **InvalidThread**: The thread does not exist, is the calling thread,
+    ///                      or is already being joined by another thread
+    /// * **OutOfMemory**: Too many threads are waiting to join other threads
+    JoinThread(TID),
+
+    /// Say that nobody will join the given thread in this process, so that it
+    /// is freed as soon as it exits and its exit code is thrown away. If a
+    /// stack is given, it is unmapped once the thread has stopped running. A
+    /// thread that has already exited is freed right away.
+    ///
+    /// # Errors
+    ///
+    /// * **InvalidThread**: The thread does not exist, or is already being joined or detached
+    /// * **OutOfMemory**: Too many threads have been detached
+    DetachThread(TID, Option<MemoryRange>),
+
+    /// Choose whether a server owned by this process is told when its clients
+    /// terminate. While enabled, the server receives a `Scalar` message with
+    /// the ID `CLIENT_DISCONNECTED_ID` whenever a process that is connected to
+    /// it terminates.
+    ///
+    /// # Errors
+    ///
+    /// * **ServerNotFound**: The server does not exist or belongs to another process
+    NotifyDisconnects(SID, bool),
+
+    /// Return the PID of the process that owns a server. A server such as
+    /// `xous-names` uses this to check that a SID it is given really belongs
+    /// to the process that sent it.
+    ///
+    /// # Returns
+    ///
+    /// A `ProcessID` holding the owner of the server
+    ///
+    /// # Errors
+    ///
+    /// * **ServerNotFound**: The server does not exist
+    ServerOwner(SID),
+
+    /// Return the ID of the calling thread.
+    GetThreadId,
+
+    /// Block until the given child process terminates, then free it and
+    /// return its exit code. A process that the kernel terminated because it
+    /// ran out of memory has the exit code `Error::OutOfMemory.to_usize()`.
+    ///
+    /// # Errors
+    ///
+    /// * **ProcessNotFound**: The process does not exist, or has already been
+    ///                        waited for
+    /// * **ProcessNotChild**: The process is not a child of the caller
+    /// * **OutOfMemory**: Too many threads are waiting for processes to
+    ///                    terminate
+    WaitProcess(PID),
+
+    /// Turn a range that `MapMemory` just returned, and that hasn't been
+    /// touched yet, into a region that other processes may map. The caller
+    /// holds the first reference to it, with `MemoryFlags`.
+    ///
+    /// # Returns
+    ///
+    /// A `Scalar1` holding the ID of the new region
+    ///
+    /// # Errors
+    ///
+    /// * **BadAddress**: The range isn't in userspace
+    /// * **MemoryInUse**: Part of the range is already backed by memory
+    /// * **OutOfMemory**: There is no free run of pages that large, or too many regions exist
+    CreateSharedMemory(MemoryRange, MemoryFlags),
+
+    /// Give process `PID` a reference to a shared memory region that the
+    /// caller holds, which lets it map the region with `MemoryFlags`. These
+    /// may not include anything that the caller itself lacks.
+    ///
+    /// # Errors
+    ///
+    /// * **AccessDenied**: The caller doesn't hold the region, or holds it with fewer flags
+    /// * **ProcessNotFound**: The process doesn't exist
+    /// * **MemoryInUse**: The process already holds the region
+    /// * **OutOfMemory**: The region already has as many holders as it can have
+    GrantSharedMemory(SharedMemoryId, PID, MemoryFlags),
+
+    /// Map a shared memory region that the caller has been given a reference
+    /// to.
+    ///
+    /// # Returns
+    ///
+    /// The range where the region was mapped
+    ///
+    /// # Errors
+    ///
+    /// * **AccessDenied**: The region doesn't exist, or the caller doesn't hold it
+    /// * **MemoryInUse**: The caller has already mapped the region
+    /// * **BadAddress**: There is no room for the region in the address space
+    MapSharedMemory(SharedMemoryId),
+
+    /// Give up the caller's reference to a shared memory region, unmapping it
+    /// first if the caller had mapped it. The region is freed along with its
+    /// last reference.
+    ///
+    /// # Returns
+    ///
+    /// `Ok`, or in hosted mode the region's `MemoryRange` once it has been
+    /// freed, since that memory was allocated by a process and not the kernel
+    ///
+    /// # Errors
+    ///
+    /// * **AccessDenied**: The region doesn't exist, or the caller doesn't hold it
+    UnmapSharedMemory(SharedMemoryId),
+
+    /// Report how much memory the given process is using, along with how
+    /// much is free in the whole system. Any process may ask about any other.
+    ///
+    /// # Returns
+    ///
+    /// A `MemoryStats` describing the process
+    ///
+    /// # Errors
+    ///
+    /// * **ProcessNotFound**: The process does not exist, or has terminated
+    GetMemoryStats(PID),
+
+    /// Whenever a message is queued on a server owned by this process, set
+    /// event bits on the server that the given connection goes to, as if with
+    /// `SignalEvents`. If messages are already queued, the bits are set
+    /// straight away. Messages that are handed directly to a thread waiting
+    /// in `ReceiveMessage` don't set anything. An event mask of zero stops
+    /// the notifications.
+    ///
+    /// # Errors
+    ///
+    /// * **ServerNotFound**: The server does not exist or belongs to another
+    ///                       process, or the connection is not valid
+    /// * **AccessDenied**: The connection's rights don't include `MessageKinds::EVENTS`
+    SignalOnMessage(SID, CID, usize),
+
+    /// Return a Borrowed memory region to the sender, along with the offset
+    /// and the number of valid bytes. The sender's `send_message()` returns
+    /// these as `Result::MemoryReturned`.
+    ///
+    /// # Errors
+    ///
+    /// * **ServerNotFound**: The message was not sent to a server owned by this process
+    /// * **ProcessNotFound**: There is no message waiting for this sender
+    ReturnMemoryWithValid(
+        MessageSender,
+        MemoryRange,
+        Option<MemoryAddress>, /* offset */
+        Option<MemorySize>,    /* valid */
+    ),
+
+    /// Connect another process to a server without waiting. This is how a
+    /// server such as `xous-names` hands out connections without blocking on
+    /// servers that approve their clients.
+    ///
+    /// # Errors
+    ///
+    /// * **ServerNotFound**: The server could not be found.
+    /// * **AccessDenied**: The server requires approval for new connections
+    TryConnectForProcess(PID, SID),
 
     /// This syscall does not exist. It captures all possible
     /// arguments so detailed analysis can be performed.
@@ -329,6 +658,33 @@ pub enum SysCallNumber {
     TryReceiveMessage = 28,
     CreateServer = 29,
     ConnectForProcess = 30,
+    SetPriority = 31,
+    SendMessageTimeout = 32,
+    ReceiveMessageTimeout = 33,
+    Disconnect = 34,
+    DestroyServer = 35,
+    SetConnectionRights = 36,
+    RequireConnectApproval = 37,
+    SignalEvents = 38,
+    WaitEvents = 39,
+    TryWaitEvents = 40,
+    FutexWait = 41,
+    FutexWake = 42,
+    ExitThread = 43,
+    JoinThread = 44,
+    GetThreadId = 45,
+    WaitProcess = 46,
+    CreateSharedMemory = 47,
+    GrantSharedMemory = 48,
+    MapSharedMemory = 49,
+    UnmapSharedMemory = 50,
+    GetMemoryStats = 51,
+    SignalOnMessage = 52,
+    ReturnMemoryWithValid = 53,
+    TryConnectForProcess = 54,
+    DetachThread = 55,
+    NotifyDisconnects = 56,
+    ServerOwner = 57,
     Invalid,
 }
 
@@ -365,6 +721,33 @@ impl SysCallNumber {
             28 => TryReceiveMessage,
             29 => CreateServer,
             30 => ConnectForProcess,
+            31 => SetPriority,
+            32 => SendMessageTimeout,
+            33 => ReceiveMessageTimeout,
+            34 => Disconnect,
+            35 => DestroyServer,
+            36 => SetConnectionRights,
+            37 => RequireConnectApproval,
+            38 => SignalEvents,
+            39 => WaitEvents,
+            40 => TryWaitEvents,
+            41 => FutexWait,
+            42 => FutexWake,
+            43 => ExitThread,
+            44 => JoinThread,
+            45 => GetThreadId,
+            46 => WaitProcess,
+            47 => CreateSharedMemory,
+            48 => GrantSharedMemory,
+            49 => MapSharedMemory,
+            50 => UnmapSharedMemory,
+            51 => GetMemoryStats,
+            52 => SignalOnMessage,
+            53 => ReturnMemoryWithValid,
+            54 => TryConnectForProcess,
+            55 => DetachThread,
+            56 => NotifyDisconnects,
+            57 => ServerOwner,
             _ => Invalid,
         }
     }
@@ -430,8 +813,9 @@ impl SysCall {
                     0,
                 ]
             }
-            SysCall::ConnectForProcess(pid, sid) => {
+            SysCall::ConnectForProcess(pid, sid, rights) => {
                 let s = sid.to_u32();
+                let r = rights.map(|r| r.to_usize()).unwrap_or([0, 0]);
                 [
                     SysCallNumber::ConnectForProcess as usize,
                     pid.get() as _,
@@ -439,8 +823,8 @@ impl SysCall {
                     s.1 as _,
                     s.2 as _,
                     s.3 as _,
-                    0,
-                    0,
+                    r[0],
+                    r[1],
                 ]
             }
             SysCall::ReturnToParent(a1, a2) => [
@@ -592,9 +976,9 @@ impl SysCall {
             SysCall::CreateProcess(init) => {
                 crate::arch::process_to_args(SysCallNumber::CreateProcess as usize, init)
             }
-            SysCall::TerminateProcess => [
+            SysCall::TerminateProcess(exit_code) => [
                 SysCallNumber::TerminateProcess as usize,
-                0,
+                *exit_code,
                 0,
                 0,
                 0,
@@ -658,121 +1042,409 @@ impl SysCall {
                 0,
                 0,
             ],
-            SysCall::Invalid(a1, a2, a3, a4, a5, a6, a7) => [
-                SysCallNumber::Invalid as usize,
-                *a1,
-                *a2,
-                *a3,
-                *a4,
-                *a5,
-                *a6,
-                *a7,
+            SysCall::SetPriority(pid, priority) => [
+                SysCallNumber::SetPriority as usize,
+                pid.map(|p| p.get() as usize).unwrap_or_default(),
+                *priority as usize,
+                0,
+                0,
+                0,
+                0,
+                0,
             ],
-        }
-    }
-
-    #[allow(clippy::too_many_arguments)]
-    pub fn from_args(
-        a0: usize,
-        a1: usize,
-        a2: usize,
-        a3: usize,
-        a4: usize,
-        a5: usize,
-        a6: usize,
-        a7: usize,
-    ) -> core::result::Result<Self, Error> {
-        Ok(match SysCallNumber::from(a0) {
-            SysCallNumber::MapMemory => SysCall::MapMemory(
-                MemoryAddress::new(a1),
-                MemoryAddress::new(a2),
-                MemoryAddress::new(a3).ok_or(Error::InvalidSyscall)?,
-                MemoryFlags::from_bits(a4).ok_or(Error::InvalidSyscall)?,
-            ),
-            SysCallNumber::UnmapMemory => {
-                SysCall::UnmapMemory(MemoryRange::new(a1, a2).or(Err(Error::InvalidSyscall))?)
+            SysCall::SendMessageTimeout(a1, ref a2, timeout) => {
+                // The message type only needs the bottom eight bits, so the
+                // timeout is packed in above it.
+                let msg_type = a2.message_type() | (*timeout << 8);
+                match a2 {
+                    Message::MutableBorrow(mm) | Message::Borrow(mm) | Message::Move(mm) => [
+                        SysCallNumber::SendMessageTimeout as usize,
+                        *a1,
+                        msg_type,
+                        mm.id as usize,
+                        mm.buf.as_ptr() as usize,
+                        mm.buf.len(),
+                        mm.offset.map(|x| x.get()).unwrap_or(0) as usize,
+                        mm.valid.map(|x| x.get()).unwrap_or(0) as usize,
+                    ],
+                    Message::Scalar(sc) | Message::BlockingScalar(sc) => [
+                        SysCallNumber::SendMessageTimeout as usize,
+                        *a1,
+                        msg_type,
+                        sc.id as usize,
+                        sc.arg1,
+                        sc.arg2,
+                        sc.arg3,
+                        sc.arg4,
+                    ],
+                }
             }
-            SysCallNumber::Yield => SysCall::Yield,
-            SysCallNumber::WaitEvent => SysCall::WaitEvent,
-            SysCallNumber::ReceiveMessage => {
-                SysCall::ReceiveMessage(SID::from_u32(a1 as _, a2 as _, a3 as _, a4 as _))
+            SysCall::ReceiveMessageTimeout(sid, timeout) => {
+                let s = sid.to_u32();
+                [
+                    SysCallNumber::ReceiveMessageTimeout as usize,
+                    s.0 as _,
+                    s.1 as _,
+                    s.2 as _,
+                    s.3 as _,
+                    *timeout,
+                    0,
+                    0,
+                ]
             }
-            SysCallNumber::TryReceiveMessage => {
-                SysCall::TryReceiveMessage(SID::from_u32(a1 as _, a2 as _, a3 as _, a4 as _))
+            SysCall::Disconnect(cid) => {
+                [SysCallNumber::Disconnect as usize, *cid, 0, 0, 0, 0, 0, 0]
             }
-            SysCallNumber::ReturnToParent => SysCall::ReturnToParent(pid_from_usize(a1)?, a2),
-            SysCallNumber::ClaimInterrupt => SysCall::ClaimInterrupt(
-                a1,
-                MemoryAddress::new(a2).ok_or(Error::InvalidSyscall)?,
-                MemoryAddress::new(a3),
-            ),
-            SysCallNumber::FreeInterrupt => SysCall::FreeInterrupt(a1),
-            SysCallNumber::SwitchTo => SysCall::SwitchTo(pid_from_usize(a1)?, a2 as usize),
-            SysCallNumber::ReadyThreads => SysCall::ReadyThreads(pid_from_usize(a1)?),
-            SysCallNumber::IncreaseHeap => SysCall::IncreaseHeap(
-                a1 as usize,
-                MemoryFlags::from_bits(a2).ok_or(Error::InvalidSyscall)?,
-            ),
-            SysCallNumber::DecreaseHeap => SysCall::DecreaseHeap(a1 as usize),
-            SysCallNumber::UpdateMemoryFlags => SysCall::UpdateMemoryFlags(
-                MemoryAddress::new(a1).ok_or(Error::InvalidSyscall)?,
-                a2 as usize,
-                MemoryFlags::from_bits(a3).ok_or(Error::InvalidSyscall)?,
-            ),
-            SysCallNumber::SetMemRegion => SysCall::SetMemRegion(
-                pid_from_usize(a1)?,
-                MemoryType::from(a2),
-                MemoryAddress::new(a3).ok_or(Error::InvalidSyscall)?,
-                a4,
-            ),
-            SysCallNumber::CreateServerWithAddress => {
-                SysCall::CreateServerWithAddress(SID::from_u32(a1 as _, a2 as _, a3 as _, a4 as _))
+            SysCall::DestroyServer(sid) => {
+                let s = sid.to_u32();
+                [
+                    SysCallNumber::DestroyServer as usize,
+                    s.0 as _,
+                    s.1 as _,
+                    s.2 as _,
+                    s.3 as _,
+                    0,
+                    0,
+                    0,
+                ]
             }
-            SysCallNumber::CreateServer => SysCall::CreateServer,
-            SysCallNumber::Connect => {
-                SysCall::Connect(SID::from_u32(a1 as _, a2 as _, a3 as _, a4 as _))
+            SysCall::SetConnectionRights(sid, pid, rights) => {
+                let s = sid.to_u32();
+                let r = rights.to_usize();
+                [
+                    SysCallNumber::SetConnectionRights as usize,
+                    s.0 as _,
+                    s.1 as _,
+                    s.2 as _,
+                    s.3 as _,
+                    pid.map(|p| p.get() as usize).unwrap_or(0),
+                    r[0],
+                    r[1],
+                ]
             }
-            SysCallNumber::SendMessage => match a2 {
-                1 => SysCall::SendMessage(
-                    a1,
-                    Message::MutableBorrow(MemoryMessage {
-                        id: a3,
-                        buf: MemoryRange::new(a4, a5)?,
-                        offset: MemoryAddress::new(a6),
-                        valid: MemorySize::new(a7),
-                    }),
-                ),
-                2 => SysCall::SendMessage(
-                    a1,
-                    Message::Borrow(MemoryMessage {
-                        id: a3,
-                        buf: MemoryRange::new(a4, a5)?,
-                        offset: MemoryAddress::new(a6),
-                        valid: MemorySize::new(a7),
-                    }),
-                ),
-                3 => SysCall::SendMessage(
-                    a1,
-                    Message::Move(MemoryMessage {
-                        id: a3,
-                        buf: MemoryRange::new(a4, a5)?,
-                        offset: MemoryAddress::new(a6),
-                        valid: MemorySize::new(a7),
-                    }),
-                ),
-                4 => SysCall::SendMessage(
-                    a1,
-                    Message::Scalar(ScalarMessage {
-                        id: a3,
-                        arg1: a4,
-                        arg2: a5,
-                        arg3: a6,
-                        arg4: a7,
-                    }),
-                ),
-                5 => SysCall::SendMessage(
-                    a1,
-                    Message::BlockingScalar(ScalarMessage {
+            SysCall::RequireConnectApproval(sid, required) => {
+                let s = sid.to_u32();
+                [
+                    SysCallNumber::RequireConnectApproval as usize,
+                    s.0 as _,
+                    s.1 as _,
+                    s.2 as _,
+                    s.3 as _,
+                    *required as usize,
+                    0,
+                    0,
+                ]
+            }
+            SysCall::SignalEvents(cid, events) => [
+                SysCallNumber::SignalEvents as usize,
+                *cid,
+                *events,
+                0,
+                0,
+                0,
+                0,
+                0,
+            ],
+            SysCall::WaitEvents(sid) => {
+                let s = sid.to_u32();
+                [
+                    SysCallNumber::WaitEvents as usize,
+                    s.0 as _,
+                    s.1 as _,
+                    s.2 as _,
+                    s.3 as _,
+                    0,
+                    0,
+                    0,
+                ]
+            }
+            SysCall::TryWaitEvents(sid) => {
+                let s = sid.to_u32();
+                [
+                    SysCallNumber::TryWaitEvents as usize,
+                    s.0 as _,
+                    s.1 as _,
+                    s.2 as _,
+                    s.3 as _,
+                    0,
+                    0,
+                    0,
+                ]
+            }
+            SysCall::FutexWait(addr, expected) => [
+                SysCallNumber::FutexWait as usize,
+                addr.get(),
+                *expected,
+                0,
+                0,
+                0,
+                0,
+                0,
+            ],
+            SysCall::FutexWake(addr, count) => [
+                SysCallNumber::FutexWake as usize,
+                addr.get(),
+                *count,
+                0,
+                0,
+                0,
+                0,
+                0,
+            ],
+            SysCall::ExitThread(code) => {
+                [SysCallNumber::ExitThread as usize, *code, 0, 0, 0, 0, 0, 0]
+            }
+            SysCall::JoinThread(tid) => {
+                [SysCallNumber::JoinThread as usize, *tid, 0, 0, 0, 0, 0, 0]
+            }
+            SysCall::GetThreadId => [SysCallNumber::GetThreadId as usize, 0, 0, 0, 0, 0, 0, 0],
+            SysCall::WaitProcess(pid) => [
+                SysCallNumber::WaitProcess as usize,
+                pid.get() as usize,
+                0,
+                0,
+                0,
+                0,
+                0,
+                0,
+            ],
+            SysCall::CreateSharedMemory(range, flags) => [
+                SysCallNumber::CreateSharedMemory as usize,
+                range.addr.get(),
+                range.size.get(),
+                flags.bits(),
+                0,
+                0,
+                0,
+                0,
+            ],
+            SysCall::GrantSharedMemory(id, pid, flags) => [
+                SysCallNumber::GrantSharedMemory as usize,
+                *id,
+                pid.get() as usize,
+                flags.bits(),
+                0,
+                0,
+                0,
+                0,
+            ],
+            SysCall::MapSharedMemory(id) => [
+                SysCallNumber::MapSharedMemory as usize,
+                *id,
+                0,
+                0,
+                0,
+                0,
+                0,
+                0,
+            ],
+            SysCall::UnmapSharedMemory(id) => [
+                SysCallNumber::UnmapSharedMemory as usize,
+                *id,
+                0,
+                0,
+                0,
+                0,
+                0,
+                0,
+            ],
+            SysCall::GetMemoryStats(pid) => [
+                SysCallNumber::GetMemoryStats as usize,
+                pid.get() as usize,
+                0,
+                0,
+                0,
+                0,
+                0,
+                0,
+            ],
+            SysCall::SignalOnMessage(sid, cid, events) => {
+                let s = sid.to_u32();
+                [
+                    SysCallNumber::SignalOnMessage as usize,
+                    s.0 as _,
+                    s.1 as _,
+                    s.2 as _,
+                    s.3 as _,
+                    *cid,
+                    *events,
+                    0,
+                ]
+            }
+            SysCall::ReturnMemoryWithValid(sender, buf, offset, valid) => [
+                SysCallNumber::ReturnMemoryWithValid as usize,
+                sender.to_usize(),
+                buf.as_ptr() as usize,
+                buf.len(),
+                offset.map(|o| o.get()).unwrap_or(0),
+                valid.map(|v| v.get()).unwrap_or(0),
+                0,
+                0,
+            ],
+            SysCall::TryConnectForProcess(pid, sid) => {
+                let s = sid.to_u32();
+                [
+                    SysCallNumber::TryConnectForProcess as usize,
+                    pid.get() as _,
+                    s.0 as _,
+                    s.1 as _,
+                    s.2 as _,
+                    s.3 as _,
+                    0,
+                    0,
+                ]
+            }
+            SysCall::DetachThread(tid, stack) => [
+                SysCallNumber::DetachThread as usize,
+                *tid,
+                stack.map(|s| s.as_ptr() as usize).unwrap_or(0),
+                stack.map(|s| s.len()).unwrap_or(0),
+                0,
+                0,
+                0,
+                0,
+            ],
+            SysCall::NotifyDisconnects(sid, enabled) => {
+                let s = sid.to_u32();
+                [
+                    SysCallNumber::NotifyDisconnects as usize,
+                    s.0 as _,
+                    s.1 as _,
+                    s.2 as _,
+                    s.3 as _,
+                    *enabled as usize,
+                    0,
+                    0,
+                ]
+            }
+            SysCall::ServerOwner(sid) => {
+                let s = sid.to_u32();
+                [
+                    SysCallNumber::ServerOwner as usize,
+                    s.0 as _,
+                    s.1 as _,
+                    s.2 as _,
+                    s.3 as _,
+                    0,
+                    0,
+                    0,
+                ]
+            }
+            SysCall::Invalid(a1, a2, a3, a4, a5, a6, a7) => [
+                SysCallNumber::Invalid as usize,
+                *a1,
+                *a2,
+                *a3,
+                *a4,
+                *a5,
+                *a6,
+                *a7,
+            ],
+        }
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    pub fn from_args(
+        a0: usize,
+        a1: usize,
+        a2: usize,
+        a3: usize,
+        a4: usize,
+        a5: usize,
+        a6: usize,
+        a7: usize,
+    ) -> core::result::Result<Self, Error> {
+        Ok(match SysCallNumber::from(a0) {
+            SysCallNumber::MapMemory => SysCall::MapMemory(
+                MemoryAddress::new(a1),
+                MemoryAddress::new(a2),
+                MemoryAddress::new(a3).ok_or(Error::InvalidSyscall)?,
+                MemoryFlags::from_bits(a4).ok_or(Error::InvalidSyscall)?,
+            ),
+            SysCallNumber::UnmapMemory => {
+                SysCall::UnmapMemory(MemoryRange::new(a1, a2).or(Err(Error::InvalidSyscall))?)
+            }
+            SysCallNumber::Yield => SysCall::Yield,
+            SysCallNumber::WaitEvent => SysCall::WaitEvent,
+            SysCallNumber::ReceiveMessage => {
+                SysCall::ReceiveMessage(SID::from_u32(a1 as _, a2 as _, a3 as _, a4 as _))
+            }
+            SysCallNumber::TryReceiveMessage => {
+                SysCall::TryReceiveMessage(SID::from_u32(a1 as _, a2 as _, a3 as _, a4 as _))
+            }
+            SysCallNumber::ReturnToParent => SysCall::ReturnToParent(pid_from_usize(a1)?, a2),
+            SysCallNumber::ClaimInterrupt => SysCall::ClaimInterrupt(
+                a1,
+                MemoryAddress::new(a2).ok_or(Error::InvalidSyscall)?,
+                MemoryAddress::new(a3),
+            ),
+            SysCallNumber::FreeInterrupt => SysCall::FreeInterrupt(a1),
+            SysCallNumber::SwitchTo => SysCall::SwitchTo(pid_from_usize(a1)?, a2 as usize),
+            SysCallNumber::ReadyThreads => SysCall::ReadyThreads(pid_from_usize(a1)?),
+            SysCallNumber::IncreaseHeap => SysCall::IncreaseHeap(
+                a1 as usize,
+                MemoryFlags::from_bits(a2).ok_or(Error::InvalidSyscall)?,
+            ),
+            SysCallNumber::DecreaseHeap => SysCall::DecreaseHeap(a1 as usize),
+            SysCallNumber::UpdateMemoryFlags => SysCall::UpdateMemoryFlags(
+                MemoryAddress::new(a1).ok_or(Error::InvalidSyscall)?,
+                a2 as usize,
+                MemoryFlags::from_bits(a3).ok_or(Error::InvalidSyscall)?,
+            ),
+            SysCallNumber::SetMemRegion => SysCall::SetMemRegion(
+                pid_from_usize(a1)?,
+                MemoryType::from(a2),
+                MemoryAddress::new(a3).ok_or(Error::InvalidSyscall)?,
+                a4,
+            ),
+            SysCallNumber::CreateServerWithAddress => {
+                SysCall::CreateServerWithAddress(SID::from_u32(a1 as _, a2 as _, a3 as _, a4 as _))
+            }
+            SysCallNumber::CreateServer => SysCall::CreateServer,
+            SysCallNumber::Connect => {
+                SysCall::Connect(SID::from_u32(a1 as _, a2 as _, a3 as _, a4 as _))
+            }
+            SysCallNumber::SendMessage => match a2 {
+                1 => SysCall::SendMessage(
+                    a1,
+                    Message::MutableBorrow(MemoryMessage {
+                        id: a3,
+                        buf: MemoryRange::new(a4, a5)?,
+                        offset: MemoryAddress::new(a6),
+                        valid: MemorySize::new(a7),
+                    }),
+                ),
+                2 => SysCall::SendMessage(
+                    a1,
+                    Message::Borrow(MemoryMessage {
+                        id: a3,
+                        buf: MemoryRange::new(a4, a5)?,
+                        offset: MemoryAddress::new(a6),
+                        valid: MemorySize::new(a7),
+                    }),
+                ),
+                3 => SysCall::SendMessage(
+                    a1,
+                    Message::Move(MemoryMessage {
+                        id: a3,
+                        buf: MemoryRange::new(a4, a5)?,
+                        offset: MemoryAddress::new(a6),
+                        valid: MemorySize::new(a7),
+                    }),
+                ),
+                4 => SysCall::SendMessage(
+                    a1,
+                    Message::Scalar(ScalarMessage {
+                        id: a3,
+                        arg1: a4,
+                        arg2: a5,
+                        arg3: a6,
+                        arg4: a7,
+                    }),
+                ),
+                5 => SysCall::SendMessage(
+                    a1,
+                    Message::BlockingScalar(ScalarMessage {
                         id: a3,
                         arg1: a4,
                         arg2: a5,
@@ -791,7 +1463,7 @@ impl SysCall {
             SysCallNumber::CreateProcess => {
                 SysCall::CreateProcess(crate::arch::args_to_process(a1, a2, a3, a4, a5, a6, a7)?)
             }
-            SysCallNumber::TerminateProcess => SysCall::TerminateProcess,
+            SysCallNumber::TerminateProcess => SysCall::TerminateProcess(a1),
             SysCallNumber::Shutdown => SysCall::Shutdown,
             SysCallNumber::TryConnect => {
                 SysCall::TryConnect(SID::from_u32(a1 as _, a2 as _, a3 as _, a4 as _))
@@ -855,7 +1527,143 @@ impl SysCall {
             SysCallNumber::ConnectForProcess => SysCall::ConnectForProcess(
                 PID::new(a1 as _).ok_or(Error::InvalidSyscall)?,
                 SID::from_u32(a2 as _, a3 as _, a4 as _, a5 as _),
+                // A grant with no message kinds is meaningless, so a zero
+                // marks the absence of a grant.
+                if a6 == 0 {
+                    None
+                } else {
+                    Some(ConnectionRights::from_usize(a6, a7).ok_or(Error::InvalidSyscall)?)
+                },
+            ),
+            SysCallNumber::SetPriority => SysCall::SetPriority(
+                if a1 == 0 {
+                    None
+                } else {
+                    Some(pid_from_usize(a1)?)
+                },
+                if a2 > Priority::MAX as usize {
+                    return Err(Error::InvalidSyscall);
+                } else {
+                    a2 as Priority
+                },
+            ),
+            SysCallNumber::SendMessageTimeout => SysCall::SendMessageTimeout(
+                a1,
+                match a2 & 0xff {
+                    1 => Message::MutableBorrow(MemoryMessage {
+                        id: a3,
+                        buf: MemoryRange::new(a4, a5)?,
+                        offset: MemoryAddress::new(a6),
+                        valid: MemorySize::new(a7),
+                    }),
+                    2 => Message::Borrow(MemoryMessage {
+                        id: a3,
+                        buf: MemoryRange::new(a4, a5)?,
+                        offset: MemoryAddress::new(a6),
+                        valid: MemorySize::new(a7),
+                    }),
+                    3 => Message::Move(MemoryMessage {
+                        id: a3,
+                        buf: MemoryRange::new(a4, a5)?,
+                        offset: MemoryAddress::new(a6),
+                        valid: MemorySize::new(a7),
+                    }),
+                    4 => Message::Scalar(ScalarMessage {
+                        id: a3,
+                        arg1: a4,
+                        arg2: a5,
+                        arg3: a6,
+                        arg4: a7,
+                    }),
+                    5 => Message::BlockingScalar(ScalarMessage {
+                        id: a3,
+                        arg1: a4,
+                        arg2: a5,
+                        arg3: a6,
+                        arg4: a7,
+                    }),
+                    _ => return Ok(SysCall::Invalid(a1, a2, a3, a4, a5, a6, a7)),
+                },
+                a2 >> 8,
+            ),
+            SysCallNumber::ReceiveMessageTimeout => SysCall::ReceiveMessageTimeout(
+                SID::from_u32(a1 as _, a2 as _, a3 as _, a4 as _),
+                a5,
+            ),
+            SysCallNumber::Disconnect => SysCall::Disconnect(a1),
+            SysCallNumber::DestroyServer => {
+                SysCall::DestroyServer(SID::from_u32(a1 as _, a2 as _, a3 as _, a4 as _))
+            }
+            SysCallNumber::SetConnectionRights => SysCall::SetConnectionRights(
+                SID::from_u32(a1 as _, a2 as _, a3 as _, a4 as _),
+                if a5 == 0 {
+                    None
+                } else {
+                    Some(pid_from_usize(a5)?)
+                },
+                ConnectionRights::from_usize(a6, a7).ok_or(Error::InvalidSyscall)?,
+            ),
+            SysCallNumber::RequireConnectApproval => SysCall::RequireConnectApproval(
+                SID::from_u32(a1 as _, a2 as _, a3 as _, a4 as _),
+                a5 != 0,
+            ),
+            SysCallNumber::SignalEvents => SysCall::SignalEvents(a1, a2),
+            SysCallNumber::WaitEvents => {
+                SysCall::WaitEvents(SID::from_u32(a1 as _, a2 as _, a3 as _, a4 as _))
+            }
+            SysCallNumber::TryWaitEvents => {
+                SysCall::TryWaitEvents(SID::from_u32(a1 as _, a2 as _, a3 as _, a4 as _))
+            }
+            SysCallNumber::FutexWait => {
+                SysCall::FutexWait(MemoryAddress::new(a1).ok_or(Error::InvalidSyscall)?, a2)
+            }
+            SysCallNumber::FutexWake => {
+                SysCall::FutexWake(MemoryAddress::new(a1).ok_or(Error::InvalidSyscall)?, a2)
+            }
+            SysCallNumber::ExitThread => SysCall::ExitThread(a1),
+            SysCallNumber::JoinThread => SysCall::JoinThread(a1),
+            SysCallNumber::GetThreadId => SysCall::GetThreadId,
+            SysCallNumber::WaitProcess => SysCall::WaitProcess(pid_from_usize(a1)?),
+            SysCallNumber::CreateSharedMemory => SysCall::CreateSharedMemory(
+                MemoryRange::new(a1, a2).or(Err(Error::InvalidSyscall))?,
+                MemoryFlags::from_bits(a3).ok_or(Error::InvalidSyscall)?,
+            ),
+            SysCallNumber::GrantSharedMemory => SysCall::GrantSharedMemory(
+                a1,
+                pid_from_usize(a2)?,
+                MemoryFlags::from_bits(a3).ok_or(Error::InvalidSyscall)?,
+            ),
+            SysCallNumber::MapSharedMemory => SysCall::MapSharedMemory(a1),
+            SysCallNumber::UnmapSharedMemory => SysCall::UnmapSharedMemory(a1),
+            SysCallNumber::GetMemoryStats => SysCall::GetMemoryStats(pid_from_usize(a1)?),
+            SysCallNumber::SignalOnMessage => {
+                SysCall::SignalOnMessage(SID::from_u32(a1 as _, a2 as _, a3 as _, a4 as _), a5, a6)
+            }
+            SysCallNumber::ReturnMemoryWithValid => SysCall::ReturnMemoryWithValid(
+                MessageSender::from_usize(a1),
+                MemoryRange::new(a2, a3)?,
+                MemoryAddress::new(a4),
+                MemorySize::new(a5),
+            ),
+            SysCallNumber::TryConnectForProcess => SysCall::TryConnectForProcess(
+                PID::new(a1 as _).ok_or(Error::InvalidSyscall)?,
+                SID::from_u32(a2 as _, a3 as _, a4 as _, a5 as _),
+            ),
+            SysCallNumber::DetachThread => SysCall::DetachThread(
+                a1,
+                if a2 == 0 {
+                    None
+                } else {
+                    Some(MemoryRange::new(a2, a3)?)
+                },
+            ),
+            SysCallNumber::NotifyDisconnects => SysCall::NotifyDisconnects(
+                SID::from_u32(a1 as _, a2 as _, a3 as _, a4 as _),
+                a5 != 0,
             ),
+            SysCallNumber::ServerOwner => {
+                SysCall::ServerOwner(SID::from_u32(a1 as _, a2 as _, a3 as _, a4 as _))
+            }
             SysCallNumber::Invalid => SysCall::Invalid(a1, a2, a3, a4, a5, a6, a7),
         })
     }
@@ -863,10 +1671,13 @@ impl SysCall {
     /// Returns `true` if the associated syscall is a message that has memory attached to it
     pub fn has_memory(&self) -> bool {
         match self {
-            SysCall::TrySendMessage(_, msg) | SysCall::SendMessage(_, msg) => {
-                matches!(msg, Message::Move(_) | Message::Borrow(_) | Message::MutableBorrow(_))
-            }
-            SysCall::ReturnMemory(_, _) => true,
+            SysCall::TrySendMessage(_, msg)
+            | SysCall::SendMessage(_, msg)
+            | SysCall::SendMessageTimeout(_, msg, _) => matches!(
+                msg,
+                Message::Move(_) | Message::Borrow(_) | Message::MutableBorrow(_)
+            ),
+            SysCall::ReturnMemory(_, _) | SysCall::ReturnMemoryWithValid(_, _, _, _) => true,
             _ => false,
         }
     }
@@ -874,7 +1685,9 @@ impl SysCall {
     /// Returns `true` if the associated syscall is a message that is a Move
     pub fn is_move(&self) -> bool {
         match self {
-            SysCall::TrySendMessage(_, msg) | SysCall::SendMessage(_, msg) => {
+            SysCall::TrySendMessage(_, msg)
+            | SysCall::SendMessage(_, msg)
+            | SysCall::SendMessageTimeout(_, msg, _) => {
                 matches!(msg, Message::Move(_))
             }
             _ => false,
@@ -884,7 +1697,9 @@ impl SysCall {
     /// Returns `true` if the associated syscall is a message that is a Borrow
     pub fn is_borrow(&self) -> bool {
         match self {
-            SysCall::TrySendMessage(_, msg) | SysCall::SendMessage(_, msg) => {
+            SysCall::TrySendMessage(_, msg)
+            | SysCall::SendMessage(_, msg)
+            | SysCall::SendMessageTimeout(_, msg, _) => {
                 matches!(msg, Message::Borrow(_))
             }
             _ => false,
@@ -894,7 +1709,9 @@ impl SysCall {
     /// Returns `true` if the associated syscall is a message that is a MutableBorrow
     pub fn is_mutableborrow(&self) -> bool {
         match self {
-            SysCall::TrySendMessage(_, msg) | SysCall::SendMessage(_, msg) => {
+            SysCall::TrySendMessage(_, msg)
+            | SysCall::SendMessage(_, msg)
+            | SysCall::SendMessageTimeout(_, msg, _) => {
                 matches!(msg, Message::MutableBorrow(_))
             }
             _ => false,
@@ -903,19 +1720,26 @@ impl SysCall {
 
     /// Returns `true` if the associated syscall is returning memory
     pub fn is_return_memory(&self) -> bool {
-        matches!(self, SysCall::ReturnMemory(_, _))
+        matches!(
+            self,
+            SysCall::ReturnMemory(_, _) | SysCall::ReturnMemoryWithValid(_, _, _, _)
+        )
     }
 
     /// If the syscall has memory attached to it, return the memory
     pub fn memory(&self) -> Option<MemoryRange> {
         match self {
-            SysCall::TrySendMessage(_, msg) | SysCall::SendMessage(_, msg) => match msg {
+            SysCall::TrySendMessage(_, msg)
+            | SysCall::SendMessage(_, msg)
+            | SysCall::SendMessageTimeout(_, msg, _) => match msg {
                 Message::Move(memory_message)
                 | Message::Borrow(memory_message)
                 | Message::MutableBorrow(memory_message) => Some(memory_message.buf),
                 _ => None,
             },
-            SysCall::ReturnMemory(_, range) => Some(*range),
+            SysCall::ReturnMemory(_, range) | SysCall::ReturnMemoryWithValid(_, range, _, _) => {
+                Some(*range)
+            }
             _ => None,
         }
     }
@@ -924,11 +1748,15 @@ impl SysCall {
     pub fn can_call_from_interrupt(&self) -> bool {
         matches!(self, SysCall::TrySendMessage(_, _)
             | SysCall::TryConnect(_)
+            | SysCall::TryConnectForProcess(_, _)
             | SysCall::TryReceiveMessage(_)
+            | SysCall::SignalEvents(_, _)
+            | SysCall::TryWaitEvents(_)
             | SysCall::ReturnToParent(_, _)
             | SysCall::ReturnScalar2(_, _, _)
             | SysCall::ReturnScalar1(_, _)
-            | SysCall::ReturnMemory(_, _))
+            | SysCall::ReturnMemory(_, _)
+            | SysCall::ReturnMemoryWithValid(_, _, _, _))
     }
 }
 
@@ -998,6 +1826,118 @@ pub fn unmap_memory(range: MemoryRange) -> core::result::Result<(), Error> {
     }
 }
 
+/// Allocate `size` bytes, which must be a multiple of the page size, as a
+/// region that other processes may map once they have been given a reference
+/// to it with `grant_shared_memory()`. Returns the ID of the region along with
+/// where it is mapped in this process.
+///
+/// In hosted mode every process sees the region at the address it has in the
+/// process that created it, so only processes that share an address space,
+/// such as those started with `create_process_as_thread()`, may share memory.
+///
+/// # Errors
+///
+/// * **BadAlignment**: `size` isn't a multiple of the page size
+/// * **OutOfMemory**: There is no free run of pages that large, or too many regions exist
+pub fn create_shared_memory(
+    size: usize,
+    flags: MemoryFlags,
+) -> core::result::Result<(SharedMemoryId, MemoryRange), Error> {
+    let range = map_memory(None, None, size, flags)?;
+    let result = rsyscall(SysCall::CreateSharedMemory(range, flags));
+    if let Ok(Result::Scalar1(id)) = result {
+        Ok((id, range))
+    } else {
+        unmap_memory(range).ok();
+        match result {
+            Ok(Result::Error(e)) | Err(e) => Err(e),
+            _ => Err(Error::InternalError),
+        }
+    }
+}
+
+/// Let process `pid` map shared memory region `id` with `flags`, which may not
+/// include anything this process lacks. `pid` holds a reference to the region
+/// from now on, until it calls `unmap_shared_memory()` or terminates.
+///
+/// # Errors
+///
+/// * **AccessDenied**: This process doesn't hold the region, or holds it with fewer flags
+/// * **ProcessNotFound**: The process doesn't exist
+/// * **MemoryInUse**: The process already holds the region
+/// * **OutOfMemory**: The region already has as many holders as it can have
+pub fn grant_shared_memory(
+    id: SharedMemoryId,
+    pid: PID,
+    flags: MemoryFlags,
+) -> core::result::Result<(), Error> {
+    let result = rsyscall(SysCall::GrantSharedMemory(id, pid, flags))?;
+    if let Result::Ok = result {
+        Ok(())
+    } else if let Result::Error(e) = result {
+        Err(e)
+    } else {
+        Err(Error::InternalError)
+    }
+}
+
+/// Map shared memory region `id`, which has been granted to this process.
+///
+/// # Errors
+///
+/// * **AccessDenied**: The region doesn't exist, or this process doesn't hold it
+/// * **MemoryInUse**: This process has already mapped the region
+/// * **BadAddress**: There is no room for the region in the address space
+pub fn map_shared_memory(id: SharedMemoryId) -> core::result::Result<MemoryRange, Error> {
+    let result = rsyscall(SysCall::MapSharedMemory(id))?;
+    if let Result::MemoryRange(range) = result {
+        Ok(range)
+    } else if let Result::Error(e) = result {
+        Err(e)
+    } else {
+        Err(Error::InternalError)
+    }
+}
+
+/// Give up this process' reference to shared memory region `id`, unmapping it
+/// if it was mapped. The memory is freed once every process that held the
+/// region has given it up. Use this rather than `unmap_memory()`, which
+/// refuses to unmap shared memory.
+///
+/// # Errors
+///
+/// * **AccessDenied**: The region doesn't exist, or this process doesn't hold it
+pub fn unmap_shared_memory(id: SharedMemoryId) -> core::result::Result<(), Error> {
+    let result = rsyscall(SysCall::UnmapSharedMemory(id))?;
+    if let Result::Ok = result {
+        Ok(())
+    } else if let Result::MemoryRange(range) = result {
+        // This was the last reference to memory that a process allocated
+        crate::arch::unmap_memory_post(range)
+    } else if let Result::Error(e) = result {
+        Err(e)
+    } else {
+        Err(Error::InternalError)
+    }
+}
+
+/// Find out how much memory process `pid` is using, and how much memory is
+/// free in the whole system.
+///
+/// # Errors
+///
+/// * **ProcessNotFound**: The process does not exist, or has terminated
+pub fn memory_stats(pid: PID) -> core::result::Result<MemoryStats, Error> {
+    let result = rsyscall(SysCall::GetMemoryStats(pid))?;
+    if let Result::MemoryStats(stats) = result {
+        Ok(stats)
+    } else if let Result::Error(e) = result {
+        Err(e)
+    } else {
+        Err(Error::InternalError)
+    }
+}
+
 /// Map the given physical address to the given virtual address.
 /// The `size` field must be page-aligned.
 pub fn return_memory(sender: MessageSender, mem: MemoryRange) -> core::result::Result<(), Error> {
@@ -1011,6 +1951,30 @@ pub fn return_memory(sender: MessageSender, mem: MemoryRange) -> core::result::R
     }
 }
 
+/// Return a Borrowed memory region to the sender, telling it where the
+/// interesting part of the buffer starts and how many bytes of it are valid.
+/// The sender's `send_message()` returns these as `Result::MemoryReturned`.
+///
+/// # Errors
+///
+/// * **ServerNotFound**: The message was not sent to a server owned by this process
+/// * **ProcessNotFound**: There is no message waiting for this sender
+pub fn return_memory_offset_valid(
+    sender: MessageSender,
+    mem: MemoryRange,
+    offset: Option<MemoryAddress>,
+    valid: Option<MemorySize>,
+) -> core::result::Result<(), Error> {
+    let result = rsyscall(SysCall::ReturnMemoryWithValid(sender, mem, offset, valid))?;
+    if let crate::Result::Ok = result {
+        Ok(())
+    } else if let Result::Error(e) = result {
+        Err(e)
+    } else {
+        Err(Error::InternalError)
+    }
+}
+
 /// Map the given physical address to the given virtual address.
 /// The `size` field must be page-aligned.
 pub fn return_scalar(sender: MessageSender, val: usize) -> core::result::Result<(), Error> {
@@ -1024,6 +1988,16 @@ pub fn return_scalar(sender: MessageSender, val: usize) -> core::result::Result<
     }
 }
 
+/// Answer a `CONNECT_REQUEST_ID` message. If `approved` is `true` the process
+/// that asked to connect is given its connection, and otherwise its connect
+/// call fails with `AccessDenied`.
+pub fn approve_connection(
+    sender: MessageSender,
+    approved: bool,
+) -> core::result::Result<(), Error> {
+    return_scalar(sender, approved as usize)
+}
+
 /// Map the given physical address to the given virtual address.
 /// The `size` field must be page-aligned.
 pub fn return_scalar2(
@@ -1104,6 +2078,75 @@ pub fn create_server() -> core::result::Result<SID, Error> {
     }
 }
 
+/// Tear down a server that was created by this process. Clients that are
+/// waiting on it, along with any threads in this process that are waiting
+/// for a message, receive `ServerNotFound`.
+///
+/// # Errors
+///
+/// * **ServerNotFound**: The server does not exist or belongs to another process
+pub fn destroy_server(server: SID) -> core::result::Result<(), Error> {
+    let result = rsyscall(SysCall::DestroyServer(server))?;
+    if let Result::Ok = result {
+        Ok(())
+    } else if let Result::Error(e) = result {
+        Err(e)
+    } else {
+        Err(Error::InternalError)
+    }
+}
+
+/// Choose whether `server` must approve each process that connects to it.
+/// See `SysCall::RequireConnectApproval` for how requests are delivered.
+///
+/// # Errors
+///
+/// * **ServerNotFound**: The server does not exist or belongs to another process
+pub fn require_connect_approval(server: SID, required: bool) -> core::result::Result<(), Error> {
+    let result = rsyscall(SysCall::RequireConnectApproval(server, required))?;
+    if let Result::Ok = result {
+        Ok(())
+    } else if let Result::Error(e) = result {
+        Err(e)
+    } else {
+        Err(Error::InternalError)
+    }
+}
+
+/// Choose whether `server` is sent a `CLIENT_DISCONNECTED_ID` message each
+/// time a process that is connected to it terminates. Servers that keep
+/// something on behalf of their clients use this to know when to let go of it.
+///
+/// # Errors
+///
+/// * **ServerNotFound**: The server does not exist or belongs to another process
+pub fn notify_disconnects(server: SID, enabled: bool) -> core::result::Result<(), Error> {
+    let result = rsyscall(SysCall::NotifyDisconnects(server, enabled))?;
+    if let Result::Ok = result {
+        Ok(())
+    } else if let Result::Error(e) = result {
+        Err(e)
+    } else {
+        Err(Error::InternalError)
+    }
+}
+
+/// Return the PID of the process that owns `server`.
+///
+/// # Errors
+///
+/// * **ServerNotFound**: The server does not exist
+pub fn server_owner(server: SID) -> core::result::Result<PID, Error> {
+    let result = rsyscall(SysCall::ServerOwner(server))?;
+    if let Result::ProcessID(pid) = result {
+        Ok(pid)
+    } else if let Result::Error(e) = result {
+        Err(e)
+    } else {
+        Err(Error::InternalError)
+    }
+}
+
 /// Connect to a server with the given SID
 pub fn connect(server: SID) -> core::result::Result<CID, Error> {
     let result = rsyscall(SysCall::Connect(server))?;
@@ -1116,6 +2159,23 @@ pub fn connect(server: SID) -> core::result::Result<CID, Error> {
     }
 }
 
+/// Release a connection that is no longer needed. The connection ID may be
+/// handed out again by a later call to `connect()`.
+///
+/// # Errors
+///
+/// * **ServerNotFound**: The connection ID is not in use
+pub fn disconnect(connection: CID) -> core::result::Result<(), Error> {
+    let result = rsyscall(SysCall::Disconnect(connection))?;
+    if let Result::Ok = result {
+        Ok(())
+    } else if let Result::Error(e) = result {
+        Err(e)
+    } else {
+        Err(Error::InternalError)
+    }
+}
+
 /// Connect to a server with the given SID
 pub fn try_connect(server: SID) -> core::result::Result<CID, Error> {
     let result = rsyscall(SysCall::TryConnect(server))?;
@@ -1133,8 +2193,9 @@ pub fn try_connect(server: SID) -> core::result::Result<CID, Error> {
 ///
 /// # Errors
 ///
+/// * **ServerNotFound**: The server does not exist, or was destroyed while waiting
 pub fn receive_message(server: SID) -> core::result::Result<MessageEnvelope, Error> {
-    let result = rsyscall(SysCall::ReceiveMessage(server)).expect("Couldn't call ReceiveMessage");
+    let result = rsyscall(SysCall::ReceiveMessage(server))?;
     if let Result::Message(envelope) = result {
         Ok(envelope)
     } else if let Result::Error(e) = result {
@@ -1163,6 +2224,27 @@ pub fn try_receive_message(server: SID) -> core::result::Result<Option<MessageEn
     }
 }
 
+/// Suspend the current thread until a message is received, or until
+/// `timeout_ms` milliseconds have passed.
+///
+/// # Errors
+///
+/// * **ServerNotFound**: The server does not exist or belongs to another process
+/// * **Timeout**: No message arrived in time
+pub fn receive_message_timeout(
+    server: SID,
+    timeout_ms: usize,
+) -> core::result::Result<MessageEnvelope, Error> {
+    let result = rsyscall(SysCall::ReceiveMessageTimeout(server, timeout_ms))?;
+    if let Result::Message(envelope) = result {
+        Ok(envelope)
+    } else if let Result::Error(e) = result {
+        Err(e)
+    } else {
+        Err(Error::InternalError)
+    }
+}
+
 /// Send a message to a server.  Depending on the mesage type (move or borrow), it
 /// will either block (borrow) or return immediately (move).
 /// If the message type is `borrow`, then the memory addresses pointed to will be
@@ -1171,6 +2253,7 @@ pub fn try_receive_message(server: SID) -> core::result::Result<Option<MessageEn
 /// # Errors
 ///
 /// * **ServerNotFound**: The server does not exist so the connection is now invalid
+/// * **ProcessTerminated**: The server's process terminated before responding
 /// * **BadAddress**: The client tried to pass a Memory message using an address it doesn't own
 /// * **ServerQueueFull**: The queue in the server is full, and this call would block
 /// * **Timeout**: The timeout limit has been reached
@@ -1180,6 +2263,7 @@ pub fn try_send_message(connection: CID, message: Message) -> core::result::Resu
         Ok(Result::Ok) => Ok(Result::Ok),
         Ok(Result::Scalar1(a)) => Ok(Result::Scalar1(a)),
         Ok(Result::Scalar2(a, b)) => Ok(Result::Scalar2(a, b)),
+        Ok(Result::MemoryReturned(offset, valid)) => Ok(Result::MemoryReturned(offset, valid)),
         Err(e) => Err(e),
         v => panic!("Unexpected return value: {:?}", v),
     }
@@ -1195,7 +2279,7 @@ pub fn try_send_message(connection: CID, message: Message) -> core::result::Resu
 /// * **ServerQueueFull**: The queue in the server is full, and this call would block
 /// * **Timeout**: The timeout limit has been reached
 pub fn connect_for_process(pid: PID, sid: SID) -> core::result::Result<Result, Error> {
-    let result = rsyscall(SysCall::ConnectForProcess(pid, sid));
+    let result = rsyscall(SysCall::ConnectForProcess(pid, sid, None));
     match result {
         Ok(Result::ConnectionID(cid)) => Ok(Result::ConnectionID(cid)),
         Err(e) => Err(e),
@@ -1203,6 +2287,94 @@ pub fn connect_for_process(pid: PID, sid: SID) -> core::result::Result<Result, E
     }
 }
 
+/// Connect to a server on behalf of another process, but fail rather than
+/// wait if the server must approve the connection first.
+///
+/// # Errors
+///
+/// * **ServerNotFound**: The server does not exist
+/// * **AccessDenied**: The server requires approval for new connections
+pub fn try_connect_for_process(pid: PID, sid: SID) -> core::result::Result<CID, Error> {
+    let result = rsyscall(SysCall::TryConnectForProcess(pid, sid))?;
+    if let Result::ConnectionID(cid) = result {
+        Ok(cid)
+    } else if let Result::Error(e) = result {
+        Err(e)
+    } else {
+        Err(Error::InternalError)
+    }
+}
+
+/// Connect to a server on behalf of another process, and grant that process
+/// `rights` on the server. A process may only grant rights that it holds
+/// itself, so only the owner of a server can hand out more than the default,
+/// and only the owner can take rights away. If the server must approve the
+/// connection, the rights are only granted once it has.
+///
+/// # Errors
+///
+/// * **ServerNotFound**: The server does not exist
+/// * **AccessDenied**: The rights go beyond what this process holds, take
+///   rights away from a process on a server this process doesn't own, or the
+///   server turned the connection down
+pub fn connect_for_process_with_rights(
+    pid: PID,
+    sid: SID,
+    rights: ConnectionRights,
+) -> core::result::Result<CID, Error> {
+    let result = rsyscall(SysCall::ConnectForProcess(pid, sid, Some(rights)))?;
+    if let Result::ConnectionID(cid) = result {
+        Ok(cid)
+    } else if let Result::Error(e) = result {
+        Err(e)
+    } else {
+        Err(Error::InternalError)
+    }
+}
+
+/// Limit what clients may send to `server`, which must belong to this process.
+/// Pass a PID to set the rights of one process, or `None` to set the default
+/// for all processes that haven't been given rights of their own.
+///
+/// # Errors
+///
+/// * **ServerNotFound**: The server does not exist or belongs to another process
+pub fn set_connection_rights(
+    server: SID,
+    pid: Option<PID>,
+    rights: ConnectionRights,
+) -> core::result::Result<(), Error> {
+    let result = rsyscall(SysCall::SetConnectionRights(server, pid, rights))?;
+    if let Result::Ok = result {
+        Ok(())
+    } else if let Result::Error(e) = result {
+        Err(e)
+    } else {
+        Err(Error::InternalError)
+    }
+}
+
+/// Set the scheduling priority of a process. Pass `None` to change the
+/// priority of the calling process. Higher priorities are scheduled first.
+/// A process starts at `DEFAULT_PRIORITY`, and can't raise itself any higher
+/// unless its parent raises it first.
+///
+/// # Errors
+///
+/// * **ProcessNotFound**: The requested process does not exist
+/// * **ProcessNotChild**: The given process is neither the caller nor one of its children
+/// * **AccessDenied**: The priority is higher than the caller may use
+pub fn set_priority(pid: Option<PID>, priority: Priority) -> core::result::Result<(), Error> {
+    let result = rsyscall(SysCall::SetPriority(pid, priority))?;
+    if let Result::Ok = result {
+        Ok(())
+    } else if let Result::Error(e) = result {
+        Err(e)
+    } else {
+        Err(Error::InternalError)
+    }
+}
+
 /// Send a message to a server.  Depending on the mesage type (move or borrow), it
 /// will either block (borrow) or return immediately (move).
 /// If the message type is `borrow`, then the memory addresses pointed to will be
@@ -1213,7 +2385,9 @@ pub fn connect_for_process(pid: PID, sid: SID) -> core::result::Result<Result, E
 /// # Errors
 ///
 /// * **ServerNotFound**: The server does not exist so the connection is now invalid
+/// * **ProcessTerminated**: The server's process terminated before responding
 /// * **BadAddress**: The client tried to pass a Memory message using an address it doesn't own
+/// * **ShareViolation**: The memory is already mutably lent, or was to be mutably lent while already lent
 /// * **Timeout**: The timeout limit has been reached
 pub fn send_message(connection: CID, message: Message) -> core::result::Result<Result, Error> {
     let result = rsyscall(SysCall::SendMessage(connection, message));
@@ -1221,13 +2395,74 @@ pub fn send_message(connection: CID, message: Message) -> core::result::Result<R
         Ok(Result::Ok) => Ok(Result::Ok),
         Ok(Result::Scalar1(a)) => Ok(Result::Scalar1(a)),
         Ok(Result::Scalar2(a, b)) => Ok(Result::Scalar2(a, b)),
+        Ok(Result::MemoryReturned(offset, valid)) => Ok(Result::MemoryReturned(offset, valid)),
+        Err(e) => Err(e),
+        v => panic!("Unexpected return value: {:?}", v),
+    }
+}
+
+/// Send a message to a server, waiting at most `timeout_ms` milliseconds for
+/// a blocking message to be responded to. Non-blocking messages are sent as
+/// with `send_message()`.
+///
+/// Memory lent with a message that the server has already received stays lent
+/// after a timeout, and must not be used or unmapped until the server responds.
+/// A server that never responds keeps it for good. `memory_stats()` shows how
+/// many pages are still lent.
+///
+/// # Errors
+///
+/// * **ServerNotFound**: The server does not exist so the connection is now invalid
+/// * **ProcessTerminated**: The server's process terminated before responding
+/// * **BadAddress**: The client tried to pass a Memory message using an address it doesn't own
+/// * **ServerQueueFull**: The queue in the server is full, and this call would block
+/// * **InvalidSyscall**: `timeout_ms` is larger than `usize::MAX >> 8`
+/// * **Timeout**: The server did not respond in time. If the server hadn't received
+///                the message yet, any lent memory has been returned. Otherwise
+///                it stays lent until the server responds.
+pub fn send_message_timeout(
+    connection: CID,
+    message: Message,
+    timeout_ms: usize,
+) -> core::result::Result<Result, Error> {
+    // The timeout is packed in above the message type, which needs eight bits.
+    if timeout_ms > usize::MAX >> 8 {
+        return Err(Error::InvalidSyscall);
+    }
+    let result = rsyscall(SysCall::SendMessageTimeout(connection, message, timeout_ms));
+    match result {
+        Ok(Result::Ok) => Ok(Result::Ok),
+        Ok(Result::Scalar1(a)) => Ok(Result::Scalar1(a)),
+        Ok(Result::Scalar2(a, b)) => Ok(Result::Scalar2(a, b)),
+        Ok(Result::MemoryReturned(offset, valid)) => Ok(Result::MemoryReturned(offset, valid)),
         Err(e) => Err(e),
         v => panic!("Unexpected return value: {:?}", v),
     }
 }
 
-pub fn terminate_process() {
-    rsyscall(SysCall::TerminateProcess).expect("terminate_process returned an error");
+/// End the current process. `exit_code` is handed to the parent process when
+/// it collects this one with `join_process()`.
+pub fn terminate_process(exit_code: usize) -> ! {
+    crate::arch::terminate_process(exit_code)
+}
+
+/// Block until child process `pid` terminates, then return its exit code.
+///
+/// # Errors
+///
+/// * **ProcessNotFound**: The process does not exist, or has already been
+///                        joined
+/// * **ProcessNotChild**: The process is not a child of the caller
+/// * **OutOfMemory**: Too many threads are waiting for processes to terminate
+pub fn join_process(pid: PID) -> core::result::Result<usize, Error> {
+    let result = rsyscall(SysCall::WaitProcess(pid))?;
+    if let Result::Scalar1(code) = result {
+        Ok(code)
+    } else if let Result::Error(e) = result {
+        Err(e)
+    } else {
+        Err(Error::InternalError)
+    }
 }
 
 /// Return execution to the kernel. This function may return at any time,
@@ -1241,6 +2476,171 @@ pub fn wait_event() {
     rsyscall(SysCall::WaitEvent).expect("wait_event returned an error");
 }
 
+/// Set `events` on the server that `connection` goes to, waking one of its
+/// threads if it is waiting in `wait_events()`.
+///
+/// # Errors
+///
+/// * **ServerNotFound**: The connection is not valid
+/// * **AccessDenied**: The connection's rights don't include `MessageKinds::EVENTS`
+pub fn signal_events(connection: CID, events: usize) -> core::result::Result<(), Error> {
+    let result = rsyscall(SysCall::SignalEvents(connection, events))?;
+    if let Result::Ok = result {
+        Ok(())
+    } else if let Result::Error(e) = result {
+        Err(e)
+    } else {
+        Err(Error::InternalError)
+    }
+}
+
+/// Set `events` on the server that `connection` goes to whenever a message is
+/// queued on `server`, which must belong to this process. This lets a thread
+/// that waits in `wait_events()` find out about messages on several servers.
+/// Passing zero for `events` stops the notifications.
+///
+/// # Errors
+///
+/// * **ServerNotFound**: The server does not exist or belongs to another
+///                       process, or the connection is not valid
+/// * **AccessDenied**: The connection's rights don't include `MessageKinds::EVENTS`
+pub fn signal_on_message(
+    server: SID,
+    connection: CID,
+    events: usize,
+) -> core::result::Result<(), Error> {
+    let result = rsyscall(SysCall::SignalOnMessage(server, connection, events))?;
+    if let Result::Ok = result {
+        Ok(())
+    } else if let Result::Error(e) = result {
+        Err(e)
+    } else {
+        Err(Error::InternalError)
+    }
+}
+
+/// Block until at least one event has been signalled on `server`, then
+/// return and clear every event bit that is set.
+///
+/// # Errors
+///
+/// * **ServerNotFound**: The server does not exist or belongs to another process
+pub fn wait_events(server: SID) -> core::result::Result<usize, Error> {
+    let result = rsyscall(SysCall::WaitEvents(server))?;
+    if let Result::Scalar1(events) = result {
+        Ok(events)
+    } else if let Result::Error(e) = result {
+        Err(e)
+    } else {
+        Err(Error::InternalError)
+    }
+}
+
+/// Return and clear the event bits that are set on `server`, which is zero
+/// if none have been signalled.
+///
+/// # Errors
+///
+/// * **ServerNotFound**: The server does not exist or belongs to another process
+pub fn try_wait_events(server: SID) -> core::result::Result<usize, Error> {
+    let result = rsyscall(SysCall::TryWaitEvents(server))?;
+    if let Result::Scalar1(events) = result {
+        Ok(events)
+    } else if let Result::Error(e) = result {
+        Err(e)
+    } else {
+        Err(Error::InternalError)
+    }
+}
+
+/// Block the current thread until `futex` is woken by `futex_wake()`, unless
+/// it no longer holds `expected`. This may return spuriously, so the caller
+/// should check the value again afterwards.
+///
+/// # Errors
+///
+/// * **OutOfMemory**: Too many threads are waiting on futexes
+pub fn futex_wait(futex: &AtomicUsize, expected: usize) -> core::result::Result<(), Error> {
+    let addr = MemoryAddress::new(futex as *const AtomicUsize as usize).unwrap();
+    let result = rsyscall(SysCall::FutexWait(addr, expected))?;
+    if let Result::Ok = result {
+        Ok(())
+    } else if let Result::Error(e) = result {
+        Err(e)
+    } else {
+        Err(Error::InternalError)
+    }
+}
+
+/// Wake up to `count` threads that are waiting on `futex`, returning the number
+/// that were woken.
+pub fn futex_wake(futex: &AtomicUsize, count: usize) -> core::result::Result<usize, Error> {
+    let addr = MemoryAddress::new(futex as *const AtomicUsize as usize).unwrap();
+    let result = rsyscall(SysCall::FutexWake(addr, count))?;
+    if let Result::Scalar1(woken) = result {
+        Ok(woken)
+    } else if let Result::Error(e) = result {
+        Err(e)
+    } else {
+        Err(Error::InternalError)
+    }
+}
+
+/// End the current thread. `code` is handed to whichever thread joins this
+/// one with `join_thread()`.
+pub fn exit_thread(code: usize) -> ! {
+    crate::arch::exit_thread(code)
+}
+
+/// Block until thread `tid` in this process exits, then return its exit code.
+///
+/// # Errors
+///
+/// * **InvalidThread**: The thread does not exist, is the calling thread, or
+///                      is already being joined by another thread
+/// * **OutOfMemory**: Too many threads are waiting to join other threads
+pub fn join_thread(tid: TID) -> core::result::Result<usize, Error> {
+    let result = rsyscall(SysCall::JoinThread(tid))?;
+    if let Result::Scalar1(code) = result {
+        Ok(code)
+    } else if let Result::Error(e) = result {
+        Err(e)
+    } else {
+        Err(Error::InternalError)
+    }
+}
+
+/// Let thread `tid` in this process be freed as soon as it exits, instead of
+/// waiting for `join_thread()`. Its exit code is thrown away, and `stack` is
+/// unmapped once the thread has stopped running.
+///
+/// # Errors
+///
+/// * **InvalidThread**: The thread does not exist, or is already being joined or detached
+/// * **OutOfMemory**: Too many threads have been detached
+pub fn detach_thread(tid: TID, stack: Option<MemoryRange>) -> core::result::Result<(), Error> {
+    let result = rsyscall(SysCall::DetachThread(tid, stack))?;
+    if let Result::Ok = result {
+        Ok(())
+    } else if let Result::Error(e) = result {
+        Err(e)
+    } else {
+        Err(Error::InternalError)
+    }
+}
+
+/// Return the ID of the calling thread.
+pub fn current_tid() -> core::result::Result<TID, Error> {
+    let result = rsyscall(SysCall::GetThreadId)?;
+    if let Result::ThreadID(tid) = result {
+        Ok(tid)
+    } else if let Result::Error(e) = result {
+        Err(e)
+    } else {
+        Err(Error::InternalError)
+    }
+}
+
 pub fn create_thread_simple<T, U>(
     f: fn(T) -> U,
     arg: T,
@@ -1250,16 +2650,22 @@ where
     U: Send + 'static,
 {
     let thread_info = crate::arch::create_thread_simple_pre(&f, &arg)?;
-    rsyscall(SysCall::CreateThread(thread_info)).and_then(|result| {
-        if let Result::ThreadID(thread_id) = result {
-            crate::arch::create_thread_simple_post(f, arg, thread_id)
-        } else {
-            Err(Error::InternalError)
+    match rsyscall(SysCall::CreateThread(thread_info)) {
+        Ok(Result::ThreadID(thread_id)) => {
+            crate::arch::create_thread_simple_post(f, arg, thread_info, thread_id)
         }
-    })
+        Ok(_) => Err(Error::InternalError),
+        Err(e) => {
+            // The thread never existed, so nothing else can be using its stack.
+            crate::arch::create_thread_failed(thread_info);
+            Err(e)
+        }
+    }
 }
 
-/// Create a new thread with the given closure.
+/// Create a new thread with the given closure. If the returned handle is
+/// dropped rather than passed to `wait_thread()`, the thread is detached and
+/// cleans up after itself when it exits.
 pub fn create_thread<F, T>(f: F) -> core::result::Result<crate::arch::WaitHandle<T>, Error>
 where
     F: FnOnce() -> T,
@@ -1267,17 +2673,25 @@ where
     T: Send + 'static,
 {
     let thread_info = crate::arch::create_thread_pre(&f)?;
-    rsyscall(SysCall::CreateThread(thread_info)).and_then(|result| {
-        if let Result::ThreadID(thread_id) = result {
-            crate::arch::create_thread_post(f, thread_id)
-        } else {
-            Err(Error::InternalError)
+    match rsyscall(SysCall::CreateThread(thread_info)) {
+        Ok(Result::ThreadID(thread_id)) => {
+            crate::arch::create_thread_post(f, thread_info, thread_id)
         }
-    })
+        Ok(_) => Err(Error::InternalError),
+        Err(e) => {
+            crate::arch::create_thread_failed(thread_info);
+            Err(e)
+        }
+    }
 }
 
-/// Wait for a thread to finish
-pub fn wait_thread<T>(joiner: crate::arch::WaitHandle<T>) -> SysCallResult {
+/// Wait for a thread to finish, and return the value that it returned.
+///
+/// # Errors
+///
+/// * **ThreadNotAvailable**: The thread ended without returning a value, for
+///                           example by calling `exit_thread()`
+pub fn wait_thread<T>(joiner: crate::arch::WaitHandle<T>) -> core::result::Result<T, Error> {
     crate::arch::wait_thread(joiner)
 }
 
@@ -1305,6 +2719,15 @@ pub fn wait_process_as_thread(joiner: crate::arch::ProcessHandleAsThread) -> Sys
     crate::arch::wait_process_as_thread(joiner)
 }
 
+/// Start a new process as a child of this one. On hardware, `args` points at
+/// the MiniElf image of the program to run.
+///
+/// # Errors
+///
+/// * **BadAddress**: The image is truncated or can't be read, or it places a
+///                   section outside of userspace
+/// * **OutOfMemory**: There wasn't enough memory to load the image
+/// * **ProcessNotFound**: No more processes can be created
 pub fn create_process(
     args: ProcessArgs,
 ) -> core::result::Result<crate::arch::ProcessHandle, Error> {
@@ -1318,8 +2741,8 @@ pub fn create_process(
     })
 }
 
-/// Wait for a thread to finish
-pub fn wait_process(joiner: crate::arch::ProcessHandle) -> SysCallResult {
+/// Wait for a process to finish, and return its exit code
+pub fn wait_process(joiner: crate::arch::ProcessHandle) -> core::result::Result<usize, Error> {
     crate::arch::wait_process(joiner)
 }
 
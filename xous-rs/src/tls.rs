@@ -0,0 +1,109 @@
+//! Thread-local storage that works without `std`. Every key has one slot for
+//! each possible thread ID, and the slot of whichever thread is calling is
+//! picked by asking the kernel for its ID.
+//!
+//! Thread IDs are reused once a thread has been joined, so each thread that is
+//! started with `create_thread()` or `create_thread_simple()` is also given a
+//! generation number. A slot that was filled in by an earlier thread with the
+//! same ID is thrown away and initialized again.
+
+use core::cell::UnsafeCell;
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+/// One more than the highest thread ID that a process may have.
+pub const MAX_THREADS: usize = 32;
+
+/// The generation of the thread currently using each thread ID. Threads that
+/// weren't started by this library, such as the first one, are generation 0.
+static THREAD_GENERATION: [AtomicUsize; MAX_THREADS] = {
+    #[allow(clippy::declare_interior_mutable_const)]
+    const ZERO: AtomicUsize = AtomicUsize::new(0);
+    [ZERO; MAX_THREADS]
+};
+static NEXT_GENERATION: AtomicUsize = AtomicUsize::new(1);
+
+/// Give thread `tid` a new generation. This must be called by the new thread
+/// before it runs any code that might use thread-local storage.
+pub(crate) fn thread_started(tid: usize) {
+    if let Some(slot) = THREAD_GENERATION.get(tid) {
+        let generation = NEXT_GENERATION.fetch_add(1, Ordering::Relaxed);
+        slot.store(generation, Ordering::Relaxed);
+    }
+}
+
+struct Slot<T> {
+    generation: usize,
+    value: Option<T>,
+}
+
+/// A value that each thread gets its own copy of. These are declared with
+/// `xous::thread_local!`, and each thread's copy is created the first time
+/// that thread calls `with()`.
+pub struct LocalKey<T: 'static> {
+    init: fn() -> T,
+    slots: [UnsafeCell<Slot<T>>; MAX_THREADS],
+}
+
+// Each thread only ever touches its own slot, but a value left behind by a
+// thread that has exited is dropped by the next thread to get the same ID.
+unsafe impl<T: Send> Sync for LocalKey<T> {}
+
+impl<T: 'static> LocalKey<T> {
+    #[allow(clippy::declare_interior_mutable_const)]
+    const EMPTY: UnsafeCell<Slot<T>> = UnsafeCell::new(Slot {
+        generation: 0,
+        value: None,
+    });
+
+    #[doc(hidden)]
+    pub const fn new(init: fn() -> T) -> LocalKey<T> {
+        LocalKey {
+            init,
+            slots: [Self::EMPTY; MAX_THREADS],
+        }
+    }
+
+    /// Call `f` with this thread's copy of the value, creating it first if
+    /// necessary.
+    ///
+    /// # Panics
+    ///
+    /// If the kernel won't say which thread is calling, or if its ID is
+    /// `MAX_THREADS` or higher.
+    pub fn with<F, R>(&'static self, f: F) -> R
+    where
+        F: FnOnce(&T) -> R,
+    {
+        let tid = crate::current_tid().expect("couldn't get current thread ID");
+        let generation = THREAD_GENERATION[tid].load(Ordering::Relaxed);
+        let slot = self.slots[tid].get();
+        unsafe {
+            if (*slot).generation != generation || (*slot).value.is_none() {
+                (*slot).value = Some((self.init)());
+                (*slot).generation = generation;
+            }
+            f((*slot).value.as_ref().unwrap())
+        }
+    }
+}
+
+/// Declare one or more thread-local values, in the same way as
+/// `std::thread_local!`. Each one becomes a `xous::tls::LocalKey`.
+#[macro_export]
+macro_rules! thread_local {
+    () => {};
+
+    ($(#[$attr:meta])* $vis:vis static $name:ident: $t:ty = $init:expr; $($rest:tt)*) => {
+        $crate::thread_local!($(#[$attr])* $vis static $name: $t = $init);
+        $crate::thread_local!($($rest)*);
+    };
+
+    ($(#[$attr:meta])* $vis:vis static $name:ident: $t:ty = $init:expr) => {
+        $(#[$attr])* $vis static $name: $crate::tls::LocalKey<$t> = {
+            fn __init() -> $t {
+                $init
+            }
+            $crate::tls::LocalKey::new(__init)
+        };
+    };
+}
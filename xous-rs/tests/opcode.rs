@@ -0,0 +1,95 @@
+use core::convert::TryFrom;
+
+use xous::{MemoryAddress, MemoryMessage, MemoryRange, MemorySize, Message, ScalarMessage};
+
+const LOOKUP_ID: usize = 4;
+
+#[derive(Debug, PartialEq, xous::Opcode)]
+enum Opcode {
+    #[opcode(scalar, id = 1)]
+    Reset,
+
+    #[opcode(blocking_scalar, id = 1)]
+    SleepMs(usize),
+
+    #[opcode(scalar, id = 2)]
+    Move { x: usize, y: usize },
+
+    #[opcode(borrow, id = 3)]
+    Print(MemoryMessage),
+
+    #[opcode(mutable_borrow, id = LOOKUP_ID)]
+    Lookup(MemoryMessage),
+
+    #[opcode(move, id = 3)]
+    Store { message: MemoryMessage },
+}
+
+fn memory(id: usize) -> MemoryMessage {
+    MemoryMessage {
+        id,
+        buf: MemoryRange::new(0x1000, 0x2000).unwrap(),
+        offset: MemoryAddress::new(0x10),
+        valid: MemorySize::new(0x80),
+    }
+}
+
+#[test]
+fn scalar_opcodes_round_trip() {
+    let message: Message = Opcode::Move { x: 5, y: 7 }.into();
+    assert_eq!(
+        message,
+        Message::Scalar(ScalarMessage {
+            id: 2,
+            arg1: 5,
+            arg2: 7,
+            arg3: 0,
+            arg4: 0
+        })
+    );
+    assert_eq!(Opcode::try_from(&message), Ok(Opcode::Move { x: 5, y: 7 }));
+
+    // The kind of message tells opcodes with the same ID apart.
+    let reset: Message = Opcode::Reset.into();
+    let sleep: Message = Opcode::SleepMs(10).into();
+    assert_eq!(Opcode::try_from(&reset), Ok(Opcode::Reset));
+    assert_eq!(Opcode::try_from(&sleep), Ok(Opcode::SleepMs(10)));
+
+    let unknown = Message::Scalar(ScalarMessage {
+        id: 9,
+        arg1: 0,
+        arg2: 0,
+        arg3: 0,
+        arg4: 0,
+    });
+    assert_eq!(Opcode::try_from(&unknown), Err("unrecognized opcode"));
+}
+
+#[test]
+fn memory_opcodes_round_trip() {
+    // The ID of the message that is passed in is replaced, but the memory,
+    // offset and valid size are all kept.
+    let message: Message = Opcode::Lookup(memory(0)).into();
+    assert_eq!(message, Message::MutableBorrow(memory(LOOKUP_ID)));
+    assert_eq!(
+        Opcode::try_from(&message),
+        Ok(Opcode::Lookup(memory(LOOKUP_ID)))
+    );
+
+    let message: Message = Opcode::Store { message: memory(0) }.into();
+    assert_eq!(message, Message::Move(memory(3)));
+    assert_eq!(
+        Opcode::try_from(&message),
+        Ok(Opcode::Store { message: memory(3) })
+    );
+
+    let message: Message = Opcode::Print(memory(0)).into();
+    assert_eq!(message, Message::Borrow(memory(3)));
+    assert_eq!(Opcode::try_from(&message), Ok(Opcode::Print(memory(3))));
+
+    // Borrows and moves are different kinds of message, even with the same ID.
+    assert_eq!(
+        Opcode::try_from(&Message::MutableBorrow(memory(3))),
+        Err("unrecognized opcode")
+    );
+}
@@ -9,6 +9,26 @@ type DynError = Box<dyn std::error::Error>;
 
 const TARGET: &str = "riscv32imac-unknown-none-elf";
 
+/// Priority that init processes start at, unless listed in `INIT_PRIORITIES`.
+/// This matches `xous::DEFAULT_PRIORITY`.
+const DEFAULT_PRIORITY: u8 = 128;
+
+/// Init processes that start above the default priority. A process can't
+/// raise itself above the priority it was started with.
+const INIT_PRIORITIES: &[(&str, u8)] = &[
+    // Keep the display responsive even when other processes are busy.
+    ("graphics-server", DEFAULT_PRIORITY + 64),
+    // The supervisor lowers itself once running, but needs to start out as
+    // high as the services it starts.
+    ("supervisor", DEFAULT_PRIORITY + 64),
+];
+
+/// Services that the supervisor starts and restarts when hosted, rather than
+/// being started as init processes. They are built into the supervisor, so
+/// they must be built before it is. Hardware images still list them as init
+/// processes until the supervisor has been tried there.
+const SUPERVISED: &[&str] = &["com", "graphics-server"];
+
 enum MemorySpec {
     SvdFile(String),
 }
@@ -43,6 +63,7 @@ fn try_main() -> Result<(), DynError> {
         Some("run") => run(false)?,
         Some("hw-image") => build_hw_image(false, env::args().nth(2))?,
         Some("debug") => run(true)?,
+        Some("generate-idl") => generate_idl()?,
         _ => print_help(),
     }
     Ok(())
@@ -56,6 +77,7 @@ renode-image-debug      builds a test image for renode in debug mode
 hw-image [soc.svd]      builds an image for real hardware
 run                     runs a release build using a hosted environment
 debug                   runs a debug build using a hosted environment
+generate-idl            regenerates the API of every service that has an IDL file
 "
     )
 }
@@ -75,9 +97,10 @@ fn build_hw_image(debug: bool, svd: Option<String>) -> Result<(), DynError> {
     std::env::set_var("XOUS_SVD_FILE", path.canonicalize().unwrap());
 
     let kernel = build_kernel(debug)?;
+    std::env::set_var("XOUS_SUPERVISED", "");
     let mut init = vec![];
-    for pkg in &["shell", "graphics-server", "ticktimer-server", "log-server", "com"] { // "xous-names"
-        init.push(build(pkg, debug, Some(TARGET), None)?);
+    for pkg in &["shell", "graphics-server", "ticktimer-server", "log-server", "com", "xous-names", "supervisor"] {
+        init.push((build(pkg, debug, Some(TARGET), None)?, init_priority(pkg)));
     }
     let loader = build("loader", debug, Some(TARGET), Some("loader".into()))?;
 
@@ -144,9 +167,10 @@ fn renode_image(debug: bool) -> Result<(), DynError> {
     let path = std::path::Path::new("emulation/renode.svd");
     std::env::set_var("XOUS_SVD_FILE", path.canonicalize().unwrap());
     let kernel = build_kernel(debug)?;
+    std::env::set_var("XOUS_SUPERVISED", "");
     let mut init = vec![];
-    for pkg in &["shell", "log-server", "graphics-server", "ticktimer-server", "com"] {
-        init.push(build(pkg, debug, Some(TARGET), None)?);
+    for pkg in &["shell", "log-server", "graphics-server", "ticktimer-server", "com", "xous-names", "supervisor"] {
+        init.push((build(pkg, debug, Some(TARGET), None)?, init_priority(pkg)));
     }
     build("loader", debug, Some(TARGET), Some("loader".into()))?;
 
@@ -162,9 +186,10 @@ fn renode_image(debug: bool) -> Result<(), DynError> {
 
 fn run(debug: bool) -> Result<(), DynError> {
     let stream = if debug { "debug" } else { "release" };
-    let init = ["shell", "log-server", "graphics-server", "ticktimer-server", "com"]; // , "xous-names"
+    let init = ["shell", "log-server", "ticktimer-server", "xous-names", "supervisor"];
 
     // let mut init_paths = vec![];
+    build_supervised(debug, None)?;
     for pkg in &init {
         build(pkg, debug, None, None)?;
     }
@@ -208,6 +233,36 @@ fn run(debug: bool) -> Result<(), DynError> {
     Ok(())
 }
 
+/// Run `idl-gen` on every `services/*/src/*.idl`, writing the `.rs` file next to it.
+fn generate_idl() -> Result<(), DynError> {
+    let mut idl_files = vec![];
+    for service in std::fs::read_dir(project_root().join("services"))? {
+        let src = service?.path().join("src");
+        if !src.is_dir() {
+            continue;
+        }
+        for file in std::fs::read_dir(src)? {
+            let path = file?.path();
+            if path.extension().and_then(|ext| ext.to_str()) == Some("idl") {
+                idl_files.push(path);
+            }
+        }
+    }
+    idl_files.sort();
+
+    for idl in idl_files {
+        let status = Command::new(cargo())
+            .current_dir(project_root())
+            .args(["run", "--package", "tools", "--bin", "idl-gen", "--"])
+            .arg(&idl)
+            .status()?;
+        if !status.success() {
+            return Err(format!("couldn't generate code from {}", idl.display()).into());
+        }
+    }
+    Ok(())
+}
+
 fn build_kernel(debug: bool) -> Result<PathBuf, DynError> {
     build("kernel", debug, Some(TARGET), Some("kernel".into()))
 }
@@ -259,9 +314,49 @@ fn build(
     }
 }
 
+/// Build every service in `SUPERVISED`, and list them in `XOUS_SUPERVISED` for
+/// the supervisor to pick up when it is built. On hardware each one is turned
+/// into a process image, and when hosted the supervisor runs the binary.
+fn build_supervised(debug: bool, target: Option<&str>) -> Result<(), DynError> {
+    let mut supervised = vec![];
+    for pkg in SUPERVISED {
+        let elf = build(pkg, debug, target, None)?;
+        let program = if target.is_some() {
+            let image = elf.with_extension("img");
+            let status = Command::new(cargo())
+                .current_dir(project_root())
+                .args(["run", "--package", "tools", "--bin", "make-minielf", "--"])
+                .arg(&elf)
+                .arg(&image)
+                .status()?;
+            if !status.success() {
+                return Err(format!("couldn't make an image of {}", pkg).into());
+            }
+            image
+        } else {
+            elf
+        };
+        supervised.push(format!(
+            "{},{},{}",
+            pkg,
+            init_priority(pkg),
+            program.to_str().ok_or(BuildError::PathConversionError)?
+        ));
+    }
+    std::env::set_var("XOUS_SUPERVISED", supervised.join(";"));
+    Ok(())
+}
+
+fn init_priority(pkg: &str) -> u8 {
+    INIT_PRIORITIES
+        .iter()
+        .find(|(name, _)| *name == pkg)
+        .map_or(DEFAULT_PRIORITY, |(_, priority)| *priority)
+}
+
 fn create_image(
     kernel: &Path,
-    init: &[PathBuf],
+    init: &[(PathBuf, u8)],
     debug: bool,
     memory_spec: MemorySpec,
 ) -> Result<PathBuf, DynError> {
@@ -274,9 +369,15 @@ fn create_image(
     args.push("--kernel");
     args.push(kernel.to_str().ok_or(BuildError::PathConversionError)?);
 
-    for i in init {
+    let priorities: Vec<String> = init
+        .iter()
+        .map(|(_, priority)| priority.to_string())
+        .collect();
+    for ((i, _), priority) in init.iter().zip(priorities.iter()) {
         args.push("--init");
         args.push(i.to_str().ok_or(BuildError::PathConversionError)?);
+        args.push("--init-priority");
+        args.push(priority);
     }
 
     match memory_spec {